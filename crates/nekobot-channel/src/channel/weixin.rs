@@ -6,6 +6,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
+use nekobot_secrets::Keyring;
 use reqwest::Client;
 use serde::Deserialize;
 use serde_json::Value;
@@ -46,6 +47,7 @@ pub struct WeiXinChannel {
     name: String,
     http: Client,
     base_url: String,
+    keyring: Keyring,
     state: Arc<Mutex<ChannelState>>,
 }
 
@@ -55,12 +57,19 @@ impl WeiXinChannel {
             name: name.into(),
             http: Client::new(),
             base_url: base_url.into(),
+            keyring: Keyring::empty(),
             state: Arc::new(Mutex::new(ChannelState {
                 credentials: None,
             })),
         }
     }
 
+    /// Encrypt the persisted login credentials with `keyring`.
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = keyring;
+        self
+    }
+
     /// Perform QR code login and return credentials.
     async fn login(base_url: &str, http: &Client) -> anyhow::Result<WeiXinCredentials> {
         // 1. Get QR code
@@ -354,10 +363,16 @@ impl Channel for WeiXinChannel {
 
         let mut creds: Option<WeiXinCredentials> = None;
         if let Some(ref db) = app_db {
-            if let Ok(Some(json)) = entity::get(db, &self.name).await {
-                creds = serde_json::from_str(&json).ok();
-                if creds.is_some() {
-                    tracing::info!(target: "weixin", "loaded cached credentials from DB");
+            match entity::get(db, &self.keyring, &self.name).await {
+                Ok(Some(json)) => {
+                    creds = serde_json::from_str(&json).ok();
+                    if creds.is_some() {
+                        tracing::info!(target: "weixin", "loaded cached credentials from DB");
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!(target: "weixin", "failed to read cached credentials: {e:#}")
                 }
             }
         }
@@ -376,7 +391,7 @@ impl Channel for WeiXinChannel {
             self.state.lock().await.credentials = Some(creds.clone());
 
             if let (Some(ref db), Ok(json)) = (app_db.as_ref(), serde_json::to_string(&creds)) {
-                let _ = entity::upsert(db, &self.name, &json).await;
+                let _ = entity::upsert(db, &self.keyring, &self.name, &json).await;
                 tracing::info!(target: "weixin", "登录成功，凭证已持久化");
             }
         } else {
@@ -394,7 +409,7 @@ impl Channel for WeiXinChannel {
                 self.state.lock().await.credentials = Some(creds.clone());
 
                 if let (Some(ref db), Ok(json)) = (app_db.as_ref(), serde_json::to_string(&creds)) {
-                    let _ = entity::upsert(db, &self.name, &json).await;
+                    let _ = entity::upsert(db, &self.keyring, &self.name, &json).await;
                 }
                 tracing::info!(target: "weixin", "重新登录成功");
             }
@@ -1,22 +1,48 @@
 //! Channel credential entity — persistent credentials for channels that need login.
+//!
+//! Credentials are sealed with the [`Keyring`] before they are stored, unless
+//! no key is configured.
 
+use nekobot_secrets::{Keyring, SecretColumn};
 use turso::Connection;
 
+use crate::migration::{Migration, MigrationSet};
+
+/// Migrations of the channel credential store; registered by `nekobot-core`.
+pub const MIGRATIONS: MigrationSet = MigrationSet {
+    component: "channel",
+    migrations: &[Migration {
+        version: 1,
+        description: "channel credentials",
+        statements: &[CREATE_TABLE],
+    }],
+};
+
+/// Schema of the `channel_credentials` table (migration 1).
+pub const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS channel_credentials (
+    channel_name TEXT NOT NULL PRIMARY KEY,
+    credentials TEXT NOT NULL
+)";
+
+/// The encrypted `credentials` column, for key rotation.
+pub const CREDENTIALS: SecretColumn = SecretColumn {
+    table: "channel_credentials",
+    key_column: "channel_name",
+    column: "credentials",
+};
+
 /// Create the `channel_credentials` table.
 pub async fn create_table(conn: &Connection) -> anyhow::Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS channel_credentials (
-            channel_name TEXT NOT NULL PRIMARY KEY,
-            credentials TEXT NOT NULL
-        )",
-        (),
-    )
-    .await?;
+    conn.execute(CREATE_TABLE, ()).await?;
     Ok(())
 }
 
 /// Get stored credentials for a channel by its user-defined name.
-pub async fn get(conn: &Connection, channel_name: &str) -> anyhow::Result<Option<String>> {
+pub async fn get(
+    conn: &Connection,
+    keyring: &Keyring,
+    channel_name: &str,
+) -> anyhow::Result<Option<String>> {
     let mut rows = conn
         .query(
             "SELECT credentials FROM channel_credentials WHERE channel_name = ?1",
@@ -24,7 +50,8 @@ pub async fn get(conn: &Connection, channel_name: &str) -> anyhow::Result<Option
         )
         .await?;
     if let Some(row) = rows.next().await? {
-        Ok(Some(row.get(0)?))
+        let stored: String = row.get(0)?;
+        Ok(Some(keyring.open(&CREDENTIALS, channel_name, &stored)?))
     } else {
         Ok(None)
     }
@@ -33,12 +60,14 @@ pub async fn get(conn: &Connection, channel_name: &str) -> anyhow::Result<Option
 /// Insert or replace credentials for a channel.
 pub async fn upsert(
     conn: &Connection,
+    keyring: &Keyring,
     channel_name: &str,
     credentials: &str,
 ) -> anyhow::Result<()> {
+    let sealed = keyring.seal(&CREDENTIALS, channel_name, credentials)?;
     conn.execute(
         "INSERT OR REPLACE INTO channel_credentials (channel_name, credentials) VALUES (?1, ?2)",
-        (channel_name, credentials),
+        (channel_name, sealed.as_str()),
     )
     .await?;
     Ok(())
@@ -69,17 +98,45 @@ mod tests {
     async fn upsert_get_delete() {
         let c = conn().await;
         create_table(&c).await.unwrap();
+        let k = Keyring::empty();
 
-        assert!(get(&c, "test").await.unwrap().is_none());
+        assert!(get(&c, &k, "test").await.unwrap().is_none());
 
-        upsert(&c, "test", r#"{"token":"abc"}"#).await.unwrap();
-        assert_eq!(get(&c, "test").await.unwrap().unwrap(), r#"{"token":"abc"}"#);
+        upsert(&c, &k, "test", r#"{"token":"abc"}"#).await.unwrap();
+        assert_eq!(
+            get(&c, &k, "test").await.unwrap().unwrap(),
+            r#"{"token":"abc"}"#
+        );
 
-        upsert(&c, "test", r#"{"token":"xyz"}"#).await.unwrap();
-        assert_eq!(get(&c, "test").await.unwrap().unwrap(), r#"{"token":"xyz"}"#);
+        upsert(&c, &k, "test", r#"{"token":"xyz"}"#).await.unwrap();
+        assert_eq!(
+            get(&c, &k, "test").await.unwrap().unwrap(),
+            r#"{"token":"xyz"}"#
+        );
 
         assert!(delete(&c, "test").await.unwrap());
         assert!(!delete(&c, "test").await.unwrap());
-        assert!(get(&c, "test").await.unwrap().is_none());
+        assert!(get(&c, &k, "test").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn credentials_are_encrypted_at_rest() {
+        let c = conn().await;
+        create_table(&c).await.unwrap();
+        let k = Keyring::parse("k1:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=").unwrap();
+
+        upsert(&c, &k, "test", r#"{"token":"abc"}"#).await.unwrap();
+        let mut rows = c
+            .query("SELECT credentials FROM channel_credentials", ())
+            .await
+            .unwrap();
+        let stored: String = rows.next().await.unwrap().unwrap().get(0).unwrap();
+        assert!(!stored.contains("abc"));
+
+        assert_eq!(
+            get(&c, &k, "test").await.unwrap().unwrap(),
+            r#"{"token":"abc"}"#
+        );
+        assert!(get(&c, &Keyring::empty(), "test").await.is_err());
     }
 }
@@ -5,6 +5,7 @@
 
 pub mod channel;
 pub mod entity;
+pub mod migration;
 mod types;
 
 pub use types::{ChannelId, ChannelName, ChatId, ChatName, ChatType, ReplyTarget, SenderId, SenderName};
@@ -0,0 +1,30 @@
+//! Schema migration descriptors.
+//!
+//! Every crate that owns tables describes their schema changes as a
+//! [`MigrationSet`]; `nekobot-core` records and applies them. They are
+//! defined here, below `nekobot-core`, so that this crate can describe the
+//! migrations of its own tables.
+
+/// One schema change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    /// Statements run in order inside one transaction.
+    pub statements: &'static [&'static str],
+}
+
+/// The migrations of one component, in version order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationSet {
+    /// Name the versions are recorded under, e.g. `core`.
+    pub component: &'static str,
+    pub migrations: &'static [Migration],
+}
+
+impl MigrationSet {
+    /// The highest version in this set, or 0 if it is empty.
+    pub fn latest(&self) -> u32 {
+        self.migrations.last().map_or(0, |m| m.version)
+    }
+}
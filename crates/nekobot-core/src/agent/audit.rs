@@ -0,0 +1,168 @@
+//! Turn recorder — collects what happened during a turn for the `turns` and
+//! `turn_steps` audit tables.
+
+use std::{sync::Mutex, time::Duration};
+
+use serde_json::{Value, json};
+
+use crate::{
+    agent::{
+        tool::ToolResult,
+        types::{ChatMessageContent, ChatRequest, ChatResponse, ToolCall},
+    },
+    entity::turn_step::{NewTurnStep, StepKind},
+    provider::ProviderError,
+};
+
+/// Provider requests, usage and tool calls of the turn in progress.
+#[derive(Debug, Default)]
+pub(crate) struct TurnRecorder {
+    record: Mutex<TurnRecord>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TurnRecord {
+    /// Last request sent to the provider.
+    pub request: Value,
+    pub tool_calls: Vec<Value>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+    /// Every provider and tool call, in the order they finished.
+    pub steps: Vec<NewTurnStep>,
+}
+
+impl TurnRecorder {
+    /// Start a new turn and return what was recorded for the previous one.
+    pub fn take(&self) -> TurnRecord {
+        std::mem::take(&mut *self.lock())
+    }
+
+    pub fn provider_call(
+        &self,
+        provider: &str,
+        request: &ChatRequest,
+        result: Result<&ChatResponse, &ProviderError>,
+        elapsed: Duration,
+    ) {
+        let request = request_json(request);
+        let usage = result.ok().and_then(|response| response.usage.as_ref());
+        let mut record = self.lock();
+        if let Some(usage) = usage {
+            record.input_tokens = add(record.input_tokens, usage.input_tokens);
+            record.output_tokens = add(record.output_tokens, usage.output_tokens);
+        }
+        record.steps.push(NewTurnStep {
+            kind: StepKind::Provider,
+            name: provider.to_owned(),
+            input: request.to_string(),
+            output: result
+                .ok()
+                .map(|response| response_json(response).to_string()),
+            error: result.err().map(ToString::to_string),
+            input_tokens: usage.and_then(|u| u.input_tokens).map(|n| n as i64),
+            output_tokens: usage.and_then(|u| u.output_tokens).map(|n| n as i64),
+            duration_ms: elapsed.as_millis() as i64,
+        });
+        record.request = request;
+    }
+
+    pub fn tool_call(
+        &self,
+        call: &ToolCall,
+        args: &Value,
+        result: &ToolResult<Value>,
+        elapsed: Duration,
+    ) {
+        let (result, error) = match result {
+            Ok(value) => (value.clone(), None),
+            Err(e) => (Value::Null, Some(e.to_string())),
+        };
+        let mut record = self.lock();
+        record.steps.push(NewTurnStep {
+            kind: StepKind::Tool,
+            name: call.function.name.clone(),
+            input: args.to_string(),
+            output: error.is_none().then(|| result.to_string()),
+            error: error.clone(),
+            input_tokens: None,
+            output_tokens: None,
+            duration_ms: elapsed.as_millis() as i64,
+        });
+        record.tool_calls.push(json!({
+            "id": call.id,
+            "name": call.function.name,
+            "arguments": args,
+            "result": result,
+            "error": error,
+            "duration_ms": elapsed.as_millis() as u64,
+        }));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TurnRecord> {
+        self.record.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn add(total: Option<u64>, value: Option<u64>) -> Option<u64> {
+    match (total, value) {
+        (Some(total), Some(value)) => Some(total + value),
+        (total, value) => total.or(value),
+    }
+}
+
+/// JSON form of a provider response for the audit trail; images are reduced
+/// to their MIME type and size.
+fn response_json(response: &ChatResponse) -> Value {
+    json!({
+        "content": response.content,
+        "reasoning": response.reasoning_content,
+        "tool_calls": response.tool_calls,
+        "images": response
+            .images
+            .iter()
+            .map(|image| json!({ "mime_type": image.mime_type, "bytes": image.data.len() }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// JSON form of a request for the audit trail; images are reduced to their
+/// MIME type and size.
+fn request_json(request: &ChatRequest) -> Value {
+    let messages: Vec<Value> = request
+        .messages
+        .iter()
+        .map(|message| {
+            let mut value = json!({
+                "role": message.role.to_string(),
+                "content": message.content.text(),
+            });
+            match &message.content {
+                ChatMessageContent::User { images, .. } if !images.is_empty() => {
+                    value["images"] = images
+                        .iter()
+                        .map(|image| json!({ "mime_type": image.mime_type, "bytes": image.data.len() }))
+                        .collect();
+                }
+                ChatMessageContent::Assistant {
+                    reasoning,
+                    tool_calls,
+                    ..
+                } => {
+                    value["reasoning"] = json!(reasoning);
+                    value["tool_calls"] = json!(tool_calls);
+                }
+                ChatMessageContent::Tool { tool_call_id, .. } => {
+                    value["tool_call_id"] = json!(tool_call_id);
+                }
+                ChatMessageContent::User { .. } => {}
+            }
+            value
+        })
+        .collect();
+
+    json!({
+        "system_prompt": request.system_prompt,
+        "tools": request.tools.iter().map(|tool| tool.name.as_str()).collect::<Vec<_>>(),
+        "messages": messages,
+    })
+}
@@ -0,0 +1,316 @@
+//! Slash commands — `/name args…` messages answered by the channel runtime
+//! without calling the provider.
+//!
+//! The channel runtime owns a [`CommandRegistry`] with the built-in commands;
+//! every agent session owns another one that middleware can register into
+//! from [`Middleware::init`](crate::agent::middleware::Middleware::init) via
+//! [`Context::command_registry`](crate::agent::Context::command_registry).
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use nekobot_channel::{ChannelInfo, ChatInfo, ChatType, SenderInfo};
+use turso::Connection;
+
+use crate::runtime::access::Grants;
+
+/// Access level required to run a command, ordered from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    /// Anyone who can send a message to the bot.
+    Anyone,
+    /// Senders that passed the [`SessionGate`](crate::runtime::session_gate::SessionGate)
+    /// login. Everyone qualifies where no gate applies.
+    LoggedIn,
+    /// Logged-in senders whose roles grant the
+    /// [`ADMIN`](crate::runtime::access::ADMIN) capability.
+    Admin,
+}
+
+/// A single positional argument of a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgSpec {
+    pub name: String,
+    pub required: bool,
+}
+
+/// Name, arguments, permission and help text of a command.
+///
+/// The last argument captures the rest of the line, so it may contain spaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSpec {
+    /// Name without the leading `/`.
+    pub name: String,
+    pub args: Vec<ArgSpec>,
+    pub permission: Permission,
+    /// Stricter permission required in group chats, whose history and
+    /// settings all members share.
+    pub group_permission: Option<Permission>,
+    pub help: String,
+}
+
+impl CommandSpec {
+    /// A command with no arguments that [`Permission::LoggedIn`] senders may run.
+    pub fn new(name: impl Into<String>, help: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            args: Vec::new(),
+            permission: Permission::LoggedIn,
+            group_permission: None,
+            help: help.into(),
+        }
+    }
+
+    /// Append a required argument.
+    pub fn arg(mut self, name: impl Into<String>) -> Self {
+        self.args.push(ArgSpec {
+            name: name.into(),
+            required: true,
+        });
+        self
+    }
+
+    /// Append an optional argument.
+    pub fn optional_arg(mut self, name: impl Into<String>) -> Self {
+        self.args.push(ArgSpec {
+            name: name.into(),
+            required: false,
+        });
+        self
+    }
+
+    /// Set the permission required to run the command.
+    pub fn permission(mut self, permission: Permission) -> Self {
+        self.permission = permission;
+        self
+    }
+
+    /// Require `permission` instead when the command is sent in a group chat.
+    pub fn group_permission(mut self, permission: Permission) -> Self {
+        self.group_permission = Some(permission);
+        self
+    }
+
+    /// Permission required to run the command in a chat of `chat_type`.
+    pub fn required_permission(&self, chat_type: ChatType) -> Permission {
+        match self.group_permission {
+            Some(group) if !chat_type.is_private() => group.max(self.permission),
+            _ => self.permission,
+        }
+    }
+
+    /// Usage line, e.g. `/resume <id> [name]`.
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for arg in &self.args {
+            if arg.required {
+                usage.push_str(&format!(" <{}>", arg.name));
+            } else {
+                usage.push_str(&format!(" [{}]", arg.name));
+            }
+        }
+        usage
+    }
+
+    /// Split `input` into the declared arguments.
+    ///
+    /// Returns the usage line as the error when a required argument is missing
+    /// or extra arguments are given to a command that takes none.
+    pub fn parse_args(&self, input: &str) -> Result<CommandArgs, String> {
+        let mut values = HashMap::new();
+        let mut rest = input.trim();
+        for (index, arg) in self.args.iter().enumerate() {
+            if rest.is_empty() {
+                if arg.required {
+                    return Err(self.usage());
+                }
+                break;
+            }
+            let value = if index + 1 == self.args.len() {
+                std::mem::take(&mut rest)
+            } else {
+                let (value, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                rest = tail.trim_start();
+                value
+            };
+            values.insert(arg.name.clone(), value.to_owned());
+        }
+        if !rest.is_empty() {
+            return Err(self.usage());
+        }
+        Ok(CommandArgs { values })
+    }
+}
+
+/// Parsed arguments of a command invocation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandArgs {
+    values: HashMap<String, String>,
+}
+
+impl CommandArgs {
+    /// Value of the named argument, if given.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+}
+
+/// Who invoked a command, and where.
+pub struct CommandContext {
+    pub channel: ChannelInfo,
+    pub chat: ChatInfo,
+    pub sender: SenderInfo,
+    /// Agent that handles this chat, if one is resolved.
+    pub agent_name: Option<String>,
+    /// Session bound to this chat and agent, if one exists.
+    pub session_id: Option<i64>,
+    /// Permission level of the sender.
+    pub permission: Permission,
+    /// Agents and capabilities of the sender's roles.
+    pub grants: Grants,
+    /// Commands the sender is allowed to run, sorted by name.
+    pub commands: Vec<CommandSpec>,
+    pub app_db: Connection,
+}
+
+/// A slash command that can be registered in a [`CommandRegistry`].
+#[async_trait]
+pub trait Command: Send + Sync {
+    fn spec(&self) -> &CommandSpec;
+
+    /// Run the command and return the reply sent back to the chat.
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String>;
+}
+
+/// Registry of named [`Command`] implementations.
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: RwLock<BTreeMap<String, Arc<dyn Command>>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a command. Returns an error if a command with the same name already exists.
+    pub fn register(&self, command: Arc<dyn Command>) -> anyhow::Result<()> {
+        let name = command.spec().name.clone();
+        if name.trim().is_empty() || name.contains(char::is_whitespace) {
+            anyhow::bail!("invalid command name: {name:?}");
+        }
+
+        let mut commands = self
+            .commands
+            .write()
+            .map_err(|_| anyhow::anyhow!("command registry lock poisoned"))?;
+
+        if commands.contains_key(&name) {
+            anyhow::bail!("command already registered: {name}");
+        }
+
+        commands.insert(name, command);
+        Ok(())
+    }
+
+    /// Look up a command by name. Returns `Ok(None)` if not found.
+    pub fn get(&self, name: &str) -> anyhow::Result<Option<Arc<dyn Command>>> {
+        let commands = self
+            .commands
+            .read()
+            .map_err(|_| anyhow::anyhow!("command registry lock poisoned"))?;
+        Ok(commands.get(name).cloned())
+    }
+
+    /// Return the specs of all registered commands, sorted by name.
+    pub fn specs(&self) -> anyhow::Result<Vec<CommandSpec>> {
+        let commands = self
+            .commands
+            .read()
+            .map_err(|_| anyhow::anyhow!("command registry lock poisoned"))?;
+        Ok(commands.values().map(|c| c.spec().clone()).collect())
+    }
+}
+
+/// Split a message into a command name and its raw argument string.
+///
+/// Returns `None` unless the message starts with `/` followed by a name.
+pub fn parse_command_line(content: &str) -> Option<(&str, &str)> {
+    let line = content.trim().strip_prefix('/')?;
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, rest.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo(CommandSpec);
+
+    #[async_trait]
+    impl Command for Echo {
+        fn spec(&self) -> &CommandSpec {
+            &self.0
+        }
+
+        async fn execute(&self, _ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+            Ok(args.get("text").unwrap_or_default().to_owned())
+        }
+    }
+
+    #[test]
+    fn parses_command_lines() {
+        assert_eq!(parse_command_line("/help"), Some(("help", "")));
+        assert_eq!(parse_command_line("  /resume  12 work "), Some(("resume", "12 work")));
+        assert_eq!(parse_command_line("hello /help"), None);
+        assert_eq!(parse_command_line("/ help"), None);
+    }
+
+    #[test]
+    fn group_permission_applies_only_in_groups() {
+        let spec = CommandSpec::new("reset", "").group_permission(Permission::Admin);
+        assert_eq!(spec.required_permission(ChatType::Private), Permission::LoggedIn);
+        assert_eq!(spec.required_permission(ChatType::Group), Permission::Admin);
+
+        let spec = CommandSpec::new("login", "").permission(Permission::Anyone);
+        assert_eq!(spec.required_permission(ChatType::Group), Permission::Anyone);
+    }
+
+    #[test]
+    fn last_argument_takes_rest_of_line() {
+        let spec = CommandSpec::new("login", "").arg("user").optional_arg("password");
+
+        let args = spec.parse_args("neko  secret with spaces").unwrap();
+        assert_eq!(args.get("user"), Some("neko"));
+        assert_eq!(args.get("password"), Some("secret with spaces"));
+
+        let args = spec.parse_args("neko").unwrap();
+        assert_eq!(args.get("password"), None);
+
+        assert_eq!(spec.parse_args("").unwrap_err(), "/login <user> [password]");
+        assert_eq!(
+            CommandSpec::new("help", "").parse_args("extra").unwrap_err(),
+            "/help"
+        );
+    }
+
+    #[test]
+    fn registry_rejects_duplicates_and_lists_sorted_specs() -> anyhow::Result<()> {
+        let registry = CommandRegistry::new();
+        registry.register(Arc::new(Echo(CommandSpec::new("zeta", ""))))?;
+        registry.register(Arc::new(Echo(CommandSpec::new("alpha", ""))))?;
+
+        assert!(registry.register(Arc::new(Echo(CommandSpec::new("alpha", "")))).is_err());
+        assert!(registry.register(Arc::new(Echo(CommandSpec::new("two words", "")))).is_err());
+        let names: Vec<_> = registry.specs()?.into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["alpha", "zeta"]);
+        assert!(registry.get("missing")?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,458 @@
+//! Sub-agent delegation — the `delegate` tool lets an agent hand a focused
+//! task to another configured agent.
+//!
+//! Each call runs the target agent in an ephemeral [`AgentSession`] with its
+//! own provider, middlewares and tool registry. The sub-session gets a session
+//! row for the duration of the call but no chat history, and its messages are
+//! not persisted; only the final reply (and optionally the transcript) is
+//! returned to the caller.
+//! Tools denied to the caller's sender stay denied in the sub-session.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use turso::Connection;
+
+use crate::{
+    agent::{
+        AgentSession, AgentSessionConfig, Context, run_shutdown_hooks,
+        middleware::{Middleware, MiddlewareFlow},
+        tool::{Tool, ToolError, ToolRegistry, ToolResult, ToolSpec},
+        types::{ChatMessage, ChatMessageContent, ChatRequest, Role},
+    },
+    entity::session::Session,
+};
+
+const DELEGATE_TOOL: &str = "delegate";
+
+/// Session configs of every configured agent, keyed by agent name.
+pub struct AgentDirectory {
+    agents: HashMap<String, AgentSessionConfig>,
+}
+
+impl AgentDirectory {
+    pub fn new(configs: impl IntoIterator<Item = AgentSessionConfig>) -> Self {
+        Self {
+            agents: configs
+                .into_iter()
+                .map(|mut config| {
+                    // Sub-sessions get their delegation scope from the caller.
+                    config.delegation = None;
+                    (config.agent_name.clone(), config)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, agent_name: &str) -> Option<&AgentSessionConfig> {
+        self.agents.get(agent_name)
+    }
+}
+
+/// Delegation scope of a session: where targets are looked up and how deep
+/// the current session is nested below the chat's session.
+#[derive(Clone)]
+pub struct Delegation {
+    directory: Arc<AgentDirectory>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Delegation {
+    /// Scope of a top-level session that may nest up to `max_depth` sub-agents.
+    pub fn new(directory: Arc<AgentDirectory>, max_depth: usize) -> Self {
+        Self {
+            directory,
+            depth: 0,
+            max_depth,
+        }
+    }
+
+    /// Returns `true` if a session in this scope may start another sub-agent.
+    pub fn can_delegate(&self) -> bool {
+        self.depth < self.max_depth
+    }
+
+    fn nested(&self) -> Self {
+        Self {
+            directory: Arc::clone(&self.directory),
+            depth: self.depth + 1,
+            max_depth: self.max_depth,
+        }
+    }
+}
+
+/// Middleware that registers the `delegate` tool. Added by
+/// [`AgentSessionConfig::resolve_middlewares`] for agents with `delegates`.
+pub struct DelegateMiddleware {
+    targets: Vec<String>,
+    delegation: Delegation,
+}
+
+impl DelegateMiddleware {
+    pub fn new(targets: Vec<String>, delegation: Delegation) -> Self {
+        Self {
+            targets,
+            delegation,
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for DelegateMiddleware {
+    fn name(&self) -> &'static str {
+        "delegate"
+    }
+
+    async fn init(&self, ctx: &Context) -> Result<(), anyhow::Error> {
+        let description = format!(
+            "Hand a self-contained task to another agent and return its final answer. \
+             Available agents: {}.",
+            self.targets.join(", ")
+        );
+        ctx.tool_registry().register(Arc::new(DelegateTool {
+            targets: self.targets.clone(),
+            description,
+            delegation: self.delegation.clone(),
+            app_db: ctx.app_db.clone(),
+            caller_tools: Arc::downgrade(&ctx.tool_registry),
+        }))
+    }
+
+    async fn before_chat(
+        &self,
+        ctx: &Context,
+        request: &mut ChatRequest,
+    ) -> Result<MiddlewareFlow, anyhow::Error> {
+        if let Some(tool) = ctx.tool_registry().get(DELEGATE_TOOL)? {
+            request.tools.push(ToolSpec::from_tool(tool.as_ref()));
+        }
+        Ok(MiddlewareFlow::Continue)
+    }
+}
+
+#[derive(Deserialize)]
+struct DelegateArgs {
+    agent: String,
+    task: String,
+    #[serde(default)]
+    include_transcript: bool,
+}
+
+struct DelegateTool {
+    targets: Vec<String>,
+    description: String,
+    delegation: Delegation,
+    app_db: Connection,
+    /// Registry of the calling session, which holds this tool.
+    caller_tools: Weak<ToolRegistry>,
+}
+
+impl DelegateTool {
+    /// Run `task` in an ephemeral session of `config` and return the final
+    /// reply with the messages exchanged along the way.
+    ///
+    /// The session row, and everything stored for it, is deleted afterwards.
+    async fn run(
+        &self,
+        config: &AgentSessionConfig,
+        task: String,
+    ) -> anyhow::Result<(String, Vec<ChatMessage>)> {
+        let session = Session::create(&self.app_db, config.agent_name.as_str()).await?;
+        let result = self.run_in(session.id, config, task).await;
+        Session::delete(&self.app_db, session.id).await?;
+        result
+    }
+
+    async fn run_in(
+        &self,
+        session_id: i64,
+        config: &AgentSessionConfig,
+        task: String,
+    ) -> anyhow::Result<(String, Vec<ChatMessage>)> {
+        let mut config = config.clone();
+        config.delegation = Some(self.delegation.nested());
+        let middlewares = config.resolve_middlewares()?;
+        let agent = AgentSession::new(session_id, config);
+        if let Some(caller_tools) = self.caller_tools.upgrade() {
+            agent.tool_registry.set_denied(caller_tools.denied()?)?;
+        }
+        // Nothing drains activations of an ephemeral session; middleware that
+        // emits events sees the channel as closed.
+        let (event_sender, _) = tokio::sync::mpsc::channel(1);
+        let ctx = agent.context(event_sender, self.app_db.clone());
+        for mw in &middlewares {
+            mw.init(&ctx).await?;
+        }
+
+        let system_prompt = match agent.render_system_prompt(&ctx) {
+            Ok(system_prompt) => system_prompt,
+            Err(error) => {
+                run_shutdown_hooks(&middlewares, &ctx).await;
+                return Err(error);
+            }
+        };
+        let mut request = ChatRequest {
+            messages: vec![ChatMessage {
+                role: Role::User,
+                content: ChatMessageContent::User {
+                    text: task,
+                    images: Vec::new(),
+                },
+            }],
+            system_prompt,
+            ..ChatRequest::default()
+        };
+        let result = agent.interact_in(&middlewares, ctx.clone(), &mut request).await;
+        run_shutdown_hooks(&middlewares, &ctx).await;
+        Ok((result?.content, request.messages))
+    }
+}
+
+#[async_trait]
+impl Tool for DelegateTool {
+    fn name(&self) -> &str {
+        DELEGATE_TOOL
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "agent": {
+                    "type": "string",
+                    "enum": self.targets,
+                    "description": "Name of the agent to delegate to"
+                },
+                "task": {
+                    "type": "string",
+                    "description": "Complete instructions; the agent does not see this conversation"
+                },
+                "include_transcript": {
+                    "type": "boolean",
+                    "description": "Also return the agent's intermediate messages and tool calls"
+                }
+            },
+            "required": ["agent", "task"]
+        })
+    }
+
+    async fn call(&self, args: Value) -> ToolResult<Value> {
+        let args: DelegateArgs = serde_json::from_value(args)
+            .map_err(|e| ToolError::InvalidArguments(e.to_string()))?;
+        if !self.targets.contains(&args.agent) {
+            return Err(ToolError::InvalidArguments(format!(
+                "cannot delegate to {}; available agents: {}",
+                args.agent,
+                self.targets.join(", ")
+            )));
+        }
+        let config = self
+            .delegation
+            .directory
+            .get(&args.agent)
+            .ok_or_else(|| ToolError::NotFound(args.agent.clone()))?;
+
+        let (content, messages) = self
+            .run(config, args.task)
+            .await
+            .map_err(|e| ToolError::Execution(format!("{e:#}")))?;
+
+        let mut result = json!({ "agent": args.agent, "content": content });
+        if args.include_transcript {
+            result["transcript"] = messages
+                .iter()
+                .map(|message| {
+                    json!({
+                        "role": message.role.to_string(),
+                        "content": message.content.text(),
+                        "tool_calls": message.content.tool_calls(),
+                    })
+                })
+                .collect();
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        agent::{
+            MiddlewareRegistry,
+            types::{ChatResponse, ToolCall, ToolCallFunction},
+        },
+        entity::Entity,
+        provider::{ModelOptions, Provider, ProviderError, ProviderRequest},
+    };
+
+    /// Delegates to `Translator` until it sees a tool result, then echoes it.
+    struct ManagerProvider;
+
+    #[async_trait]
+    impl Provider for ManagerProvider {
+        async fn complete(&self, request: ProviderRequest) -> Result<ChatResponse, ProviderError> {
+            let last = request.chat.messages.last().expect("request has messages");
+            let (content, tool_calls) = match last.role {
+                Role::Tool => (format!("manager: {}", last.content.text()), Vec::new()),
+                _ => (
+                    String::new(),
+                    vec![ToolCall {
+                        id: "call-1".to_owned(),
+                        r#type: "function".to_owned(),
+                        function: ToolCallFunction {
+                            name: DELEGATE_TOOL.to_owned(),
+                            arguments: json!({
+                                "agent": "Translator",
+                                "task": "translate hello",
+                                "include_transcript": true
+                            })
+                            .to_string(),
+                        },
+                    }],
+                ),
+            };
+            Ok(ChatResponse {
+                content,
+                reasoning_content: None,
+                tool_calls,
+                images: Vec::new(),
+                usage: None,
+            })
+        }
+    }
+
+    /// Answers every task and records the tools it was offered.
+    struct TranslatorProvider {
+        offered_tools: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Provider for TranslatorProvider {
+        async fn complete(&self, request: ProviderRequest) -> Result<ChatResponse, ProviderError> {
+            self.offered_tools
+                .lock()
+                .unwrap()
+                .extend(request.chat.tools.into_iter().map(|tool| tool.name));
+            Ok(ChatResponse {
+                content: "bonjour".to_owned(),
+                reasoning_content: None,
+                tool_calls: Vec::new(),
+                images: Vec::new(),
+                usage: None,
+            })
+        }
+    }
+
+    fn agent(name: &str, provider: Arc<dyn Provider>, delegates: &[&str]) -> AgentSessionConfig {
+        AgentSessionConfig {
+            delegates: delegates.iter().map(|d| (*d).to_owned()).collect(),
+            max_delegation_depth: 1,
+            ..AgentSessionConfig::new(
+                name,
+                provider,
+                ModelOptions::default(),
+                Vec::new(),
+                MiddlewareRegistry::new(),
+                None,
+                10,
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn delegate_runs_target_agent_within_depth_limit() -> anyhow::Result<()> {
+        let conn = crate::entity::test_connection().await?;
+        Session::create_table(&conn).await?;
+        let offered_tools = Arc::new(Mutex::new(Vec::new()));
+        let manager = agent("Manager", Arc::new(ManagerProvider), &["Translator"]);
+        // The translator could delegate back, but depth 1 is already the limit.
+        let translator = agent(
+            "Translator",
+            Arc::new(TranslatorProvider {
+                offered_tools: Arc::clone(&offered_tools),
+            }),
+            &["Manager"],
+        );
+        let directory = Arc::new(AgentDirectory::new([manager.clone(), translator]));
+        let manager = manager.with_delegation(directory);
+
+        let middlewares = manager.resolve_middlewares()?;
+        assert_eq!(middlewares.len(), 1);
+        let session = Session::create(&conn, "Manager").await?;
+        let agent = AgentSession::new(session.id, manager);
+        let (event_sender, _event_receiver) = tokio::sync::mpsc::channel(1);
+        let ctx = agent.context(event_sender, conn.clone());
+        for mw in &middlewares {
+            mw.init(&ctx).await?;
+        }
+
+        let response = agent
+            .interact(
+                &middlewares,
+                ctx,
+                ChatRequest {
+                    messages: vec![ChatMessage {
+                        role: Role::User,
+                        content: ChatMessageContent::User {
+                            text: "say hello in French".to_owned(),
+                            images: Vec::new(),
+                        },
+                    }],
+                    ..ChatRequest::default()
+                },
+            )
+            .await?;
+
+        let result: Value = serde_json::from_str(
+            response
+                .content
+                .strip_prefix("manager: ")
+                .expect("manager echoes the tool result"),
+        )?;
+        assert_eq!(result["agent"], "Translator");
+        assert_eq!(result["content"], "bonjour");
+        assert_eq!(result["transcript"][0]["content"], "translate hello");
+        assert!(offered_tools.lock().unwrap().is_empty());
+        // The translator's sub-session is gone again.
+        assert_eq!(Session::list(&conn).await?, vec![session]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delegate_rejects_agents_outside_its_targets() -> anyhow::Result<()> {
+        let conn = crate::entity::test_connection().await?;
+        let directory = Arc::new(AgentDirectory::new([agent(
+            "Manager",
+            Arc::new(ManagerProvider),
+            &[],
+        )]));
+        let tool = DelegateTool {
+            targets: vec!["Translator".to_owned()],
+            description: String::new(),
+            delegation: Delegation::new(directory, 1),
+            app_db: conn,
+            caller_tools: Weak::new(),
+        };
+
+        let result = tool
+            .call(json!({ "agent": "Manager", "task": "anything" }))
+            .await;
+        assert!(matches!(result, Err(ToolError::InvalidArguments(_))));
+        let result = tool.call(json!({ "agent": "Translator" })).await;
+        assert!(matches!(result, Err(ToolError::InvalidArguments(_))));
+        Ok(())
+    }
+}
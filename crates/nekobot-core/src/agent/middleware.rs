@@ -1,8 +1,12 @@
 //! Middleware trait and activation types that hook into the agent processing pipeline.
 
+use nekobot_channel::ChatType;
+use serde_json::Value;
+
 use crate::agent::{
     Context,
-    types::{ChatRequest, ChatResponse},
+    tool::ToolResult,
+    types::{ChatRequest, ChatResponse, ToolCall},
 };
 
 /// Events that middleware can emit during its lifecycle.
@@ -10,13 +14,19 @@ use crate::agent::{
 #[non_exhaustive]
 pub enum MiddlewareEvent {
     /// Request the agent to process a given prompt as if the user sent it.
-    Activate { prompt: String },
+    Activate {
+        prompt: String,
+        /// Restricted tools denied during the turn: those of the sender the
+        /// activation acts for, such as the creator of a schedule.
+        denied_tools: Vec<String>,
+    },
 }
 
 impl MiddlewareEvent {
-    pub fn activate(prompt: impl Into<String>) -> Self {
+    pub fn activate(prompt: impl Into<String>, denied_tools: Vec<String>) -> Self {
         Self::Activate {
             prompt: prompt.into(),
+            denied_tools,
         }
     }
 }
@@ -26,9 +36,13 @@ impl MiddlewareEvent {
 pub enum AgentActivation {
     /// A real message from a channel user.
     ChannelMessage {
+        channel_name: String,
         chat_name: String,
+        chat_type: ChatType,
         sender_name: String,
         content: String,
+        /// Restricted tools the sender may not call.
+        denied_tools: Vec<String>,
     },
     /// A synthetic activation from middleware.
     Middleware(MiddlewareEvent),
@@ -42,11 +56,43 @@ pub enum MiddlewareFlow {
     Respond(ChatResponse),
 }
 
+/// Verdict of [`Middleware::check_response`] on a final response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseFlow {
+    /// Keep the response.
+    Accept,
+    /// Discard the response and ask the provider again, with this instruction
+    /// appended to the request as a user message, which every provider
+    /// accepts in the middle of a conversation.
+    Regenerate(String),
+}
+
+/// Controls what happens to an activation after [`Middleware::on_activation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationFlow {
+    /// Persist the activation and run the turn.
+    Continue,
+    /// Drop the activation: nothing is persisted and no turn runs.
+    Ignore,
+}
+
+/// Controls a single tool execution after [`Middleware::before_tool_call`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolCallFlow {
+    /// Proceed to the next middleware, then run the tool.
+    Continue,
+    /// Do not run the tool; the model sees the call failing with this reason.
+    Deny(String),
+    /// Do not run the tool; the model sees this value as its result.
+    Respond(Value),
+}
+
 /// Middleware hooks into the agent processing pipeline.
 ///
 /// Middleware is called in registration order for `before_chat`, then in
 /// reverse order for `after_chat`. Any middleware can short-circuit by
-/// returning [`MiddlewareFlow::Respond`] from `before_chat`.
+/// returning [`MiddlewareFlow::Respond`] from `before_chat`. Tool calls follow
+/// the same pattern with `before_tool_call` and `after_tool_call`.
 #[async_trait::async_trait]
 pub trait Middleware: Send + Sync {
     /// Return a human-readable name for this middleware instance.
@@ -59,6 +105,17 @@ pub trait Middleware: Send + Sync {
         Ok(())
     }
 
+    /// Called for every activation before it is persisted, in registration order.
+    ///
+    /// Can rewrite the activation or drop it with [`ActivationFlow::Ignore`].
+    async fn on_activation(
+        &self,
+        _ctx: &Context,
+        _activation: &mut AgentActivation,
+    ) -> Result<ActivationFlow, anyhow::Error> {
+        Ok(ActivationFlow::Continue)
+    }
+
     /// Called before the chat request is sent to the provider.
     ///
     /// Can mutate the request (e.g. inject context) or short-circuit with
@@ -71,7 +128,24 @@ pub trait Middleware: Send + Sync {
         Ok(MiddlewareFlow::Continue)
     }
 
-    /// Called after the provider returns a response.
+    /// Called on a final response (one without tool calls) before
+    /// `after_chat`, in registration order.
+    ///
+    /// Can discard the response with [`ResponseFlow::Regenerate`]; the
+    /// provider is then asked again, which counts towards the session's max
+    /// tool iterations. Once those are used up the response is kept.
+    async fn check_response(
+        &self,
+        _ctx: &Context,
+        _response: &ChatResponse,
+    ) -> Result<ResponseFlow, anyhow::Error> {
+        Ok(ResponseFlow::Accept)
+    }
+
+    /// Called on the response a turn returns, in reverse registration order:
+    /// the provider's final answer, the last response when the tool
+    /// iteration limit is reached, or the response of a `before_chat` hook
+    /// (then only on the middlewares that ran before it).
     ///
     /// Can mutate the response before it is sent back to the channel.
     async fn after_chat(
@@ -82,6 +156,32 @@ pub trait Middleware: Send + Sync {
         Ok(())
     }
 
+    /// Called before a tool requested by the model runs.
+    ///
+    /// Can rewrite the arguments, deny the call, or answer it without running
+    /// the tool. Unparseable arguments arrive as `Value::Null`.
+    async fn before_tool_call(
+        &self,
+        _ctx: &Context,
+        _call: &ToolCall,
+        _args: &mut Value,
+    ) -> Result<ToolCallFlow, anyhow::Error> {
+        Ok(ToolCallFlow::Continue)
+    }
+
+    /// Called after a tool call finished, was denied or was answered by
+    /// `before_tool_call`, in reverse order.
+    ///
+    /// Can transform or redact the result before the model sees it.
+    async fn after_tool_call(
+        &self,
+        _ctx: &Context,
+        _call: &ToolCall,
+        _result: &mut ToolResult<Value>,
+    ) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
     /// Called when the provider or a previous middleware hook returned an error.
     ///
     /// Called in reverse order for each middleware that had its `before_chat`
@@ -89,4 +189,11 @@ pub trait Middleware: Send + Sync {
     async fn on_error(&self, _ctx: &Context, _error: &anyhow::Error) -> Result<(), anyhow::Error> {
         Ok(())
     }
+
+    /// Called once when the agent session stops, in reverse registration order.
+    ///
+    /// Release external resources (child processes, background runtimes) here.
+    async fn shutdown(&self, _ctx: &Context) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
 }
@@ -3,25 +3,41 @@
 use std::sync::Arc;
 
 use anyhow::Context as _;
+use nekobot_channel::{ChannelId, ReplyTarget};
 use serde_json::Value;
 use tokio::sync::mpsc::{Receiver, Sender};
-use tracing::debug;
+use tracing::{Instrument, Span, debug, info_span};
 use turso::Connection;
 
 use crate::{
     agent::{
-        middleware::{AgentActivation, Middleware, MiddlewareEvent, MiddlewareFlow},
-        tool::{ToolError, ToolRegistry},
+        audit::TurnRecorder,
+        command::CommandRegistry,
+        delegate::{AgentDirectory, Delegation, DelegateMiddleware},
+        middleware::{
+            ActivationFlow, AgentActivation, Middleware, MiddlewareEvent, MiddlewareFlow,
+            ResponseFlow, ToolCallFlow,
+        },
+        prompt::{ChatDetails, PromptVars, SystemPrompt},
+        tool::{ToolError, ToolRegistry, ToolResult},
         types::{ChatMessage, ChatMessageContent, ChatRequest, ChatResponse, Role, ToolCall},
     },
     config::MiddlewareConfig,
-    entity::message::{Message, Role as MessageRole},
+    entity::{
+        message::{Message, Role as MessageRole},
+        turn::{NewTurn, Turn},
+        turn_step::TurnStep,
+    },
     provider::{ModelOptions, Provider, ProviderRequest},
     registry::FactoryRegistry,
     session::SessionHandle,
 };
 
+mod audit;
+pub mod command;
+pub mod delegate;
 pub mod middleware;
+pub mod prompt;
 pub mod tool;
 pub mod types;
 
@@ -36,6 +52,11 @@ pub struct Context {
     pub event_sender: Sender<MiddlewareEvent>,
     /// Registry of runtime-registered tools available to this agent.
     pub tool_registry: Arc<ToolRegistry>,
+    /// Registry of slash commands provided by this session's middleware.
+    pub command_registry: Arc<CommandRegistry>,
+    /// Sender for output outside the normal reply, e.g. messages to other
+    /// chats. Set for sessions started with [`AgentSession::start`].
+    pub output_sender: Option<Sender<AgentOutput>>,
     /// Database connection for middleware that needs direct DB access.
     pub app_db: turso::Connection,
 }
@@ -54,6 +75,8 @@ impl Context {
             session_id,
             event_sender,
             tool_registry,
+            command_registry: Arc::new(CommandRegistry::new()),
+            output_sender: None,
             app_db,
         }
     }
@@ -72,12 +95,50 @@ impl Context {
     pub fn tool_registry(&self) -> &ToolRegistry {
         self.tool_registry.as_ref()
     }
+
+    /// Returns a reference to the command registry for session-scoped slash commands.
+    pub fn command_registry(&self) -> &CommandRegistry {
+        self.command_registry.as_ref()
+    }
 }
 
 /// Registry that maps middleware names to factory functions for dynamic instantiation.
 #[derive(Clone, Default)]
 pub struct MiddlewareRegistry {
     inner: FactoryRegistry<MiddlewareConfig, Arc<dyn Middleware>>,
+    /// Config types of the factories registered with
+    /// [`register_with_config`](MiddlewareRegistry::register_with_config).
+    config_types: std::collections::HashMap<String, ConfigType>,
+}
+
+/// The type a middleware's [`MiddlewareConfig::data`] deserializes into.
+#[derive(Clone, Copy)]
+pub(crate) struct ConfigType {
+    pub(crate) schema: fn(&mut schemars::SchemaGenerator) -> schemars::Schema,
+    pub(crate) check: fn(&serde_json::Map<String, Value>) -> Result<(), ConfigDataError>,
+}
+
+/// A middleware config that does not match its type.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{}: {message}", if path.is_empty() { "." } else { path.as_str() })]
+pub struct ConfigDataError {
+    /// Dotted path of the offending value inside the config, empty for the
+    /// config itself.
+    pub path: String,
+    pub message: String,
+}
+
+/// Deserialize middleware config data, reporting where it fails.
+pub fn parse_config_data<T: serde::de::DeserializeOwned>(
+    data: &serde_json::Map<String, Value>,
+) -> Result<T, ConfigDataError> {
+    serde_path_to_error::deserialize(Value::Object(data.clone())).map_err(|e| {
+        let path = e.path().to_string();
+        ConfigDataError {
+            path: if path == "." { String::new() } else { path },
+            message: e.into_inner().to_string(),
+        }
+    })
 }
 
 impl MiddlewareRegistry {
@@ -94,6 +155,58 @@ impl MiddlewareRegistry {
         self.inner.register(name, create)
     }
 
+    /// Registers a factory that takes its config as `T`, deserialized from
+    /// [`MiddlewareConfig::data`].
+    ///
+    /// Unlike with [`register`](Self::register), configs for this middleware
+    /// are checked against `T` at startup and described by `T`'s JSON Schema.
+    pub fn register_with_config<T, F>(
+        &mut self,
+        name: impl Into<String>,
+        create: F,
+    ) -> anyhow::Result<()>
+    where
+        T: serde::de::DeserializeOwned + schemars::JsonSchema,
+        F: Fn(T) -> anyhow::Result<Arc<dyn Middleware>> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.inner
+            .register(name.clone(), move |config: &MiddlewareConfig| {
+                create(parse_config_data(&config.data)?)
+            })?;
+        self.config_types.insert(
+            name,
+            ConfigType {
+                schema: schemars::SchemaGenerator::subschema_for::<T>,
+                check: |data| parse_config_data::<T>(data).map(drop),
+            },
+        );
+        Ok(())
+    }
+
+    /// Whether a factory is registered under `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.inner.get(name).is_some()
+    }
+
+    /// Names of all registered middlewares, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        self.inner.keys()
+    }
+
+    /// Check a config against the config type of its middleware. Configs of
+    /// middlewares registered without a type always pass.
+    pub fn check(&self, config: &MiddlewareConfig) -> Result<(), ConfigDataError> {
+        match self.config_types.get(&config.name) {
+            Some(config_type) => (config_type.check)(&config.data),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn config_type(&self, name: &str) -> Option<ConfigType> {
+        self.config_types.get(name).copied()
+    }
+
     /// Looks up the factory for the given config's name and creates a middleware instance, returning `None` if not found.
     pub fn create(&self, config: &MiddlewareConfig) -> anyhow::Result<Option<Arc<dyn Middleware>>> {
         let Some(factory) = self.inner.get(&config.name) else {
@@ -105,7 +218,7 @@ impl MiddlewareRegistry {
             .map(Some)
     }
 
-    /// Create middleware instances for all given configs, skipping unrecognized names.
+    /// Create middleware instances for all given configs; fails on unrecognized names.
     pub fn create_many(&self, configs: &[MiddlewareConfig]) -> anyhow::Result<Vec<Arc<dyn Middleware>>> {
         middlewares_from_config(configs, self)
     }
@@ -120,8 +233,24 @@ pub struct AgentSessionConfig {
     pub middleware_registry: MiddlewareRegistry,
     pub provider: Arc<dyn Provider>,
     pub model_options: ModelOptions,
+    /// Every model the provider declares; chats may switch between them.
+    pub available_models: Vec<ModelOptions>,
+    /// Drop tools and images the model's [`ModelCapabilities`](crate::provider::ModelCapabilities)
+    /// do not declare. Set for models switched to at runtime, so configured
+    /// models keep sending whatever their middleware adds.
+    pub check_capabilities: bool,
     pub max_message_count: Option<usize>,
     pub max_tool_iterations: usize,
+    /// Agents reachable through the `delegate` tool.
+    pub delegates: Vec<String>,
+    pub max_delegation_depth: usize,
+    /// Where delegation targets are looked up; set by
+    /// [`with_delegation`](AgentSessionConfig::with_delegation).
+    pub delegation: Option<Delegation>,
+    /// Rendered into `ChatRequest.system_prompt` at the start of every turn.
+    pub system_prompt: Option<SystemPrompt>,
+    /// Record every turn of this agent's sessions in the `turns` table.
+    pub audit: bool,
 }
 
 impl AgentSessionConfig {
@@ -134,15 +263,26 @@ impl AgentSessionConfig {
         model_options: ModelOptions,
         middleware_registry: &MiddlewareRegistry,
     ) -> anyhow::Result<Self> {
-        Ok(Self::new(
-            agent.name.clone(),
-            provider,
-            model_options,
-            agent.middlewares.clone(),
-            middleware_registry.clone(),
-            agent.max_message_count,
-            agent.max_tool_iterations,
-        ))
+        let system_prompt = agent
+            .system_prompt
+            .as_ref()
+            .map(SystemPrompt::from_config)
+            .transpose()
+            .with_context(|| format!("agent {}", agent.name))?;
+        Ok(Self {
+            delegates: agent.delegates.clone(),
+            max_delegation_depth: agent.max_delegation_depth,
+            system_prompt,
+            ..Self::new(
+                agent.name.clone(),
+                provider,
+                model_options,
+                agent.middlewares.clone(),
+                middleware_registry.clone(),
+                agent.max_message_count,
+                agent.max_tool_iterations,
+            )
+        })
     }
 
     /// Creates a session config directly from its constituent parts.
@@ -161,27 +301,61 @@ impl AgentSessionConfig {
             middleware_registry,
             provider,
             model_options,
+            available_models: Vec::new(),
+            check_capabilities: false,
             max_message_count,
             max_tool_iterations,
+            delegates: Vec::new(),
+            max_delegation_depth: 0,
+            delegation: None,
+            system_prompt: None,
+            audit: false,
         }
     }
 
+    /// Set the models chats may switch to.
+    pub fn with_available_models(mut self, models: Vec<ModelOptions>) -> Self {
+        self.available_models = models;
+        self
+    }
+
+    /// Let sessions of this agent delegate to the agents in `directory`.
+    pub fn with_delegation(mut self, directory: Arc<AgentDirectory>) -> Self {
+        self.delegation = Some(Delegation::new(directory, self.max_delegation_depth));
+        self
+    }
+
     /// Create fresh middleware instances. Called once per session.
+    ///
+    /// Appends a [`DelegateMiddleware`] when the agent has delegates and the
+    /// delegation depth limit is not reached yet.
     pub fn resolve_middlewares(&self) -> anyhow::Result<Vec<Arc<dyn Middleware>>> {
-        middlewares_from_config(&self.middleware_configs, &self.middleware_registry)
+        let mut middlewares =
+            middlewares_from_config(&self.middleware_configs, &self.middleware_registry)?;
+        if let Some(delegation) = &self.delegation
+            && delegation.can_delegate()
+            && !self.delegates.is_empty()
+        {
+            middlewares.push(Arc::new(DelegateMiddleware::new(
+                self.delegates.clone(),
+                delegation.clone(),
+            )));
+        }
+        Ok(middlewares)
     }
 }
 
-/// Resolves a slice of `MiddlewareConfig` entries into middleware instances via the registry, skipping unrecognized names.
+/// Resolves a slice of `MiddlewareConfig` entries into middleware instances via the registry, failing on unrecognized names.
 pub fn middlewares_from_config(
     configs: &[MiddlewareConfig],
     middleware_registry: &MiddlewareRegistry,
 ) -> anyhow::Result<Vec<Arc<dyn Middleware>>> {
     let mut middlewares = Vec::with_capacity(configs.len());
     for config in configs {
-        if let Some(middleware) = middleware_registry.create(config)? {
-            middlewares.push(middleware);
-        }
+        let Some(middleware) = middleware_registry.create(config)? else {
+            anyhow::bail!("unknown middleware '{}'", config.name);
+        };
+        middlewares.push(middleware);
     }
     Ok(middlewares)
 }
@@ -196,9 +370,16 @@ pub struct AgentSession {
     pub(crate) middleware_registry: MiddlewareRegistry,
     pub(crate) provider: Arc<dyn Provider>,
     pub(crate) model_options: ModelOptions,
+    pub(crate) check_capabilities: bool,
     pub(crate) tool_registry: Arc<ToolRegistry>,
+    pub(crate) command_registry: Arc<CommandRegistry>,
     pub(crate) max_message_count: Option<usize>,
     pub(crate) max_tool_iterations: usize,
+    pub(crate) system_prompt: Option<SystemPrompt>,
+    /// Origin of the latest channel message, for the system prompt.
+    pub(crate) chat: Option<ChatDetails>,
+    /// Set when turns are audited.
+    pub(crate) recorder: Option<TurnRecorder>,
 }
 
 impl AgentSession {
@@ -211,9 +392,14 @@ impl AgentSession {
             middleware_registry: config.middleware_registry,
             provider: config.provider,
             model_options: config.model_options,
+            check_capabilities: config.check_capabilities,
             tool_registry: Arc::new(ToolRegistry::new()),
+            command_registry: Arc::new(CommandRegistry::new()),
             max_message_count: config.max_message_count,
             max_tool_iterations: config.max_tool_iterations,
+            system_prompt: config.system_prompt,
+            chat: None,
+            recorder: config.audit.then(TurnRecorder::default),
         }
     }
 
@@ -223,13 +409,16 @@ impl AgentSession {
         event_sender: Sender<MiddlewareEvent>,
         app_db: turso::Connection,
     ) -> Context {
-        Context::new(
-            self.agent_name.clone(),
-            self.session_id,
-            event_sender,
-            Arc::clone(&self.tool_registry),
-            app_db,
-        )
+        Context {
+            command_registry: Arc::clone(&self.command_registry),
+            ..Context::new(
+                self.agent_name.clone(),
+                self.session_id,
+                event_sender,
+                Arc::clone(&self.tool_registry),
+                app_db,
+            )
+        }
     }
 
     /// Calls init on all middlewares, then spawns the background event loop.
@@ -241,7 +430,10 @@ impl AgentSession {
     ) -> anyhow::Result<AgentSessionHandle> {
         let (activation_sender, activation_receiver) = tokio::sync::mpsc::channel(32);
         let (event_sender, event_receiver) = tokio::sync::mpsc::channel(32);
-        let ctx = self.context(event_sender, app_db.clone());
+        let ctx = Context {
+            output_sender: Some(output_sender.clone()),
+            ..self.context(event_sender, app_db.clone())
+        };
 
         // Init each middleware with the session context
         for mw in &middlewares {
@@ -249,6 +441,8 @@ impl AgentSession {
         }
 
         let session_id = self.session_id;
+        let commands = Arc::clone(&self.command_registry);
+        let model_options = self.model_options.clone();
         tokio::spawn(async move {
             self.run_loop(
                 middlewares,
@@ -264,6 +458,8 @@ impl AgentSession {
         Ok(AgentSessionHandle {
             session_id,
             activation_sender,
+            commands,
+            model_options,
         })
     }
 
@@ -274,6 +470,17 @@ impl AgentSession {
         middlewares: &[Arc<dyn Middleware>],
         ctx: Context,
         mut request: ChatRequest,
+    ) -> anyhow::Result<ChatResponse> {
+        self.interact_in(middlewares, ctx, &mut request).await
+    }
+
+    /// Like [`interact`](AgentSession::interact), but leaves the assistant
+    /// and tool messages of the tool-call loop in `request`.
+    pub(crate) async fn interact_in(
+        &self,
+        middlewares: &[Arc<dyn Middleware>],
+        ctx: Context,
+        request: &mut ChatRequest,
     ) -> anyhow::Result<ChatResponse> {
         let max_iterations = self.max_tool_iterations;
 
@@ -287,7 +494,11 @@ impl AgentSession {
                 first_iteration = false;
                 let mut aborted = None;
                 for (index, middleware) in middlewares.iter().enumerate() {
-                    match middleware.before_chat(&ctx, &mut request).await {
+                    let flow = middleware
+                        .before_chat(&ctx, request)
+                        .instrument(hook_span(middleware.as_ref(), "before_chat"))
+                        .await;
+                    match flow {
                         Ok(MiddlewareFlow::Continue) => {}
                         Ok(MiddlewareFlow::Respond(resp)) => {
                             aborted = Some((index, resp));
@@ -305,10 +516,10 @@ impl AgentSession {
                     return Ok(resp);
                 }
 
-                self.call_provider(middlewares, &ctx, &request).await?
+                self.call_provider(middlewares, &ctx, request).await?
             } else {
                 // Subsequent iterations: skip before_chat, go straight to provider
-                self.call_provider(middlewares, &ctx, &request).await?
+                self.call_provider(middlewares, &ctx, request).await?
             };
 
             // No more tool calls → final response
@@ -316,14 +527,29 @@ impl AgentSession {
                 response.tool_calls.len(),
                 response.tool_calls.iter().map(|tc| &tc.function.name).collect::<Vec<_>>());
             if response.tool_calls.is_empty() {
+                if iteration < max_iterations
+                    && let Some(instruction) =
+                        run_check_response_hooks(middlewares, &ctx, &response).await?
+                {
+                    debug!(target: "agent", "response rejected by middleware, regenerating");
+                    request.messages.push(ChatMessage {
+                        role: Role::User,
+                        content: ChatMessageContent::User {
+                            text: instruction,
+                            images: Vec::new(),
+                        },
+                    });
+                    continue;
+                }
                 debug!(target: "agent", "no tool calls, returning final response");
-                run_after_chat_hooks(middlewares, &ctx, &mut response, 0).await;
+                run_after_chat_hooks(middlewares, &ctx, &mut response, middlewares.len()).await;
                 return Ok(response);
             }
 
             // Too many iterations
             if iteration >= max_iterations {
                 debug!(target: "agent", "max iterations reached, returning response");
+                run_after_chat_hooks(middlewares, &ctx, &mut response, middlewares.len()).await;
                 return Ok(response);
             }
 
@@ -340,20 +566,18 @@ impl AgentSession {
             // Execute tools and add results
             debug!(target: "agent", "executing {} tool(s)...", response.tool_calls.len());
             for tc in &response.tool_calls {
-                let tool = ctx.tool_registry().get(&tc.function.name)?;
-                let result = match tool {
-                    Some(tool) => {
-                        let args: Value =
-                            serde_json::from_str(&tc.function.arguments).unwrap_or_else(|e| {
-                                tracing::warn!(target: "agent", "failed to parse tool arguments for {}: {e}", tc.function.name);
-                                Value::Null
-                            });
-                        debug!(target: "agent", "calling tool {} with args {args}", tc.function.name);
-                        let result = tool.call(args).await;
-                        debug!(target: "agent", "tool {} returned {result:?}", tc.function.name);
-                        result
+                let span = info_span!(
+                    "tool.call",
+                    tool = %tc.function.name,
+                    agent = %self.agent_name,
+                    session_id = self.session_id,
+                );
+                let result = match self.call_tool(middlewares, &ctx, tc).instrument(span).await {
+                    Ok(result) => result,
+                    Err(error) => {
+                        run_error_hooks(middlewares, &ctx, &error, middlewares.len()).await;
+                        return Err(error);
                     }
-                    None => Err(ToolError::NotFound(tc.function.name.clone())),
                 };
 
                 let content = match result {
@@ -372,17 +596,124 @@ impl AgentSession {
         }
     }
 
+    /// Run one tool call through the `before_tool_call` hooks, the tool
+    /// itself, and the `after_tool_call` hooks of the middlewares that saw it.
+    async fn call_tool(
+        &self,
+        middlewares: &[Arc<dyn Middleware>],
+        ctx: &Context,
+        tc: &ToolCall,
+    ) -> anyhow::Result<ToolResult<Value>> {
+        let started = std::time::Instant::now();
+        let mut args: Value = serde_json::from_str(&tc.function.arguments).unwrap_or_else(|e| {
+            tracing::warn!(target: "agent", "failed to parse tool arguments for {}: {e}", tc.function.name);
+            Value::Null
+        });
+
+        // Calls the sender may not make never reach the middlewares.
+        let denied = ctx.tool_registry().is_denied(&tc.function.name)?;
+        let hooks = if denied { &[][..] } else { middlewares };
+        let mut answered = None;
+        for (index, middleware) in hooks.iter().enumerate() {
+            let flow = middleware
+                .before_tool_call(ctx, tc, &mut args)
+                .instrument(hook_span(middleware.as_ref(), "before_tool_call"))
+                .await?;
+            match flow {
+                ToolCallFlow::Continue => {}
+                ToolCallFlow::Deny(reason) => {
+                    debug!(target: "agent", "{} denied tool {}: {reason}", middleware.name(), tc.function.name);
+                    answered = Some((index, Err(ToolError::Denied(reason))));
+                    break;
+                }
+                ToolCallFlow::Respond(value) => {
+                    answered = Some((index, Ok(value)));
+                    break;
+                }
+            }
+        }
+
+        let recorded_args = self.recorder.as_ref().map(|_| args.clone());
+        let (applied, mut result) = match answered {
+            Some((index, result)) => (index + 1, result),
+            None if denied => {
+                debug!(target: "agent", "tool {} is denied to the sender", tc.function.name);
+                let reason = format!("{} is not available to this sender", tc.function.name);
+                (0, Err(ToolError::Denied(reason)))
+            }
+            None => {
+                let result = match ctx.tool_registry().get(&tc.function.name)? {
+                    Some(tool) => {
+                        debug!(target: "agent", "calling tool {} with args {args}", tc.function.name);
+                        let result = tool.call(args).await;
+                        debug!(target: "agent", "tool {} returned {result:?}", tc.function.name);
+                        result
+                    }
+                    None => Err(ToolError::NotFound(tc.function.name.clone())),
+                };
+                (middlewares.len(), result)
+            }
+        };
+
+        for middleware in middlewares[..applied].iter().rev() {
+            middleware
+                .after_tool_call(ctx, tc, &mut result)
+                .instrument(hook_span(middleware.as_ref(), "after_tool_call"))
+                .await?;
+        }
+
+        let outcome = match &result {
+            Ok(_) => "ok",
+            Err(ToolError::Denied(_)) => "denied",
+            Err(_) => "error",
+        };
+        crate::telemetry::metrics().record_tool_call(&tc.function.name, outcome);
+        if let (Some(recorder), Some(args)) = (&self.recorder, recorded_args) {
+            recorder.tool_call(tc, &args, &result, started.elapsed());
+        }
+        Ok(result)
+    }
+
     async fn call_provider(
         &self,
         middlewares: &[Arc<dyn Middleware>],
         ctx: &Context,
         request: &ChatRequest,
     ) -> anyhow::Result<ChatResponse> {
+        let mut chat = request.clone();
+        if self.check_capabilities {
+            strip_unsupported(&mut chat, &self.model_options);
+        }
+        let denied = ctx.tool_registry().denied()?;
+        if !denied.is_empty() {
+            chat.tools.retain(|tool| !denied.contains(&tool.name));
+        }
+        let recorded_request = self.recorder.as_ref().map(|_| chat.clone());
         let provider_request = ProviderRequest {
-            chat: request.clone(),
+            chat,
             options: self.model_options.clone(),
         };
-        match self.provider.complete(provider_request).await {
+        let provider = self.provider.id();
+        let model = self.model_options.model.as_deref().unwrap_or("default");
+        let span = info_span!(
+            "provider.complete",
+            provider,
+            model,
+            agent = %self.agent_name,
+            session_id = self.session_id,
+        );
+        let started = std::time::Instant::now();
+        let result = self.provider.complete(provider_request).instrument(span).await;
+        crate::telemetry::metrics().record_provider_call(
+            provider,
+            model,
+            started.elapsed(),
+            result.as_ref().map(|resp| resp.usage.as_ref()),
+        );
+        if let (Some(recorder), Some(request)) = (&self.recorder, recorded_request) {
+            recorder.provider_call(provider, &request, result.as_ref(), started.elapsed());
+        }
+        match result {
             Ok(resp) => Ok(resp),
             Err(error) => {
                 run_error_hooks(middlewares, ctx, &anyhow::anyhow!("{}", error), middlewares.len())
@@ -393,7 +724,7 @@ impl AgentSession {
     }
 
     async fn run_loop(
-        self,
+        mut self,
         middlewares: Vec<Arc<dyn Middleware>>,
         app_db: Connection,
         ctx: Context,
@@ -401,29 +732,32 @@ impl AgentSession {
         mut event_receiver: Receiver<MiddlewareEvent>,
         output_sender: Sender<AgentOutput>,
     ) {
-        let mut activation_open = true;
         let mut event_open = true;
 
-        while activation_open || event_open {
+        // The session lives as long as its activation sender: once the owning
+        // runtime drops the handle, queued activations are drained and the
+        // session stops, even though middleware may still hold event senders.
+        loop {
             tokio::select! {
-                activation = activation_receiver.recv(), if activation_open => {
+                activation = activation_receiver.recv() => {
                     match activation {
                         Some(activation) => {
+                            let span = self.turn_span();
                             if let Err(e) = self
                                 .handle_activation(&middlewares, &app_db, ctx.clone(), activation, &output_sender)
+                                .instrument(span)
                                 .await
                             {
                                 tracing::error!(target: "agent", "interact error: {e:#}");
                             }
                         }
-                        None => {
-                            activation_open = false;
-                        }
+                        None => break,
                     }
                 }
                 event = event_receiver.recv(), if event_open => {
                     match event {
                         Some(event) => {
+                            let span = self.turn_span();
                             if let Err(e) = self
                                 .handle_activation(
                                     &middlewares,
@@ -432,6 +766,7 @@ impl AgentSession {
                                     AgentActivation::Middleware(event),
                                     &output_sender,
                                 )
+                                .instrument(span)
                                 .await
                             {
                                 tracing::error!(target: "agent", "middleware activation error: {e:#}");
@@ -444,39 +779,96 @@ impl AgentSession {
                 }
             }
         }
+
+        debug!(target: "agent", "session {} stopping", self.session_id);
+        run_shutdown_hooks(&middlewares, &ctx).await;
+    }
+
+    fn turn_span(&self) -> Span {
+        info_span!("agent.turn", agent = %self.agent_name, session_id = self.session_id)
     }
 
     async fn handle_activation(
-        &self,
+        &mut self,
         middlewares: &[Arc<dyn Middleware>],
         app_db: &Connection,
         ctx: Context,
-        activation: AgentActivation,
+        mut activation: AgentActivation,
         output_sender: &Sender<AgentOutput>,
     ) -> anyhow::Result<()> {
+        for middleware in middlewares {
+            let flow = middleware
+                .on_activation(&ctx, &mut activation)
+                .instrument(hook_span(middleware.as_ref(), "on_activation"))
+                .await?;
+            if flow == ActivationFlow::Ignore {
+                debug!(target: "agent", "{} ignored activation", middleware.name());
+                return Ok(());
+            }
+        }
+
         let session = SessionHandle {
             session_id: self.session_id,
             app_db: app_db.clone(),
         };
 
-        let should_interact = match activation {
-            AgentActivation::ChannelMessage { content, .. } => {
-                session
+        let trigger_message_id = match activation {
+            AgentActivation::ChannelMessage {
+                channel_name,
+                chat_name,
+                chat_type,
+                sender_name,
+                content,
+                denied_tools,
+            } => {
+                self.tool_registry.set_denied(denied_tools)?;
+                self.chat = Some(ChatDetails {
+                    channel_name,
+                    chat_name,
+                    chat_type,
+                    sender_name: Some(sender_name),
+                });
+                let message = session
                     .add_message(MessageRole::User.to_string(), content, None, None)
                     .await?;
-                true
+                Some(message.id)
             }
             AgentActivation::Middleware(event) => {
+                if let Some(chat) = &mut self.chat {
+                    chat.sender_name = None;
+                }
                 self.handle_middleware_event(&session, event).await?
             }
         };
 
-        if !should_interact {
+        let Some(trigger_message_id) = trigger_message_id else {
             return Ok(());
-        }
+        };
 
-        let request = self.build_chat_request(app_db).await?;
-        let response = self.interact(middlewares, ctx, request).await?;
+        let request = self.build_chat_request(app_db, &ctx).await?;
+        let started_at = chrono::Utc::now().timestamp();
+        let started = std::time::Instant::now();
+        if let Some(recorder) = &self.recorder {
+            recorder.take();
+        }
+        let response = self.interact(middlewares, ctx, request).await;
+        let elapsed = started.elapsed();
+        crate::telemetry::metrics().record_turn(&self.agent_name, response.is_ok(), elapsed);
+        let response = match response {
+            Ok(response) => response,
+            Err(error) => {
+                self.record_turn(
+                    app_db,
+                    trigger_message_id,
+                    None,
+                    started_at,
+                    elapsed,
+                    Some(&error),
+                )
+                .await;
+                return Err(error);
+            }
+        };
         let tool = if response.tool_calls.is_empty() {
             None
         } else {
@@ -485,7 +877,7 @@ impl AgentSession {
                 tool_calls: serde_json::to_string(&response.tool_calls).ok(),
             })
         };
-        session
+        let reply = session
             .add_message(
                 MessageRole::Assistant.to_string(),
                 response.content.clone(),
@@ -493,6 +885,15 @@ impl AgentSession {
                 tool,
             )
             .await?;
+        self.record_turn(
+            app_db,
+            trigger_message_id,
+            Some(reply.id),
+            started_at,
+            elapsed,
+            None,
+        )
+        .await;
 
         output_sender
             .send(AgentOutput::SendMessage {
@@ -504,14 +905,20 @@ impl AgentSession {
         Ok(())
     }
 
+    /// Persist a middleware event; returns the message that should start a
+    /// turn, if any.
     async fn handle_middleware_event(
         &self,
         session: &SessionHandle,
         event: MiddlewareEvent,
-    ) -> anyhow::Result<bool> {
+    ) -> anyhow::Result<Option<i64>> {
         match event {
-            MiddlewareEvent::Activate { prompt } => {
-                session
+            MiddlewareEvent::Activate {
+                prompt,
+                denied_tools,
+            } => {
+                self.tool_registry.set_denied(denied_tools)?;
+                let message = session
                     .add_message(
                         MessageRole::Custom("internal".to_owned()).to_string(),
                         prompt,
@@ -519,20 +926,83 @@ impl AgentSession {
                         None,
                     )
                     .await?;
-                Ok(true)
+                Ok(Some(message.id))
             }
         }
     }
 
-    /// Loads persisted messages from the database and builds a `ChatRequest` for the current session.
-    async fn build_chat_request(&self, app_db: &Connection) -> anyhow::Result<ChatRequest> {
-        let all_messages = Message::list_by_session(app_db, self.session_id).await?;
-        let messages: Vec<_> = match self.max_message_count {
-            Some(limit) if all_messages.len() > limit => {
-                let skip = all_messages.len() - limit;
-                all_messages.into_iter().skip(skip).collect()
+    /// Write the audit record of the finished turn, if turns are audited.
+    ///
+    /// `started_at` is in Unix seconds. Failures are logged, not returned.
+    async fn record_turn(
+        &self,
+        app_db: &Connection,
+        trigger_message_id: i64,
+        reply_message_id: Option<i64>,
+        started_at: i64,
+        elapsed: std::time::Duration,
+        error: Option<&anyhow::Error>,
+    ) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+        let record = recorder.take();
+        let new_turn = NewTurn {
+            session_id: self.session_id,
+            trigger_message_id: Some(trigger_message_id),
+            reply_message_id,
+            provider: self.provider.id().to_owned(),
+            model: self.model_options.model.clone(),
+            request: record.request.to_string(),
+            tool_calls: Value::Array(record.tool_calls).to_string(),
+            input_tokens: record.input_tokens.map(|n| n as i64),
+            output_tokens: record.output_tokens.map(|n| n as i64),
+            error: error.map(|e| format!("{e:#}")),
+            started_at,
+            duration_ms: elapsed.as_millis() as i64,
+        };
+        let turn = match Turn::create(app_db, new_turn).await {
+            Ok(turn) => turn,
+            Err(e) => {
+                tracing::warn!(target: "agent", "failed to record turn: {e:#}");
+                return;
             }
-            _ => all_messages,
+        };
+        for (position, step) in record.steps.into_iter().enumerate() {
+            if let Err(e) = TurnStep::create(app_db, turn.id, position as i64, step).await {
+                tracing::warn!(target: "agent", "failed to record turn step: {e:#}");
+            }
+        }
+    }
+
+    /// Render the configured system prompt for the current turn, if any.
+    pub(crate) fn render_system_prompt(&self, ctx: &Context) -> anyhow::Result<Option<String>> {
+        let Some(prompt) = &self.system_prompt else {
+            return Ok(None);
+        };
+        let mut vars = PromptVars::new(self.agent_name.clone(), chrono::Local::now());
+        if let Some(chat) = &self.chat {
+            vars = vars.with_chat(chat);
+        }
+        vars.tools = ctx
+            .tool_registry()
+            .tool_specs()?
+            .into_iter()
+            .map(|spec| spec.name)
+            .collect();
+        vars.tools.sort();
+        prompt.render(&vars).map(Some)
+    }
+
+    /// Loads persisted messages from the database and builds a `ChatRequest` for the current session.
+    async fn build_chat_request(
+        &self,
+        app_db: &Connection,
+        ctx: &Context,
+    ) -> anyhow::Result<ChatRequest> {
+        let messages = match self.max_message_count {
+            Some(limit) => Message::list_page(app_db, self.session_id, None, limit).await?,
+            None => Message::list_by_session(app_db, self.session_id).await?,
         };
         let messages = messages
             .into_iter()
@@ -566,12 +1036,36 @@ impl AgentSession {
 
         Ok(ChatRequest {
             messages,
-            system_prompt: None,
+            system_prompt: self.render_system_prompt(ctx)?,
             tools: Vec::new(),
         })
     }
 }
 
+/// Runs check_response hooks in order; returns the instruction of the first
+/// middleware asking to regenerate. On error, runs the error hooks.
+async fn run_check_response_hooks(
+    middlewares: &[Arc<dyn Middleware>],
+    ctx: &Context,
+    response: &ChatResponse,
+) -> anyhow::Result<Option<String>> {
+    for middleware in middlewares {
+        let flow = middleware
+            .check_response(ctx, response)
+            .instrument(hook_span(middleware.as_ref(), "check_response"))
+            .await;
+        match flow {
+            Ok(ResponseFlow::Accept) => {}
+            Ok(ResponseFlow::Regenerate(instruction)) => return Ok(Some(instruction)),
+            Err(error) => {
+                run_error_hooks(middlewares, ctx, &error, middlewares.len()).await;
+                return Err(error);
+            }
+        }
+    }
+    Ok(None)
+}
+
 /// Runs after_chat hooks on middlewares that ran before the response.
 /// Errors are logged rather than propagated.
 async fn run_after_chat_hooks(
@@ -581,12 +1075,20 @@ async fn run_after_chat_hooks(
     start: usize,
 ) {
     for middleware in middlewares[..start].iter().rev() {
-        if let Err(error) = middleware.after_chat(ctx, response).await {
+        if let Err(error) = middleware
+            .after_chat(ctx, response)
+            .instrument(hook_span(middleware.as_ref(), "after_chat"))
+            .await
+        {
             tracing::error!(target: "agent", "after_chat error in {}: {error:#}", middleware.name());
         }
     }
 }
 
+fn hook_span(middleware: &dyn Middleware, hook: &'static str) -> Span {
+    info_span!("middleware", middleware = middleware.name(), hook)
+}
+
 async fn run_error_hooks(
     middlewares: &[Arc<dyn Middleware>],
     ctx: &Context,
@@ -600,6 +1102,29 @@ async fn run_error_hooks(
     }
 }
 
+/// Remove tools and images the model does not declare support for.
+fn strip_unsupported(request: &mut ChatRequest, options: &ModelOptions) {
+    if !options.capabilities.tools && !request.tools.is_empty() {
+        debug!(target: "agent", "model does not support tools, dropping {}", request.tools.len());
+        request.tools.clear();
+    }
+    if !options.capabilities.vision {
+        for message in &mut request.messages {
+            if let ChatMessageContent::User { images, .. } = &mut message.content {
+                images.clear();
+            }
+        }
+    }
+}
+
+async fn run_shutdown_hooks(middlewares: &[Arc<dyn Middleware>], ctx: &Context) {
+    for middleware in middlewares.iter().rev() {
+        if let Err(e) = middleware.shutdown(ctx).await {
+            tracing::error!(target: "agent", "shutdown hook failed in {}: {e:#}", middleware.name());
+        }
+    }
+}
+
 /// A handle to a running agent session that can send activations (channel messages or middleware events).
 #[derive(Clone)]
 pub struct AgentSessionHandle {
@@ -607,6 +1132,10 @@ pub struct AgentSessionHandle {
     pub session_id: i64,
     /// Sender for triggering activations in the background event loop.
     pub(crate) activation_sender: Sender<AgentActivation>,
+    /// Slash commands registered by the session's middleware.
+    pub(crate) commands: Arc<CommandRegistry>,
+    /// Model options the session was started with.
+    pub(crate) model_options: ModelOptions,
 }
 
 /// Output produced by an agent session, sent through the output channel to the application layer.
@@ -614,6 +1143,13 @@ pub struct AgentSessionHandle {
 pub enum AgentOutput {
     /// Instructs the application to send a message to the chat with the given content.
     SendMessage { session_id: i64, content: String },
+    /// Instructs the application to send a message to another known chat,
+    /// possibly on another channel.
+    SendToChat {
+        channel_id: ChannelId,
+        reply_target: ReplyTarget,
+        content: String,
+    },
 }
 
 fn chat_role(role: &str) -> Role {
@@ -632,16 +1168,20 @@ mod tests {
         atomic::{AtomicBool, Ordering},
     };
 
+    use nekobot_channel::ChatType;
     use serde_json::{Value, json};
     use turso::Builder;
 
     use crate::{
         agent::{
-            middleware::{AgentActivation, Middleware, MiddlewareEvent, MiddlewareFlow},
+            middleware::{
+                ActivationFlow, AgentActivation, Middleware, MiddlewareEvent, MiddlewareFlow,
+                ResponseFlow, ToolCallFlow,
+            },
             tool::{Tool, ToolRegistry, ToolResult, ToolSpec},
             types::ChatResponse,
         },
-        entity::{Entity, message::Message, session::Session},
+        entity::{Entity, message::Message, session::Session, turn_step::StepKind},
         provider::{ModelCapabilities, ProviderError},
     };
 
@@ -704,7 +1244,7 @@ mod tests {
     impl Middleware for ActivateOnInitMiddleware {
         async fn init(&self, ctx: &Context) -> Result<(), anyhow::Error> {
             ctx.event_sender
-                .send(MiddlewareEvent::activate("wake up"))
+                .send(MiddlewareEvent::activate("wake up", Vec::new()))
                 .await?;
             Ok(())
         }
@@ -786,6 +1326,10 @@ mod tests {
             middlewares: Vec::new(),
             max_message_count: None,
             max_tool_iterations: 10,
+            delegates: Vec::new(),
+            max_delegation_depth: 2,
+            system_prompt: None,
+            retention: Default::default(),
         };
 
         let session_config = AgentSessionConfig::from_agent_config(
@@ -804,16 +1348,16 @@ mod tests {
     }
 
     #[test]
-    fn middlewares_from_config_skips_unknown_names() -> anyhow::Result<()> {
+    fn middlewares_from_config_rejects_unknown_names() -> anyhow::Result<()> {
         let registry = MiddlewareRegistry::new();
         let config: crate::config::MiddlewareConfig = serde_json::from_value(json!({
             "name": "memory",
             "path": "./memory.db"
         }))?;
 
-        let middlewares = middlewares_from_config(&[config], &registry)?;
+        let error = middlewares_from_config(&[config], &registry).err().unwrap();
 
-        assert!(middlewares.is_empty());
+        assert_eq!(error.to_string(), "unknown middleware 'memory'");
         Ok(())
     }
 
@@ -851,6 +1395,10 @@ mod tests {
             middlewares: vec![serde_json::from_value(json!({ "name": "broken" })).unwrap()],
             max_message_count: None,
             max_tool_iterations: 10,
+            delegates: Vec::new(),
+            max_delegation_depth: 2,
+            system_prompt: None,
+            retention: Default::default(),
         };
 
         let config = AgentSessionConfig::from_agent_config(
@@ -915,6 +1463,72 @@ mod tests {
         Ok(())
     }
 
+    /// Answers "draft" first and "final" afterwards, keeping every request.
+    struct DraftingProvider {
+        requests: Arc<Mutex<Vec<ChatRequest>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for DraftingProvider {
+        async fn complete(&self, request: ProviderRequest) -> Result<ChatResponse, ProviderError> {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(request.chat);
+            Ok(chat_response(if requests.len() == 1 { "draft" } else { "final" }))
+        }
+    }
+
+    struct RejectDraftMiddleware;
+
+    #[async_trait::async_trait]
+    impl Middleware for RejectDraftMiddleware {
+        async fn check_response(
+            &self,
+            _ctx: &Context,
+            response: &ChatResponse,
+        ) -> Result<ResponseFlow, anyhow::Error> {
+            Ok(if response.content == "draft" {
+                ResponseFlow::Regenerate("try again".to_owned())
+            } else {
+                ResponseFlow::Accept
+            })
+        }
+
+        async fn after_chat(
+            &self,
+            _ctx: &Context,
+            response: &mut ChatResponse,
+        ) -> Result<(), anyhow::Error> {
+            response.content.push_str("-after");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_response_is_regenerated_before_after_chat() -> anyhow::Result<()> {
+        let (event_sender, _event_receiver) = tokio::sync::mpsc::channel(16);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let agent = build_agent(Arc::new(DraftingProvider {
+            requests: Arc::clone(&requests),
+        }));
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(RejectDraftMiddleware)];
+
+        let response = agent
+            .interact(
+                &middlewares,
+                Context::new("Neko", 1, event_sender, Arc::new(ToolRegistry::new()), test_db()),
+                ChatRequest::default(),
+            )
+            .await?;
+
+        assert_eq!(response.content, "final-after");
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let instruction = requests[1].messages.last().unwrap();
+        assert_eq!(instruction.role, Role::User);
+        assert_eq!(instruction.content.text(), "try again");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn middleware_can_activate_agent_from_init() -> anyhow::Result<()> {
         let provider_called = Arc::new(AtomicBool::new(false));
@@ -936,6 +1550,7 @@ mod tests {
             event_receiver.recv().await,
             Some(MiddlewareEvent::Activate {
                 prompt: "wake up".to_owned(),
+                denied_tools: Vec::new(),
             })
         );
         assert!(!provider_called.load(Ordering::SeqCst));
@@ -956,9 +1571,12 @@ mod tests {
         handle
             .activation_sender
             .send(AgentActivation::ChannelMessage {
+                channel_name: "qq".to_owned(),
                 chat_name: "Alice".to_owned(),
+                chat_type: ChatType::Private,
                 sender_name: "Alice".to_owned(),
                 content: "hello".to_owned(),
+                denied_tools: Vec::new(),
             })
             .await?;
 
@@ -981,6 +1599,99 @@ mod tests {
         Ok(())
     }
 
+    struct CapturePromptMiddleware {
+        seen: Arc<Mutex<Option<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Middleware for CapturePromptMiddleware {
+        async fn before_chat(
+            &self,
+            _ctx: &Context,
+            request: &mut ChatRequest,
+        ) -> Result<MiddlewareFlow, anyhow::Error> {
+            *self.seen.lock().unwrap() = request.system_prompt.clone();
+            Ok(MiddlewareFlow::Continue)
+        }
+    }
+
+    #[tokio::test]
+    async fn system_prompt_is_rendered_before_middleware() -> anyhow::Result<()> {
+        let (conn, _session) = connection().await?;
+        let seen = Arc::new(Mutex::new(None));
+        let mut agent = build_agent(Arc::new(StaticProvider {
+            called: Arc::new(AtomicBool::new(false)),
+        }));
+        agent.system_prompt = Some(SystemPrompt::new(
+            "{{ agent }} in {{ channel }}/{{ chat }} ({{ chat_type }}) with {{ sender }}",
+        )?);
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(CapturePromptMiddleware {
+            seen: Arc::clone(&seen),
+        })];
+        let (output_sender, mut output_receiver) = tokio::sync::mpsc::channel(16);
+        let handle = agent.start(middlewares, conn, output_sender).await?;
+
+        handle
+            .activation_sender
+            .send(AgentActivation::ChannelMessage {
+                channel_name: "qq".to_owned(),
+                chat_name: "Ops".to_owned(),
+                chat_type: ChatType::Group,
+                sender_name: "Bob".to_owned(),
+                content: "hello".to_owned(),
+                denied_tools: Vec::new(),
+            })
+            .await?;
+        output_receiver.recv().await;
+
+        assert_eq!(
+            seen.lock().unwrap().as_deref(),
+            Some("Neko in qq/Ops (group) with Bob")
+        );
+        Ok(())
+    }
+
+    struct NamedTool(&'static str);
+
+    #[async_trait::async_trait]
+    impl Tool for NamedTool {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn description(&self) -> &str {
+            "named test tool"
+        }
+
+        fn parameters_schema(&self) -> Value {
+            json!({ "type": "object" })
+        }
+
+        async fn call(&self, _args: Value) -> ToolResult<Value> {
+            Ok(json!({ "ok": true }))
+        }
+    }
+
+    #[test]
+    fn system_prompt_lists_tools_in_name_order() -> anyhow::Result<()> {
+        let (event_sender, _event_receiver) = tokio::sync::mpsc::channel(16);
+        let tool_registry = Arc::new(ToolRegistry::new());
+        for name in ["web_search", "bash", "current_time", "delegate"] {
+            tool_registry.register(Arc::new(NamedTool(name)))?;
+        }
+        let mut agent = build_agent(Arc::new(StaticProvider {
+            called: Arc::new(AtomicBool::new(false)),
+        }));
+        agent.system_prompt = Some(SystemPrompt::new("{{ tools | join(\", \") }}")?);
+        let ctx = Context::new("Neko", 1, event_sender, tool_registry, test_db());
+
+        assert_eq!(
+            agent.render_system_prompt(&ctx)?.as_deref(),
+            Some("bash, current_time, delegate, web_search")
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn middleware_activation_records_internal_message_and_response() -> anyhow::Result<()> {
         let (conn, session) = connection().await?;
@@ -1010,6 +1721,123 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn audited_turn_records_request_and_reply() -> anyhow::Result<()> {
+        let (conn, session) = connection().await?;
+        Turn::create_table(&conn).await?;
+        let mut agent = build_agent(Arc::new(StaticProvider {
+            called: Arc::new(AtomicBool::new(false)),
+        }));
+        agent.recorder = Some(TurnRecorder::default());
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(ActivateOnInitMiddleware)];
+        let (output_sender, mut output_receiver) = tokio::sync::mpsc::channel(16);
+        let _handle = agent.start(middlewares, conn.clone(), output_sender).await?;
+        output_receiver.recv().await;
+
+        let messages = Message::list_by_session(&conn, session.id).await?;
+        let turns = Turn::list_by_session(&conn, session.id).await?;
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].trigger_message_id, Some(messages[0].id));
+        assert_eq!(turns[0].reply_message_id, Some(messages[1].id));
+        assert_eq!(turns[0].error, None);
+        let request: serde_json::Value = serde_json::from_str(&turns[0].request)?;
+        assert_eq!(request["messages"][0]["content"], "wake up");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn audited_turn_records_each_provider_and_tool_call() -> anyhow::Result<()> {
+        let (conn, session) = connection().await?;
+        Turn::create_table(&conn).await?;
+        TurnStep::create_table(&conn).await?;
+        let mut agent = build_agent(Arc::new(ToolCallingProvider {
+            values: vec!["first"],
+            results: Arc::new(Mutex::new(Vec::new())),
+        }));
+        agent.recorder = Some(TurnRecorder::default());
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(RegisterToolMiddleware),
+            Arc::new(ActivateOnInitMiddleware),
+        ];
+        let (output_sender, mut output_receiver) = tokio::sync::mpsc::channel(16);
+        let _handle = agent.start(middlewares, conn.clone(), output_sender).await?;
+        output_receiver.recv().await;
+
+        let turns = Turn::list_by_session(&conn, session.id).await?;
+        assert_eq!(turns.len(), 1);
+        let steps = TurnStep::list_by_turn(&conn, turns[0].id).await?;
+        let provider = std::any::type_name::<ToolCallingProvider>();
+        let kinds: Vec<_> = steps
+            .iter()
+            .map(|step| (step.kind, step.name.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (StepKind::Provider, provider),
+                (StepKind::Tool, "registered_tool"),
+                (StepKind::Provider, provider),
+            ]
+        );
+        let first_request: serde_json::Value = serde_json::from_str(&steps[0].input)?;
+        assert_eq!(first_request["messages"][0]["content"], "wake up");
+        assert_eq!(steps[1].input, json!({ "value": "first" }).to_string());
+        assert_eq!(steps[1].output.as_deref(), Some(r#"{"ok":true}"#));
+        assert_eq!(steps[2].input, turns[0].request);
+        assert!(steps.iter().all(|step| step.error.is_none()));
+        Ok(())
+    }
+
+    struct ShutdownObserverMiddleware {
+        called: Arc<tokio::sync::Notify>,
+    }
+
+    #[async_trait::async_trait]
+    impl Middleware for ShutdownObserverMiddleware {
+        async fn shutdown(&self, _ctx: &Context) -> Result<(), anyhow::Error> {
+            self.called.notify_one();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn dropping_handle_drains_queue_and_runs_shutdown_hooks() -> anyhow::Result<()> {
+        let (conn, session) = connection().await?;
+        let shutdown_called = Arc::new(tokio::sync::Notify::new());
+        let agent = build_agent(Arc::new(StaticProvider {
+            called: Arc::new(AtomicBool::new(false)),
+        }));
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(ShutdownObserverMiddleware {
+            called: Arc::clone(&shutdown_called),
+        })];
+        let (output_sender, mut output_receiver) = tokio::sync::mpsc::channel(16);
+        let handle = agent.start(middlewares, conn.clone(), output_sender).await?;
+
+        handle
+            .activation_sender
+            .send(AgentActivation::ChannelMessage {
+                channel_name: "qq".to_owned(),
+                chat_name: "Alice".to_owned(),
+                chat_type: ChatType::Private,
+                sender_name: "Alice".to_owned(),
+                content: "hello".to_owned(),
+                denied_tools: Vec::new(),
+            })
+            .await?;
+        drop(handle);
+
+        shutdown_called.notified().await;
+        assert_eq!(
+            output_receiver.recv().await,
+            Some(AgentOutput::SendMessage {
+                session_id: session.id,
+                content: "provider".to_owned(),
+            })
+        );
+        assert_eq!(output_receiver.recv().await, None);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn registered_tools_are_injected_when_model_supports_tools() -> anyhow::Result<()> {
         let (event_sender, _event_receiver) = tokio::sync::mpsc::channel(16);
@@ -1038,6 +1866,330 @@ mod tests {
         Ok(())
     }
 
+    /// Asks for `registered_tool` once per value in `values`, then records
+    /// the tool results it gets back.
+    struct ToolCallingProvider {
+        values: Vec<&'static str>,
+        results: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for ToolCallingProvider {
+        async fn complete(&self, request: ProviderRequest) -> Result<ChatResponse, ProviderError> {
+            let results: Vec<String> = request
+                .chat
+                .messages
+                .iter()
+                .filter_map(|message| match &message.content {
+                    ChatMessageContent::Tool { result, .. } => Some(result.clone()),
+                    _ => None,
+                })
+                .collect();
+            if !results.is_empty() {
+                *self.results.lock().unwrap() = results;
+                return Ok(chat_response("done"));
+            }
+            Ok(ChatResponse {
+                tool_calls: self
+                    .values
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| ToolCall {
+                        id: format!("call-{index}"),
+                        r#type: "function".to_owned(),
+                        function: crate::agent::types::ToolCallFunction {
+                            name: "registered_tool".to_owned(),
+                            arguments: json!({ "value": value }).to_string(),
+                        },
+                    })
+                    .collect(),
+                ..chat_response("")
+            })
+        }
+    }
+
+    struct SuffixMiddleware;
+
+    #[async_trait::async_trait]
+    impl Middleware for SuffixMiddleware {
+        async fn after_chat(
+            &self,
+            _ctx: &Context,
+            response: &mut ChatResponse,
+        ) -> Result<(), anyhow::Error> {
+            response.content.push_str("-after");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn after_chat_sees_every_provider_response_returned() -> anyhow::Result<()> {
+        let middlewares: Vec<Arc<dyn Middleware>> =
+            vec![Arc::new(RegisterToolMiddleware), Arc::new(SuffixMiddleware)];
+        let context = || {
+            let (event_sender, _event_receiver) = tokio::sync::mpsc::channel(16);
+            Context::new("Neko", 1, event_sender, Arc::new(ToolRegistry::new()), test_db())
+        };
+        let provider = || {
+            Arc::new(ToolCallingProvider {
+                values: vec!["x"],
+                results: Arc::new(Mutex::new(Vec::new())),
+            })
+        };
+
+        let ctx = context();
+        for mw in &middlewares {
+            mw.init(&ctx).await?;
+        }
+        let response = build_agent(provider())
+            .interact(&middlewares, ctx, ChatRequest::default())
+            .await?;
+        assert_eq!(response.content, "done-after");
+
+        // A response cut short by the iteration limit is returned as well.
+        let mut agent = build_agent(provider());
+        agent.max_tool_iterations = 1;
+        let ctx = context();
+        for mw in &middlewares {
+            mw.init(&ctx).await?;
+        }
+        let response = agent.interact(&middlewares, ctx, ChatRequest::default()).await?;
+        assert_eq!(response.content, "-after");
+        assert_eq!(response.tool_calls.len(), 1);
+        Ok(())
+    }
+
+    struct ToolPolicyMiddleware;
+
+    #[async_trait::async_trait]
+    impl Middleware for ToolPolicyMiddleware {
+        async fn before_tool_call(
+            &self,
+            _ctx: &Context,
+            _call: &ToolCall,
+            args: &mut Value,
+        ) -> Result<ToolCallFlow, anyhow::Error> {
+            match args["value"].as_str() {
+                Some("deny") => return Ok(ToolCallFlow::Deny("not allowed".to_owned())),
+                Some("cached") => return Ok(ToolCallFlow::Respond(json!({ "cached": true }))),
+                Some("rewrite") => args["value"] = json!("rewritten"),
+                _ => {}
+            }
+            Ok(ToolCallFlow::Continue)
+        }
+
+        async fn after_tool_call(
+            &self,
+            _ctx: &Context,
+            _call: &ToolCall,
+            result: &mut ToolResult<Value>,
+        ) -> Result<(), anyhow::Error> {
+            if let Ok(Value::Object(fields)) = result {
+                fields.insert("checked".to_owned(), json!(true));
+            }
+            Ok(())
+        }
+    }
+
+    struct ArgsRecordingMiddleware {
+        args: Arc<Mutex<Vec<Value>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Middleware for ArgsRecordingMiddleware {
+        async fn before_tool_call(
+            &self,
+            _ctx: &Context,
+            _call: &ToolCall,
+            args: &mut Value,
+        ) -> Result<ToolCallFlow, anyhow::Error> {
+            self.args.lock().unwrap().push(args.clone());
+            Ok(ToolCallFlow::Continue)
+        }
+    }
+
+    #[tokio::test]
+    async fn tool_call_hooks_rewrite_deny_and_answer_calls() -> anyhow::Result<()> {
+        let (event_sender, _event_receiver) = tokio::sync::mpsc::channel(16);
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let args = Arc::new(Mutex::new(Vec::new()));
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(RegisterToolMiddleware),
+            Arc::new(ToolPolicyMiddleware),
+            Arc::new(ArgsRecordingMiddleware {
+                args: Arc::clone(&args),
+            }),
+        ];
+        let agent = build_agent(Arc::new(ToolCallingProvider {
+            values: vec!["deny", "cached", "rewrite"],
+            results: Arc::clone(&results),
+        }));
+        let ctx = Context::new("Neko", 1, event_sender, Arc::new(ToolRegistry::new()), test_db());
+        for mw in &middlewares {
+            mw.init(&ctx).await?;
+        }
+
+        let response = agent.interact(&middlewares, ctx, ChatRequest::default()).await?;
+
+        assert_eq!(response.content, "done");
+        assert_eq!(
+            *results.lock().unwrap(),
+            vec![
+                "Error: tool call denied: not allowed".to_owned(),
+                json!({ "cached": true, "checked": true }).to_string(),
+                json!({ "ok": true, "checked": true }).to_string(),
+            ]
+        );
+        // Short-circuited calls never reach later middlewares.
+        assert_eq!(*args.lock().unwrap(), vec![json!({ "value": "rewritten" })]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn denied_tools_are_hidden_and_refused() -> anyhow::Result<()> {
+        let (event_sender, _event_receiver) = tokio::sync::mpsc::channel(16);
+        let args = Arc::new(Mutex::new(Vec::new()));
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(RegisterToolMiddleware),
+            Arc::new(ArgsRecordingMiddleware {
+                args: Arc::clone(&args),
+            }),
+        ];
+        let ctx = Context::new("Neko", 1, event_sender, Arc::new(ToolRegistry::new()), test_db());
+        for mw in &middlewares {
+            mw.init(&ctx).await?;
+        }
+        ctx.tool_registry().set_denied(["registered_tool".to_owned()])?;
+
+        let tools = Arc::new(Mutex::new(None));
+        let agent = build_agent(Arc::new(ToolCapturingProvider {
+            tools: Arc::clone(&tools),
+        }));
+        agent.interact(&middlewares, ctx.clone(), ChatRequest::default()).await?;
+        assert_eq!(tools.lock().unwrap().as_deref(), Some(&[][..]));
+
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let agent = build_agent(Arc::new(ToolCallingProvider {
+            values: vec!["anything"],
+            results: Arc::clone(&results),
+        }));
+        agent.interact(&middlewares, ctx, ChatRequest::default()).await?;
+        assert_eq!(
+            *results.lock().unwrap(),
+            vec!["Error: tool call denied: registered_tool is not available to this sender".to_owned()]
+        );
+        assert!(args.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    struct EventSenderMiddleware {
+        sender: Arc<Mutex<Option<tokio::sync::mpsc::Sender<MiddlewareEvent>>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Middleware for EventSenderMiddleware {
+        async fn init(&self, ctx: &Context) -> Result<(), anyhow::Error> {
+            *self.sender.lock().unwrap() = Some(ctx.event_sender.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_activation_runs_with_its_own_grants() -> anyhow::Result<()> {
+        let (conn, _session) = connection().await?;
+        let tools = Arc::new(Mutex::new(None));
+        let agent = build_agent(Arc::new(ToolCapturingProvider {
+            tools: Arc::clone(&tools),
+        }));
+        let sender = Arc::new(Mutex::new(None));
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(RegisterToolMiddleware),
+            Arc::new(EventSenderMiddleware {
+                sender: Arc::clone(&sender),
+            }),
+        ];
+        let (output_sender, mut output_receiver) = tokio::sync::mpsc::channel(16);
+        let handle = agent.start(middlewares, conn, output_sender).await?;
+
+        handle
+            .activation_sender
+            .send(AgentActivation::ChannelMessage {
+                channel_name: "qq".to_owned(),
+                chat_name: "Alice".to_owned(),
+                chat_type: ChatType::Private,
+                sender_name: "Alice".to_owned(),
+                content: "hello".to_owned(),
+                denied_tools: Vec::new(),
+            })
+            .await?;
+        output_receiver.recv().await;
+        assert_eq!(tools.lock().unwrap().as_ref().map(Vec::len), Some(1));
+
+        let sender = sender.lock().unwrap().clone().unwrap();
+        sender
+            .send(MiddlewareEvent::activate(
+                "reminder from a guest",
+                vec!["registered_tool".to_owned()],
+            ))
+            .await?;
+        output_receiver.recv().await;
+        assert_eq!(tools.lock().unwrap().as_deref(), Some(&[][..]));
+        Ok(())
+    }
+
+    struct IgnoreActivationMiddleware;
+
+    #[async_trait::async_trait]
+    impl Middleware for IgnoreActivationMiddleware {
+        async fn on_activation(
+            &self,
+            _ctx: &Context,
+            activation: &mut AgentActivation,
+        ) -> Result<ActivationFlow, anyhow::Error> {
+            match activation {
+                AgentActivation::ChannelMessage { content, .. } if content == "spam" => {
+                    Ok(ActivationFlow::Ignore)
+                }
+                AgentActivation::ChannelMessage { content, .. } => {
+                    *content = content.to_uppercase();
+                    Ok(ActivationFlow::Continue)
+                }
+                _ => Ok(ActivationFlow::Continue),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn activation_hook_rewrites_or_drops_before_persisting() -> anyhow::Result<()> {
+        let (conn, session) = connection().await?;
+        let agent = build_agent(Arc::new(StaticProvider {
+            called: Arc::new(AtomicBool::new(false)),
+        }));
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(IgnoreActivationMiddleware)];
+        let (output_sender, mut output_receiver) = tokio::sync::mpsc::channel(16);
+        let handle = agent.start(middlewares, conn.clone(), output_sender).await?;
+
+        for content in ["spam", "hello"] {
+            handle
+                .activation_sender
+                .send(AgentActivation::ChannelMessage {
+                    channel_name: "qq".to_owned(),
+                    chat_name: "Alice".to_owned(),
+                    chat_type: ChatType::Private,
+                    sender_name: "Alice".to_owned(),
+                    content: content.to_owned(),
+                    denied_tools: Vec::new(),
+                })
+                .await?;
+        }
+        output_receiver.recv().await;
+
+        let messages = Message::list_by_session(&conn, session.id).await?;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].content, "HELLO");
+        Ok(())
+    }
+
     struct CapturingProvider {
         capabilities: Arc<Mutex<Option<ModelCapabilities>>>,
     }
@@ -1082,6 +2234,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn strip_unsupported_drops_undeclared_tools_and_images() {
+        let mut request = ChatRequest {
+            messages: vec![ChatMessage {
+                role: Role::User,
+                content: ChatMessageContent::User {
+                    text: "look".to_owned(),
+                    images: vec![crate::agent::types::Image {
+                        data: vec![1, 2, 3],
+                        mime_type: "image/png".to_owned(),
+                    }],
+                },
+            }],
+            system_prompt: None,
+            tools: vec![ToolSpec {
+                name: "time".to_owned(),
+                description: String::new(),
+                parameters_schema: json!({}),
+            }],
+        };
+        let mut options = ModelOptions::default();
+        options.capabilities.vision = true;
+
+        strip_unsupported(&mut request, &options);
+
+        assert!(request.tools.is_empty());
+        assert_eq!(request.messages[0].content.images().len(), 1);
+        options.capabilities.vision = false;
+        strip_unsupported(&mut request, &options);
+        assert!(request.messages[0].content.images().is_empty());
+    }
+
     struct FailingProvider;
 
     #[async_trait::async_trait]
@@ -1137,9 +2321,14 @@ mod tests {
             middleware_registry: MiddlewareRegistry::new(),
             provider,
             model_options: ModelOptions::default(),
+            check_capabilities: false,
             tool_registry: Arc::new(ToolRegistry::new()),
+            command_registry: Arc::new(CommandRegistry::new()),
             max_message_count: None,
             max_tool_iterations: 10,
+            system_prompt: None,
+            chat: None,
+            recorder: None,
         }
     }
 
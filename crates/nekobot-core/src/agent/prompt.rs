@@ -0,0 +1,161 @@
+//! System prompt templates — renders `AgentConfig.system_prompt` for every turn.
+//!
+//! Templates use Jinja syntax (via minijinja) and see the variables of
+//! [`PromptVars`], e.g. `You are {{ agent }}, chatting in {{ chat }}.`
+//! Variables that are unknown for a turn, such as the sender of a scheduled
+//! activation, render as empty strings.
+
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use chrono::{DateTime, Local};
+use minijinja::Environment;
+use nekobot_channel::ChatType;
+use serde::Serialize;
+
+use crate::config::SystemPromptConfig;
+
+const TEMPLATE_NAME: &str = "system_prompt";
+
+/// A compiled system prompt template.
+#[derive(Clone)]
+pub struct SystemPrompt {
+    env: Arc<Environment<'static>>,
+}
+
+impl std::fmt::Debug for SystemPrompt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SystemPrompt").finish_non_exhaustive()
+    }
+}
+
+impl SystemPrompt {
+    /// Compile a template source, rejecting syntax errors.
+    pub fn new(source: impl Into<String>) -> anyhow::Result<Self> {
+        let mut env = Environment::new();
+        env.add_template_owned(TEMPLATE_NAME, source.into())
+            .context("invalid system prompt template")?;
+        Ok(Self { env: Arc::new(env) })
+    }
+
+    /// Load an inline prompt or read it from its file.
+    pub fn from_config(config: &SystemPromptConfig) -> anyhow::Result<Self> {
+        match config {
+            SystemPromptConfig::Inline(source) => Self::new(source.clone()),
+            SystemPromptConfig::File { file } => {
+                let source = std::fs::read_to_string(file)
+                    .with_context(|| format!("failed to read system prompt file {file}"))?;
+                Self::new(source).with_context(|| format!("in system prompt file {file}"))
+            }
+        }
+    }
+
+    /// Render the template with the variables of the current turn.
+    pub fn render(&self, vars: &PromptVars) -> anyhow::Result<String> {
+        let template = self.env.get_template(TEMPLATE_NAME)?;
+        template
+            .render(vars)
+            .context("failed to render system prompt")
+    }
+}
+
+/// Variables available to system prompt templates.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PromptVars {
+    /// Current local time in RFC 3339, e.g. `2026-10-19T09:30:00+08:00`.
+    pub now: String,
+    /// Current local date, e.g. `2026-10-19`.
+    pub date: String,
+    /// Current local time of day, e.g. `09:30`.
+    pub time: String,
+    /// UTC offset of the local time zone, e.g. `+08:00`.
+    pub timezone: String,
+    pub agent: String,
+    pub channel: String,
+    pub chat: String,
+    /// `private` or `group`.
+    pub chat_type: String,
+    pub sender: String,
+    /// Names of the tools registered for the session.
+    pub tools: Vec<String>,
+}
+
+impl PromptVars {
+    /// Variables for `agent` at time `now`; chat details are left empty.
+    pub fn new(agent: impl Into<String>, now: DateTime<Local>) -> Self {
+        Self {
+            now: now.to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+            date: now.format("%Y-%m-%d").to_string(),
+            time: now.format("%H:%M").to_string(),
+            timezone: now.format("%:z").to_string(),
+            agent: agent.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Fill in where the turn comes from.
+    pub fn with_chat(mut self, chat: &ChatDetails) -> Self {
+        self.channel = chat.channel_name.clone();
+        self.chat = chat.chat_name.clone();
+        self.chat_type = match chat.chat_type {
+            ChatType::Private => "private",
+            ChatType::Group => "group",
+        }
+        .to_owned();
+        self.sender = chat.sender_name.clone().unwrap_or_default();
+        self
+    }
+}
+
+/// Where the latest channel message of a session came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatDetails {
+    pub channel_name: String,
+    pub chat_name: String,
+    pub chat_type: ChatType,
+    /// Unset for activations that no user sent, e.g. scheduled prompts.
+    pub sender_name: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn renders_chat_variables_and_tools() -> anyhow::Result<()> {
+        let prompt = SystemPrompt::new(
+            "You are {{ agent }} in {{ channel }}/{{ chat }} ({{ chat_type }}), \
+             talking to {{ sender or \"nobody\" }} on {{ date }}. \
+             Tools: {{ tools | join(\", \") }}.",
+        )?;
+        let now = Local.with_ymd_and_hms(2026, 10, 19, 9, 30, 0).unwrap();
+        let chat = ChatDetails {
+            channel_name: "qq".to_owned(),
+            chat_name: "Ops".to_owned(),
+            chat_type: ChatType::Group,
+            sender_name: Some("Bob".to_owned()),
+        };
+        let mut vars = PromptVars::new("Neko", now).with_chat(&chat);
+        vars.tools = vec!["bash".to_owned(), "time".to_owned()];
+
+        assert_eq!(
+            prompt.render(&vars)?,
+            "You are Neko in qq/Ops (group), talking to Bob on 2026-10-19. Tools: bash, time."
+        );
+
+        let scheduled = ChatDetails {
+            sender_name: None,
+            ..chat
+        };
+        let vars = PromptVars::new("Neko", now).with_chat(&scheduled);
+        assert!(prompt.render(&vars)?.contains("talking to nobody"));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(SystemPrompt::new("{% if agent %}unterminated").is_err());
+    }
+}
@@ -1,7 +1,7 @@
 //! Tool system — callable functions that agents can invoke via provider tool-use.
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     sync::{Arc, RwLock},
 };
 
@@ -19,6 +19,9 @@ pub enum ToolError {
 
     #[error("tool not found: {0}")]
     NotFound(String),
+
+    #[error("tool call denied: {0}")]
+    Denied(String),
 }
 
 pub type ToolResult<T> = Result<T, ToolError>;
@@ -63,9 +66,13 @@ pub trait Tool: Send + Sync {
 ///
 /// Uses `RwLock<BTreeMap>` for concurrent read access. Tools are injected
 /// into provider requests when the model supports tool use.
+///
+/// Tools named by [`set_denied`](ToolRegistry::set_denied) stay registered
+/// but are left out of requests and refuse calls.
 #[derive(Default)]
 pub struct ToolRegistry {
     tools: RwLock<BTreeMap<String, Arc<dyn Tool>>>,
+    denied: RwLock<BTreeSet<String>>,
 }
 
 impl ToolRegistry {
@@ -117,6 +124,34 @@ impl ToolRegistry {
             .collect())
     }
 
+    /// Replace the names of the tools the current sender may not call.
+    pub fn set_denied(&self, names: impl IntoIterator<Item = String>) -> anyhow::Result<()> {
+        let mut denied = self
+            .denied
+            .write()
+            .map_err(|_| anyhow::anyhow!("tool registry lock poisoned"))?;
+        *denied = names.into_iter().collect();
+        Ok(())
+    }
+
+    /// Names of the tools the current sender may not call.
+    pub fn denied(&self) -> anyhow::Result<BTreeSet<String>> {
+        let denied = self
+            .denied
+            .read()
+            .map_err(|_| anyhow::anyhow!("tool registry lock poisoned"))?;
+        Ok(denied.clone())
+    }
+
+    /// Return `true` if the current sender may not call `name`.
+    pub fn is_denied(&self, name: &str) -> anyhow::Result<bool> {
+        let denied = self
+            .denied
+            .read()
+            .map_err(|_| anyhow::anyhow!("tool registry lock poisoned"))?;
+        Ok(denied.contains(name))
+    }
+
     /// Return `true` if no tools are registered.
     pub fn is_empty(&self) -> anyhow::Result<bool> {
         let tools = self
@@ -0,0 +1,211 @@
+//! Password hashes and one-time codes for the login gate.
+//!
+//! `password_hash` in the config may be an Argon2 hash in PHC format
+//! (`$argon2id$…`), a bcrypt hash (`$2b$…`), or the hex SHA-256 digest of
+//! the password that older configs use. `nekobot hash-password` prints an
+//! Argon2id hash for a new password.
+
+use sha2::{Digest, Sha256};
+
+/// A parsed `password_hash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordHash {
+    /// Argon2 hash in PHC string format.
+    Argon2(String),
+    /// bcrypt hash in modular crypt format.
+    Bcrypt(String),
+    /// Unsalted hex SHA-256 digest, kept for existing configs.
+    Sha256(String),
+}
+
+impl PasswordHash {
+    /// Recognise the hash format, failing for anything that cannot be verified.
+    pub fn parse(hash: &str) -> anyhow::Result<Self> {
+        let hash = hash.trim();
+        if hash.starts_with("$argon2") {
+            let parsed = argon2::PasswordHash::new(hash)
+                .map_err(|e| anyhow::anyhow!("invalid Argon2 hash: {e}"))?;
+            if parsed.salt.is_none() || parsed.hash.is_none() {
+                anyhow::bail!("invalid Argon2 hash: missing salt or hash");
+            }
+            Ok(Self::Argon2(hash.to_owned()))
+        } else if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+        {
+            hash.parse::<bcrypt::HashParts>()
+                .map_err(|e| anyhow::anyhow!("invalid bcrypt hash: {e}"))?;
+            Ok(Self::Bcrypt(hash.to_owned()))
+        } else if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Ok(Self::Sha256(hash.to_ascii_lowercase()))
+        } else {
+            anyhow::bail!("expected an Argon2 or bcrypt hash, or a hex SHA-256 digest")
+        }
+    }
+
+    /// Hash `password` with Argon2id and a random salt.
+    pub fn hash(password: &str) -> anyhow::Result<String> {
+        use password_hash::{PasswordHasher, SaltString, rand_core::OsRng};
+
+        let salt = SaltString::generate(&mut OsRng);
+        argon2::Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| anyhow::anyhow!("failed to hash password: {e}"))
+    }
+
+    /// Whether `password` matches. Argon2 and bcrypt are deliberately slow,
+    /// so call this off the async executor.
+    pub fn verify(&self, password: &str) -> bool {
+        match self {
+            Self::Argon2(hash) => {
+                use password_hash::PasswordVerifier;
+
+                argon2::PasswordHash::new(hash).is_ok_and(|parsed| {
+                    argon2::Argon2::default()
+                        .verify_password(password.as_bytes(), &parsed)
+                        .is_ok()
+                })
+            }
+            Self::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Self::Sha256(hash) => {
+                let digest: String = Sha256::digest(password.as_bytes())
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect();
+                constant_time_eq(digest.as_bytes(), hash.as_bytes())
+            }
+        }
+    }
+
+    /// Whether this is the unsalted SHA-256 format.
+    pub fn is_legacy(&self) -> bool {
+        matches!(self, Self::Sha256(_))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Time-based one-time codes (RFC 6238) as shown by authenticator apps:
+/// HMAC-SHA1, 30-second steps, 6 digits.
+#[derive(Clone)]
+pub struct Totp {
+    secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for Totp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Totp").finish_non_exhaustive()
+    }
+}
+
+const TOTP_STEP_SECS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+
+impl Totp {
+    /// Parse a base32 secret, ignoring case, spaces and padding. The secret
+    /// must be at least 80 bits long.
+    pub fn parse(secret: &str) -> anyhow::Result<Self> {
+        let normalized: String = secret
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        let secret = data_encoding::BASE32_NOPAD
+            .decode(normalized.as_bytes())
+            .map_err(|e| anyhow::anyhow!("TOTP secret is not valid base32: {e}"))?;
+        if secret.len() < 10 {
+            anyhow::bail!("TOTP secret must be at least 80 bits long");
+        }
+        Ok(Self { secret })
+    }
+
+    /// The code for the step containing `unix_secs`.
+    pub fn code_at(&self, unix_secs: i64) -> String {
+        use hmac::{Hmac, Mac};
+
+        let counter = unix_secs.div_euclid(TOTP_STEP_SECS) as u64;
+        let mut mac = Hmac::<sha1::Sha1>::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(&counter.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let value = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            value % 10u32.pow(TOTP_DIGITS),
+            width = TOTP_DIGITS as usize
+        )
+    }
+
+    /// Whether `code` is valid at `unix_secs`, allowing one step of clock
+    /// drift either way.
+    pub fn verify(&self, code: &str, unix_secs: i64) -> bool {
+        self.matching_step(code, unix_secs).is_some()
+    }
+
+    /// The time step whose code `code` is, if it is valid at `unix_secs`.
+    ///
+    /// Callers that remember the last accepted step can refuse a code that
+    /// was already used, or one older than it.
+    pub fn matching_step(&self, code: &str, unix_secs: i64) -> Option<i64> {
+        let code = code.trim();
+        [-TOTP_STEP_SECS, 0, TOTP_STEP_SECS]
+            .iter()
+            .map(|drift| unix_secs + drift)
+            .find(|at| constant_time_eq(self.code_at(*at).as_bytes(), code.as_bytes()))
+            .map(|at| at.div_euclid(TOTP_STEP_SECS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_every_hash_format() {
+        let argon2 = PasswordHash::parse(&PasswordHash::hash("hunter2").unwrap()).unwrap();
+        assert!(matches!(argon2, PasswordHash::Argon2(_)));
+        assert!(argon2.verify("hunter2"));
+        assert!(!argon2.verify("hunter3"));
+
+        let bcrypt = PasswordHash::parse(&bcrypt::hash("hunter2", 4).unwrap()).unwrap();
+        assert!(bcrypt.verify("hunter2"));
+        assert!(!bcrypt.verify("hunter3"));
+
+        // sha256("hunter2")
+        let legacy =
+            PasswordHash::parse("F52FBD32B2B3B86FF88EF6C490628285F482AF15DDCB29541F94BCF526A3F6C7")
+                .unwrap();
+        assert!(legacy.is_legacy());
+        assert!(legacy.verify("hunter2"));
+        assert!(!legacy.verify("hunter3"));
+
+        assert!(PasswordHash::parse("hunter2").is_err());
+        assert!(PasswordHash::parse("$argon2id$broken").is_err());
+    }
+
+    #[test]
+    fn totp_matches_rfc_6238() {
+        // The RFC's SHA-1 secret "12345678901234567890", base32-encoded.
+        let totp = Totp::parse("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(totp.code_at(59), "287082");
+        assert_eq!(totp.code_at(1_111_111_109), "081804");
+        assert_eq!(totp.code_at(2_000_000_000), "279037");
+
+        assert!(totp.verify("081804", 1_111_111_109 + 30));
+        assert!(!totp.verify("081804", 1_111_111_109 + 90));
+        assert_eq!(
+            totp.matching_step("081804", 1_111_111_109 + 30),
+            Some(1_111_111_109 / 30)
+        );
+        assert!(Totp::parse("GEZDGNBV").is_err());
+    }
+}
@@ -28,6 +28,11 @@ impl ChannelRegistry {
         self.inner.register(name, create)
     }
 
+    /// Whether a factory is registered for the type name.
+    pub fn contains(&self, name: &str) -> bool {
+        self.inner.get(name).is_some()
+    }
+
     /// Create a channel from its config.
     ///
     /// Returns `Ok(None)` if no factory is registered for this config's type.
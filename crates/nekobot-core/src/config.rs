@@ -3,31 +3,358 @@
 
 use std::collections::HashSet;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
 use crate::provider::ModelOptions;
 
+mod loader;
+pub mod schema;
+
+pub use loader::ConfigLoadError;
+
 /// Top-level application configuration listing all channels, providers, and agents.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     pub channels: Vec<ChannelConfig>,
     pub providers: Vec<ProviderConfig>,
     pub agents: Vec<AgentConfig>,
-    /// Optional SHA256 hex hash of the global login password.
+    /// Optional hash of the global login password: an Argon2 (`$argon2id$…`)
+    /// or bcrypt (`$2b$…`) hash, or the hex SHA-256 digest older configs use.
     /// When set, C2C users must `/login <password>` before accessing any agent.
     #[serde(default)]
     pub password_hash: Option<String>,
+    /// Throttling, expiry and second factor of `/login`.
+    #[serde(default)]
+    pub login: LoginConfig,
+    /// Roles, per-agent and per-capability grants, sender lists and group
+    /// gating. Without roles every sender that passes the login may use
+    /// every agent.
+    #[serde(default)]
+    pub access: AccessConfig,
     /// Path to the libSQL database file. Defaults to `"nekobot.db"`.
     #[serde(default = "default_database_path")]
     pub database_path: String,
+    /// File with the keys that encrypt secrets stored in the database, such
+    /// as channel credentials. The `NEKOBOT_SECRET_KEYS` environment variable
+    /// takes precedence. Without either, secrets are stored unencrypted.
+    #[serde(default)]
+    pub secret_key_file: Option<String>,
+    /// Seconds to wait for in-flight agent turns to finish on shutdown before
+    /// exiting anyway. Defaults to 30.
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
+    /// Forward unknown `/commands` to the agent instead of replying with an
+    /// error. Defaults to `false`.
+    #[serde(default)]
+    pub pass_unknown_commands: bool,
+    /// Ordered rules that pick the agent for an incoming message; the first
+    /// match wins. A rule without conditions matches everything and acts as
+    /// the default. Without any match the first agent in `agents` is used.
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// Span export and metrics endpoint; both are off by default.
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    /// Per-turn audit records; off by default.
+    #[serde(default)]
+    pub audit: AuditConfig,
 }
 
 fn default_database_path() -> String {
     "nekobot.db".to_owned()
 }
 
+fn default_shutdown_grace_secs() -> u64 {
+    30
+}
+
+/// Tracing export and metrics settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// OTLP/gRPC collector endpoint receiving spans, e.g. `http://localhost:4317`.
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    /// Service name reported with exported spans. Defaults to `"nekobot"`.
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Address serving Prometheus metrics at `/metrics`, e.g. `127.0.0.1:9464`.
+    #[serde(default)]
+    pub metrics_addr: Option<String>,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: default_service_name(),
+            metrics_addr: None,
+        }
+    }
+}
+
+fn default_service_name() -> String {
+    "nekobot".to_owned()
+}
+
+/// Pruning of stored messages, checked hourly. Every limit that is set applies.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Delete messages older than this many days.
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+    /// Keep at most this many messages per session, deleting the oldest.
+    #[serde(default)]
+    pub max_messages: Option<usize>,
+    /// Append pruned messages to `<archive_dir>/<agent>/<session id>.jsonl.gz`
+    /// before deleting them. Pruned messages are discarded when unset.
+    #[serde(default)]
+    pub archive_dir: Option<String>,
+}
+
+impl RetentionConfig {
+    /// Whether any limit is set.
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days.is_some() || self.max_messages.is_some()
+    }
+}
+
+/// Settings for the `turns` audit table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Record the provider request, tool calls, usage, timing and errors of
+    /// every agent turn.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Delete audit records older than this many days. Kept forever when unset.
+    #[serde(default)]
+    pub retention_days: Option<u64>,
+}
+
+/// Settings of the `/login` gate enabled by `password_hash`.
+///
+/// `Debug` output leaves out the TOTP secret.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LoginConfig {
+    /// Failed attempts after which a sender is locked out. Defaults to 5.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Seconds a locked out sender has to wait. Defaults to 900.
+    #[serde(default = "default_lockout_secs")]
+    pub lockout_secs: u64,
+    /// Failed attempts of all senders together within `global_window_secs`
+    /// after which every login is refused until older failures age out.
+    /// Defaults to 20.
+    #[serde(default = "default_global_max_attempts")]
+    pub global_max_attempts: u32,
+    /// Defaults to 60.
+    #[serde(default = "default_global_window_secs")]
+    pub global_window_secs: u64,
+    /// Seconds after which a login expires. Logins never expire when unset.
+    #[serde(default)]
+    pub session_ttl_secs: Option<u64>,
+    /// Base32 TOTP secret. When set, `/login` also needs the current code of
+    /// an authenticator app: `/login <password> <code>`.
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+}
+
+impl std::fmt::Debug for LoginConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginConfig")
+            .field("max_attempts", &self.max_attempts)
+            .field("lockout_secs", &self.lockout_secs)
+            .field("global_max_attempts", &self.global_max_attempts)
+            .field("global_window_secs", &self.global_window_secs)
+            .field("session_ttl_secs", &self.session_ttl_secs)
+            .field("totp_secret", &self.totp_secret.as_ref().map(|_| Redacted))
+            .finish()
+    }
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            lockout_secs: default_lockout_secs(),
+            global_max_attempts: default_global_max_attempts(),
+            global_window_secs: default_global_window_secs(),
+            session_ttl_secs: None,
+            totp_secret: None,
+        }
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_lockout_secs() -> u64 {
+    900
+}
+
+fn default_global_max_attempts() -> u32 {
+    20
+}
+
+fn default_global_window_secs() -> u64 {
+    60
+}
+
+/// Access control on top of the `/login` gate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AccessConfig {
+    /// Roles senders can hold, through `users`, `default_role` or an invite
+    /// code.
+    #[serde(default)]
+    pub roles: Vec<RoleConfig>,
+    /// Roles of senders, matched by channel and sender ID.
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+    /// Role of senders that hold no other role.
+    #[serde(default)]
+    pub default_role: Option<String>,
+    /// Tools that only senders with the `tool:<name>` capability may call.
+    #[serde(default)]
+    pub restricted_tools: Vec<String>,
+    /// Senders allowed and denied per channel.
+    #[serde(default)]
+    pub channels: Vec<ChannelAccessConfig>,
+    /// How group chats reach agents. Defaults to `open`.
+    #[serde(default)]
+    pub groups: GroupMode,
+    /// Seconds an invite code can be redeemed for. Defaults to 7 days.
+    #[serde(default = "default_invite_ttl_secs")]
+    pub invite_ttl_secs: u64,
+}
+
+impl Default for AccessConfig {
+    fn default() -> Self {
+        Self {
+            roles: Vec::new(),
+            users: Vec::new(),
+            default_role: None,
+            restricted_tools: Vec::new(),
+            channels: Vec::new(),
+            groups: GroupMode::default(),
+            invite_ttl_secs: default_invite_ttl_secs(),
+        }
+    }
+}
+
+fn default_invite_ttl_secs() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+/// A named set of agents and capabilities.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RoleConfig {
+    pub name: String,
+    /// Agents the role may `/connect` to and talk to in groups; `*` stands
+    /// for all of them.
+    #[serde(default)]
+    pub agents: Vec<String>,
+    /// `admin` for the admin commands, `tool:<name>` for a restricted tool,
+    /// or `*` for everything.
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+/// Roles granted to the senders matching a pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    /// Sender ID; `*` matches any run of characters.
+    pub sender_id: String,
+    /// Channel name as configured in `channels`; any channel when unset.
+    #[serde(default)]
+    pub channel: Option<String>,
+    pub roles: Vec<String>,
+}
+
+/// Sender lists of one channel. Patterns match sender IDs, with `*`
+/// standing for any run of characters.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelAccessConfig {
+    /// Channel name as configured in `channels`.
+    pub channel: String,
+    /// When non-empty, messages of all other senders are dropped.
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Senders whose messages are dropped.
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// How group chats reach agents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupMode {
+    /// Every group is answered by the agent its route picks, unless an
+    /// admin bound it to another one with `/bind`.
+    #[default]
+    Open,
+    /// Groups are ignored until an admin binds them to an agent with `/bind`.
+    Bound,
+}
+
+impl AccessConfig {
+    fn validate(
+        &self,
+        agents: &HashSet<String>,
+        channels: &HashSet<String>,
+    ) -> Result<(), ConfigValidationError> {
+        let mut role_names = HashSet::new();
+        for role in &self.roles {
+            if !role_names.insert(role.name.as_str()) {
+                return Err(ConfigValidationError::DuplicateRoleName(role.name.clone()));
+            }
+            if let Some(agent) = role
+                .agents
+                .iter()
+                .find(|agent| *agent != "*" && !agents.contains(*agent))
+            {
+                return Err(ConfigValidationError::UnknownRoleAgent {
+                    role: role.name.clone(),
+                    agent: agent.clone(),
+                });
+            }
+        }
+
+        let referenced = self
+            .users
+            .iter()
+            .flat_map(|user| &user.roles)
+            .chain(&self.default_role);
+        for role in referenced {
+            if !role_names.contains(role.as_str()) {
+                return Err(ConfigValidationError::UnknownRole(role.clone()));
+            }
+        }
+
+        let referenced = self
+            .users
+            .iter()
+            .filter_map(|user| user.channel.as_ref())
+            .chain(self.channels.iter().map(|c| &c.channel));
+        for channel in referenced {
+            if !channels.contains(channel) {
+                return Err(ConfigValidationError::UnknownAccessChannel(channel.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Config {
     /// Validates the entire configuration, checking for duplicate/empty names,
     /// missing models, unknown provider references, and invalid middlewares.
@@ -43,6 +370,18 @@ impl Config {
             }
         }
 
+        if let Some(hash) = &self.password_hash {
+            crate::auth::PasswordHash::parse(hash)
+                .map_err(|e| ConfigValidationError::InvalidPasswordHash(e.to_string()))?;
+        }
+        if let Some(secret) = &self.login.totp_secret {
+            crate::auth::Totp::parse(secret)
+                .map_err(|e| ConfigValidationError::InvalidTotpSecret(e.to_string()))?;
+        }
+        if self.login.max_attempts == 0 || self.login.global_max_attempts == 0 {
+            return Err(ConfigValidationError::ZeroLoginAttempts);
+        }
+
         let mut provider_names = HashSet::new();
         for provider in &self.providers {
             let provider_name = provider.name();
@@ -117,6 +456,40 @@ impl Config {
             }
         }
 
+        for agent in &self.agents {
+            if let Some(delegate) = agent.delegates.iter().find(|d| !agent_names.contains(*d)) {
+                return Err(ConfigValidationError::UnknownDelegateAgent {
+                    agent: agent.name.clone(),
+                    delegate: delegate.clone(),
+                });
+            }
+        }
+
+        for (index, route) in self.routes.iter().enumerate() {
+            if !agent_names.contains(&route.agent) {
+                return Err(ConfigValidationError::UnknownRouteAgent {
+                    index,
+                    agent: route.agent.clone(),
+                });
+            }
+            if let Some(channel) = &route.channel
+                && !channel_names.contains(channel)
+            {
+                return Err(ConfigValidationError::UnknownRouteChannel {
+                    index,
+                    channel: channel.clone(),
+                });
+            }
+            if [&route.chat_id, &route.sender_id]
+                .into_iter()
+                .any(|pattern| pattern.as_deref().is_some_and(|p| p.trim().is_empty()))
+            {
+                return Err(ConfigValidationError::EmptyRoutePattern { index });
+            }
+        }
+
+        self.access.validate(&agent_names, &channel_names)?;
+
         Ok(())
     }
 
@@ -142,7 +515,7 @@ impl Config {
 
 /// Configuration for a single agent, referencing a provider, model, and
 /// an ordered list of middlewares.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct AgentConfig {
     pub name: String,
@@ -155,19 +528,128 @@ pub struct AgentConfig {
     /// Max tool call iterations per interaction (default: 10).
     #[serde(default = "default_max_tool_iterations")]
     pub max_tool_iterations: usize,
+    /// Agents this agent may hand tasks to through the `delegate` tool.
+    #[serde(default)]
+    pub delegates: Vec<String>,
+    /// Max nesting of delegated sub-agents below this agent's chat sessions (default: 2).
+    #[serde(default = "default_max_delegation_depth")]
+    pub max_delegation_depth: usize,
+    /// Template for the system prompt, rendered before middleware runs.
+    #[serde(default)]
+    pub system_prompt: Option<SystemPromptConfig>,
+    /// How long the history of this agent's sessions is kept (default: forever).
+    #[serde(default)]
+    pub retention: RetentionConfig,
 }
 
 fn default_max_tool_iterations() -> usize { 10 }
 
+fn default_max_delegation_depth() -> usize { 2 }
+
+/// System prompt of an agent: inline template text, or `{ file: <path> }`.
+/// See [`SystemPrompt`](crate::agent::prompt::SystemPrompt) for the template variables.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum SystemPromptConfig {
+    Inline(String),
+    File { file: String },
+}
+
+/// A routing rule assigning an agent to matching messages. Every condition
+/// that is set must match; `chat_id` and `sender_id` accept `*` wildcards.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    pub agent: String,
+    /// Channel name as configured in `channels`.
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub chat_id: Option<String>,
+    #[serde(default)]
+    pub chat_type: Option<RouteChatType>,
+    #[serde(default)]
+    pub sender_id: Option<String>,
+}
+
+/// Chat type condition of a [`RouteConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteChatType {
+    Private,
+    Group,
+}
+
 /// Configuration for a single middleware, identified by name with additional
 /// properties flattened from the serialized form.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+///
+/// `Debug` output leaves out values under keys that look like secrets, such
+/// as `api_key` or `token`, at any depth.
+#[derive(Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MiddlewareConfig {
     pub name: String,
     #[serde(flatten)]
     pub data: Map<String, Value>,
 }
 
+impl std::fmt::Debug for MiddlewareConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MiddlewareConfig")
+            .field("name", &self.name)
+            .field("data", &RedactedMap(&self.data))
+            .finish()
+    }
+}
+
+/// Key fragments that mark a middleware setting as a secret.
+const SECRET_KEY_PARTS: &[&str] = &[
+    "secret",
+    "password",
+    "passphrase",
+    "token",
+    "api_key",
+    "apikey",
+    "credential",
+    "private_key",
+];
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SECRET_KEY_PARTS.iter().any(|part| key.contains(part))
+}
+
+/// Formats a JSON object with the values of secret-looking keys redacted.
+struct RedactedMap<'a>(&'a Map<String, Value>);
+
+impl std::fmt::Debug for RedactedMap<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut map = f.debug_map();
+        for (key, value) in self.0 {
+            if is_secret_key(key) {
+                map.entry(key, &Redacted);
+            } else {
+                map.entry(key, &RedactedValue(value));
+            }
+        }
+        map.finish()
+    }
+}
+
+struct RedactedValue<'a>(&'a Value);
+
+impl std::fmt::Debug for RedactedValue<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Value::Object(map) => RedactedMap(map).fmt(f),
+            Value::Array(items) => f
+                .debug_list()
+                .entries(items.iter().map(RedactedValue))
+                .finish(),
+            other => other.fmt(f),
+        }
+    }
+}
+
 impl MiddlewareConfig {
     fn validate(&self, agent: &str) -> Result<(), ConfigValidationError> {
         if self.name.trim().is_empty() {
@@ -181,7 +663,9 @@ impl MiddlewareConfig {
 }
 
 /// Available chat channel integrations.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// `Debug` output leaves out the client secret.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", deny_unknown_fields)]
 pub enum ChannelConfig {
     /// QQ Bot channel using the official QQ Bot API.
@@ -202,6 +686,33 @@ fn default_wx_base_url() -> String {
     "https://ilinkai.weixin.qq.com".to_owned()
 }
 
+/// Stands in for a secret in `Debug` output.
+struct Redacted;
+
+impl std::fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl std::fmt::Debug for ChannelConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChannelConfig::QQ { name, app_id, .. } => f
+                .debug_struct("QQ")
+                .field("name", name)
+                .field("app_id", app_id)
+                .field("client_secret", &Redacted)
+                .finish(),
+            ChannelConfig::WeiXin { name, base_url } => f
+                .debug_struct("WeiXin")
+                .field("name", name)
+                .field("base_url", base_url)
+                .finish(),
+        }
+    }
+}
+
 impl ChannelConfig {
     /// Returns the channel type tag as it appears in the JSON `"type"` field.
     pub fn type_name(&self) -> &str {
@@ -221,7 +732,9 @@ impl ChannelConfig {
 
 /// Supported LLM provider configurations, each with a name, credentials, and
 /// a list of available models.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// `Debug` output leaves out API keys and access tokens.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", deny_unknown_fields)]
 pub enum ProviderConfig {
     /// Standard OpenAI API provider (chat completions).
@@ -246,6 +759,76 @@ pub enum ProviderConfig {
         models: Vec<ModelOptions>,
         base_url: Option<String>,
     },
+    /// Records the traffic of the wrapped `provider` to a cassette file, or
+    /// replays it from there without contacting the provider. The wrapped
+    /// provider supplies the models.
+    Cassette {
+        name: String,
+        mode: CassetteMode,
+        path: String,
+        provider: Box<ProviderConfig>,
+    },
+}
+
+impl std::fmt::Debug for ProviderConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderConfig::OpenAI { name, models, .. } => f
+                .debug_struct("OpenAI")
+                .field("name", name)
+                .field("api_key", &Redacted)
+                .field("models", models)
+                .finish(),
+            ProviderConfig::OpenAICodex {
+                name,
+                account_id,
+                models,
+                base_url,
+                ..
+            } => f
+                .debug_struct("OpenAICodex")
+                .field("name", name)
+                .field("access_token", &Redacted)
+                .field("account_id", account_id)
+                .field("models", models)
+                .field("base_url", base_url)
+                .finish(),
+            ProviderConfig::DeepSeek {
+                name,
+                models,
+                base_url,
+                ..
+            } => f
+                .debug_struct("DeepSeek")
+                .field("name", name)
+                .field("api_key", &Redacted)
+                .field("models", models)
+                .field("base_url", base_url)
+                .finish(),
+            ProviderConfig::Cassette {
+                name,
+                mode,
+                path,
+                provider,
+            } => f
+                .debug_struct("Cassette")
+                .field("name", name)
+                .field("mode", mode)
+                .field("path", path)
+                .field("provider", provider)
+                .finish(),
+        }
+    }
+}
+
+/// Whether a `Cassette` provider writes or serves its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    /// Forward requests to the wrapped provider and save every exchange.
+    Record,
+    /// Answer requests from the file; unrecorded requests fail.
+    Replay,
 }
 
 impl ProviderConfig {
@@ -256,6 +839,7 @@ impl ProviderConfig {
             ProviderConfig::OpenAI { .. } => "OpenAI",
             ProviderConfig::OpenAICodex { .. } => "OpenAICodex",
             ProviderConfig::DeepSeek { .. } => "DeepSeek",
+            ProviderConfig::Cassette { .. } => "Cassette",
         }
     }
 
@@ -264,7 +848,8 @@ impl ProviderConfig {
         match self {
             ProviderConfig::OpenAI { name, .. }
             | ProviderConfig::OpenAICodex { name, .. }
-            | ProviderConfig::DeepSeek { name, .. } => name,
+            | ProviderConfig::DeepSeek { name, .. }
+            | ProviderConfig::Cassette { name, .. } => name,
         }
     }
 
@@ -274,6 +859,7 @@ impl ProviderConfig {
             ProviderConfig::OpenAI { models, .. }
             | ProviderConfig::OpenAICodex { models, .. }
             | ProviderConfig::DeepSeek { models, .. } => models,
+            ProviderConfig::Cassette { provider, .. } => provider.models(),
         }
     }
 
@@ -335,6 +921,39 @@ pub enum ConfigValidationError {
         provider: String,
         model: String,
     },
+
+    #[error("agent {agent} delegates to unknown agent {delegate}")]
+    UnknownDelegateAgent { agent: String, delegate: String },
+
+    #[error("route #{index} references unknown agent {agent}")]
+    UnknownRouteAgent { index: usize, agent: String },
+
+    #[error("route #{index} references unknown channel {channel}")]
+    UnknownRouteChannel { index: usize, channel: String },
+
+    #[error("route #{index} has an empty chat_id or sender_id pattern")]
+    EmptyRoutePattern { index: usize },
+
+    #[error("invalid password_hash: {0}")]
+    InvalidPasswordHash(String),
+
+    #[error("invalid login.totp_secret: {0}")]
+    InvalidTotpSecret(String),
+
+    #[error("login.max_attempts and login.global_max_attempts must be at least 1")]
+    ZeroLoginAttempts,
+
+    #[error("duplicate role name: {0}")]
+    DuplicateRoleName(String),
+
+    #[error("role {role} references unknown agent {agent}")]
+    UnknownRoleAgent { role: String, agent: String },
+
+    #[error("access references unknown role {0}")]
+    UnknownRole(String),
+
+    #[error("access references unknown channel {0}")]
+    UnknownAccessChannel(String),
 }
 
 #[cfg(test)]
@@ -373,7 +992,9 @@ mod tests {
                 assert!(models[0].capabilities.streaming);
                 assert!(models[0].capabilities.tools);
             }
-            ProviderConfig::OpenAICodex { .. } | ProviderConfig::DeepSeek { .. } => {
+            ProviderConfig::OpenAICodex { .. }
+            | ProviderConfig::DeepSeek { .. }
+            | ProviderConfig::Cassette { .. } => {
                 panic!("expected OpenAI config")
             }
         }
@@ -414,7 +1035,9 @@ mod tests {
                     Some("https://example.test/backend-api/codex")
                 );
             }
-            ProviderConfig::OpenAI { .. } | ProviderConfig::DeepSeek { .. } => {
+            ProviderConfig::OpenAI { .. }
+            | ProviderConfig::DeepSeek { .. }
+            | ProviderConfig::Cassette { .. } => {
                 panic!("expected OpenAICodex config")
             }
         }
@@ -475,12 +1098,40 @@ mod tests {
                 assert_eq!(models[0].extra["thinking"], json!({ "type": "enabled" }));
                 assert_eq!(base_url.as_deref(), Some("https://api.deepseek.com"));
             }
-            ProviderConfig::OpenAI { .. } | ProviderConfig::OpenAICodex { .. } => {
+            ProviderConfig::OpenAI { .. }
+            | ProviderConfig::OpenAICodex { .. }
+            | ProviderConfig::Cassette { .. } => {
                 panic!("expected DeepSeek config")
             }
         }
     }
 
+    #[test]
+    fn cassette_provider_config_wraps_a_provider_and_uses_its_models() {
+        let config: ProviderConfig = serde_json::from_value(json!({
+            "type": "Cassette",
+            "name": "deepseek",
+            "mode": "replay",
+            "path": "tests/cassettes/deepseek.json",
+            "provider": {
+                "type": "DeepSeek",
+                "name": "deepseek-live",
+                "api_key": "sk-test",
+                "models": [{ "model": "deepseek-v4-pro" }]
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(config.type_name(), "Cassette");
+        assert_eq!(config.name(), "deepseek");
+        assert!(config.model_options("deepseek-v4-pro").is_some());
+        let ProviderConfig::Cassette { mode, provider, .. } = config else {
+            panic!("expected Cassette config")
+        };
+        assert_eq!(mode, CassetteMode::Replay);
+        assert_eq!(provider.type_name(), "DeepSeek");
+    }
+
     #[test]
     fn config_validates_agent_provider_and_model_references() {
         let config: Config = serde_json::from_value(json!({
@@ -538,6 +1189,80 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn config_validates_routes() {
+        let mut config: Config = serde_json::from_value(json!({
+            "channels": [{ "type": "QQ", "name": "qq-test", "app_id": "test-app-id", "client_secret": "test-secret" }],
+            "providers": [
+                {
+                    "type": "DeepSeek",
+                    "name": "deepseek",
+                    "api_key": "sk-test",
+                    "models": [{ "model": "deepseek-v4-pro" }]
+                }
+            ],
+            "agents": [
+                {
+                    "name": "Neko",
+                    "provider": "deepseek",
+                    "model": "deepseek-v4-pro",
+                    "middlewares": []
+                }
+            ],
+            "routes": [
+                { "agent": "Neko", "channel": "qq-test", "chat_id": "group:*", "chat_type": "group" },
+                { "agent": "Neko" }
+            ]
+        }))
+        .unwrap();
+
+        config.validate().unwrap();
+        assert_eq!(config.routes[0].chat_type, Some(RouteChatType::Group));
+
+        config.routes[1].agent = "Mimi".to_owned();
+        assert_eq!(
+            config.validate(),
+            Err(ConfigValidationError::UnknownRouteAgent {
+                index: 1,
+                agent: "Mimi".to_owned(),
+            })
+        );
+
+        config.routes[1].agent = "Neko".to_owned();
+        config.routes[0].channel = Some("discord".to_owned());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigValidationError::UnknownRouteChannel { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn config_validates_login_settings() {
+        let mut config: Config = serde_json::from_value(json!({
+            "channels": [],
+            "providers": [],
+            "agents": [],
+            "password_hash": "$2b$04$of/cgmQOU/6h5U.vl.ufYuWq8J285owC5OteSMzhbSaBkDWsL4Tu6",
+            "login": { "max_attempts": 3, "totp_secret": "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ" }
+        }))
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.login.lockout_secs, 900);
+
+        config.login.totp_secret = Some("not base32!".to_owned());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigValidationError::InvalidTotpSecret(_))
+        ));
+
+        config.login.totp_secret = None;
+        config.password_hash = Some("hunter2".to_owned());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigValidationError::InvalidPasswordHash(_))
+        ));
+    }
+
     #[test]
     fn agent_config_requires_middlewares() {
         let result = serde_json::from_value::<AgentConfig>(json!({
@@ -549,6 +1274,32 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn agent_config_accepts_inline_or_file_system_prompt() {
+        let agent = |system_prompt: Value| {
+            serde_json::from_value::<AgentConfig>(json!({
+                "name": "Neko",
+                "provider": "deepseek",
+                "model": "deepseek-v4-pro",
+                "middlewares": [],
+                "system_prompt": system_prompt
+            }))
+            .unwrap()
+            .system_prompt
+        };
+
+        assert_eq!(
+            agent(json!("You are {{ agent }}.")),
+            Some(SystemPromptConfig::Inline("You are {{ agent }}.".to_owned()))
+        );
+        assert_eq!(
+            agent(json!({ "file": "prompts/neko.md" })),
+            Some(SystemPromptConfig::File {
+                file: "prompts/neko.md".to_owned()
+            })
+        );
+    }
+
     #[test]
     fn middleware_config_deserializes_name_and_flattened_data() {
         let config: MiddlewareConfig = serde_json::from_value(json!({
@@ -0,0 +1,352 @@
+//! Reading `config.yaml`: includes and secret references.
+//!
+//! String values may reference secrets instead of containing them:
+//! `${NAME}` is replaced with the environment variable `NAME`, and
+//! `${file:PATH}` with the contents of `PATH` without its trailing newline.
+//! Relative paths are resolved against the directory of the file that
+//! contains the reference. `$${` stands for a literal `${`.
+//!
+//! A top-level `include:` list names further YAML files. Their list sections
+//! (`agents`, `providers`, `channels`, `routes`, …) are appended to the
+//! including file's; other keys may only be set by one of the files.
+
+use std::path::{Path, PathBuf};
+
+use serde_yml::{Mapping, Value};
+
+use super::Config;
+
+/// Errors while reading the configuration, before it is validated.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigLoadError {
+    #[error("failed to read {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse {}: {message}", path.display())]
+    Parse { path: PathBuf, message: String },
+    #[error("environment variable `{name}` referenced in {} is not set", path.display())]
+    MissingEnv { name: String, path: PathBuf },
+    #[error("failed to read secret file {} referenced in {}: {source}", file.display(), path.display())]
+    SecretFile {
+        file: PathBuf,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid reference in {}: {message}", path.display())]
+    Reference { path: PathBuf, message: String },
+    #[error("{} includes itself", path.display())]
+    IncludeCycle { path: PathBuf },
+    #[error("`{key}` is set in more than one file, last in {}", path.display())]
+    Conflict { key: String, path: PathBuf },
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+impl Config {
+    /// Read the configuration from `path`, following includes and resolving
+    /// secret references.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigLoadError> {
+        let value = load_file(path.as_ref(), &mut Vec::new())?;
+        serde_yml::from_value(value).map_err(|e| ConfigLoadError::Invalid(e.to_string()))
+    }
+}
+
+/// Load one file with its includes; `stack` holds the files being loaded.
+fn load_file(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Value, ConfigLoadError> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|source| ConfigLoadError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+    if stack.contains(&canonical) {
+        return Err(ConfigLoadError::IncludeCycle {
+            path: path.to_owned(),
+        });
+    }
+    let text = std::fs::read_to_string(path).map_err(|source| ConfigLoadError::Read {
+        path: path.to_owned(),
+        source,
+    })?;
+    let mut value: Value = serde_yml::from_str(&text).map_err(|e| ConfigLoadError::Parse {
+        path: path.to_owned(),
+        message: e.to_string(),
+    })?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    interpolate(&mut value, path, dir)?;
+
+    let Value::Mapping(mut mapping) = value else {
+        return Err(ConfigLoadError::Parse {
+            path: path.to_owned(),
+            message: "expected a mapping at the top level".to_owned(),
+        });
+    };
+    let includes = match mapping.remove("include") {
+        None => Vec::new(),
+        Some(Value::String(include)) => vec![include],
+        Some(Value::Sequence(includes)) => includes
+            .into_iter()
+            .map(|include| match include {
+                Value::String(include) => Ok(include),
+                _ => Err(ConfigLoadError::Parse {
+                    path: path.to_owned(),
+                    message: "`include` entries must be file paths".to_owned(),
+                }),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => {
+            return Err(ConfigLoadError::Parse {
+                path: path.to_owned(),
+                message: "`include` must be a file path or a list of them".to_owned(),
+            });
+        }
+    };
+
+    stack.push(canonical);
+    for include in includes {
+        let include_path = dir.join(include);
+        let Value::Mapping(included) = load_file(&include_path, stack)? else {
+            unreachable!("load_file returns mappings");
+        };
+        merge(&mut mapping, included, &include_path)?;
+    }
+    stack.pop();
+    Ok(Value::Mapping(mapping))
+}
+
+/// Append the list sections of `included` to `mapping` and copy its other
+/// keys, which must not be set yet.
+fn merge(mapping: &mut Mapping, included: Mapping, path: &Path) -> Result<(), ConfigLoadError> {
+    for (key, value) in included {
+        match (mapping.get_mut(&key), value) {
+            (None, value) => {
+                mapping.insert(key, value);
+            }
+            (Some(Value::Sequence(existing)), Value::Sequence(items)) => existing.extend(items),
+            (Some(_), _) => {
+                return Err(ConfigLoadError::Conflict {
+                    key: key.as_str().unwrap_or_default().to_owned(),
+                    path: path.to_owned(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolve references in every string of `value`.
+fn interpolate(value: &mut Value, path: &Path, dir: &Path) -> Result<(), ConfigLoadError> {
+    match value {
+        Value::String(text) if text.contains('$') => *text = resolve(text, path, dir)?,
+        Value::Sequence(items) => {
+            for item in items {
+                interpolate(item, path, dir)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                interpolate(item, path, dir)?;
+            }
+        }
+        Value::Tagged(tagged) => interpolate(&mut tagged.value, path, dir)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn resolve(text: &str, path: &Path, dir: &Path) -> Result<String, ConfigLoadError> {
+    let mut resolved = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        resolved.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            resolved.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| ConfigLoadError::Reference {
+                path: path.to_owned(),
+                message: format!("unterminated `${{` in '{text}'"),
+            })?;
+            resolved.push_str(&lookup(&after[..end], path, dir)?);
+            rest = &after[end + 1..];
+        } else {
+            resolved.push('$');
+            rest = &rest[1..];
+        }
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+fn lookup(reference: &str, path: &Path, dir: &Path) -> Result<String, ConfigLoadError> {
+    if let Some(file) = reference.strip_prefix("file:") {
+        let file = dir.join(file.trim());
+        let contents =
+            std::fs::read_to_string(&file).map_err(|source| ConfigLoadError::SecretFile {
+                file: file.clone(),
+                path: path.to_owned(),
+                source,
+            })?;
+        return Ok(contents.trim_end_matches(['\r', '\n']).to_owned());
+    }
+    let name = reference.trim();
+    if name.is_empty() {
+        return Err(ConfigLoadError::Reference {
+            path: path.to_owned(),
+            message: "empty `${}`".to_owned(),
+        });
+    }
+    std::env::var(name).map_err(|_| ConfigLoadError::MissingEnv {
+        name: name.to_owned(),
+        path: path.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    const PROVIDER: &str = "
+providers:
+  - type: DeepSeek
+    name: deepseek
+    api_key: ${NEKOBOT_TEST_DEEPSEEK_KEY}
+    models:
+      - model: deepseek-chat
+";
+
+    #[test]
+    fn resolves_references_and_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        // SAFETY: no other test reads or writes this variable.
+        unsafe { std::env::set_var("NEKOBOT_TEST_DEEPSEEK_KEY", "sk-from-env") };
+        write(dir.path(), "secret.txt", "qq-secret\n");
+        write(dir.path(), "providers.yaml", PROVIDER);
+        write(
+            dir.path(),
+            "agents.yaml",
+            "agents:
+  - name: Neko
+    provider: deepseek
+    model: deepseek-chat
+    middlewares: []
+    system_prompt: costs $$5, not $${HOME}
+",
+        );
+        let main = write(
+            dir.path(),
+            "config.yaml",
+            "include: [providers.yaml, agents.yaml]
+channels:
+  - type: QQ
+    name: qq
+    app_id: app
+    client_secret: ${file:secret.txt}
+providers: []
+agents: []
+",
+        );
+
+        let config = Config::load(&main).unwrap();
+        match &config.providers[0] {
+            super::super::ProviderConfig::DeepSeek { api_key, .. } => {
+                assert_eq!(api_key, "sk-from-env")
+            }
+            other => panic!("unexpected provider {other:?}"),
+        }
+        match &config.channels[0] {
+            super::super::ChannelConfig::QQ { client_secret, .. } => {
+                assert_eq!(client_secret, "qq-secret")
+            }
+            other => panic!("unexpected channel {other:?}"),
+        }
+        assert_eq!(
+            config.agents[0].system_prompt,
+            Some(super::super::SystemPromptConfig::Inline(
+                "costs $$5, not ${HOME}".to_owned()
+            ))
+        );
+
+        let debug = format!("{config:?}");
+        assert!(!debug.contains("sk-from-env"));
+        assert!(!debug.contains("qq-secret"));
+    }
+
+    #[test]
+    fn middleware_and_login_secrets_stay_out_of_debug_output() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "token.txt", "webhook-token\n");
+        let main = write(
+            dir.path(),
+            "config.yaml",
+            "channels: []
+providers: []
+agents:
+  - name: Neko
+    provider: deepseek
+    model: deepseek-chat
+    middlewares:
+      - name: webhook
+        url: https://example.test/hook
+        auth:
+          bearer_token: ${file:token.txt}
+login:
+  totp_secret: ${file:token.txt}
+",
+        );
+
+        let config = Config::load(&main).unwrap();
+        let middleware = &config.agents[0].middlewares[0];
+        assert_eq!(middleware.data["auth"]["bearer_token"], "webhook-token");
+        let debug = format!("{config:?}");
+        assert!(!debug.contains("webhook-token"));
+        assert!(debug.contains("https://example.test/hook"));
+    }
+
+    #[test]
+    fn names_the_missing_variable() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(
+            dir.path(),
+            "config.yaml",
+            "channels: []\nproviders: []\nagents: []\npassword_hash: ${NEKOBOT_TEST_UNSET_VARIABLE}\n",
+        );
+        let error = Config::load(&main).unwrap_err();
+        assert!(matches!(
+            &error,
+            ConfigLoadError::MissingEnv { name, .. } if name == "NEKOBOT_TEST_UNSET_VARIABLE"
+        ));
+        assert!(error.to_string().contains("NEKOBOT_TEST_UNSET_VARIABLE"));
+    }
+
+    #[test]
+    fn rejects_include_cycles_and_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "include: b.yaml\nagents: []\n");
+        write(dir.path(), "b.yaml", "include: a.yaml\n");
+        assert!(matches!(
+            Config::load(dir.path().join("a.yaml")),
+            Err(ConfigLoadError::IncludeCycle { .. })
+        ));
+
+        write(
+            dir.path(),
+            "c.yaml",
+            "include: d.yaml\ndatabase_path: a.db\n",
+        );
+        write(dir.path(), "d.yaml", "database_path: b.db\n");
+        assert!(matches!(
+            Config::load(dir.path().join("c.yaml")),
+            Err(ConfigLoadError::Conflict { key, .. }) if key == "database_path"
+        ));
+    }
+}
@@ -0,0 +1,272 @@
+//! Checks of the configuration against the registered factories, and the
+//! JSON Schema of `config.yaml`.
+//!
+//! [`Config::validate`] only looks at the configuration itself. Whether a
+//! channel or provider type has a factory, a middleware name is known, its
+//! settings fit the middleware's config type and its factory accepts them
+//! depends on what the binary registered, so those checks live here and run
+//! before anything starts.
+
+use serde_json::{Value, json};
+
+use super::{CassetteMode, Config, ProviderConfig};
+use crate::{
+    agent::MiddlewareRegistry, channel_registry::ChannelRegistry, provider::ProviderRegistry,
+};
+
+/// One problem found in the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Where the problem is, e.g. `agents[0].middlewares[1].max_results`.
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Every problem found by [`check`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid configuration:{}", issues.iter().map(|issue| format!("\n  {issue}")).collect::<String>())]
+pub struct ConfigCheckError {
+    pub issues: Vec<ConfigIssue>,
+}
+
+/// Check that every channel and provider type has a registered factory, and
+/// that every middleware is registered, its settings match its config type
+/// and its factory accepts them.
+///
+/// Each middleware factory is run once and the instance dropped, so settings
+/// the factory rejects, such as a pattern that does not compile, are reported
+/// here rather than when the first session starts.
+pub fn check(
+    config: &Config,
+    middlewares: &MiddlewareRegistry,
+    providers: &ProviderRegistry,
+    channels: &ChannelRegistry,
+) -> Result<(), ConfigCheckError> {
+    let mut issues = Vec::new();
+    for (index, channel) in config.channels.iter().enumerate() {
+        if !channels.contains(channel.type_name()) {
+            issues.push(ConfigIssue {
+                path: format!("channels[{index}].type"),
+                message: format!(
+                    "no channel factory registered for '{}'",
+                    channel.type_name()
+                ),
+            });
+        }
+    }
+    for (index, provider) in config.providers.iter().enumerate() {
+        check_provider(
+            provider,
+            format!("providers[{index}]"),
+            providers,
+            &mut issues,
+        );
+    }
+    for (agent_index, agent) in config.agents.iter().enumerate() {
+        for (index, middleware) in agent.middlewares.iter().enumerate() {
+            let path = format!("agents[{agent_index}].middlewares[{index}]");
+            if !middlewares.contains(&middleware.name) {
+                issues.push(ConfigIssue {
+                    path: format!("{path}.name"),
+                    message: format!(
+                        "unknown middleware '{}'; known: {}",
+                        middleware.name,
+                        middlewares.names().join(", ")
+                    ),
+                });
+            } else if let Err(error) = middlewares.check(middleware) {
+                issues.push(ConfigIssue {
+                    path: if error.path.is_empty() {
+                        path
+                    } else {
+                        format!("{path}.{}", error.path)
+                    },
+                    message: error.message,
+                });
+            } else if let Err(error) = middlewares.create(middleware) {
+                issues.push(ConfigIssue {
+                    path,
+                    message: format!("{error:#}"),
+                });
+            }
+        }
+    }
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigCheckError { issues })
+    }
+}
+
+fn check_provider(
+    provider: &ProviderConfig,
+    path: String,
+    providers: &ProviderRegistry,
+    issues: &mut Vec<ConfigIssue>,
+) {
+    if !providers.contains(provider.type_name()) {
+        issues.push(ConfigIssue {
+            path: format!("{path}.type"),
+            message: format!(
+                "no provider factory registered for '{}'",
+                provider.type_name()
+            ),
+        });
+    }
+    // Replaying never creates the wrapped provider
+    if let ProviderConfig::Cassette {
+        mode: CassetteMode::Record,
+        provider,
+        ..
+    } = provider
+    {
+        check_provider(provider, format!("{path}.provider"), providers, issues);
+    }
+}
+
+/// JSON Schema of `config.yaml`, with the settings of every registered
+/// middleware, for editor completion and validation.
+pub fn schema(middlewares: &MiddlewareRegistry) -> Value {
+    let mut generator = schemars::SchemaGenerator::default();
+    let variants: Vec<Value> = middlewares
+        .names()
+        .into_iter()
+        .map(|name| {
+            let named = json!({
+                "type": "object",
+                "properties": { "name": { "const": name } },
+                "required": ["name"],
+            });
+            match middlewares.config_type(name) {
+                Some(config_type) => json!({
+                    "allOf": [named, Value::from((config_type.schema)(&mut generator))],
+                }),
+                None => named,
+            }
+        })
+        .collect();
+
+    let mut root = Value::from(generator.into_root_schema_for::<Config>());
+    if !variants.is_empty() {
+        root["$defs"]["MiddlewareConfig"] = json!({
+            "description": "A middleware, selected by `name`, with its settings.",
+            "oneOf": variants,
+        });
+    }
+    root["properties"]["include"] = json!({
+        "description": "Further YAML files whose list sections are appended to this file's.",
+        "anyOf": [
+            { "type": "string" },
+            { "type": "array", "items": { "type": "string" } },
+        ],
+    });
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::agent::middleware::Middleware;
+
+    struct Noop;
+
+    #[async_trait::async_trait]
+    impl Middleware for Noop {}
+
+    #[derive(Deserialize, schemars::JsonSchema)]
+    struct LimitConfig {
+        /// Largest allowed value.
+        max: usize,
+    }
+
+    fn registries() -> (MiddlewareRegistry, ProviderRegistry, ChannelRegistry) {
+        let mut middlewares = MiddlewareRegistry::new();
+        middlewares
+            .register_with_config::<LimitConfig, _>("limit", |config| {
+                anyhow::ensure!(config.max <= 10, "max is too large");
+                Ok(Arc::new(Noop) as Arc<dyn Middleware>)
+            })
+            .unwrap();
+        middlewares
+            .register("plain", |_| Ok(Arc::new(Noop) as Arc<dyn Middleware>))
+            .unwrap();
+        (middlewares, ProviderRegistry::new(), ChannelRegistry::new())
+    }
+
+    fn config() -> Config {
+        serde_json::from_value(json!({
+            "channels": [],
+            "providers": [{
+                "type": "DeepSeek",
+                "name": "deepseek",
+                "api_key": "sk-test",
+                "models": [{ "model": "deepseek-chat" }]
+            }],
+            "agents": [{
+                "name": "Neko",
+                "provider": "deepseek",
+                "model": "deepseek-chat",
+                "middlewares": [
+                    { "name": "plain", "anything": true },
+                    { "name": "limit", "max": "lots" },
+                    { "name": "limit" },
+                    { "name": "limmit", "max": 1 },
+                    { "name": "limit", "max": 100 },
+                    { "name": "limit", "max": 1 }
+                ]
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn reports_every_issue_with_its_path() {
+        let (middlewares, providers, channels) = registries();
+        let error = check(&config(), &middlewares, &providers, &channels).unwrap_err();
+        let paths: Vec<&str> = error.issues.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "providers[0].type",
+                "agents[0].middlewares[1].max",
+                "agents[0].middlewares[2]",
+                "agents[0].middlewares[3].name",
+                "agents[0].middlewares[4]",
+            ]
+        );
+        assert!(error.issues[2].message.contains("missing field `max`"));
+        assert!(error.issues[3].message.contains("known: limit, plain"));
+        assert!(error.issues[4].message.contains("max is too large"));
+    }
+
+    #[test]
+    fn schema_describes_registered_middlewares() {
+        let (middlewares, _, _) = registries();
+        let schema = schema(&middlewares);
+        let variants = schema["$defs"]["MiddlewareConfig"]["oneOf"]
+            .as_array()
+            .unwrap();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(
+            variants[0]["allOf"][0]["properties"]["name"]["const"],
+            "limit"
+        );
+        assert_eq!(
+            schema["$defs"]["LimitConfig"]["properties"]["max"]["description"],
+            "Largest allowed value."
+        );
+        assert!(schema["properties"]["agents"].is_object());
+        assert!(schema["properties"]["include"].is_object());
+    }
+}
@@ -95,6 +95,16 @@ pub struct ChannelChatAgent {
     pub session_id: SessionId,
 }
 
+/// A chat the bot has talked in, regardless of which agents it is bound to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownChat {
+    pub channel_id: ChannelId,
+    pub channel_name: ChannelName,
+    pub chat_id: ChatId,
+    pub chat_name: ChatName,
+    pub reply_target: ReplyTarget,
+}
+
 /// Data needed to insert a new [`ChannelChatAgent`] row.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NewChannelChatAgent {
@@ -183,6 +193,93 @@ impl ChannelChatAgent {
             .transpose()
     }
 
+    /// Return the sessions of a channel with pending
+    /// [`Schedule`](crate::entity::schedule::Schedule)s, each with the mapping
+    /// of its chat. The session is the mapping's current one or an earlier
+    /// conversation of the chat.
+    pub async fn list_scheduled(
+        conn: &Connection,
+        channel_id: &ChannelId,
+    ) -> anyhow::Result<Vec<(Self, SessionId)>> {
+        let mut rows = conn
+            .query(
+                "SELECT m.id, m.channel_id, m.channel_name, m.chat_id, m.chat_name, m.reply_target,
+                        m.agent_name, m.session_id, s.session_id
+                    FROM channel_chat_agents m
+                    JOIN (SELECT channel_chat_agent_id AS mapping_id, session_id FROM chat_sessions
+                          UNION SELECT id, session_id FROM channel_chat_agents) s
+                        ON s.mapping_id = m.id
+                    WHERE m.channel_id = ?1
+                        AND s.session_id IN (SELECT session_id FROM schedules)
+                    ORDER BY m.id, s.session_id",
+                (channel_id.as_str(),),
+            )
+            .await?;
+
+        let mut scheduled = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let session_id: i64 = row.get(8)?;
+            scheduled.push((Self::from_row(&row)?, SessionId::from(session_id)));
+        }
+        Ok(scheduled)
+    }
+
+    /// Return the mappings of a chat, one per agent bound to it.
+    pub async fn list_by_chat(
+        conn: &Connection,
+        channel_id: &ChannelId,
+        chat_id: &ChatId,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT id, channel_id, channel_name, chat_id, chat_name, reply_target, agent_name, session_id
+                    FROM channel_chat_agents
+                    WHERE channel_id = ?1 AND chat_id = ?2
+                    ORDER BY id",
+                (channel_id.as_str(), chat_id.as_str()),
+            )
+            .await?;
+
+        let mut mappings = Vec::new();
+        while let Some(row) = rows.next().await? {
+            mappings.push(Self::from_row(&row)?);
+        }
+        Ok(mappings)
+    }
+
+    /// Return every known chat once, using the most recently created
+    /// mapping's cached names and reply target.
+    pub async fn list_chats(conn: &Connection) -> anyhow::Result<Vec<KnownChat>> {
+        let mut rows = conn
+            .query(
+                "SELECT channel_id, channel_name, chat_id, chat_name, reply_target
+                    FROM channel_chat_agents
+                    WHERE id IN (
+                        SELECT MAX(id) FROM channel_chat_agents GROUP BY channel_id, chat_id
+                    )
+                    ORDER BY channel_id, chat_id",
+                (),
+            )
+            .await?;
+
+        let mut chats = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let channel_id: String = row.get(0)?;
+            let channel_name: String = row.get(1)?;
+            let chat_id: String = row.get(2)?;
+            let chat_name: String = row.get(3)?;
+            let reply_target: String = row.get(4)?;
+            chats.push(KnownChat {
+                channel_id: channel_id.into(),
+                channel_name: channel_name.into(),
+                chat_id: chat_id.into(),
+                chat_name: chat_name.into(),
+                reply_target: reply_target.into(),
+            });
+        }
+        Ok(chats)
+    }
+
     /// Update the cached channel/chat metadata and reply target for a mapping.
     pub async fn update_chat_cache(
         conn: &Connection,
@@ -212,6 +309,26 @@ impl ChannelChatAgent {
         Self::get_by_id(conn, id).await
     }
 
+    /// Point a mapping at another session, making it the current conversation.
+    pub async fn set_session(
+        conn: &Connection,
+        id: ChannelChatAgentId,
+        session_id: SessionId,
+    ) -> anyhow::Result<Option<Self>> {
+        let changed = conn
+            .execute(
+                "UPDATE channel_chat_agents SET session_id = ?1 WHERE id = ?2",
+                (session_id.as_i64(), id.as_i64()),
+            )
+            .await?;
+
+        if changed == 0 {
+            return Ok(None);
+        }
+
+        Self::get_by_id(conn, id).await
+    }
+
     /// Look up a mapping by its primary key.
     pub async fn get_by_id(
         conn: &Connection,
@@ -255,25 +372,24 @@ impl ChannelChatAgent {
     }
 }
 
+/// Schema of the `channel_chat_agents` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS channel_chat_agents (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    channel_id TEXT NOT NULL,
+    channel_name TEXT NOT NULL,
+    chat_id TEXT NOT NULL,
+    chat_name TEXT NOT NULL,
+    reply_target TEXT NOT NULL,
+    agent_name TEXT NOT NULL,
+    session_id INTEGER NOT NULL UNIQUE,
+    UNIQUE(channel_id, chat_id, agent_name),
+    FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+)";
+
 impl Entity for ChannelChatAgent {
     async fn create_table(conn: &Connection) -> anyhow::Result<()> {
         enable_foreign_keys(conn).await?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS channel_chat_agents (
-                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                    channel_id TEXT NOT NULL,
-                    channel_name TEXT NOT NULL,
-                    chat_id TEXT NOT NULL,
-                    chat_name TEXT NOT NULL,
-                    reply_target TEXT NOT NULL,
-                    agent_name TEXT NOT NULL,
-                    session_id INTEGER NOT NULL UNIQUE,
-                    UNIQUE(channel_id, chat_id, agent_name),
-                    FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
-                )",
-            (),
-        )
-        .await?;
+        conn.execute(CREATE_TABLE, ()).await?;
         Ok(())
     }
 }
@@ -335,4 +451,34 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn list_chats_returns_each_chat_once() -> anyhow::Result<()> {
+        let (conn, neko_session) = connection().await?;
+        let mimi_session = Session::create(&conn, "Mimi").await?;
+        for (agent_name, session, reply_target) in [
+            ("Neko", &neko_session, "old-target"),
+            ("Mimi", &mimi_session, "new-target"),
+        ] {
+            ChannelChatAgent::create(
+                &conn,
+                NewChannelChatAgent {
+                    channel_id: ChannelId::from("qq-main"),
+                    channel_name: ChannelName::from("QQ"),
+                    chat_id: ChatId::from("group-1"),
+                    chat_name: ChatName::from("Ops"),
+                    reply_target: ReplyTarget::from(reply_target),
+                    agent_name: AgentName::from(agent_name),
+                    session_id: SessionId::from(session.id),
+                },
+            )
+            .await?;
+        }
+
+        let chats = ChannelChatAgent::list_chats(&conn).await?;
+        assert_eq!(chats.len(), 1);
+        assert_eq!(chats[0].chat_name, ChatName::from("Ops"));
+        assert_eq!(chats[0].reply_target, ReplyTarget::from("new-target"));
+        Ok(())
+    }
 }
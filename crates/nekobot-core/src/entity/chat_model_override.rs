@@ -0,0 +1,228 @@
+//! Chat model override entity — per-chat model and sampling parameters that
+//! take precedence over the agent's configured [`ModelOptions`].
+
+use turso::Connection;
+
+use crate::{
+    entity::{Entity, channel_chat_agent::ChannelChatAgentId, enable_foreign_keys},
+    provider::ModelOptions,
+};
+
+/// Overrides for one channel-chat-agent mapping. `None` fields keep the
+/// configured value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatModelOverride {
+    pub channel_chat_agent_id: ChannelChatAgentId,
+    /// Name of another model declared by the agent's provider.
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_output_tokens: Option<u32>,
+}
+
+impl ChatModelOverride {
+    /// An override that changes nothing.
+    pub fn new(channel_chat_agent_id: ChannelChatAgentId) -> Self {
+        Self {
+            channel_chat_agent_id,
+            model: None,
+            temperature: None,
+            top_p: None,
+            max_output_tokens: None,
+        }
+    }
+
+    /// Returns `true` if no field is overridden.
+    pub fn is_empty(&self) -> bool {
+        self.model.is_none()
+            && self.temperature.is_none()
+            && self.top_p.is_none()
+            && self.max_output_tokens.is_none()
+    }
+
+    /// Resolve the effective options on top of `base`.
+    ///
+    /// The model is switched only if `available` declares it, so its
+    /// capabilities come from the provider config rather than from the user.
+    pub fn apply(&self, base: &ModelOptions, available: &[ModelOptions]) -> ModelOptions {
+        let mut options = self
+            .model
+            .as_deref()
+            .and_then(|name| available.iter().find(|m| m.model.as_deref() == Some(name)))
+            .unwrap_or(base)
+            .clone();
+        if let Some(temperature) = self.temperature {
+            options.temperature = Some(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            options.top_p = Some(top_p);
+        }
+        if let Some(max_output_tokens) = self.max_output_tokens {
+            options.max_output_tokens = Some(max_output_tokens);
+        }
+        options
+    }
+
+    fn from_row(row: &turso::Row) -> anyhow::Result<Self> {
+        let channel_chat_agent_id: i64 = row.get(0)?;
+        let temperature: Option<f64> = row.get(2)?;
+        let top_p: Option<f64> = row.get(3)?;
+        let max_output_tokens: Option<i64> = row.get(4)?;
+        Ok(Self {
+            channel_chat_agent_id: channel_chat_agent_id.into(),
+            model: row.get(1)?,
+            temperature: temperature.map(|v| v as f32),
+            top_p: top_p.map(|v| v as f32),
+            max_output_tokens: max_output_tokens.map(u32::try_from).transpose()?,
+        })
+    }
+
+    /// Look up the override for a mapping, or return `None` if there is none.
+    pub async fn get(
+        conn: &Connection,
+        channel_chat_agent_id: ChannelChatAgentId,
+    ) -> anyhow::Result<Option<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT channel_chat_agent_id, model, temperature, top_p, max_output_tokens
+                    FROM chat_model_overrides
+                    WHERE channel_chat_agent_id = ?1",
+                (channel_chat_agent_id.as_i64(),),
+            )
+            .await?;
+
+        rows.next()
+            .await?
+            .map(|row| Self::from_row(&row))
+            .transpose()
+    }
+
+    /// Insert or replace this override. An empty override deletes the row.
+    pub async fn upsert(&self, conn: &Connection) -> anyhow::Result<()> {
+        if self.is_empty() {
+            Self::delete(conn, self.channel_chat_agent_id).await?;
+            return Ok(());
+        }
+        conn.execute(
+            "INSERT OR REPLACE INTO chat_model_overrides
+                (channel_chat_agent_id, model, temperature, top_p, max_output_tokens)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                self.channel_chat_agent_id.as_i64(),
+                self.model.as_deref(),
+                self.temperature.map(f64::from),
+                self.top_p.map(f64::from),
+                self.max_output_tokens.map(i64::from),
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Delete the override for a mapping; returns true if a row was removed.
+    pub async fn delete(
+        conn: &Connection,
+        channel_chat_agent_id: ChannelChatAgentId,
+    ) -> anyhow::Result<bool> {
+        let changed = conn
+            .execute(
+                "DELETE FROM chat_model_overrides WHERE channel_chat_agent_id = ?1",
+                (channel_chat_agent_id.as_i64(),),
+            )
+            .await?;
+
+        Ok(changed > 0)
+    }
+}
+
+/// Schema of the `chat_model_overrides` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS chat_model_overrides (
+    channel_chat_agent_id INTEGER PRIMARY KEY,
+    model TEXT,
+    temperature REAL,
+    top_p REAL,
+    max_output_tokens INTEGER,
+    FOREIGN KEY(channel_chat_agent_id) REFERENCES channel_chat_agents(id) ON DELETE CASCADE
+)";
+
+impl Entity for ChatModelOverride {
+    async fn create_table(conn: &Connection) -> anyhow::Result<()> {
+        enable_foreign_keys(conn).await?;
+        conn.execute(CREATE_TABLE, ()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nekobot_channel::{ChannelId, ChannelName, ChatId, ChatName, ReplyTarget};
+
+    use super::*;
+    use crate::{
+        entity::{
+            channel_chat_agent::{AgentName, ChannelChatAgent, NewChannelChatAgent, SessionId},
+            session::Session,
+        },
+        provider::ModelCapabilities,
+    };
+
+    fn model(name: &str, tools: bool) -> ModelOptions {
+        ModelOptions {
+            model: Some(name.to_owned()),
+            capabilities: ModelCapabilities {
+                tools,
+                ..ModelCapabilities::default()
+            },
+            ..ModelOptions::default()
+        }
+    }
+
+    #[test]
+    fn apply_switches_only_to_declared_models() {
+        let base = model("deepseek-chat", true);
+        let available = [base.clone(), model("deepseek-reasoner", false)];
+        let mut overrides = ChatModelOverride::new(ChannelChatAgentId::from(1));
+        overrides.model = Some("deepseek-reasoner".to_owned());
+        overrides.temperature = Some(0.5);
+
+        let options = overrides.apply(&base, &available);
+        assert_eq!(options.model.as_deref(), Some("deepseek-reasoner"));
+        assert!(!options.capabilities.tools);
+        assert_eq!(options.temperature, Some(0.5));
+
+        overrides.model = Some("unknown".to_owned());
+        assert_eq!(overrides.apply(&base, &available).model.as_deref(), Some("deepseek-chat"));
+    }
+
+    #[tokio::test]
+    async fn upsert_get_and_clear() -> anyhow::Result<()> {
+        let conn = crate::entity::test_connection().await?;
+        Session::create_table(&conn).await?;
+        ChannelChatAgent::create_table(&conn).await?;
+        ChatModelOverride::create_table(&conn).await?;
+        let session = Session::create(&conn, "Neko").await?;
+        let mapping = ChannelChatAgent::create(
+            &conn,
+            NewChannelChatAgent {
+                channel_id: ChannelId::from("qq-main"),
+                channel_name: ChannelName::from("QQ"),
+                chat_id: ChatId::from("chat-1"),
+                chat_name: ChatName::from("Alice"),
+                reply_target: ReplyTarget::from("target-1"),
+                agent_name: AgentName::from("Neko"),
+                session_id: SessionId::from(session.id),
+            },
+        )
+        .await?;
+
+        let mut overrides = ChatModelOverride::new(mapping.id);
+        overrides.model = Some("deepseek-reasoner".to_owned());
+        overrides.max_output_tokens = Some(2048);
+        overrides.upsert(&conn).await?;
+        assert_eq!(ChatModelOverride::get(&conn, mapping.id).await?, Some(overrides));
+
+        ChatModelOverride::new(mapping.id).upsert(&conn).await?;
+        assert_eq!(ChatModelOverride::get(&conn, mapping.id).await?, None);
+        Ok(())
+    }
+}
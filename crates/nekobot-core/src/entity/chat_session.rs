@@ -0,0 +1,163 @@
+//! Chat session entity — every conversation a channel-chat-agent mapping has had.
+//!
+//! [`ChannelChatAgent::session_id`](crate::entity::channel_chat_agent::ChannelChatAgent)
+//! points at the current conversation; this table keeps the older ones so
+//! they can be listed and resumed.
+
+use turso::Connection;
+
+use crate::entity::{
+    Entity,
+    channel_chat_agent::{ChannelChatAgentId, SessionId},
+    collect_rows, enable_foreign_keys,
+};
+
+/// A conversation that belongs to a channel-chat-agent mapping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatSession {
+    pub session_id: SessionId,
+    pub channel_chat_agent_id: ChannelChatAgentId,
+    pub title: Option<String>,
+    /// Creation time in seconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+impl ChatSession {
+    /// Record `session_id` as a conversation of the mapping. Recording the
+    /// same session twice keeps the first row.
+    pub async fn record(
+        conn: &Connection,
+        channel_chat_agent_id: ChannelChatAgentId,
+        session_id: SessionId,
+        title: Option<String>,
+    ) -> anyhow::Result<()> {
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        conn.execute(
+            "INSERT OR IGNORE INTO chat_sessions
+                (session_id, channel_chat_agent_id, title, created_at)
+                VALUES (?1, ?2, ?3, ?4)",
+            (
+                session_id.as_i64(),
+                channel_chat_agent_id.as_i64(),
+                title.as_deref(),
+                created_at,
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Look up a conversation by its session id.
+    pub async fn get(conn: &Connection, session_id: SessionId) -> anyhow::Result<Option<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT session_id, channel_chat_agent_id, title, created_at
+                    FROM chat_sessions WHERE session_id = ?1",
+                (session_id.as_i64(),),
+            )
+            .await?;
+
+        rows.next()
+            .await?
+            .map(|row| Self::from_row(&row))
+            .transpose()
+    }
+
+    /// Return every conversation of a mapping, oldest first.
+    pub async fn list_by_channel_chat_agent(
+        conn: &Connection,
+        channel_chat_agent_id: ChannelChatAgentId,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT session_id, channel_chat_agent_id, title, created_at
+                    FROM chat_sessions WHERE channel_chat_agent_id = ?1
+                    ORDER BY session_id",
+                (channel_chat_agent_id.as_i64(),),
+            )
+            .await?;
+        Self::collect_rows(&mut rows).await
+    }
+
+    collect_rows!(ChatSession);
+
+    fn from_row(row: &turso::Row) -> anyhow::Result<Self> {
+        let session_id: i64 = row.get(0)?;
+        let channel_chat_agent_id: i64 = row.get(1)?;
+        Ok(Self {
+            session_id: session_id.into(),
+            channel_chat_agent_id: channel_chat_agent_id.into(),
+            title: row.get(2)?,
+            created_at: row.get(3)?,
+        })
+    }
+}
+
+/// Schema of the `chat_sessions` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS chat_sessions (
+    session_id INTEGER PRIMARY KEY,
+    channel_chat_agent_id INTEGER NOT NULL,
+    title TEXT,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE,
+    FOREIGN KEY(channel_chat_agent_id) REFERENCES channel_chat_agents(id) ON DELETE CASCADE
+)";
+
+impl Entity for ChatSession {
+    async fn create_table(conn: &Connection) -> anyhow::Result<()> {
+        enable_foreign_keys(conn).await?;
+        conn.execute(CREATE_TABLE, ()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nekobot_channel::{ChannelId, ChannelName, ChatId, ChatName, ReplyTarget};
+
+    use super::*;
+    use crate::entity::{
+        channel_chat_agent::{AgentName, ChannelChatAgent, NewChannelChatAgent},
+        session::Session,
+    };
+
+    #[tokio::test]
+    async fn records_and_lists_conversations() -> anyhow::Result<()> {
+        let conn = crate::entity::test_connection().await?;
+        Session::create_table(&conn).await?;
+        ChannelChatAgent::create_table(&conn).await?;
+        ChatSession::create_table(&conn).await?;
+        let first = Session::create(&conn, "Neko").await?;
+        let second = Session::create(&conn, "Neko").await?;
+        let mapping = ChannelChatAgent::create(
+            &conn,
+            NewChannelChatAgent {
+                channel_id: ChannelId::from("qq-main"),
+                channel_name: ChannelName::from("QQ"),
+                chat_id: ChatId::from("chat-1"),
+                chat_name: ChatName::from("Alice"),
+                reply_target: ReplyTarget::from("target-1"),
+                agent_name: AgentName::from("Neko"),
+                session_id: SessionId::from(first.id),
+            },
+        )
+        .await?;
+
+        ChatSession::record(&conn, mapping.id, SessionId::from(first.id), None).await?;
+        ChatSession::record(&conn, mapping.id, SessionId::from(second.id), Some("work".into()))
+            .await?;
+        ChatSession::record(&conn, mapping.id, SessionId::from(first.id), Some("dup".into()))
+            .await?;
+
+        let sessions = ChatSession::list_by_channel_chat_agent(&conn, mapping.id).await?;
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].session_id, SessionId::from(first.id));
+        assert_eq!(sessions[0].title, None);
+        assert_eq!(sessions[1].title.as_deref(), Some("work"));
+        assert!(ChatSession::get(&conn, SessionId::from(999)).await?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Group binding entity — the agent an admin bound a group chat to with
+//! `/bind`.
+
+use turso::Connection;
+
+use crate::entity::{Entity, enable_foreign_keys};
+
+/// The agent serving a group chat.
+///
+/// Composite primary key is `(channel_id, chat_id)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupBinding {
+    pub channel_id: String,
+    pub chat_id: String,
+    pub agent_name: String,
+    /// Sender ID of the admin who bound the group.
+    pub bound_by: String,
+    pub bound_at: i64,
+}
+
+impl GroupBinding {
+    fn from_row(row: &turso::Row) -> anyhow::Result<Self> {
+        Ok(Self {
+            channel_id: row.get(0)?,
+            chat_id: row.get(1)?,
+            agent_name: row.get(2)?,
+            bound_by: row.get(3)?,
+            bound_at: row.get(4)?,
+        })
+    }
+
+    /// Look up the binding of a group chat.
+    pub async fn get(
+        conn: &Connection,
+        channel_id: &str,
+        chat_id: &str,
+    ) -> anyhow::Result<Option<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT channel_id, chat_id, agent_name, bound_by, bound_at
+                 FROM group_bindings WHERE channel_id = ?1 AND chat_id = ?2",
+                (channel_id, chat_id),
+            )
+            .await?;
+        rows.next()
+            .await?
+            .map(|row| Self::from_row(&row))
+            .transpose()
+    }
+
+    /// Insert or replace the binding.
+    pub async fn upsert(&self, conn: &Connection) -> anyhow::Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO group_bindings
+                 (channel_id, chat_id, agent_name, bound_by, bound_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                self.channel_id.as_str(),
+                self.chat_id.as_str(),
+                self.agent_name.as_str(),
+                self.bound_by.as_str(),
+                self.bound_at,
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Remove the binding of a group chat. Returns `true` if it existed.
+    pub async fn delete(
+        conn: &Connection,
+        channel_id: &str,
+        chat_id: &str,
+    ) -> anyhow::Result<bool> {
+        let changed = conn
+            .execute(
+                "DELETE FROM group_bindings WHERE channel_id = ?1 AND chat_id = ?2",
+                (channel_id, chat_id),
+            )
+            .await?;
+        Ok(changed > 0)
+    }
+}
+
+/// Schema of the `group_bindings` table (migration 4).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS group_bindings (
+    channel_id TEXT NOT NULL,
+    chat_id TEXT NOT NULL,
+    agent_name TEXT NOT NULL,
+    bound_by TEXT NOT NULL,
+    bound_at INTEGER NOT NULL,
+    PRIMARY KEY (channel_id, chat_id)
+)";
+
+impl Entity for GroupBinding {
+    async fn create_table(conn: &Connection) -> anyhow::Result<()> {
+        enable_foreign_keys(conn).await?;
+        conn.execute(CREATE_TABLE, ()).await?;
+        Ok(())
+    }
+}
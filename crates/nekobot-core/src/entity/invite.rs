@@ -0,0 +1,116 @@
+//! Invite entity — single-use codes that grant a role to whoever redeems
+//! them first.
+
+use turso::Connection;
+
+use crate::entity::{Entity, enable_foreign_keys};
+
+/// An invite code created by an admin within a channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    pub code: String,
+    pub channel_id: String,
+    pub role: String,
+    /// Sender ID of the admin who created the code.
+    pub created_by: String,
+    pub created_at: i64,
+    /// Unix time after which the code can no longer be redeemed.
+    pub expires_at: i64,
+    pub used_by: Option<String>,
+    pub used_at: Option<i64>,
+}
+
+impl Invite {
+    fn from_row(row: &turso::Row) -> anyhow::Result<Self> {
+        Ok(Self {
+            code: row.get(0)?,
+            channel_id: row.get(1)?,
+            role: row.get(2)?,
+            created_by: row.get(3)?,
+            created_at: row.get(4)?,
+            expires_at: row.get(5)?,
+            used_by: row.get(6)?,
+            used_at: row.get(7)?,
+        })
+    }
+
+    /// Store a new, unused invite.
+    pub async fn create(&self, conn: &Connection) -> anyhow::Result<()> {
+        conn.execute(
+            "INSERT INTO invites
+                 (code, channel_id, role, created_by, created_at, expires_at, used_by, used_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (
+                self.code.as_str(),
+                self.channel_id.as_str(),
+                self.role.as_str(),
+                self.created_by.as_str(),
+                self.created_at,
+                self.expires_at,
+                self.used_by.as_deref(),
+                self.used_at,
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Look up an invite by its code.
+    pub async fn get(conn: &Connection, code: &str) -> anyhow::Result<Option<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT code, channel_id, role, created_by, created_at, expires_at,
+                        used_by, used_at
+                 FROM invites WHERE code = ?1",
+                (code,),
+            )
+            .await?;
+        rows.next()
+            .await?
+            .map(|row| Self::from_row(&row))
+            .transpose()
+    }
+
+    /// Mark the invite of `channel_id` with `code` as used by `sender_id`,
+    /// unless it was used already or expired at `now`. Returns the invite
+    /// if this call redeemed it.
+    pub async fn redeem(
+        conn: &Connection,
+        channel_id: &str,
+        code: &str,
+        sender_id: &str,
+        now: i64,
+    ) -> anyhow::Result<Option<Self>> {
+        let changed = conn
+            .execute(
+                "UPDATE invites SET used_by = ?1, used_at = ?2
+                 WHERE code = ?3 AND channel_id = ?4 AND used_by IS NULL AND expires_at > ?2",
+                (sender_id, now, code, channel_id),
+            )
+            .await?;
+        if changed == 0 {
+            return Ok(None);
+        }
+        Self::get(conn, code).await
+    }
+}
+
+/// Schema of the `invites` table (migration 4).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS invites (
+    code TEXT PRIMARY KEY,
+    channel_id TEXT NOT NULL,
+    role TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    used_by TEXT,
+    used_at INTEGER
+)";
+
+impl Entity for Invite {
+    async fn create_table(conn: &Connection) -> anyhow::Result<()> {
+        enable_foreign_keys(conn).await?;
+        conn.execute(CREATE_TABLE, ()).await?;
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@ use std::fmt::Display;
 
 use turso::Connection;
 
-use crate::entity::{Entity, collect_rows, enable_foreign_keys};
+use crate::entity::{Entity, collect_rows, enable_foreign_keys, migrate_core};
 
 /// A single chat message belonging to a session.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -18,6 +18,8 @@ pub struct Message {
     pub tool_call_id: Option<String>,
     /// Serialized tool calls JSON, used when role is "assistant".
     pub tool_calls: Option<String>,
+    /// Unix timestamp (seconds) of when the message was stored.
+    pub created_at: i64,
 }
 
 impl Message {
@@ -33,10 +35,11 @@ impl Message {
     ) -> anyhow::Result<Self> {
         let role = role.into();
         let content = content.into();
+        let created_at = chrono::Utc::now().timestamp();
 
         conn.execute(
-            "INSERT INTO messages (session_id, role, content, reasoning_content, tool_call_id, tool_calls)
-                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            "INSERT INTO messages (session_id, role, content, reasoning_content, tool_call_id, tool_calls, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
             (
                 session_id,
                 role.as_str(),
@@ -44,6 +47,7 @@ impl Message {
                 reasoning_content.as_deref(),
                 tool_call_id.as_deref(),
                 tool_calls.as_deref(),
+                created_at,
             ),
         )
         .await?;
@@ -56,6 +60,7 @@ impl Message {
             session_id,
             tool_call_id,
             tool_calls,
+            created_at,
         })
     }
 
@@ -63,7 +68,7 @@ impl Message {
     pub async fn get(conn: &Connection, id: i64) -> anyhow::Result<Option<Self>> {
         let mut rows = conn
             .query(
-                "SELECT id, content, reasoning_content, role, session_id, tool_call_id, tool_calls
+                "SELECT id, content, reasoning_content, role, session_id, tool_call_id, tool_calls, created_at
                     FROM messages WHERE id = ?1",
                 (id,),
             )
@@ -75,11 +80,31 @@ impl Message {
             .transpose()
     }
 
+    /// Return the newest message of `role` in a session.
+    pub async fn latest_by_role(
+        conn: &Connection,
+        session_id: i64,
+        role: &str,
+    ) -> anyhow::Result<Option<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT id, content, reasoning_content, role, session_id, tool_call_id, tool_calls, created_at
+                    FROM messages WHERE session_id = ?1 AND role = ?2 ORDER BY id DESC LIMIT 1",
+                (session_id, role),
+            )
+            .await?;
+
+        rows.next()
+            .await?
+            .map(|row| Self::from_row(&row))
+            .transpose()
+    }
+
     /// Return all messages ordered by insertion order.
     pub async fn list(conn: &Connection) -> anyhow::Result<Vec<Self>> {
         let mut rows = conn
             .query(
-                "SELECT id, content, reasoning_content, role, session_id, tool_call_id, tool_calls
+                "SELECT id, content, reasoning_content, role, session_id, tool_call_id, tool_calls, created_at
                     FROM messages ORDER BY rowid",
                 (),
             )
@@ -91,7 +116,7 @@ impl Message {
     pub async fn list_by_session(conn: &Connection, session_id: i64) -> anyhow::Result<Vec<Self>> {
         let mut rows = conn
             .query(
-                "SELECT id, content, reasoning_content, role, session_id, tool_call_id, tool_calls
+                "SELECT id, content, reasoning_content, role, session_id, tool_call_id, tool_calls, created_at
                     FROM messages WHERE session_id = ?1 ORDER BY rowid",
                 (session_id,),
             )
@@ -99,6 +124,117 @@ impl Message {
         Self::collect_rows(&mut rows).await
     }
 
+    /// Return up to `limit` of the newest messages of a session older than
+    /// message `before`, ordered by insertion.
+    ///
+    /// Without `before` this is the tail of the history; passing the id of the
+    /// first returned message pages further back.
+    pub async fn list_page(
+        conn: &Connection,
+        session_id: i64,
+        before: Option<i64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT id, content, reasoning_content, role, session_id, tool_call_id, tool_calls, created_at
+                    FROM messages WHERE session_id = ?1 AND id < ?2 ORDER BY id DESC LIMIT ?3",
+                (
+                    session_id,
+                    before.unwrap_or(i64::MAX),
+                    i64::try_from(limit).unwrap_or(i64::MAX),
+                ),
+            )
+            .await?;
+        let mut messages = Self::collect_rows(&mut rows).await?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// Return the messages of a session that fall outside a retention policy,
+    /// ordered by insertion: those stored before `cutoff` and those beyond
+    /// the newest `keep`.
+    ///
+    /// Whole turns are expired: if the first message left behind does not
+    /// start a turn, the rest of its turn is kept too, so tool replies are
+    /// never separated from the assistant message that called them. Turns
+    /// start with a user message or an `internal` middleware activation.
+    pub async fn list_expired(
+        conn: &Connection,
+        session_id: i64,
+        cutoff: Option<i64>,
+        keep: Option<usize>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT id, content, reasoning_content, role, session_id, tool_call_id, tool_calls, created_at
+                    FROM messages WHERE session_id = ?1 AND (
+                        created_at < ?2
+                        OR id <= (SELECT id FROM messages WHERE session_id = ?1
+                            ORDER BY id DESC LIMIT 1 OFFSET ?3)
+                    ) ORDER BY id",
+                (
+                    session_id,
+                    cutoff.unwrap_or(i64::MIN),
+                    keep.map_or(i64::MAX, |keep| i64::try_from(keep).unwrap_or(i64::MAX)),
+                ),
+            )
+            .await?;
+        let mut expired = Self::collect_rows(&mut rows).await?;
+        let Some(last) = expired.last().map(|m| m.id) else {
+            return Ok(expired);
+        };
+
+        let Some(first_kept) = Self::scalar(
+            conn,
+            "SELECT MIN(id) FROM messages WHERE session_id = ?1 AND id > ?2",
+            session_id,
+            last,
+        )
+        .await?
+        else {
+            return Ok(expired);
+        };
+        let turn_start = Self::scalar(
+            conn,
+            "SELECT MAX(id) FROM messages WHERE session_id = ?1
+                AND role IN ('user', 'internal') AND id <= ?2",
+            session_id,
+            first_kept,
+        )
+        .await?;
+        expired.retain(|m| m.id < turn_start.unwrap_or(i64::MIN));
+        Ok(expired)
+    }
+
+    async fn scalar(
+        conn: &Connection,
+        sql: &str,
+        session_id: i64,
+        id: i64,
+    ) -> anyhow::Result<Option<i64>> {
+        let mut rows = conn.query(sql, (session_id, id)).await?;
+        match rows.next().await? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Count the messages belonging to a given session.
+    pub async fn count_by_session(conn: &Connection, session_id: i64) -> anyhow::Result<u64> {
+        let mut rows = conn
+            .query(
+                "SELECT COUNT(*) FROM messages WHERE session_id = ?1",
+                (session_id,),
+            )
+            .await?;
+        let count: i64 = match rows.next().await? {
+            Some(row) => row.get(0)?,
+            None => 0,
+        };
+        Ok(count as u64)
+    }
+
     /// Update all fields of a message and return the updated row.
     pub async fn update(
         conn: &Connection,
@@ -133,6 +269,17 @@ impl Message {
         Self::get(conn, id).await
     }
 
+    /// Set when a message was stored, e.g. to keep the original time of an
+    /// imported message.
+    pub async fn set_created_at(conn: &Connection, id: i64, created_at: i64) -> anyhow::Result<()> {
+        conn.execute(
+            "UPDATE messages SET created_at = ?1 WHERE id = ?2",
+            (created_at, id),
+        )
+        .await?;
+        Ok(())
+    }
+
     /// Delete a message by id; returns true if a row was removed.
     pub async fn delete(conn: &Connection, id: i64) -> anyhow::Result<bool> {
         let changed = conn
@@ -142,6 +289,31 @@ impl Message {
         Ok(changed > 0)
     }
 
+    /// Delete every message of a session; returns the number of rows removed.
+    pub async fn delete_by_session(conn: &Connection, session_id: i64) -> anyhow::Result<u64> {
+        let changed = conn
+            .execute("DELETE FROM messages WHERE session_id = ?1", (session_id,))
+            .await?;
+
+        Ok(changed)
+    }
+
+    /// Delete the messages with the given ids; returns the number of rows removed.
+    pub async fn delete_many(conn: &Connection, ids: &[i64]) -> anyhow::Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let ids: Vec<String> = ids.iter().map(i64::to_string).collect();
+        let changed = conn
+            .execute(
+                &format!("DELETE FROM messages WHERE id IN ({})", ids.join(", ")),
+                (),
+            )
+            .await?;
+
+        Ok(changed)
+    }
+
     collect_rows!(Message);
 
     fn from_row(row: &turso::Row) -> anyhow::Result<Self> {
@@ -153,28 +325,33 @@ impl Message {
             session_id: row.get(4)?,
             tool_call_id: row.get(5)?,
             tool_calls: row.get(6)?,
+            created_at: row.get(7)?,
         })
     }
 }
 
+/// Schema of the `messages` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    reasoning_content TEXT,
+    tool_call_id TEXT,
+    tool_calls TEXT,
+    FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+)";
+
+/// Adds `created_at` (migration 2).
+pub(crate) const ADD_CREATED_AT: &str = "ALTER TABLE messages ADD COLUMN created_at INTEGER";
+
+/// Dates messages stored before migration 2 to the time of the upgrade.
+pub(crate) const BACKFILL_CREATED_AT: &str = "UPDATE messages SET created_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE created_at IS NULL";
+
 impl Entity for Message {
     async fn create_table(conn: &Connection) -> anyhow::Result<()> {
         enable_foreign_keys(conn).await?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS messages (
-                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                    session_id INTEGER NOT NULL,
-                    role TEXT NOT NULL,
-                    content TEXT NOT NULL,
-                    reasoning_content TEXT,
-                    tool_call_id TEXT,
-                    tool_calls TEXT,
-                    FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
-                )",
-            (),
-        )
-        .await?;
-        Ok(())
+        migrate_core(conn).await
     }
 }
 
@@ -281,6 +458,7 @@ mod tests {
                 session_id: second_session.id,
                 tool_call_id: None,
                 tool_calls: None,
+                created_at: first.created_at,
             }
         );
         assert_eq!(
@@ -354,4 +532,32 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn list_page_walks_back_from_the_newest() -> anyhow::Result<()> {
+        let conn = connection().await?;
+        let session = session(&conn).await?;
+        let mut ids = Vec::new();
+        for content in ["1", "2", "3", "4", "5"] {
+            let message =
+                Message::create(&conn, session.id, "user", content, None, None, None).await?;
+            ids.push(message.id);
+        }
+
+        let contents = |messages: Vec<Message>| -> Vec<String> {
+            messages.into_iter().map(|m| m.content).collect()
+        };
+        let newest = Message::list_page(&conn, session.id, None, 2).await?;
+        let before = newest[0].id;
+        assert_eq!(contents(newest), ["4", "5"]);
+        assert_eq!(
+            contents(Message::list_page(&conn, session.id, Some(before), 2).await?),
+            ["2", "3"]
+        );
+        assert_eq!(
+            contents(Message::list_page(&conn, session.id, Some(ids[1]), 10).await?),
+            ["1"]
+        );
+        Ok(())
+    }
 }
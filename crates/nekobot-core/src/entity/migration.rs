@@ -0,0 +1,355 @@
+//! Versioned schema migrations.
+//!
+//! Each component (core, a middleware crate, …) owns an ordered list of
+//! [`Migration`]s numbered from 1. Migration 1 is the component's schema as
+//! it was before migrations existed, written with `CREATE TABLE IF NOT
+//! EXISTS` so that existing databases adopt it without changes. Later
+//! changes to a table are new migrations; the `CREATE_TABLE` constants of the
+//! entities are never edited.
+//!
+//! Applied versions are recorded in the `schema_migrations` table. A
+//! database that has a higher version than the running binary knows about
+//! is refused, since the binary cannot know what the newer schema looks like.
+
+pub use nekobot_channel::migration::{Migration, MigrationSet};
+use turso::Connection;
+
+use crate::entity::{
+    channel_chat_agent, chat_model_override, chat_session, group_binding, invite, message, persona,
+    schedule, sender_gate_state, sender_role, session, turn, turn_step,
+};
+
+/// Migrations of the core entities.
+pub const CORE_MIGRATIONS: MigrationSet = MigrationSet {
+    component: "core",
+    migrations: &[
+        Migration {
+            version: 1,
+            description: "initial schema",
+            statements: &[
+                session::CREATE_TABLE,
+                message::CREATE_TABLE,
+                channel_chat_agent::CREATE_TABLE,
+                chat_session::CREATE_TABLE,
+                chat_model_override::CREATE_TABLE,
+                schedule::CREATE_TABLE,
+                sender_gate_state::CREATE_TABLE,
+                turn::CREATE_TABLE,
+                persona::CREATE_TABLE,
+            ],
+        },
+        Migration {
+            version: 2,
+            description: "message timestamps",
+            statements: &[message::ADD_CREATED_AT, message::BACKFILL_CREATED_AT],
+        },
+        Migration {
+            version: 3,
+            description: "login expiry and throttling",
+            statements: &[
+                sender_gate_state::ADD_LOGGED_IN_AT,
+                sender_gate_state::ADD_FAILED_ATTEMPTS,
+                sender_gate_state::ADD_LOCKED_UNTIL,
+                sender_gate_state::BACKFILL_LOGGED_IN_AT,
+            ],
+        },
+        Migration {
+            version: 4,
+            description: "roles, invites and group bindings",
+            statements: &[
+                sender_role::CREATE_TABLE,
+                invite::CREATE_TABLE,
+                group_binding::CREATE_TABLE,
+            ],
+        },
+        Migration {
+            version: 5,
+            description: "turn steps",
+            statements: &[turn_step::CREATE_TABLE],
+        },
+        Migration {
+            version: 6,
+            description: "TOTP replay protection",
+            statements: &[sender_gate_state::ADD_LAST_TOTP_STEP],
+        },
+        Migration {
+            version: 7,
+            description: "grants of schedule creators",
+            statements: &[schedule::ADD_DENIED_TOOLS],
+        },
+    ],
+};
+
+/// A migration that was applied, or would be in a dry run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMigration {
+    pub component: &'static str,
+    pub version: u32,
+    pub description: &'static str,
+}
+
+/// The migration sets known to the binary, keyed by component.
+#[derive(Debug, Clone, Default)]
+pub struct MigrationRegistry {
+    sets: Vec<MigrationSet>,
+}
+
+impl MigrationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a component's migrations.
+    ///
+    /// Fails if the component is already registered or its versions do not
+    /// count up from 1.
+    pub fn register(&mut self, set: MigrationSet) -> anyhow::Result<()> {
+        if self.sets.iter().any(|s| s.component == set.component) {
+            anyhow::bail!("migrations for '{}' are already registered", set.component);
+        }
+        for (index, migration) in set.migrations.iter().enumerate() {
+            let expected = index as u32 + 1;
+            if migration.version != expected {
+                anyhow::bail!(
+                    "migration {} of '{}' should have version {expected}",
+                    migration.version,
+                    set.component
+                );
+            }
+        }
+        self.sets.push(set);
+        Ok(())
+    }
+
+    pub fn sets(&self) -> &[MigrationSet] {
+        &self.sets
+    }
+
+    /// Every registered migration, as a new database would get them.
+    pub fn all(&self) -> Vec<PlannedMigration> {
+        self.sets
+            .iter()
+            .flat_map(|set| {
+                set.migrations.iter().map(|migration| PlannedMigration {
+                    component: set.component,
+                    version: migration.version,
+                    description: migration.description,
+                })
+            })
+            .collect()
+    }
+
+    /// Bring the database up to date, or with `dry_run` only report what
+    /// would be applied.
+    ///
+    /// Each migration runs in its own transaction, in registration order of
+    /// the components. Fails without changing anything if the database has a
+    /// component at a version newer than the registered migrations.
+    pub async fn migrate(
+        &self,
+        conn: &Connection,
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<PlannedMigration>> {
+        let applied = applied_versions(conn).await?;
+        for set in &self.sets {
+            let current = current_version(&applied, set.component);
+            if current > set.latest() {
+                anyhow::bail!(
+                    "database schema of '{}' is at version {current}, but this binary only \
+                     knows up to version {}; refusing to start",
+                    set.component,
+                    set.latest()
+                );
+            }
+        }
+
+        let mut planned = Vec::new();
+        for set in &self.sets {
+            let current = current_version(&applied, set.component);
+            for migration in set.migrations.iter().filter(|m| m.version > current) {
+                if !dry_run {
+                    apply(conn, set.component, migration).await?;
+                    tracing::info!(
+                        "applied migration {} {} ({})",
+                        set.component,
+                        migration.version,
+                        migration.description
+                    );
+                }
+                planned.push(PlannedMigration {
+                    component: set.component,
+                    version: migration.version,
+                    description: migration.description,
+                });
+            }
+        }
+        Ok(planned)
+    }
+}
+
+fn current_version(applied: &[(String, u32)], component: &str) -> u32 {
+    applied
+        .iter()
+        .find(|(c, _)| c == component)
+        .map_or(0, |(_, version)| *version)
+}
+
+/// Highest applied version per component; empty if nothing was migrated yet.
+async fn applied_versions(conn: &Connection) -> anyhow::Result<Vec<(String, u32)>> {
+    let mut rows = conn
+        .query(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'",
+            (),
+        )
+        .await?;
+    if rows.next().await?.is_none() {
+        return Ok(Vec::new());
+    }
+    let mut rows = conn
+        .query(
+            "SELECT component, MAX(version) FROM schema_migrations GROUP BY component",
+            (),
+        )
+        .await?;
+    let mut applied = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let component: String = row.get(0)?;
+        let version: i64 = row.get(1)?;
+        applied.push((component, version as u32));
+    }
+    Ok(applied)
+}
+
+async fn apply(conn: &Connection, component: &str, migration: &Migration) -> anyhow::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            component TEXT NOT NULL,
+            version INTEGER NOT NULL,
+            description TEXT NOT NULL,
+            applied_at INTEGER NOT NULL,
+            PRIMARY KEY (component, version)
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute("BEGIN", ()).await?;
+    let result = async {
+        for statement in migration.statements {
+            conn.execute(*statement, ()).await?;
+        }
+        conn.execute(
+            "INSERT INTO schema_migrations (component, version, description, applied_at)
+                VALUES (?1, ?2, ?3, ?4)",
+            (
+                component,
+                migration.version as i64,
+                migration.description,
+                chrono::Utc::now().timestamp(),
+            ),
+        )
+        .await?;
+        anyhow::Ok(())
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            conn.execute("COMMIT", ()).await?;
+            Ok(())
+        }
+        Err(error) => {
+            let _ = conn.execute("ROLLBACK", ()).await;
+            Err(error.context(format!(
+                "migration {component} {} ({}) failed",
+                migration.version, migration.description
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::test_connection;
+
+    const NOTES: MigrationSet = MigrationSet {
+        component: "notes",
+        migrations: &[
+            Migration {
+                version: 1,
+                description: "notes table",
+                statements: &["CREATE TABLE IF NOT EXISTS notes (id INTEGER PRIMARY KEY)"],
+            },
+            Migration {
+                version: 2,
+                description: "note text",
+                statements: &["ALTER TABLE notes ADD COLUMN text TEXT"],
+            },
+        ],
+    };
+
+    fn registry(set: MigrationSet) -> MigrationRegistry {
+        let mut registry = MigrationRegistry::new();
+        registry.register(CORE_MIGRATIONS).unwrap();
+        registry.register(set).unwrap();
+        registry
+    }
+
+    #[tokio::test]
+    async fn applies_pending_migrations_once() -> anyhow::Result<()> {
+        let conn = test_connection().await?;
+        let registry = registry(NOTES);
+
+        let planned = registry.migrate(&conn, true).await?;
+        assert_eq!(planned.len(), CORE_MIGRATIONS.migrations.len() + 2);
+        assert!(applied_versions(&conn).await?.is_empty());
+
+        let applied = registry.migrate(&conn, false).await?;
+        assert_eq!(applied, planned);
+        conn.execute("INSERT INTO notes (text) VALUES ('hi')", ())
+            .await?;
+        assert!(registry.migrate(&conn, false).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn adopts_tables_created_before_migrations() -> anyhow::Result<()> {
+        use crate::entity::{Entity, session::Session};
+
+        let conn = test_connection().await?;
+        Session::create_table(&conn).await?;
+        Session::create(&conn, "Neko").await?;
+
+        let mut registry = MigrationRegistry::new();
+        registry.register(CORE_MIGRATIONS)?;
+        registry.migrate(&conn, false).await?;
+        assert!(Session::get(&conn, 1).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_a_newer_database() -> anyhow::Result<()> {
+        let conn = test_connection().await?;
+        registry(NOTES).migrate(&conn, false).await?;
+
+        let older = MigrationSet {
+            component: "notes",
+            migrations: &NOTES.migrations[..1],
+        };
+        let error = registry(older).migrate(&conn, false).await.unwrap_err();
+        assert!(error.to_string().contains("version 2"));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_gaps_and_duplicates() {
+        let mut registry = MigrationRegistry::new();
+        registry.register(NOTES).unwrap();
+        assert!(registry.register(NOTES).is_err());
+        let gap = MigrationSet {
+            component: "gap",
+            migrations: &NOTES.migrations[1..],
+        };
+        assert!(registry.register(gap).is_err());
+    }
+}
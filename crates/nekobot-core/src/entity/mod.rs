@@ -1,15 +1,25 @@
 //! Database entity layer backed by libSQL (turso).
 //!
 //! Each entity type corresponds to a SQL table and implements [`Entity`]
-//! for `CREATE TABLE IF NOT EXISTS` semantics.
+//! for `CREATE TABLE IF NOT EXISTS` semantics. The application database is
+//! set up and upgraded by the versioned [`migration`]s.
 
 use turso::Connection;
 
 pub mod channel_chat_agent;
+pub mod chat_model_override;
+pub mod chat_session;
+pub mod group_binding;
+pub mod invite;
 pub mod message;
+pub mod migration;
 pub mod persona;
+pub mod schedule;
 pub mod sender_gate_state;
+pub mod sender_role;
 pub mod session;
+pub mod turn;
+pub mod turn_step;
 
 pub(crate) async fn enable_foreign_keys(conn: &Connection) -> anyhow::Result<()> {
     conn.query("PRAGMA journal_mode = WAL", ()).await?;
@@ -17,10 +27,24 @@ pub(crate) async fn enable_foreign_keys(conn: &Connection) -> anyhow::Result<()>
     Ok(())
 }
 
+/// Apply the core [`migration`]s, creating every core table in its current
+/// schema.
+///
+/// Entities whose table changed after migration 1 create it this way, since
+/// their `CREATE_TABLE` constant only has the original columns.
+pub(crate) async fn migrate_core(conn: &Connection) -> anyhow::Result<()> {
+    let mut registry = migration::MigrationRegistry::new();
+    registry.register(migration::CORE_MIGRATIONS)?;
+    registry.migrate(conn, false).await?;
+    Ok(())
+}
+
 /// Trait for types that map to a database table.
 ///
 /// Implementations should issue a `CREATE TABLE IF NOT EXISTS` statement
-/// that is safe to call multiple times.
+/// that is safe to call multiple times. Tables with columns added by later
+/// [`migration`]s are created by applying the migrations instead, so the
+/// table has its current schema.
 pub trait Entity {
     fn create_table(conn: &Connection) -> impl Future<Output = anyhow::Result<()>>;
 }
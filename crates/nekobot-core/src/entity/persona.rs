@@ -2,16 +2,15 @@
 
 use turso::Connection;
 
+/// Schema of the `personae` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS personae (
+    agent_name TEXT NOT NULL PRIMARY KEY,
+    persona TEXT NOT NULL
+)";
+
 /// Create the `personae` table.
 pub async fn create_table(conn: &Connection) -> anyhow::Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS personae (
-            agent_name TEXT NOT NULL PRIMARY KEY,
-            persona TEXT NOT NULL
-        )",
-        (),
-    )
-    .await?;
+    conn.execute(CREATE_TABLE, ()).await?;
     Ok(())
 }
 
@@ -0,0 +1,256 @@
+//! Schedule entity — pending one-shot and recurring activations of a session.
+//!
+//! Rows are written by the scheduler middleware, together with the tools
+//! denied to the sender who created them, so a schedule fires with its
+//! creator's grants. The channel runtime reads them at startup to restart
+//! sessions that still have schedules pending.
+
+use turso::Connection;
+
+use crate::entity::{Entity, collect_rows, enable_foreign_keys, migrate_core};
+
+/// How a schedule repeats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleKind {
+    /// Fires once at `next_run`, then is deleted.
+    Once,
+    /// Fires whenever its cron expression matches.
+    Cron,
+}
+
+impl ScheduleKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScheduleKind::Once => "once",
+            ScheduleKind::Cron => "cron",
+        }
+    }
+}
+
+impl std::str::FromStr for ScheduleKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "once" => Ok(ScheduleKind::Once),
+            "cron" => Ok(ScheduleKind::Cron),
+            other => anyhow::bail!("unknown schedule kind: {other}"),
+        }
+    }
+}
+
+/// A row in the `schedules` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    pub id: i64,
+    pub session_id: i64,
+    pub kind: ScheduleKind,
+    /// RFC 3339 time for [`ScheduleKind::Once`], cron expression for [`ScheduleKind::Cron`].
+    pub spec: String,
+    /// Prompt delivered to the session when the schedule fires.
+    pub prompt: String,
+    /// Next firing time in seconds since the Unix epoch.
+    pub next_run: i64,
+    /// Restricted tools the creator of the schedule may not call; they are
+    /// denied while the schedule's activation runs.
+    pub denied_tools: Vec<String>,
+}
+
+/// Data needed to insert a new [`Schedule`] row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewSchedule {
+    pub session_id: i64,
+    pub kind: ScheduleKind,
+    pub spec: String,
+    pub prompt: String,
+    pub next_run: i64,
+    pub denied_tools: Vec<String>,
+}
+
+impl Schedule {
+    /// Insert a new schedule and return it.
+    pub async fn create(conn: &Connection, new_schedule: NewSchedule) -> anyhow::Result<Self> {
+        let denied_tools = serde_json::to_string(&new_schedule.denied_tools)?;
+        conn.execute(
+            "INSERT INTO schedules (session_id, kind, spec, prompt, next_run, denied_tools)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                new_schedule.session_id,
+                new_schedule.kind.as_str(),
+                new_schedule.spec.as_str(),
+                new_schedule.prompt.as_str(),
+                new_schedule.next_run,
+                denied_tools.as_str(),
+            ),
+        )
+        .await?;
+
+        Ok(Self {
+            id: conn.last_insert_rowid(),
+            session_id: new_schedule.session_id,
+            kind: new_schedule.kind,
+            spec: new_schedule.spec,
+            prompt: new_schedule.prompt,
+            next_run: new_schedule.next_run,
+            denied_tools: new_schedule.denied_tools,
+        })
+    }
+
+    /// Return the schedules of a session, soonest first.
+    pub async fn list_by_session(conn: &Connection, session_id: i64) -> anyhow::Result<Vec<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT id, session_id, kind, spec, prompt, next_run, denied_tools
+                    FROM schedules WHERE session_id = ?1
+                    ORDER BY next_run, id",
+                (session_id,),
+            )
+            .await?;
+        Self::collect_rows(&mut rows).await
+    }
+
+    /// Return the schedules of a session that are due at `now`, soonest first.
+    pub async fn list_due(
+        conn: &Connection,
+        session_id: i64,
+        now: i64,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT id, session_id, kind, spec, prompt, next_run, denied_tools
+                    FROM schedules WHERE session_id = ?1 AND next_run <= ?2
+                    ORDER BY next_run, id",
+                (session_id, now),
+            )
+            .await?;
+        Self::collect_rows(&mut rows).await
+    }
+
+    /// Earliest `next_run` of a session's schedules, if it has any.
+    pub async fn next_run(conn: &Connection, session_id: i64) -> anyhow::Result<Option<i64>> {
+        let mut rows = conn
+            .query(
+                "SELECT MIN(next_run) FROM schedules WHERE session_id = ?1",
+                (session_id,),
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Move a schedule to its next firing time.
+    pub async fn set_next_run(conn: &Connection, id: i64, next_run: i64) -> anyhow::Result<()> {
+        conn.execute(
+            "UPDATE schedules SET next_run = ?1 WHERE id = ?2",
+            (next_run, id),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Delete a schedule of a session; returns true if a row was removed.
+    pub async fn delete(conn: &Connection, session_id: i64, id: i64) -> anyhow::Result<bool> {
+        let changed = conn
+            .execute(
+                "DELETE FROM schedules WHERE session_id = ?1 AND id = ?2",
+                (session_id, id),
+            )
+            .await?;
+        Ok(changed > 0)
+    }
+
+    collect_rows!(Schedule);
+
+    fn from_row(row: &turso::Row) -> anyhow::Result<Self> {
+        let kind: String = row.get(2)?;
+        let denied_tools: String = row.get(6)?;
+        Ok(Self {
+            id: row.get(0)?,
+            session_id: row.get(1)?,
+            kind: kind.parse()?,
+            spec: row.get(3)?,
+            prompt: row.get(4)?,
+            next_run: row.get(5)?,
+            denied_tools: serde_json::from_str(&denied_tools)?,
+        })
+    }
+}
+
+/// Schema of the `schedules` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS schedules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    spec TEXT NOT NULL,
+    prompt TEXT NOT NULL,
+    next_run INTEGER NOT NULL,
+    FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+)";
+
+/// Grants of the schedule's creator (migration 7). Schedules from before
+/// it deny nothing, as they did then.
+pub(crate) const ADD_DENIED_TOOLS: &str =
+    "ALTER TABLE schedules ADD COLUMN denied_tools TEXT NOT NULL DEFAULT '[]'";
+
+impl Entity for Schedule {
+    async fn create_table(conn: &Connection) -> anyhow::Result<()> {
+        enable_foreign_keys(conn).await?;
+        migrate_core(conn).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::session::Session;
+
+    #[tokio::test]
+    async fn lists_due_schedules_and_scopes_deletes_to_session() -> anyhow::Result<()> {
+        let conn = crate::entity::test_connection().await?;
+        Session::create_table(&conn).await?;
+        Schedule::create_table(&conn).await?;
+        let session = Session::create(&conn, "Neko").await?;
+        let other = Session::create(&conn, "Neko").await?;
+
+        let later = Schedule::create(
+            &conn,
+            NewSchedule {
+                session_id: session.id,
+                kind: ScheduleKind::Cron,
+                spec: "0 9 * * *".to_owned(),
+                prompt: "daily".to_owned(),
+                next_run: 200,
+                denied_tools: vec!["bash".to_owned()],
+            },
+        )
+        .await?;
+        let soon = Schedule::create(
+            &conn,
+            NewSchedule {
+                session_id: session.id,
+                kind: ScheduleKind::Once,
+                spec: "1970-01-01T00:01:40Z".to_owned(),
+                prompt: "once".to_owned(),
+                next_run: 100,
+                denied_tools: Vec::new(),
+            },
+        )
+        .await?;
+
+        assert_eq!(Schedule::next_run(&conn, session.id).await?, Some(100));
+        assert_eq!(Schedule::next_run(&conn, other.id).await?, None);
+        assert_eq!(Schedule::list_due(&conn, session.id, 150).await?, vec![soon.clone()]);
+        assert_eq!(
+            Schedule::list_by_session(&conn, session.id).await?,
+            vec![soon.clone(), later.clone()]
+        );
+
+        assert!(!Schedule::delete(&conn, other.id, soon.id).await?);
+        assert!(Schedule::delete(&conn, session.id, soon.id).await?);
+        Schedule::set_next_run(&conn, later.id, 300).await?;
+        assert_eq!(Schedule::next_run(&conn, session.id).await?, Some(300));
+        Ok(())
+    }
+}
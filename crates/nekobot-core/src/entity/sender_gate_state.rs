@@ -2,7 +2,7 @@
 
 use turso::Connection;
 
-use crate::entity::{Entity, enable_foreign_keys};
+use crate::entity::{Entity, enable_foreign_keys, migrate_core};
 
 /// Persistent login and agent-binding state for a sender within a channel.
 ///
@@ -13,6 +13,15 @@ pub struct SenderGateState {
     pub sender_id: String,
     pub is_logged_in: bool,
     pub connected_agent: Option<String>,
+    /// Unix time of the last successful login.
+    pub logged_in_at: Option<i64>,
+    /// Failed login attempts since the last success or lockout.
+    pub failed_attempts: u32,
+    /// Unix time until which logins are refused.
+    pub locked_until: Option<i64>,
+    /// TOTP time step of the last accepted code; codes of this step or
+    /// earlier are refused.
+    pub last_totp_step: Option<i64>,
 }
 
 impl SenderGateState {
@@ -23,11 +32,17 @@ impl SenderGateState {
             sender_id: sender_id.into(),
             is_logged_in: false,
             connected_agent: None,
+            logged_in_at: None,
+            failed_attempts: 0,
+            locked_until: None,
+            last_totp_step: None,
         }
     }
 
     fn from_row(row: &turso::Row) -> anyhow::Result<Self> {
+        // Rows written before connected_agent was stored as NULL hold ''.
         let connected_agent: Option<String> = row.get(3)?;
+        let failed_attempts: i64 = row.get(5)?;
         Ok(Self {
             channel_id: row.get(0)?,
             sender_id: row.get(1)?,
@@ -35,7 +50,11 @@ impl SenderGateState {
                 let v: i64 = row.get(2)?;
                 v != 0
             },
-            connected_agent,
+            connected_agent: connected_agent.filter(|agent| !agent.is_empty()),
+            logged_in_at: row.get(4)?,
+            failed_attempts: failed_attempts.try_into().unwrap_or(u32::MAX),
+            locked_until: row.get(6)?,
+            last_totp_step: row.get(7)?,
         })
     }
 
@@ -47,7 +66,8 @@ impl SenderGateState {
     ) -> anyhow::Result<Option<Self>> {
         let mut rows = conn
             .query(
-                "SELECT channel_id, sender_id, is_logged_in, connected_agent
+                "SELECT channel_id, sender_id, is_logged_in, connected_agent,
+                        logged_in_at, failed_attempts, locked_until, last_totp_step
                  FROM sender_gate_states
                  WHERE channel_id = ?1 AND sender_id = ?2",
                 (channel_id, sender_id),
@@ -62,42 +82,55 @@ impl SenderGateState {
 
     /// Insert or replace the gate state.
     pub async fn upsert(&self, conn: &Connection) -> anyhow::Result<Self> {
-        let connected_agent = self.connected_agent.as_deref().unwrap_or("");
         conn.execute(
-            "INSERT OR REPLACE INTO sender_gate_states (channel_id, sender_id, is_logged_in, connected_agent)
-             VALUES (?1, ?2, ?3, ?4)",
+            "INSERT OR REPLACE INTO sender_gate_states
+                 (channel_id, sender_id, is_logged_in, connected_agent,
+                  logged_in_at, failed_attempts, locked_until, last_totp_step)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
             (
                 self.channel_id.as_str(),
                 self.sender_id.as_str(),
                 self.is_logged_in as i64,
-                connected_agent,
+                self.connected_agent.as_deref(),
+                self.logged_in_at,
+                self.failed_attempts as i64,
+                self.locked_until,
+                self.last_totp_step,
             ),
         )
         .await?;
 
-        Ok(Self {
-            channel_id: self.channel_id.clone(),
-            sender_id: self.sender_id.clone(),
-            is_logged_in: self.is_logged_in,
-            connected_agent: self.connected_agent.clone(),
-        })
+        Ok(self.clone())
     }
 }
 
+/// Schema of the `sender_gate_states` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS sender_gate_states (
+    channel_id TEXT NOT NULL,
+    sender_id TEXT NOT NULL,
+    is_logged_in INTEGER NOT NULL DEFAULT 0,
+    connected_agent TEXT,
+    PRIMARY KEY (channel_id, sender_id)
+)";
+
+/// Login expiry and throttling columns (migration 3).
+pub(crate) const ADD_LOGGED_IN_AT: &str =
+    "ALTER TABLE sender_gate_states ADD COLUMN logged_in_at INTEGER";
+pub(crate) const ADD_FAILED_ATTEMPTS: &str =
+    "ALTER TABLE sender_gate_states ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0";
+pub(crate) const ADD_LOCKED_UNTIL: &str =
+    "ALTER TABLE sender_gate_states ADD COLUMN locked_until INTEGER";
+
+/// Starts the expiry of logins from before migration 3 at the time of the upgrade.
+pub(crate) const BACKFILL_LOGGED_IN_AT: &str = "UPDATE sender_gate_states SET logged_in_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE is_logged_in != 0 AND logged_in_at IS NULL";
+
+/// TOTP replay protection column (migration 6).
+pub(crate) const ADD_LAST_TOTP_STEP: &str =
+    "ALTER TABLE sender_gate_states ADD COLUMN last_totp_step INTEGER";
+
 impl Entity for SenderGateState {
     async fn create_table(conn: &Connection) -> anyhow::Result<()> {
         enable_foreign_keys(conn).await?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS sender_gate_states (
-                channel_id TEXT NOT NULL,
-                sender_id TEXT NOT NULL,
-                is_logged_in INTEGER NOT NULL DEFAULT 0,
-                connected_agent TEXT,
-                PRIMARY KEY (channel_id, sender_id)
-            )",
-            (),
-        )
-        .await?;
-        Ok(())
+        migrate_core(conn).await
     }
 }
@@ -0,0 +1,75 @@
+//! Sender role entity — roles granted to senders at runtime, e.g. by
+//! redeeming an invite code. Roles from the config are not stored.
+
+use turso::Connection;
+
+use crate::entity::{Entity, enable_foreign_keys};
+
+/// A role held by a sender within a channel.
+///
+/// Composite primary key is `(channel_id, sender_id, role)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SenderRole {
+    pub channel_id: String,
+    pub sender_id: String,
+    pub role: String,
+    /// Unix time the role was granted.
+    pub granted_at: i64,
+}
+
+impl SenderRole {
+    /// Grant the role; granting a role the sender already holds keeps the
+    /// original grant.
+    pub async fn grant(&self, conn: &Connection) -> anyhow::Result<()> {
+        conn.execute(
+            "INSERT OR IGNORE INTO sender_roles (channel_id, sender_id, role, granted_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            (
+                self.channel_id.as_str(),
+                self.sender_id.as_str(),
+                self.role.as_str(),
+                self.granted_at,
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Names of the roles granted to a sender, sorted.
+    pub async fn roles_of(
+        conn: &Connection,
+        channel_id: &str,
+        sender_id: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut rows = conn
+            .query(
+                "SELECT role FROM sender_roles
+                 WHERE channel_id = ?1 AND sender_id = ?2
+                 ORDER BY role",
+                (channel_id, sender_id),
+            )
+            .await?;
+        let mut roles = Vec::new();
+        while let Some(row) = rows.next().await? {
+            roles.push(row.get(0)?);
+        }
+        Ok(roles)
+    }
+}
+
+/// Schema of the `sender_roles` table (migration 4).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS sender_roles (
+    channel_id TEXT NOT NULL,
+    sender_id TEXT NOT NULL,
+    role TEXT NOT NULL,
+    granted_at INTEGER NOT NULL,
+    PRIMARY KEY (channel_id, sender_id, role)
+)";
+
+impl Entity for SenderRole {
+    async fn create_table(conn: &Connection) -> anyhow::Result<()> {
+        enable_foreign_keys(conn).await?;
+        conn.execute(CREATE_TABLE, ()).await?;
+        Ok(())
+    }
+}
@@ -101,17 +101,16 @@ impl Session {
     }
 }
 
+/// Schema of the `sessions` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    agent_name TEXT NOT NULL
+)";
+
 impl Entity for Session {
     async fn create_table(conn: &Connection) -> anyhow::Result<()> {
         enable_foreign_keys(conn).await?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS sessions (
-                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                    agent_name TEXT NOT NULL
-                )",
-            (),
-        )
-        .await?;
+        conn.execute(CREATE_TABLE, ()).await?;
         Ok(())
     }
 }
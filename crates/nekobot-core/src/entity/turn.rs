@@ -0,0 +1,249 @@
+//! Turn entity — audit record of one agent turn.
+//!
+//! Written only when [`AuditConfig::enabled`](crate::config::AuditConfig::enabled)
+//! is set. Each row keeps the last request sent to the provider, the tool
+//! calls made along the way, token usage, timing and the error if the turn
+//! failed, linked to the message that triggered it and the reply it produced.
+//! Every provider and tool call is also kept as a
+//! [`TurnStep`](crate::entity::turn_step::TurnStep) with its own duration.
+
+use turso::Connection;
+
+use crate::entity::{Entity, collect_rows, enable_foreign_keys};
+
+/// A row in the `turns` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Turn {
+    pub id: i64,
+    pub session_id: i64,
+    /// User or internal message that started the turn.
+    pub trigger_message_id: Option<i64>,
+    /// Assistant message the turn produced; unset if it failed.
+    pub reply_message_id: Option<i64>,
+    /// [`Provider::id`](crate::provider::Provider::id) of the provider used.
+    pub provider: String,
+    pub model: Option<String>,
+    /// JSON of the last request sent to the provider.
+    pub request: String,
+    /// JSON array of the tool calls made, with arguments, results and timing.
+    pub tool_calls: String,
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub error: Option<String>,
+    /// Start of the turn in seconds since the Unix epoch.
+    pub started_at: i64,
+    pub duration_ms: i64,
+}
+
+/// Data needed to insert a new [`Turn`] row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewTurn {
+    pub session_id: i64,
+    pub trigger_message_id: Option<i64>,
+    pub reply_message_id: Option<i64>,
+    pub provider: String,
+    pub model: Option<String>,
+    pub request: String,
+    pub tool_calls: String,
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub error: Option<String>,
+    pub started_at: i64,
+    pub duration_ms: i64,
+}
+
+impl Turn {
+    /// Insert a new turn and return it.
+    pub async fn create(conn: &Connection, new_turn: NewTurn) -> anyhow::Result<Self> {
+        conn.execute(
+            "INSERT INTO turns (session_id, trigger_message_id, reply_message_id, provider, model,
+                    request, tool_calls, input_tokens, output_tokens, error, started_at, duration_ms)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            (
+                new_turn.session_id,
+                new_turn.trigger_message_id,
+                new_turn.reply_message_id,
+                new_turn.provider.as_str(),
+                new_turn.model.as_deref(),
+                new_turn.request.as_str(),
+                new_turn.tool_calls.as_str(),
+                new_turn.input_tokens,
+                new_turn.output_tokens,
+                new_turn.error.as_deref(),
+                new_turn.started_at,
+                new_turn.duration_ms,
+            ),
+        )
+        .await?;
+
+        Ok(Self {
+            id: conn.last_insert_rowid(),
+            session_id: new_turn.session_id,
+            trigger_message_id: new_turn.trigger_message_id,
+            reply_message_id: new_turn.reply_message_id,
+            provider: new_turn.provider,
+            model: new_turn.model,
+            request: new_turn.request,
+            tool_calls: new_turn.tool_calls,
+            input_tokens: new_turn.input_tokens,
+            output_tokens: new_turn.output_tokens,
+            error: new_turn.error,
+            started_at: new_turn.started_at,
+            duration_ms: new_turn.duration_ms,
+        })
+    }
+
+    /// Return the turns of a session, oldest first.
+    pub async fn list_by_session(conn: &Connection, session_id: i64) -> anyhow::Result<Vec<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT id, session_id, trigger_message_id, reply_message_id, provider, model,
+                        request, tool_calls, input_tokens, output_tokens, error, started_at, duration_ms
+                    FROM turns WHERE session_id = ?1 ORDER BY id",
+                (session_id,),
+            )
+            .await?;
+        Self::collect_rows(&mut rows).await
+    }
+
+    /// Delete turns that started before `cutoff`, with their steps; returns how
+    /// many turns were removed.
+    pub async fn delete_before(conn: &Connection, cutoff: i64) -> anyhow::Result<u64> {
+        let deleted = conn
+            .execute("DELETE FROM turns WHERE started_at < ?1", (cutoff,))
+            .await?;
+        Ok(deleted)
+    }
+
+    collect_rows!(Turn);
+
+    fn from_row(row: &turso::Row) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            session_id: row.get(1)?,
+            trigger_message_id: row.get(2)?,
+            reply_message_id: row.get(3)?,
+            provider: row.get(4)?,
+            model: row.get(5)?,
+            request: row.get(6)?,
+            tool_calls: row.get(7)?,
+            input_tokens: row.get(8)?,
+            output_tokens: row.get(9)?,
+            error: row.get(10)?,
+            started_at: row.get(11)?,
+            duration_ms: row.get(12)?,
+        })
+    }
+}
+
+/// Schema of the `turns` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS turns (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id INTEGER NOT NULL,
+    trigger_message_id INTEGER,
+    reply_message_id INTEGER,
+    provider TEXT NOT NULL,
+    model TEXT,
+    request TEXT NOT NULL,
+    tool_calls TEXT NOT NULL,
+    input_tokens INTEGER,
+    output_tokens INTEGER,
+    error TEXT,
+    started_at INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE,
+    FOREIGN KEY(trigger_message_id) REFERENCES messages(id) ON DELETE SET NULL,
+    FOREIGN KEY(reply_message_id) REFERENCES messages(id) ON DELETE SET NULL
+)";
+
+impl Entity for Turn {
+    async fn create_table(conn: &Connection) -> anyhow::Result<()> {
+        enable_foreign_keys(conn).await?;
+        conn.execute(CREATE_TABLE, ()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{
+        message::Message,
+        session::Session,
+        turn_step::{NewTurnStep, StepKind, TurnStep},
+    };
+
+    fn new_turn(session_id: i64, started_at: i64) -> NewTurn {
+        NewTurn {
+            session_id,
+            trigger_message_id: None,
+            reply_message_id: None,
+            provider: "test".to_owned(),
+            model: Some("test-model".to_owned()),
+            request: "{}".to_owned(),
+            tool_calls: "[]".to_owned(),
+            input_tokens: Some(12),
+            output_tokens: None,
+            error: None,
+            started_at,
+            duration_ms: 40,
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_turns_and_prunes_old_ones() -> anyhow::Result<()> {
+        let conn = crate::entity::test_connection().await?;
+        Session::create_table(&conn).await?;
+        Message::create_table(&conn).await?;
+        Turn::create_table(&conn).await?;
+        TurnStep::create_table(&conn).await?;
+        let session = Session::create(&conn, "Neko").await?;
+        let message = Message::create(&conn, session.id, "user", "hi", None, None, None).await?;
+
+        let old = Turn::create(&conn, new_turn(session.id, 100)).await?;
+        let step = TurnStep::create(
+            &conn,
+            old.id,
+            0,
+            NewTurnStep {
+                kind: StepKind::Tool,
+                name: "bash".to_owned(),
+                input: "{}".to_owned(),
+                output: None,
+                error: Some("timed out".to_owned()),
+                input_tokens: None,
+                output_tokens: None,
+                duration_ms: 30_000,
+            },
+        )
+        .await?;
+        assert_eq!(
+            TurnStep::list_by_turn(&conn, old.id).await?,
+            vec![step.clone()]
+        );
+        let recent = Turn::create(
+            &conn,
+            NewTurn {
+                trigger_message_id: Some(message.id),
+                ..new_turn(session.id, 200)
+            },
+        )
+        .await?;
+        assert_eq!(
+            Turn::list_by_session(&conn, session.id).await?,
+            vec![old, recent.clone()]
+        );
+
+        assert_eq!(Turn::delete_before(&conn, 150).await?, 1);
+        assert_eq!(
+            Turn::list_by_session(&conn, session.id).await?,
+            vec![recent]
+        );
+        assert!(
+            TurnStep::list_by_turn(&conn, step.turn_id)
+                .await?
+                .is_empty()
+        );
+        Ok(())
+    }
+}
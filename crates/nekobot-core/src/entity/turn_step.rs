@@ -0,0 +1,168 @@
+//! Turn step entity — one provider call or tool call of an audited turn.
+//!
+//! Steps are written together with their [`Turn`](crate::entity::turn::Turn)
+//! and removed with it, so a turn can be replayed call by call with the time
+//! each call took.
+
+use turso::Connection;
+
+use crate::entity::{Entity, collect_rows, enable_foreign_keys};
+
+/// What a [`TurnStep`] recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    /// A completion request to the provider.
+    Provider,
+    /// A tool call requested by the model.
+    Tool,
+}
+
+impl StepKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Provider => "provider",
+            Self::Tool => "tool",
+        }
+    }
+
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "provider" => Ok(Self::Provider),
+            "tool" => Ok(Self::Tool),
+            other => anyhow::bail!("unknown turn step kind '{other}'"),
+        }
+    }
+}
+
+/// A row in the `turn_steps` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnStep {
+    pub id: i64,
+    pub turn_id: i64,
+    /// Order of the step within its turn, from 0.
+    pub position: i64,
+    pub kind: StepKind,
+    /// Provider id or tool name.
+    pub name: String,
+    /// JSON of the request sent to the provider, or of the tool arguments.
+    pub input: String,
+    /// JSON of the provider response or tool result; unset if the step failed.
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub duration_ms: i64,
+}
+
+/// Data needed to insert a new [`TurnStep`] row, before its turn exists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewTurnStep {
+    pub kind: StepKind,
+    pub name: String,
+    pub input: String,
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub duration_ms: i64,
+}
+
+impl TurnStep {
+    /// Insert a step of `turn_id` at `position` and return it.
+    pub async fn create(
+        conn: &Connection,
+        turn_id: i64,
+        position: i64,
+        step: NewTurnStep,
+    ) -> anyhow::Result<Self> {
+        conn.execute(
+            "INSERT INTO turn_steps (turn_id, position, kind, name, input, output, error,
+                    input_tokens, output_tokens, duration_ms)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            (
+                turn_id,
+                position,
+                step.kind.as_str(),
+                step.name.as_str(),
+                step.input.as_str(),
+                step.output.as_deref(),
+                step.error.as_deref(),
+                step.input_tokens,
+                step.output_tokens,
+                step.duration_ms,
+            ),
+        )
+        .await?;
+
+        Ok(Self {
+            id: conn.last_insert_rowid(),
+            turn_id,
+            position,
+            kind: step.kind,
+            name: step.name,
+            input: step.input,
+            output: step.output,
+            error: step.error,
+            input_tokens: step.input_tokens,
+            output_tokens: step.output_tokens,
+            duration_ms: step.duration_ms,
+        })
+    }
+
+    /// Return the steps of a turn in the order they ran.
+    pub async fn list_by_turn(conn: &Connection, turn_id: i64) -> anyhow::Result<Vec<Self>> {
+        let mut rows = conn
+            .query(
+                "SELECT id, turn_id, position, kind, name, input, output, error,
+                        input_tokens, output_tokens, duration_ms
+                    FROM turn_steps WHERE turn_id = ?1 ORDER BY position",
+                (turn_id,),
+            )
+            .await?;
+        Self::collect_rows(&mut rows).await
+    }
+
+    collect_rows!(TurnStep);
+
+    fn from_row(row: &turso::Row) -> anyhow::Result<Self> {
+        let kind: String = row.get(3)?;
+        Ok(Self {
+            id: row.get(0)?,
+            turn_id: row.get(1)?,
+            position: row.get(2)?,
+            kind: StepKind::parse(&kind)?,
+            name: row.get(4)?,
+            input: row.get(5)?,
+            output: row.get(6)?,
+            error: row.get(7)?,
+            input_tokens: row.get(8)?,
+            output_tokens: row.get(9)?,
+            duration_ms: row.get(10)?,
+        })
+    }
+}
+
+/// Schema of the `turn_steps` table (migration 5).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS turn_steps (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    turn_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    input TEXT NOT NULL,
+    output TEXT,
+    error TEXT,
+    input_tokens INTEGER,
+    output_tokens INTEGER,
+    duration_ms INTEGER NOT NULL,
+    FOREIGN KEY(turn_id) REFERENCES turns(id) ON DELETE CASCADE,
+    UNIQUE(turn_id, position)
+)";
+
+impl Entity for TurnStep {
+    async fn create_table(conn: &Connection) -> anyhow::Result<()> {
+        enable_foreign_keys(conn).await?;
+        conn.execute(CREATE_TABLE, ()).await?;
+        Ok(())
+    }
+}
@@ -8,13 +8,20 @@ use anyhow::Context;
 use nekobot_channel::Channel;
 
 pub mod agent;
+pub mod auth;
 pub mod channel_registry;
 pub mod config;
 pub mod entity;
 pub mod provider;
 pub mod registry;
+pub mod retention;
 pub mod runtime;
 pub mod session;
+pub mod telemetry;
+pub mod transcript;
+
+/// Channels created from the configuration, each with its configured name.
+type NamedChannels = Vec<(String, Box<dyn Channel>)>;
 
 /// Top-level application struct.
 pub struct NekoBot {
@@ -22,16 +29,31 @@ pub struct NekoBot {
     middleware_registry: agent::MiddlewareRegistry,
     provider_registry: provider::ProviderRegistry,
     channel_registry: channel_registry::ChannelRegistry,
+    migration_registry: entity::migration::MigrationRegistry,
+    health: runtime::supervisor::HealthMonitor,
 }
 
 impl NekoBot {
     /// Create a new [`NekoBot`] from a parsed [`config::Config`].
+    ///
+    /// The core and channel credential migrations are registered already.
     pub fn new(config: config::Config) -> Self {
+        let mut migration_registry = entity::migration::MigrationRegistry::new();
+        for set in [
+            entity::migration::CORE_MIGRATIONS,
+            nekobot_channel::entity::MIGRATIONS,
+        ] {
+            migration_registry
+                .register(set)
+                .expect("built-in migrations are valid");
+        }
         Self {
             config,
             middleware_registry: agent::MiddlewareRegistry::new(),
             provider_registry: provider::ProviderRegistry::new(),
             channel_registry: channel_registry::ChannelRegistry::new(),
+            migration_registry,
+            health: runtime::supervisor::HealthMonitor::new(),
         }
     }
 
@@ -70,46 +92,99 @@ impl NekoBot {
         Ok(self)
     }
 
+    /// Register the schema migrations of a component, run by [`run`](NekoBot::run).
+    pub fn with_migrations(
+        mut self,
+        set: entity::migration::MigrationSet,
+    ) -> anyhow::Result<Self> {
+        self.migration_registry.register(set)?;
+        Ok(self)
+    }
+
     pub fn middleware_registry(&self) -> &agent::MiddlewareRegistry { &self.middleware_registry }
     pub fn middleware_registry_mut(&mut self) -> &mut agent::MiddlewareRegistry { &mut self.middleware_registry }
     pub fn provider_registry(&self) -> &provider::ProviderRegistry { &self.provider_registry }
     pub fn provider_registry_mut(&mut self) -> &mut provider::ProviderRegistry { &mut self.provider_registry }
     pub fn channel_registry(&self) -> &channel_registry::ChannelRegistry { &self.channel_registry }
     pub fn channel_registry_mut(&mut self) -> &mut channel_registry::ChannelRegistry { &mut self.channel_registry }
+    pub fn migration_registry(&self) -> &entity::migration::MigrationRegistry { &self.migration_registry }
+    pub fn migration_registry_mut(&mut self) -> &mut entity::migration::MigrationRegistry { &mut self.migration_registry }
 
-    async fn init(&self) -> Result<Vec<crate::runtime::channel::ChannelRuntime>, anyhow::Error> {
-        self.config.validate()?;
+    /// Check the configuration against the registered factories: unknown
+    /// channel, provider and middleware types, and middleware settings that
+    /// do not match their config type or that their factory rejects. Called
+    /// by [`run`](NekoBot::run) before anything starts.
+    pub fn check_config(&self) -> Result<(), config::schema::ConfigCheckError> {
+        config::schema::check(
+            &self.config,
+            &self.middleware_registry,
+            &self.provider_registry,
+            &self.channel_registry,
+        )
+    }
 
-        let db = self.init_database().await?;
+    /// JSON Schema of `config.yaml` with the settings of every registered
+    /// middleware.
+    pub fn config_schema(&self) -> serde_json::Value {
+        config::schema::schema(&self.middleware_registry)
+    }
+
+    /// Health of every channel runtime, keyed by configured channel name.
+    ///
+    /// The returned monitor is shared; clone it before calling
+    /// [`run`](NekoBot::run) to observe channels while the bot is running.
+    pub fn health(&self) -> runtime::supervisor::HealthMonitor {
+        self.health.clone()
+    }
+
+    async fn init(
+        &self,
+        db: &turso::Database,
+    ) -> Result<Vec<(String, crate::runtime::channel::ChannelRuntime)>, anyhow::Error> {
         let providers = self.init_providers()?;
         let channels = self.init_channels()?;
         tracing::info!("initialized {} channel(s)", channels.len());
         let agent_configs = self.build_agent_configs(&providers)?;
-        let gate = self.build_gate(&db)?;
+        let gate = self.build_gate(db)?;
 
-        self.build_runtimes(channels, &db, agent_configs, gate)
+        self.build_runtimes(channels, db, agent_configs, gate)
     }
 
     async fn init_database(&self) -> Result<turso::Database, anyhow::Error> {
-        use crate::entity::{
-            Entity, channel_chat_agent::ChannelChatAgent, message::Message,
-            persona, sender_gate_state::SenderGateState, session::Session,
-        };
-
         let db = turso::Builder::new_local(&self.config.database_path)
             .build()
             .await?;
         let conn = db.connect()?;
         crate::entity::enable_foreign_keys(&conn).await?;
-        Session::create_table(&conn).await?;
-        Message::create_table(&conn).await?;
-        ChannelChatAgent::create_table(&conn).await?;
-        SenderGateState::create_table(&conn).await?;
-        persona::create_table(&conn).await?;
+        self.migration_registry.migrate(&conn, false).await?;
         drop(conn);
         Ok(db)
     }
 
+    /// Open the configured database with all migrations applied, for
+    /// maintenance tasks such as exporting and importing conversations.
+    pub async fn open_database(&self) -> anyhow::Result<turso::Database> {
+        self.init_database().await
+    }
+
+    /// List the migrations [`run`](NekoBot::run) would apply to the configured
+    /// database, without applying them.
+    ///
+    /// Fails like `run` if the database is newer than the registered migrations.
+    /// A database file that does not exist yet is not created.
+    pub async fn pending_migrations(
+        &self,
+    ) -> anyhow::Result<Vec<entity::migration::PlannedMigration>> {
+        let path = &self.config.database_path;
+        if path != ":memory:" && !std::path::Path::new(path).exists() {
+            return Ok(self.migration_registry.all());
+        }
+        let db = turso::Builder::new_local(&self.config.database_path)
+            .build()
+            .await?;
+        self.migration_registry.migrate(&db.connect()?, true).await
+    }
+
     fn init_providers(
         &self,
     ) -> Result<
@@ -128,11 +203,16 @@ impl NekoBot {
             .collect::<Result<_, anyhow::Error>>()
     }
 
-    fn init_channels(&self) -> Result<Vec<Box<dyn Channel>>, anyhow::Error> {
+    fn init_channels(&self) -> Result<NamedChannels, anyhow::Error> {
         self.config
             .channels
             .iter()
-            .filter_map(|cc| self.channel_registry.create(cc).transpose())
+            .filter_map(|cc| {
+                self.channel_registry
+                    .create(cc)
+                    .map(|ch| ch.map(|ch| (cc.name().to_owned(), ch)))
+                    .transpose()
+            })
             .collect::<Result<_, anyhow::Error>>()
     }
 
@@ -140,9 +220,10 @@ impl NekoBot {
         &self,
         providers: &std::collections::HashMap<String, std::sync::Arc<dyn provider::Provider>>,
     ) -> Result<Vec<crate::agent::AgentSessionConfig>, anyhow::Error> {
-        use crate::agent::AgentSessionConfig;
+        use crate::agent::{AgentSessionConfig, delegate::AgentDirectory};
 
-        self.config
+        let configs: Vec<AgentSessionConfig> = self
+            .config
             .agents
             .iter()
             .map(|agent| {
@@ -158,8 +239,25 @@ impl NekoBot {
                         .unwrap_or_default(),
                     &self.middleware_registry,
                 )
+                .map(|config| {
+                    config.with_available_models(
+                        self.config
+                            .provider(&agent.provider)
+                            .map(|provider| provider.models().to_vec())
+                            .unwrap_or_default(),
+                    )
+                })
             })
-            .collect::<Result<_, anyhow::Error>>()
+            .collect::<Result<_, anyhow::Error>>()?;
+
+        let directory = std::sync::Arc::new(AgentDirectory::new(configs.clone()));
+        Ok(configs
+            .into_iter()
+            .map(|config| AgentSessionConfig {
+                audit: self.config.audit.enabled,
+                ..config.with_delegation(std::sync::Arc::clone(&directory))
+            })
+            .collect())
     }
 
     fn build_gate(
@@ -177,37 +275,65 @@ impl NekoBot {
                 let conn = db
                     .connect()
                     .context("failed to connect for gate")?;
-                Ok(std::sync::Arc::new(SessionGate::new(
-                    hash.clone(),
-                    valid_agents,
-                    conn,
-                )))
+                let hash = crate::auth::PasswordHash::parse(hash)?;
+                if hash.is_legacy() {
+                    tracing::warn!(
+                        "password_hash is an unsalted SHA-256 digest; replace it with the output of `nekobot hash-password`"
+                    );
+                }
+                Ok(std::sync::Arc::new(
+                    SessionGate::new(hash, valid_agents, conn)
+                        .with_login_config(self.config.login.clone())?,
+                ))
             })
             .transpose()
     }
 
     fn build_runtimes(
         &self,
-        channels: Vec<Box<dyn Channel>>,
+        channels: NamedChannels,
         db: &turso::Database,
         agent_configs: Vec<crate::agent::AgentSessionConfig>,
         gate: Option<std::sync::Arc<crate::runtime::session_gate::SessionGate>>,
-    ) -> anyhow::Result<Vec<crate::runtime::channel::ChannelRuntime>> {
-        use crate::runtime::channel::{ChannelContext, ChannelRuntime};
+    ) -> anyhow::Result<Vec<(String, crate::runtime::channel::ChannelRuntime)>> {
+        use crate::runtime::{
+            access::AccessControl,
+            channel::{ChannelContext, ChannelRuntime},
+            outbox::Outbox,
+        };
 
+        let outbox = Outbox::new();
+        let access = std::sync::Arc::new(AccessControl::new(
+            self.config.access.clone(),
+            db.connect().context("failed to connect for access control")?,
+        ));
         channels
             .into_iter()
-            .map(|ch| {
+            .map(|(name, ch)| {
                 let app_db = db.connect().context("failed to connect for runtime")?;
                 let mut rt = ChannelRuntime::new(
                     ch,
                     ChannelContext { app_db },
                     agent_configs.clone(),
-                );
+                )
+                .with_pass_unknown_commands(self.config.pass_unknown_commands)
+                .with_outbox(outbox.clone());
                 if let Some(ref g) = gate {
                     rt = rt.with_gate(std::sync::Arc::clone(g));
                 }
-                Ok(rt)
+                rt = rt.with_access(std::sync::Arc::clone(&access));
+                if !self.config.routes.is_empty() {
+                    let fallback = agent_configs
+                        .first()
+                        .map(|c| c.agent_name.clone())
+                        .unwrap_or_default();
+                    rt = rt.with_route(crate::runtime::route::from_rules(
+                        &self.config.routes,
+                        &name,
+                        fallback,
+                    ));
+                }
+                Ok((name, rt))
             })
             .collect()
     }
@@ -215,29 +341,138 @@ impl NekoBot {
     /// Validate config, initialize the database, wire up channel runtimes
     /// for every channel×agent combination, and run them concurrently.
     ///
-    /// Awaits all runtimes; returns early on first error or on SIGINT (Ctrl+C).
+    /// Each runtime is supervised: when it fails or its channel disconnects it
+    /// is restarted with backoff while the other channels keep running, and
+    /// its state is reported through [`health`](NekoBot::health). On SIGINT (Ctrl+C)
+    /// or SIGTERM, runtimes stop accepting channel events and in-flight agent
+    /// turns get [`shutdown_grace_secs`](config::Config::shutdown_grace_secs)
+    /// to finish and flush their replies before the remaining tasks are aborted.
     pub async fn run(&mut self) -> Result<(), anyhow::Error> {
-        use crate::runtime::Runtime;
+        use crate::runtime::supervisor::{RestartBackoff, supervise};
 
-        let runtimes = self.init().await?;
-        let handles: Vec<_> = runtimes
-            .into_iter()
-            .map(|mut rt| tokio::spawn(async move { rt.run().await }))
+        self.config.validate()?;
+        self.check_config()?;
+        let db = self.init_database().await?;
+        let runtimes = self.init(&db).await?;
+        // Background tasks below stop when `run` returns.
+        let _turn_pruner = match self.config.audit.retention_days {
+            Some(days) => Some(AbortOnDrop(tokio::spawn(prune_turns(db.connect()?, days)))),
+            None => None,
+        };
+        let policies: Vec<_> = self
+            .config
+            .agents
+            .iter()
+            .filter(|agent| agent.retention.is_enabled())
+            .map(|agent| (agent.name.clone(), agent.retention.clone()))
             .collect();
+        let _message_pruner = if policies.is_empty() {
+            None
+        } else {
+            Some(AbortOnDrop(tokio::spawn(retention::prune_messages(
+                db.connect()?,
+                policies,
+            ))))
+        };
+        let _metrics_server = match &self.config.telemetry.metrics_addr {
+            Some(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("failed to bind metrics endpoint {addr}"))?;
+                tracing::info!("serving metrics on http://{addr}/metrics");
+                Some(AbortOnDrop(tokio::spawn(telemetry::serve_metrics(listener))))
+            }
+            None => None,
+        };
+        let (trigger, signal) = runtime::shutdown::channel();
+        let mut tasks = tokio::task::JoinSet::new();
+        for (name, rt) in runtimes {
+            let health = self.health.reporter(name);
+            let rt = rt
+                .with_shutdown(signal.clone())
+                .with_health(health.clone());
+            tasks.spawn(supervise(rt, health, signal.clone(), RestartBackoff::default()));
+        }
 
         tokio::select! {
-            result = async {
-                for h in handles {
-                    h.await??;
-                }
-                Ok::<_, anyhow::Error>(())
-            } => result,
-            _ = tokio::signal::ctrl_c() => {
-                tracing::info!("received SIGINT, shutting down");
+            result = join_runtimes(&mut tasks) => return result,
+            name = shutdown_signal() => {
+                tracing::info!("received {name}, shutting down");
+            },
+        }
+
+        trigger.trigger();
+        let grace = std::time::Duration::from_secs(self.config.shutdown_grace_secs);
+        match tokio::time::timeout(grace, join_runtimes(&mut tasks)).await {
+            Ok(result) => result,
+            Err(_) => {
+                tracing::warn!(
+                    "runtimes did not stop within {}s, aborting",
+                    grace.as_secs()
+                );
+                tasks.abort_all();
                 Ok(())
+            }
+        }
+    }
+}
+
+/// Delete audit records older than `retention_days`, at startup and then hourly.
+async fn prune_turns(conn: turso::Connection, retention_days: u64) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        let cutoff = chrono::Utc::now().timestamp()
+            - i64::try_from(retention_days.saturating_mul(86_400)).unwrap_or(i64::MAX);
+        match entity::turn::Turn::delete_before(&conn, cutoff).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("pruned {n} audit record(s)"),
+            Err(e) => tracing::warn!("failed to prune audit records: {e:#}"),
+        }
+    }
+}
+
+/// Aborts a background task when dropped.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn join_runtimes(
+    tasks: &mut tokio::task::JoinSet<()>,
+) -> anyhow::Result<()> {
+    while let Some(result) = tasks.join_next().await {
+        result?;
+    }
+    Ok(())
+}
+
+/// Resolves on SIGINT, or on SIGTERM where supported, naming the signal.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = sigterm.recv() => "SIGTERM",
             },
+            Err(e) => {
+                tracing::warn!("failed to install SIGTERM handler: {e}");
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
         }
     }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "SIGINT"
+    }
 }
 
 #[cfg(test)]
@@ -248,6 +483,7 @@ mod tests {
         NekoBot,
         agent::{self},
         config::{self, MiddlewareConfig},
+        entity::migration::PlannedMigration,
     };
 
     struct TestMiddleware;
@@ -262,7 +498,15 @@ mod tests {
             providers: Vec::new(),
             agents: Vec::new(),
             password_hash: None,
+            login: config::LoginConfig::default(),
+            access: config::AccessConfig::default(),
             database_path: ":memory:".into(),
+            secret_key_file: None,
+            shutdown_grace_secs: 30,
+            pass_unknown_commands: false,
+            routes: Vec::new(),
+            telemetry: config::TelemetryConfig::default(),
+            audit: config::AuditConfig::default(),
         })
         .with_middleware("test", |_config| {
             Ok(Arc::new(TestMiddleware) as Arc<dyn agent::middleware::Middleware>)
@@ -275,4 +519,26 @@ mod tests {
         assert_eq!(middlewares.len(), 1);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn dry_run_leaves_a_missing_database_alone() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("nekobot.db");
+        let bot = NekoBot::new(serde_json::from_value(serde_json::json!({
+            "channels": [],
+            "providers": [],
+            "agents": [],
+            "database_path": path.to_str().unwrap(),
+        }))?);
+
+        let pending = bot.pending_migrations().await?;
+        assert_eq!(pending, bot.migration_registry.all());
+        assert!(pending.contains(&PlannedMigration {
+            component: "core",
+            version: 1,
+            description: "initial schema",
+        }));
+        assert!(!path.exists());
+        Ok(())
+    }
 }
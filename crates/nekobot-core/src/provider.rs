@@ -7,6 +7,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use tokio::sync::mpsc::Sender;
@@ -23,7 +24,7 @@ pub struct ProviderRequest {
 }
 
 /// Per-request model configuration overrides.
-#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(default)]
 pub struct ModelOptions {
     /// Model name override. Falls back to the provider's default model if `None`.
@@ -38,7 +39,7 @@ pub struct ModelOptions {
 }
 
 /// Feature flags advertised by a model.
-#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(default)]
 pub struct ModelCapabilities {
     pub streaming: bool,
@@ -139,6 +140,11 @@ impl ProviderRegistry {
         self.inner.register(name, create)
     }
 
+    /// Whether a factory is registered for the type name.
+    pub fn contains(&self, name: &str) -> bool {
+        self.inner.get(name).is_some()
+    }
+
     /// Create a provider from its config, looking up the factory by type name.
     ///
     /// Returns `Ok(None)` if no factory is registered for the config's type.
@@ -47,6 +47,13 @@ impl<C, O> FactoryRegistry<C, O> {
         Ok(())
     }
 
+    /// Registered keys, sorted.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.factories.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        keys
+    }
+
     /// Look up a factory by key.
     pub fn get(&self, key: &str) -> Option<&Arc<dyn Fn(&C) -> anyhow::Result<O> + Send + Sync>> {
         self.factories.get(key)
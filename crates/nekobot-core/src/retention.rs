@@ -0,0 +1,229 @@
+//! Message retention — prunes old history according to each agent's
+//! [`RetentionConfig`], optionally archiving it first.
+//!
+//! Archives are gzip-compressed JSONL, one file per session. Every pruning
+//! run appends a new gzip member, which `zcat` and other gzip readers read
+//! as one continuous stream.
+
+use std::{io::Write, path::PathBuf};
+
+use flate2::{Compression, write::GzEncoder};
+use turso::Connection;
+
+use crate::{
+    config::RetentionConfig,
+    entity::{message::Message, session::Session},
+};
+
+/// Prune every agent's sessions per its policy, at startup and then hourly.
+pub(crate) async fn prune_messages(conn: Connection, policies: Vec<(String, RetentionConfig)>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        for (agent_name, policy) in &policies {
+            match prune_agent(&conn, agent_name, policy).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!(agent = %agent_name, "pruned {n} message(s)"),
+                Err(e) => {
+                    tracing::warn!(agent = %agent_name, "failed to prune messages: {e:#}")
+                }
+            }
+        }
+    }
+}
+
+/// Prune the sessions of one agent; returns the number of deleted messages.
+///
+/// A session whose archive cannot be written keeps its messages.
+pub async fn prune_agent(
+    conn: &Connection,
+    agent_name: &str,
+    policy: &RetentionConfig,
+) -> anyhow::Result<u64> {
+    let cutoff = policy.max_age_days.map(|days| {
+        chrono::Utc::now().timestamp()
+            - i64::try_from(days.saturating_mul(86_400)).unwrap_or(i64::MAX)
+    });
+    let mut deleted = 0;
+    for session in Session::list_by_agent(conn, agent_name).await? {
+        let expired = Message::list_expired(conn, session.id, cutoff, policy.max_messages).await?;
+        if expired.is_empty() {
+            continue;
+        }
+        if let Some(dir) = &policy.archive_dir {
+            let path = PathBuf::from(dir)
+                .join(agent_name)
+                .join(format!("{}.jsonl.gz", session.id));
+            let lines = to_jsonl(&expired)?;
+            tokio::task::spawn_blocking(move || append_gzip(&path, &lines)).await??;
+        }
+        let ids: Vec<i64> = expired.iter().map(|m| m.id).collect();
+        deleted += Message::delete_many(conn, &ids).await?;
+    }
+    Ok(deleted)
+}
+
+fn to_jsonl(messages: &[Message]) -> anyhow::Result<Vec<u8>> {
+    let mut lines = Vec::new();
+    for message in messages {
+        serde_json::to_writer(
+            &mut lines,
+            &serde_json::json!({
+                "id": message.id,
+                "session_id": message.session_id,
+                "role": message.role,
+                "content": message.content,
+                "reasoning_content": message.reasoning_content,
+                "tool_call_id": message.tool_call_id,
+                "tool_calls": message.tool_calls,
+                "created_at": message.created_at,
+            }),
+        )?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+fn append_gzip(path: &std::path::Path, data: &[u8]) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::MultiGzDecoder;
+
+    use super::*;
+    use crate::entity::{Entity, test_connection};
+
+    #[tokio::test]
+    async fn prunes_old_and_excess_messages_into_the_archive() -> anyhow::Result<()> {
+        let conn = test_connection().await?;
+        Session::create_table(&conn).await?;
+        Message::create_table(&conn).await?;
+        let session = Session::create(&conn, "Neko").await?;
+        let other = Session::create(&conn, "Other").await?;
+        for content in ["old", "a", "b", "c", "d"] {
+            Message::create(&conn, session.id, "user", content, None, None, None).await?;
+        }
+        Message::create(&conn, other.id, "user", "untouched", None, None, None).await?;
+        conn.execute(
+            "UPDATE messages SET created_at = created_at - 10 * 86400 WHERE content = 'old'",
+            (),
+        )
+        .await?;
+
+        let dir = tempfile::tempdir()?;
+        let policy = RetentionConfig {
+            max_age_days: Some(7),
+            max_messages: Some(3),
+            archive_dir: Some(dir.path().to_string_lossy().into_owned()),
+        };
+        assert_eq!(prune_agent(&conn, "Neko", &policy).await?, 2);
+        assert_eq!(prune_agent(&conn, "Neko", &policy).await?, 0);
+
+        let kept: Vec<String> = Message::list_by_session(&conn, session.id)
+            .await?
+            .into_iter()
+            .map(|m| m.content)
+            .collect();
+        assert_eq!(kept, ["b", "c", "d"]);
+        assert_eq!(Message::count_by_session(&conn, other.id).await?, 1);
+
+        let archive = dir
+            .path()
+            .join("Neko")
+            .join(format!("{}.jsonl.gz", session.id));
+        let mut text = String::new();
+        MultiGzDecoder::new(std::fs::File::open(archive)?).read_to_string(&mut text)?;
+        let archived: Vec<serde_json::Value> = text
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(archived.len(), 2);
+        assert_eq!(archived[0]["content"], "old");
+        assert_eq!(archived[1]["content"], "a");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keeps_tool_replies_with_their_turn() -> anyhow::Result<()> {
+        let conn = test_connection().await?;
+        Session::create_table(&conn).await?;
+        Message::create_table(&conn).await?;
+        let session = Session::create(&conn, "Neko").await?;
+        let calls = Some(r#"[{"id":"1","name":"clock","arguments":"{}"}]"#.to_owned());
+        Message::create(&conn, session.id, "user", "hi", None, None, None).await?;
+        Message::create(&conn, session.id, "assistant", "hello", None, None, None).await?;
+        Message::create(&conn, session.id, "user", "time?", None, None, None).await?;
+        Message::create(&conn, session.id, "assistant", "", None, None, calls).await?;
+        Message::create(
+            &conn,
+            session.id,
+            "tool",
+            "12:00",
+            None,
+            Some("1".to_owned()),
+            None,
+        )
+        .await?;
+        Message::create(&conn, session.id, "assistant", "noon", None, None, None).await?;
+
+        let policy = RetentionConfig {
+            max_age_days: None,
+            max_messages: Some(2),
+            archive_dir: None,
+        };
+        assert_eq!(prune_agent(&conn, "Neko", &policy).await?, 2);
+
+        let kept: Vec<String> = Message::list_by_session(&conn, session.id)
+            .await?
+            .into_iter()
+            .map(|m| m.role)
+            .collect();
+        assert_eq!(kept, ["user", "assistant", "tool", "assistant"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prunes_sessions_driven_by_activations() -> anyhow::Result<()> {
+        let conn = test_connection().await?;
+        Session::create_table(&conn).await?;
+        Message::create_table(&conn).await?;
+        let session = Session::create(&conn, "Neko").await?;
+        for prompt in ["first reminder", "second reminder", "third reminder"] {
+            Message::create(&conn, session.id, "internal", prompt, None, None, None).await?;
+            Message::create(&conn, session.id, "assistant", "done", None, None, None).await?;
+        }
+
+        let policy = RetentionConfig {
+            max_age_days: None,
+            max_messages: Some(3),
+            archive_dir: None,
+        };
+        assert_eq!(prune_agent(&conn, "Neko", &policy).await?, 2);
+
+        let kept: Vec<String> = Message::list_by_session(&conn, session.id)
+            .await?
+            .into_iter()
+            .map(|m| m.content)
+            .collect();
+        assert_eq!(kept, ["second reminder", "done", "third reminder", "done"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,555 @@
+//! Access control — roles that grant agents and capabilities, sender lists
+//! per channel, group bindings and invite codes.
+//!
+//! A sender's roles come from `access.users` in the config and from invite
+//! codes redeemed with `/join`; senders without either hold
+//! `access.default_role`. Roles decide which agents a sender may `/connect`
+//! to, whether they may run [`Permission::Admin`] commands, and which of
+//! `access.restricted_tools` they may call. Without any configured role
+//! nothing is restricted.
+//!
+//! Group chats in [`GroupMode::Bound`] are ignored until an admin binds them
+//! to an agent with `/bind`; in [`GroupMode::Open`] a binding overrides the
+//! route.
+
+use std::{collections::BTreeSet, sync::Arc};
+
+use async_trait::async_trait;
+use turso::Connection;
+
+use super::{route::glob_match, session_gate::SessionGate};
+use crate::{
+    agent::command::{Command, CommandArgs, CommandContext, CommandSpec, Permission},
+    config::{AccessConfig, GroupMode, RoleConfig},
+    entity::{group_binding::GroupBinding, invite::Invite, sender_role::SenderRole},
+};
+
+/// Capability of the admin commands.
+pub const ADMIN: &str = "admin";
+
+/// Capability needed to call `tool` when it is listed in
+/// `access.restricted_tools`.
+pub fn tool_capability(tool: &str) -> String {
+    format!("tool:{tool}")
+}
+
+/// Agents or capabilities covered by a set of roles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Scope {
+    All,
+    Only(BTreeSet<String>),
+}
+
+impl Scope {
+    fn contains(&self, item: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(items) => items.contains(item),
+        }
+    }
+
+    fn extend(&mut self, items: &[String]) {
+        if items.iter().any(|item| item == "*") {
+            *self = Self::All;
+        } else if let Self::Only(set) = self {
+            set.extend(items.iter().cloned());
+        }
+    }
+}
+
+/// What a sender may do, merged from all their roles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grants {
+    /// Names of the roles held, sorted. Empty for [`Grants::all`].
+    pub roles: Vec<String>,
+    agents: Scope,
+    capabilities: Scope,
+}
+
+impl Grants {
+    /// Every agent and capability, for setups without roles.
+    pub fn all() -> Self {
+        Self {
+            roles: Vec::new(),
+            agents: Scope::All,
+            capabilities: Scope::All,
+        }
+    }
+
+    /// No agent and no capability.
+    pub fn none() -> Self {
+        Self {
+            roles: Vec::new(),
+            agents: Scope::Only(BTreeSet::new()),
+            capabilities: Scope::Only(BTreeSet::new()),
+        }
+    }
+
+    fn from_roles(roles: Vec<String>, config: &[RoleConfig]) -> Self {
+        let mut grants = Self::none();
+        for role in config.iter().filter(|role| roles.contains(&role.name)) {
+            grants.agents.extend(&role.agents);
+            grants.capabilities.extend(&role.capabilities);
+        }
+        grants.roles = roles;
+        grants
+    }
+
+    /// Whether the sender may talk to `agent`.
+    pub fn can_use_agent(&self, agent: &str) -> bool {
+        self.agents.contains(agent)
+    }
+
+    /// Whether the sender holds `capability`.
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.contains(capability)
+    }
+}
+
+/// Roles, sender lists, group bindings and invites of the `access` config.
+///
+/// One instance serves every channel; config entries are matched against
+/// the channel name, stored state is keyed by the channel ID.
+pub struct AccessControl {
+    config: AccessConfig,
+    conn: Connection,
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+impl AccessControl {
+    pub fn new(config: AccessConfig, conn: Connection) -> Self {
+        Self { config, conn }
+    }
+
+    /// Whether any role is configured. Without roles every sender gets
+    /// [`Grants::all`].
+    pub fn has_roles(&self) -> bool {
+        !self.config.roles.is_empty()
+    }
+
+    /// Whether a role named `name` is configured.
+    pub fn has_role(&self, name: &str) -> bool {
+        self.config.roles.iter().any(|role| role.name == name)
+    }
+
+    pub fn group_mode(&self) -> GroupMode {
+        self.config.groups
+    }
+
+    /// Whether the sender lists of `channel_name` let `sender_id` through.
+    pub fn admits(&self, channel_name: &str, sender_id: &str) -> bool {
+        self.config
+            .channels
+            .iter()
+            .filter(|lists| lists.channel == channel_name)
+            .all(|lists| {
+                (lists.allow.is_empty() || lists.allow.iter().any(|p| glob_match(p, sender_id)))
+                    && !lists.deny.iter().any(|p| glob_match(p, sender_id))
+            })
+    }
+
+    /// Grants of a sender from their configured and stored roles.
+    pub async fn grants(
+        &self,
+        channel_id: &str,
+        channel_name: &str,
+        sender_id: &str,
+    ) -> anyhow::Result<Grants> {
+        if !self.has_roles() {
+            return Ok(Grants::all());
+        }
+        let mut roles: Vec<String> = self
+            .config
+            .users
+            .iter()
+            .filter(|user| {
+                user.channel.as_deref().is_none_or(|c| c == channel_name)
+                    && glob_match(&user.sender_id, sender_id)
+            })
+            .flat_map(|user| user.roles.iter().cloned())
+            .collect();
+        roles.extend(SenderRole::roles_of(&self.conn, channel_id, sender_id).await?);
+        roles.sort();
+        roles.dedup();
+        if roles.is_empty()
+            && let Some(role) = &self.config.default_role
+        {
+            roles.push(role.clone());
+        }
+        Ok(Grants::from_roles(roles, &self.config.roles))
+    }
+
+    /// The restricted tools `grants` does not cover.
+    pub fn denied_tools(&self, grants: &Grants) -> Vec<String> {
+        self.config
+            .restricted_tools
+            .iter()
+            .filter(|tool| !grants.has(&tool_capability(tool)))
+            .cloned()
+            .collect()
+    }
+
+    /// Agent a group chat is bound to, if any.
+    pub async fn group_agent(
+        &self,
+        channel_id: &str,
+        chat_id: &str,
+    ) -> anyhow::Result<Option<String>> {
+        Ok(GroupBinding::get(&self.conn, channel_id, chat_id)
+            .await?
+            .map(|binding| binding.agent_name))
+    }
+
+    /// Bind a group chat to `agent`, replacing an earlier binding.
+    pub async fn bind_group(
+        &self,
+        channel_id: &str,
+        chat_id: &str,
+        agent: &str,
+        bound_by: &str,
+    ) -> anyhow::Result<()> {
+        GroupBinding {
+            channel_id: channel_id.to_owned(),
+            chat_id: chat_id.to_owned(),
+            agent_name: agent.to_owned(),
+            bound_by: bound_by.to_owned(),
+            bound_at: now(),
+        }
+        .upsert(&self.conn)
+        .await
+    }
+
+    /// Remove the binding of a group chat. Returns `true` if it was bound.
+    pub async fn unbind_group(&self, channel_id: &str, chat_id: &str) -> anyhow::Result<bool> {
+        GroupBinding::delete(&self.conn, channel_id, chat_id).await
+    }
+
+    /// Create a single-use code of `channel_id` that grants `role`.
+    pub async fn create_invite(
+        &self,
+        channel_id: &str,
+        created_by: &str,
+        role: &str,
+    ) -> anyhow::Result<String> {
+        use password_hash::rand_core::{OsRng, RngCore};
+
+        let mut bytes = [0u8; 10];
+        OsRng.fill_bytes(&mut bytes);
+        let code = data_encoding::BASE32_NOPAD.encode(&bytes);
+        let created_at = now();
+        Invite {
+            code: code.clone(),
+            channel_id: channel_id.to_owned(),
+            role: role.to_owned(),
+            created_by: created_by.to_owned(),
+            created_at,
+            expires_at: created_at + self.config.invite_ttl_secs as i64,
+            used_by: None,
+            used_at: None,
+        }
+        .create(&self.conn)
+        .await?;
+        Ok(code)
+    }
+
+    /// Redeem an invite code and grant its role to the sender. Returns the
+    /// role, or `None` if the code is unknown, used or expired.
+    pub async fn redeem_invite(
+        &self,
+        channel_id: &str,
+        sender_id: &str,
+        code: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let now = now();
+        let code = code.trim().to_ascii_uppercase();
+        let Some(invite) = Invite::redeem(&self.conn, channel_id, &code, sender_id, now).await?
+        else {
+            return Ok(None);
+        };
+        SenderRole {
+            channel_id: channel_id.to_owned(),
+            sender_id: sender_id.to_owned(),
+            role: invite.role.clone(),
+            granted_at: now,
+        }
+        .grant(&self.conn)
+        .await?;
+        tracing::info!(target: "gate", channel_id, sender_id, role = %invite.role, "invite redeemed");
+        Ok(Some(invite.role))
+    }
+
+    fn role_names(&self) -> String {
+        self.config
+            .roles
+            .iter()
+            .map(|role| role.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// `/invite <role>` — create an invite code that grants a role.
+pub struct InviteCommand {
+    access: Arc<AccessControl>,
+    spec: CommandSpec,
+}
+
+impl InviteCommand {
+    pub fn new(access: Arc<AccessControl>) -> Self {
+        Self {
+            access,
+            spec: CommandSpec::new("invite", "创建授予角色的邀请码")
+                .arg("role")
+                .permission(Permission::Admin),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for InviteCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        if !ctx.chat.chat_type.is_private() {
+            return Ok("请在私聊中创建邀请码".into());
+        }
+        let role = args.get("role").unwrap_or_default();
+        if !self.access.has_role(role) {
+            return Ok(format!(
+                "未知角色: {role}，可用: {}",
+                self.access.role_names()
+            ));
+        }
+        let code = self
+            .access
+            .create_invite(ctx.channel.id.as_str(), ctx.sender.id.as_str(), role)
+            .await?;
+        let hours = self.access.config.invite_ttl_secs.div_ceil(3600);
+        Ok(format!(
+            "邀请码: {code}\n{hours} 小时内有效，仅可使用一次。对方私聊发送 /join {code} 即可获得角色 {role}"
+        ))
+    }
+}
+
+/// `/join <code>` — redeem an invite code from a private chat. With a
+/// [`SessionGate`], redeeming also logs the sender in.
+pub struct JoinCommand {
+    access: Arc<AccessControl>,
+    gate: Option<Arc<SessionGate>>,
+    spec: CommandSpec,
+}
+
+impl JoinCommand {
+    pub fn new(access: Arc<AccessControl>, gate: Option<Arc<SessionGate>>) -> Self {
+        Self {
+            access,
+            gate,
+            spec: CommandSpec::new("join", "使用邀请码")
+                .arg("code")
+                .permission(Permission::Anyone),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for JoinCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        if !ctx.chat.chat_type.is_private() {
+            return Ok("请在私聊中使用邀请码".into());
+        }
+        let channel_id = ctx.channel.id.as_str();
+        let sender_id = ctx.sender.id.as_str();
+        let code = args.get("code").unwrap_or_default();
+        let Some(role) = self
+            .access
+            .redeem_invite(channel_id, sender_id, code)
+            .await?
+        else {
+            return Ok("邀请码无效或已过期".into());
+        };
+        match &self.gate {
+            Some(gate) => {
+                gate.admit(channel_id, sender_id).await?;
+                Ok(format!(
+                    "已获得角色 {role} 并登录，请 /connect <agent> 选择要连接的 agent"
+                ))
+            }
+            None => Ok(format!("已获得角色 {role}")),
+        }
+    }
+}
+
+/// `/bind <agent>` — bind a group chat to an agent.
+pub struct BindCommand {
+    access: Arc<AccessControl>,
+    agents: Vec<String>,
+    spec: CommandSpec,
+}
+
+impl BindCommand {
+    pub fn new(access: Arc<AccessControl>, agents: Vec<String>) -> Self {
+        Self {
+            access,
+            agents,
+            spec: CommandSpec::new("bind", "将本群绑定到 agent")
+                .arg("agent")
+                .permission(Permission::Admin),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for BindCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        if ctx.chat.chat_type.is_private() {
+            return Ok("请在群聊中使用 /bind".into());
+        }
+        let agent = args.get("agent").unwrap_or_default();
+        if !self.agents.iter().any(|a| a == agent) {
+            return Ok(format!(
+                "未知 agent: {agent}，可用: {}",
+                self.agents.join(", ")
+            ));
+        }
+        if !ctx.grants.can_use_agent(agent) {
+            return Ok(format!("无权使用 agent: {agent}"));
+        }
+        self.access
+            .bind_group(
+                ctx.channel.id.as_str(),
+                ctx.chat.id.as_str(),
+                agent,
+                ctx.sender.id.as_str(),
+            )
+            .await?;
+        Ok(format!("本群已绑定到 {agent}"))
+    }
+}
+
+/// `/unbind` — remove the binding of a group chat.
+pub struct UnbindCommand {
+    access: Arc<AccessControl>,
+    spec: CommandSpec,
+}
+
+impl UnbindCommand {
+    pub fn new(access: Arc<AccessControl>) -> Self {
+        Self {
+            access,
+            spec: CommandSpec::new("unbind", "解除本群的 agent 绑定").permission(Permission::Admin),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for UnbindCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, _args: &CommandArgs) -> anyhow::Result<String> {
+        if ctx.chat.chat_type.is_private() {
+            return Ok("请在群聊中使用 /unbind".into());
+        }
+        let unbound = self
+            .access
+            .unbind_group(ctx.channel.id.as_str(), ctx.chat.id.as_str())
+            .await?;
+        Ok(if unbound {
+            "已解除本群的绑定".into()
+        } else {
+            "本群未绑定 agent".into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::entity::Entity;
+
+    async fn access() -> anyhow::Result<AccessControl> {
+        let conn = crate::entity::test_connection().await?;
+        SenderRole::create_table(&conn).await?;
+        Invite::create_table(&conn).await?;
+        GroupBinding::create_table(&conn).await?;
+        let config: AccessConfig = serde_json::from_value(json!({
+            "roles": [
+                { "name": "admin", "agents": ["*"], "capabilities": ["*"] },
+                { "name": "member", "agents": ["Neko"], "capabilities": ["tool:web_search"] },
+                { "name": "guest", "agents": ["Neko"] }
+            ],
+            "users": [{ "channel": "qq", "sender_id": "owner", "roles": ["admin"] }],
+            "default_role": "guest",
+            "restricted_tools": ["bash", "web_search"],
+            "channels": [{ "channel": "qq", "deny": ["spam*"] }]
+        }))?;
+        Ok(AccessControl::new(config, conn))
+    }
+
+    #[tokio::test]
+    async fn merges_configured_stored_and_default_roles() -> anyhow::Result<()> {
+        let access = access().await?;
+
+        let owner = access.grants("qq-1", "qq", "owner").await?;
+        assert_eq!(owner.roles, ["admin"]);
+        assert!(owner.has(ADMIN) && owner.can_use_agent("Tama"));
+        assert!(access.denied_tools(&owner).is_empty());
+        // Configured users are matched by channel name.
+        assert_eq!(access.grants("wx-1", "wx", "owner").await?.roles, ["guest"]);
+
+        let guest = access.grants("qq-1", "qq", "alice").await?;
+        assert!(guest.can_use_agent("Neko") && !guest.can_use_agent("Tama"));
+        assert!(!guest.has(ADMIN));
+        assert_eq!(access.denied_tools(&guest), ["bash", "web_search"]);
+
+        let code = access.create_invite("qq-1", "owner", "member").await?;
+        assert_eq!(
+            access
+                .redeem_invite("qq-1", "alice", &code.to_lowercase())
+                .await?
+                .as_deref(),
+            Some("member")
+        );
+        assert!(access.redeem_invite("qq-1", "bob", &code).await?.is_none());
+        let member = access.grants("qq-1", "qq", "alice").await?;
+        assert_eq!(member.roles, ["member"]);
+        assert_eq!(access.denied_tools(&member), ["bash"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn filters_senders_and_binds_groups() -> anyhow::Result<()> {
+        let access = access().await?;
+        assert!(access.admits("qq", "alice"));
+        assert!(!access.admits("qq", "spammer"));
+        assert!(access.admits("wx", "spammer"));
+
+        assert_eq!(access.group_agent("qq-1", "group-1").await?, None);
+        access
+            .bind_group("qq-1", "group-1", "Neko", "owner")
+            .await?;
+        assert_eq!(
+            access.group_agent("qq-1", "group-1").await?.as_deref(),
+            Some("Neko")
+        );
+        assert!(access.unbind_group("qq-1", "group-1").await?);
+        assert!(!access.unbind_group("qq-1", "group-1").await?);
+        Ok(())
+    }
+}
@@ -5,32 +5,50 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use nekobot_channel::{
-    Channel, ChannelId, ChannelInfo, ChatId, ChatInfo, Event, ReplyTarget, Request,
+    Channel, ChannelId, ChannelInfo, ChatId, ChatInfo, Event, ReplyTarget, Request, SenderInfo,
 };
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::Instrument;
 use turso::Connection;
 
 use super::Runtime;
 use crate::{
     agent::{
         AgentOutput, AgentSession, AgentSessionConfig, AgentSessionHandle,
+        command::{Command, CommandContext, CommandRegistry, Permission, parse_command_line},
         middleware::AgentActivation,
     },
+    config::GroupMode,
     entity::{
         channel_chat_agent::{AgentName, ChannelChatAgent, NewChannelChatAgent, SessionId},
+        chat_model_override::ChatModelOverride,
+        chat_session::ChatSession,
+        schedule::Schedule,
         session::Session,
     },
 };
 
-use super::session_gate::{InterceptResult, SessionGate};
+use super::access::{
+    ADMIN, AccessControl, BindCommand, Grants, InviteCommand, JoinCommand, UnbindCommand,
+};
+use super::commands::register_builtin_commands;
+use super::outbox::{OutboundMessage, Outbox};
+use super::session_gate::{
+    ConnectCommand, InterceptResult, LoginCommand, LogoutCommand, SessionGate,
+};
+use super::shutdown::ShutdownSignal;
+use super::supervisor::{HealthReporter, HealthState};
 
 type ChannelAgentKey = (ChannelId, ChatId, AgentName);
 
-/// Decides which agent handles a given chat.
+const UNBOUND_GROUP_REPLY: &str = "本群未绑定 agent，请管理员 /bind <agent>";
+
+/// Decides which agent handles a given message.
 ///
-/// Receives `(channel_id, chat_id)` and returns the agent name. The default
-/// route returns the first configured agent for every chat.
-pub type AgentRoute = Arc<dyn Fn(&ChannelId, &ChatId) -> String + Send + Sync>;
+/// Receives the channel, chat and sender of the message and returns the agent
+/// name. The default route returns the first configured agent for every chat;
+/// [`route::from_rules`](super::route::from_rules) builds one from `routes:` config.
+pub type AgentRoute = Arc<dyn Fn(&ChannelInfo, &ChatInfo, &SenderInfo) -> String + Send + Sync>;
 
 /// Runtime that ties a [`Channel`] adapter to one or more agent sessions,
 /// routing each chat to an agent via [`AgentRoute`].
@@ -40,7 +58,16 @@ pub struct ChannelRuntime {
     agent_configs: Vec<AgentSessionConfig>,
     route: AgentRoute,
     gate: Option<Arc<SessionGate>>,
+    access: Option<Arc<AccessControl>>,
+    shutdown: Option<ShutdownSignal>,
+    health: Option<HealthReporter>,
+    outbox: Option<Outbox>,
+    commands: Arc<CommandRegistry>,
+    pass_unknown_commands: bool,
     sessions: HashMap<ChannelAgentKey, AgentSessionHandle>,
+    /// Earlier conversations of chats that keep running because they have
+    /// pending schedules.
+    background_sessions: HashMap<SessionId, AgentSessionHandle>,
     session_targets: HashMap<SessionId, ReplyTarget>,
 }
 
@@ -63,14 +90,24 @@ impl ChannelRuntime {
             .first()
             .map(|c| c.agent_name.clone())
             .unwrap_or_default();
-        let route: AgentRoute = Arc::new(move |_, _| first.clone());
+        let route: AgentRoute = Arc::new(move |_, _, _| first.clone());
+        let commands = Arc::new(CommandRegistry::new());
+        register_builtin_commands(&commands, &agent_configs)
+            .expect("built-in command names are unique");
         Self {
             channel,
             context,
             agent_configs,
             route,
             gate: None,
+            access: None,
+            shutdown: None,
+            health: None,
+            outbox: None,
+            commands,
+            pass_unknown_commands: false,
             sessions: HashMap::new(),
+            background_sessions: HashMap::new(),
             session_targets: HashMap::new(),
         }
     }
@@ -81,12 +118,86 @@ impl ChannelRuntime {
         self
     }
 
-    /// Attach a [`SessionGate`] for C2C access control.
+    /// Attach a [`SessionGate`] for C2C access control and register its
+    /// `/login`, `/logout` and `/connect` commands.
     pub fn with_gate(mut self, gate: Arc<SessionGate>) -> Self {
+        for command in [
+            Arc::new(LoginCommand::new(Arc::clone(&gate))) as Arc<dyn Command>,
+            Arc::new(LogoutCommand::new(Arc::clone(&gate))),
+            Arc::new(ConnectCommand::new(Arc::clone(&gate))),
+        ] {
+            if let Err(e) = self.commands.register(command) {
+                tracing::warn!(target: "runtime", "gate command not registered: {e:#}");
+            }
+        }
         self.gate = Some(gate);
         self
     }
 
+    /// Attach an [`AccessControl`] and register `/bind` and `/unbind`, plus
+    /// `/invite` and `/join` when roles are configured. Call after
+    /// [`with_gate`](ChannelRuntime::with_gate) so that `/join` logs senders in.
+    pub fn with_access(mut self, access: Arc<AccessControl>) -> Self {
+        let agents = self
+            .agent_configs
+            .iter()
+            .map(|c| c.agent_name.clone())
+            .collect();
+        let mut commands: Vec<Arc<dyn Command>> = vec![
+            Arc::new(BindCommand::new(Arc::clone(&access), agents)),
+            Arc::new(UnbindCommand::new(Arc::clone(&access))),
+        ];
+        if access.has_roles() {
+            commands.push(Arc::new(InviteCommand::new(Arc::clone(&access))));
+            commands.push(Arc::new(JoinCommand::new(
+                Arc::clone(&access),
+                self.gate.clone(),
+            )));
+        }
+        for command in commands {
+            if let Err(e) = self.commands.register(command) {
+                tracing::warn!(target: "runtime", "access command not registered: {e:#}");
+            }
+        }
+        self.access = Some(access);
+        self
+    }
+
+    /// Forward unknown `/commands` to the agent as ordinary messages instead
+    /// of replying with an error.
+    pub fn with_pass_unknown_commands(mut self, pass: bool) -> Self {
+        self.pass_unknown_commands = pass;
+        self
+    }
+
+    /// Runtime-wide slash commands. Register additional commands here; agent
+    /// middleware registers session-scoped ones through its
+    /// [`Context`](crate::agent::Context).
+    pub fn commands(&self) -> &CommandRegistry {
+        &self.commands
+    }
+
+    /// Attach a [`ShutdownSignal`]. Once it fires, the runtime stops accepting
+    /// channel events, lets in-flight agent turns finish, flushes their
+    /// replies and returns from [`run`](Runtime::run).
+    pub fn with_shutdown(mut self, shutdown: ShutdownSignal) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Attach a [`HealthReporter`], marked [`HealthState::Up`] once the
+    /// channel has registered.
+    pub fn with_health(mut self, health: HealthReporter) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Exchange messages for other chats with the runtimes sharing `outbox`.
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
     async fn handle_channel_event(
         &mut self,
         channel_info: &ChannelInfo,
@@ -99,11 +210,34 @@ impl ChannelRuntime {
                 sender,
                 content,
             } => {
-                // Gate interception — login / connect before agent (private chats only).
+                if let Some(access) = &self.access
+                    && !access.admits(channel_info.name.as_str(), sender.id.as_str())
+                {
+                    tracing::debug!(target: "runtime", sender_id = sender.id.as_str(), "dropped message of a denied sender");
+                    return Ok(());
+                }
+
+                if let Some((name, args)) = parse_command_line(&content)
+                    && let Some(reply) = self
+                        .handle_command(channel_info, &chat, &sender, name, args, &output_sender)
+                        .await?
+                {
+                    self.channel
+                        .send(Request::SendMessage {
+                            target: chat.reply_target.clone(),
+                            content: reply,
+                        })
+                        .await?;
+                    return Ok(());
+                }
+
+                let grants = self.grants(channel_info, &sender).await?;
+                // Gate interception — require login / connect before agent in private
+                // chats; groups may be bound to an agent.
                 let agent_name_override = if chat.chat_type.is_private() {
                     if let Some(gate) = &self.gate {
                         match gate
-                            .intercept(channel_info.id.as_str(), sender.id.as_str(), &content)
+                            .intercept(channel_info.id.as_str(), sender.id.as_str(), &grants)
                             .await?
                         {
                             InterceptResult::Reject { reply } => {
@@ -121,19 +255,48 @@ impl ChannelRuntime {
                         None
                     }
                 } else {
-                    None
+                    let bound = self.bound_agent(channel_info, &chat).await?;
+                    if bound.is_none() && self.group_mode() == GroupMode::Bound {
+                        self.channel
+                            .send(Request::SendMessage {
+                                target: chat.reply_target.clone(),
+                                content: UNBOUND_GROUP_REPLY.into(),
+                            })
+                            .await?;
+                        return Ok(());
+                    }
+                    bound
                 };
 
+                let agent_name = agent_name_override
+                    .unwrap_or_else(|| (self.route)(channel_info, &chat, &sender));
+                if !grants.can_use_agent(&agent_name) {
+                    self.channel
+                        .send(Request::SendMessage {
+                            target: chat.reply_target.clone(),
+                            content: format!("无权使用 agent: {agent_name}"),
+                        })
+                        .await?;
+                    return Ok(());
+                }
+                let denied_tools = self
+                    .access
+                    .as_ref()
+                    .map(|access| access.denied_tools(&grants))
+                    .unwrap_or_default();
                 let handle = self
-                    .ensure_agent_session(channel_info, &chat, output_sender, agent_name_override)
+                    .ensure_agent_session(channel_info, &chat, output_sender, &agent_name)
                     .await?;
 
                 handle
                     .activation_sender
                     .send(AgentActivation::ChannelMessage {
+                        channel_name: channel_info.name.to_string(),
                         chat_name: chat.name.into_inner(),
+                        chat_type: chat.chat_type,
                         sender_name: sender.name.into_inner(),
                         content,
+                        denied_tools,
                     })
                     .await?;
             }
@@ -142,20 +305,218 @@ impl ChannelRuntime {
         Ok(())
     }
 
+    /// Run a slash command and return its reply, or `None` if the message
+    /// should fall through to the agent.
+    ///
+    /// Runtime commands are looked up first, then the commands registered by
+    /// the middleware of the chat's agent session.
+    async fn handle_command(
+        &mut self,
+        channel_info: &ChannelInfo,
+        chat: &ChatInfo,
+        sender: &SenderInfo,
+        name: &str,
+        args: &str,
+        output_sender: &Sender<AgentOutput>,
+    ) -> anyhow::Result<Option<String>> {
+        let grants = self.grants(channel_info, sender).await?;
+        let login = match &self.gate {
+            Some(gate) => gate
+                .state(channel_info.id.as_str(), sender.id.as_str())
+                .await?
+                .filter(|state| gate.is_logged_in(state)),
+            None => None,
+        };
+        // The gate only applies to private chats; elsewhere everyone counts as
+        // logged in, unless the group waits for a `/bind`.
+        let (mut permission, agent_name) = if chat.chat_type.is_private() {
+            match (&self.gate, login.as_ref()) {
+                (Some(_), Some(state)) => (Permission::LoggedIn, state.connected_agent.clone()),
+                (Some(_), None) => (Permission::Anyone, None),
+                (None, _) => (
+                    Permission::LoggedIn,
+                    Some((self.route)(channel_info, chat, sender)),
+                ),
+            }
+        } else {
+            match self.bound_agent(channel_info, chat).await? {
+                Some(agent) => (Permission::LoggedIn, Some(agent)),
+                None if self.group_mode() == GroupMode::Bound => (Permission::Anyone, None),
+                None => (
+                    Permission::LoggedIn,
+                    Some((self.route)(channel_info, chat, sender)),
+                ),
+            }
+        };
+        // Admins prove who they are with a login, or by a configured role
+        // where there is no gate.
+        let identified = match &self.gate {
+            Some(_) => login.is_some(),
+            None => self
+                .access
+                .as_ref()
+                .is_some_and(|access| access.has_roles()),
+        };
+        if identified && grants.has(ADMIN) {
+            permission = Permission::Admin;
+        }
+        let agent_name = agent_name.filter(|agent| {
+            self.agent_configs.iter().any(|c| &c.agent_name == agent) && grants.can_use_agent(agent)
+        });
+
+        let mut command = self.commands.get(name)?;
+        let mut session_commands = agent_name
+            .as_ref()
+            .and_then(|agent| {
+                self.sessions.get(&(
+                    channel_info.id.clone(),
+                    chat.id.clone(),
+                    AgentName::from(agent.as_str()),
+                ))
+            })
+            .map(|handle| Arc::clone(&handle.commands));
+        // Middleware commands live in the agent session. Start it for the
+        // lookup only if the chat has one already, so that an unknown command
+        // creates nothing.
+        if command.is_none()
+            && session_commands.is_none()
+            && permission >= Permission::LoggedIn
+            && let Some(agent) = &agent_name
+            && let Some(mapping) = ChannelChatAgent::get_by_channel_chat_agent(
+                &self.context.app_db,
+                &channel_info.id,
+                &chat.id,
+                &AgentName::from(agent.as_str()),
+            )
+            .await?
+        {
+            let handle = self
+                .session_for_mapping(mapping, output_sender.clone())
+                .await?;
+            session_commands = Some(handle.commands);
+        }
+        if command.is_none()
+            && let Some(session_commands) = &session_commands
+        {
+            command = session_commands.get(name)?;
+        }
+
+        let Some(command) = command else {
+            if self.pass_unknown_commands {
+                return Ok(None);
+            }
+            return Ok(Some(format!("未知命令: /{name}，发送 /help 查看可用命令")));
+        };
+        let spec = command.spec();
+        if spec.required_permission(chat.chat_type) > permission {
+            let reply = if permission >= Permission::LoggedIn {
+                "需要管理员权限"
+            } else if chat.chat_type.is_private() {
+                "请先 /login <password>"
+            } else {
+                UNBOUND_GROUP_REPLY
+            };
+            return Ok(Some(reply.into()));
+        }
+        let args = match spec.parse_args(args) {
+            Ok(args) => args,
+            Err(usage) => return Ok(Some(format!("用法: {usage}"))),
+        };
+
+        let mut commands = self.commands.specs()?;
+        if let Some(session_commands) = &session_commands {
+            for spec in session_commands.specs()? {
+                if !commands.iter().any(|c| c.name == spec.name) {
+                    commands.push(spec);
+                }
+            }
+        }
+        commands.retain(|c| c.required_permission(chat.chat_type) <= permission);
+        commands.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let session_id = match &agent_name {
+            Some(agent) => ChannelChatAgent::get_by_channel_chat_agent(
+                &self.context.app_db,
+                &channel_info.id,
+                &chat.id,
+                &AgentName::from(agent.as_str()),
+            )
+            .await?
+            .map(|mapping| mapping.session_id.as_i64()),
+            None => None,
+        };
+        let ctx = CommandContext {
+            channel: channel_info.clone(),
+            chat: chat.clone(),
+            sender: sender.clone(),
+            agent_name,
+            session_id,
+            permission,
+            grants,
+            commands,
+            app_db: self.context.app_db.clone(),
+        };
+
+        match command.execute(&ctx, &args).await {
+            Ok(reply) => Ok(Some(reply)),
+            Err(e) => {
+                tracing::error!(target: "runtime", "command /{name} failed: {e:#}");
+                Ok(Some(format!("命令执行失败: /{name}")))
+            }
+        }
+    }
+
+    /// Grants of the sender; everything when no [`AccessControl`] is attached.
+    async fn grants(
+        &self,
+        channel_info: &ChannelInfo,
+        sender: &SenderInfo,
+    ) -> anyhow::Result<Grants> {
+        match &self.access {
+            Some(access) => {
+                access
+                    .grants(
+                        channel_info.id.as_str(),
+                        channel_info.name.as_str(),
+                        sender.id.as_str(),
+                    )
+                    .await
+            }
+            None => Ok(Grants::all()),
+        }
+    }
+
+    fn group_mode(&self) -> GroupMode {
+        self.access
+            .as_ref()
+            .map_or(GroupMode::Open, |access| access.group_mode())
+    }
+
+    /// Configured agent a group chat is bound to with `/bind`, if any.
+    async fn bound_agent(
+        &self,
+        channel_info: &ChannelInfo,
+        chat: &ChatInfo,
+    ) -> anyhow::Result<Option<String>> {
+        let Some(access) = &self.access else {
+            return Ok(None);
+        };
+        let agent = access
+            .group_agent(channel_info.id.as_str(), chat.id.as_str())
+            .await?;
+        Ok(agent.filter(|agent| self.agent_configs.iter().any(|c| &c.agent_name == agent)))
+    }
+
     async fn ensure_agent_session(
         &mut self,
         channel_info: &ChannelInfo,
         chat: &ChatInfo,
         output_sender: Sender<AgentOutput>,
-        agent_name_override: Option<String>,
+        agent_name_str: &str,
     ) -> anyhow::Result<AgentSessionHandle> {
-        let agent_name_str =
-            agent_name_override.unwrap_or_else(|| (self.route)(&channel_info.id, &chat.id));
-        let config = self
-            .agent_configs
-            .iter()
-            .find(|c| c.agent_name == agent_name_str)
-            .ok_or_else(|| anyhow::anyhow!("no agent config found for agent '{agent_name_str}'"))?;
+        if !self.agent_configs.iter().any(|c| c.agent_name == agent_name_str) {
+            anyhow::bail!("no agent config found for agent '{agent_name_str}'");
+        }
 
         let agent_name = AgentName::from(agent_name_str);
         let mapping = match ChannelChatAgent::get_by_channel_chat_agent(
@@ -186,7 +547,7 @@ impl ChannelRuntime {
             }
             None => {
                 let session = Session::create(&self.context.app_db, agent_name.as_str()).await?;
-                ChannelChatAgent::create(
+                let mapping = ChannelChatAgent::create(
                     &self.context.app_db,
                     NewChannelChatAgent {
                         channel_id: channel_info.id.clone(),
@@ -198,10 +559,23 @@ impl ChannelRuntime {
                         session_id: SessionId::from(session.id),
                     },
                 )
-                .await?
+                .await?;
+                ChatSession::record(&self.context.app_db, mapping.id, mapping.session_id, None)
+                    .await?;
+                mapping
             }
         };
 
+        self.session_for_mapping(mapping, output_sender).await
+    }
+
+    /// Return the running session of `mapping`, starting it if needed.
+    async fn session_for_mapping(
+        &mut self,
+        mapping: ChannelChatAgent,
+        output_sender: Sender<AgentOutput>,
+    ) -> anyhow::Result<AgentSessionHandle> {
+        let config = self.session_config(&mapping).await?;
         self.session_targets
             .insert(mapping.session_id, mapping.reply_target.clone());
 
@@ -211,11 +585,32 @@ impl ChannelRuntime {
             mapping.agent_name.clone(),
         );
 
+        // `/new`, `/resume` and `/model` change the mapping or its overrides;
+        // a stale handle is dropped so the old session drains and stops,
+        // unless the chat switched away from a conversation with schedules.
         if let Some(handle) = self.sessions.get(&key) {
-            return Ok(handle.clone());
+            if handle.session_id == mapping.session_id.as_i64()
+                && handle.model_options == config.model_options
+            {
+                return Ok(handle.clone());
+            }
+            if let Some(stale) = self.sessions.remove(&key)
+                && stale.session_id != mapping.session_id.as_i64()
+                && Schedule::next_run(&self.context.app_db, stale.session_id)
+                    .await?
+                    .is_some()
+            {
+                self.background_sessions
+                    .insert(SessionId::from(stale.session_id), stale);
+            }
+        }
+        if let Some(handle) = self.background_sessions.remove(&mapping.session_id)
+            && handle.model_options == config.model_options
+        {
+            self.sessions.insert(key, handle.clone());
+            return Ok(handle);
         }
 
-        let config = config.clone();
         let middlewares = config.resolve_middlewares()?;
         let agent_session = AgentSession::new(mapping.session_id.as_i64(), config);
         let handle = agent_session
@@ -226,7 +621,78 @@ impl ChannelRuntime {
         Ok(handle)
     }
 
-    async fn handle_agent_output(&self, output: AgentOutput) -> anyhow::Result<()> {
+    /// Session config of the agent of `mapping`, with the chat's model overrides.
+    async fn session_config(
+        &self,
+        mapping: &ChannelChatAgent,
+    ) -> anyhow::Result<AgentSessionConfig> {
+        let mut config = self
+            .agent_configs
+            .iter()
+            .find(|c| c.agent_name == mapping.agent_name.as_str())
+            .ok_or_else(|| {
+                anyhow::anyhow!("no agent config found for agent '{}'", mapping.agent_name)
+            })?
+            .clone();
+        if let Some(overrides) = ChatModelOverride::get(&self.context.app_db, mapping.id).await? {
+            let options = overrides.apply(&config.model_options, &config.available_models);
+            config.check_capabilities = options.model != config.model_options.model;
+            config.model_options = options;
+        }
+        Ok(config)
+    }
+
+    /// Start the sessions of this channel that have pending schedules, so
+    /// their scheduler middleware re-arms without waiting for a message.
+    ///
+    /// Earlier conversations of a chat run next to its current one and
+    /// reply to the same chat.
+    async fn resume_scheduled_sessions(
+        &mut self,
+        channel_info: &ChannelInfo,
+        output_sender: &Sender<AgentOutput>,
+    ) -> anyhow::Result<()> {
+        let scheduled =
+            ChannelChatAgent::list_scheduled(&self.context.app_db, &channel_info.id).await?;
+        for (mapping, session_id) in scheduled {
+            let result = if mapping.session_id == session_id {
+                self.session_for_mapping(mapping, output_sender.clone())
+                    .await
+                    .map(drop)
+            } else {
+                self.start_background_session(mapping, session_id, output_sender.clone())
+                    .await
+            };
+            if let Err(e) = result {
+                tracing::warn!(target: "runtime", "failed to resume session {session_id:?}: {e:#}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Start `session_id`, an earlier conversation of the chat of `mapping`.
+    async fn start_background_session(
+        &mut self,
+        mapping: ChannelChatAgent,
+        session_id: SessionId,
+        output_sender: Sender<AgentOutput>,
+    ) -> anyhow::Result<()> {
+        let config = self.session_config(&mapping).await?;
+        self.session_targets
+            .insert(session_id, mapping.reply_target.clone());
+        let middlewares = config.resolve_middlewares()?;
+        let handle = AgentSession::new(session_id.as_i64(), config)
+            .start(middlewares, self.context.app_db.clone(), output_sender)
+            .await?;
+        self.background_sessions.insert(session_id, handle);
+        Ok(())
+    }
+
+    async fn handle_agent_output(
+        &self,
+        channel_info: &ChannelInfo,
+        output: AgentOutput,
+    ) -> anyhow::Result<()> {
         match output {
             AgentOutput::SendMessage {
                 session_id,
@@ -246,6 +712,31 @@ impl ChannelRuntime {
                     })
                     .await?;
             }
+            AgentOutput::SendToChat {
+                channel_id,
+                reply_target,
+                content,
+            } => {
+                if channel_id == channel_info.id {
+                    self.channel
+                        .send(Request::SendMessage {
+                            target: reply_target,
+                            content,
+                        })
+                        .await?;
+                } else {
+                    let outbox = self.outbox.as_ref().ok_or_else(|| {
+                        anyhow::anyhow!("cannot reach channel {channel_id} without an outbox")
+                    })?;
+                    outbox.deliver(
+                        &channel_id,
+                        OutboundMessage {
+                            target: reply_target,
+                            content,
+                        },
+                    )?;
+                }
+            }
         }
 
         Ok(())
@@ -257,9 +748,21 @@ impl Runtime for ChannelRuntime {
     async fn run(&mut self) -> anyhow::Result<()> {
         let (event_sender, mut event_receiver) = tokio::sync::mpsc::channel(64);
         let (output_sender, mut output_receiver) = tokio::sync::mpsc::channel(64);
+        let mut shutdown = self.shutdown.clone();
         tracing::info!(target: "runtime", "registering channel...");
         let channel_info = self.channel.register(event_sender, Some(self.context.app_db.clone())).await?;
+        let mut inbox = self
+            .outbox
+            .as_ref()
+            .map(|outbox| outbox.register(channel_info.id.clone()))
+            .transpose()?;
         tracing::info!(target: "runtime", "channel {} registered as {}", channel_info.name, channel_info.id.as_str());
+        if let Some(health) = &self.health {
+            health.set(HealthState::Up);
+        }
+        if let Err(e) = self.resume_scheduled_sessions(&channel_info, &output_sender).await {
+            tracing::error!(target: "runtime", "failed to resume scheduled sessions: {e:#}");
+        }
 
         loop {
             tokio::select! {
@@ -267,7 +770,13 @@ impl Runtime for ChannelRuntime {
                     let Some(event) = event else {
                         break;
                     };
-                    if let Err(e) = self.handle_channel_event(&channel_info, event, output_sender.clone()).await {
+                    crate::telemetry::metrics().record_channel_event(channel_info.name.as_str());
+                    let span = tracing::info_span!("channel.event", channel = %channel_info.name);
+                    if let Err(e) = self
+                        .handle_channel_event(&channel_info, event, output_sender.clone())
+                        .instrument(span)
+                        .await
+                    {
                         tracing::error!(target: "runtime", "channel event error: {e:#}");
                     }
                 }
@@ -275,17 +784,63 @@ impl Runtime for ChannelRuntime {
                     let Some(output) = output else {
                         break;
                     };
-                    if let Err(e) = self.handle_agent_output(output).await {
+                    if let Err(e) = self.handle_agent_output(&channel_info, output).await {
                         tracing::error!(target: "runtime", "agent output error: {e:#}");
                     }
                 }
+                Some(message) = recv_inbox(&mut inbox) => {
+                    if let Err(e) = self
+                        .channel
+                        .send(Request::SendMessage {
+                            target: message.target,
+                            content: message.content,
+                        })
+                        .await
+                    {
+                        tracing::error!(target: "runtime", "outbox delivery error: {e:#}");
+                    }
+                }
+                _ = wait_for_shutdown(&mut shutdown) => {
+                    tracing::info!(target: "runtime", "channel {} shutting down", channel_info.name);
+                    break;
+                }
+            }
+        }
+
+        // Stop accepting channel events, then close the activation queue of
+        // every session, including those kept for schedules. Sessions finish
+        // their queued turns and drop their output senders, so the receiver
+        // below ends once all replies are flushed.
+        drop(event_receiver);
+        drop(output_sender);
+        self.sessions.clear();
+        self.background_sessions.clear();
+        while let Some(output) = output_receiver.recv().await {
+            if let Err(e) = self.handle_agent_output(&channel_info, output).await {
+                tracing::error!(target: "runtime", "agent output error: {e:#}");
             }
         }
+        // Reply targets are needed until the last reply above went out.
+        self.session_targets.clear();
 
         Ok(())
     }
 }
 
+async fn recv_inbox(inbox: &mut Option<Receiver<OutboundMessage>>) -> Option<OutboundMessage> {
+    match inbox {
+        Some(inbox) => inbox.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn wait_for_shutdown(shutdown: &mut Option<ShutdownSignal>) {
+    match shutdown {
+        Some(signal) => signal.wait().await,
+        None => std::future::pending().await,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::{
@@ -405,6 +960,9 @@ mod tests {
         Session::create_table(&conn).await?;
         Message::create_table(&conn).await?;
         ChannelChatAgent::create_table(&conn).await?;
+        ChatSession::create_table(&conn).await?;
+        ChatModelOverride::create_table(&conn).await?;
+        Schedule::create_table(&conn).await?;
         let (runtime, calls) = runtime_with_connection(channel, conn.clone(), "Neko");
         Ok((runtime, conn, calls))
     }
@@ -422,6 +980,10 @@ mod tests {
             middlewares: Vec::new(),
             max_message_count: None,
             max_tool_iterations: 10,
+            delegates: Vec::new(),
+            max_delegation_depth: 2,
+            system_prompt: None,
+            retention: Default::default(),
         };
         let middleware_registry = crate::agent::MiddlewareRegistry::new();
         let agent_session_config = AgentSessionConfig::from_agent_config(
@@ -594,11 +1156,13 @@ mod tests {
         Session::create_table(&conn).await?;
         Message::create_table(&conn).await?;
         ChannelChatAgent::create_table(&conn).await?;
+        ChatSession::create_table(&conn).await?;
+        ChatModelOverride::create_table(&conn).await?;
         neko_runtime
-            .ensure_agent_session(&channel_info, &chat, output_sender.clone(), None)
+            .ensure_agent_session(&channel_info, &chat, output_sender.clone(), "Neko")
             .await?;
         mimi_runtime
-            .ensure_agent_session(&channel_info, &chat, output_sender, None)
+            .ensure_agent_session(&channel_info, &chat, output_sender, "Mimi")
             .await?;
 
         let neko_mapping = ChannelChatAgent::get_by_channel_chat_agent(
@@ -675,16 +1239,498 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn shutdown_flushes_in_flight_replies_and_returns() -> anyhow::Result<()> {
+        let channel = TestChannel::new();
+        let (runtime, _conn, calls) = runtime(channel.clone()).await?;
+        let (trigger, signal) = crate::runtime::shutdown::channel();
+        let mut runtime = runtime.with_shutdown(signal);
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        channel
+            .emit(Event::IncomingMessage {
+                chat: chat("chat-1", "Alice", "target-1"),
+                sender: sender("sender-alice", "Alice"),
+                content: "hello".to_owned(),
+            })
+            .await?;
+        while calls.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        trigger.trigger();
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), runtime_task).await???;
+
+        assert_eq!(
+            channel.sent_requests().await,
+            vec![Request::SendMessage {
+                target: ReplyTarget::from("target-1"),
+                content: "echo: hello".to_owned(),
+            }]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slash_commands_are_answered_without_calling_provider() -> anyhow::Result<()> {
+        let channel = TestChannel::new();
+        let (mut runtime, conn, calls) = runtime(channel.clone()).await?;
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        for (index, content) in ["hello", "/reset", "/help", "/nope"].into_iter().enumerate() {
+            channel
+                .emit(Event::IncomingMessage {
+                    chat: chat("chat-1", "Alice", "target-1"),
+                    sender: sender("sender-alice", "Alice"),
+                    content: content.to_owned(),
+                })
+                .await?;
+            wait_for_sent_requests(&channel, index + 1).await;
+        }
+
+        let replies: Vec<String> = channel
+            .sent_requests()
+            .await
+            .into_iter()
+            .map(|request| match request {
+                Request::SendMessage { content, .. } => content,
+                other => panic!("unexpected request: {other:?}"),
+            })
+            .collect();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(replies[1], "已清空 2 条消息");
+        assert!(replies[2].contains("/reset"));
+        assert!(replies[2].contains("/whoami"));
+        assert_eq!(replies[3], "未知命令: /nope，发送 /help 查看可用命令");
+
+        let mapping = ChannelChatAgent::get_by_channel_chat_agent(
+            &conn,
+            &ChannelId::from("test-channel"),
+            &ChatId::from("chat-1"),
+            &AgentName::from("Neko"),
+        )
+        .await?
+        .expect("mapping should exist");
+        assert!(Message::list_by_session(&conn, mapping.session_id.as_i64()).await?.is_empty());
+
+        runtime_task.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn new_and_resume_switch_conversations_and_keep_history() -> anyhow::Result<()> {
+        let channel = TestChannel::new();
+        let (mut runtime, conn, _calls) = runtime(channel.clone()).await?;
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        let mut sent = 0;
+        reply(&channel, &mut sent, "first").await?;
+        let first = current_session(&conn).await?;
+        reply(&channel, &mut sent, "/new work").await?;
+        let second = current_session(&conn).await?;
+        assert_ne!(first, second);
+        assert_eq!(reply(&channel, &mut sent, "second").await?, "echo: second");
+
+        let listing = reply(&channel, &mut sent, "/sessions").await?;
+        assert!(listing.contains(&format!("#{first} （未命名） · 2 条消息")));
+        assert!(listing.contains(&format!("#{second} work · 2 条消息（当前）")));
+
+        assert_eq!(
+            reply(&channel, &mut sent, &format!("/resume {first}")).await?,
+            format!("已切换到会话 #{first}")
+        );
+        reply(&channel, &mut sent, "again").await?;
+        assert_eq!(current_session(&conn).await?, first);
+        assert_eq!(Message::list_by_session(&conn, first).await?.len(), 4);
+        assert_eq!(Message::list_by_session(&conn, second).await?.len(), 2);
+        assert_eq!(
+            reply(&channel, &mut sent, "/resume 999").await?,
+            "本聊天没有会话 #999，发送 /sessions 查看"
+        );
+
+        runtime_task.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_and_import_commands_copy_conversations() -> anyhow::Result<()> {
+        use crate::entity::{group_binding::GroupBinding, invite::Invite, sender_role::SenderRole};
+
+        let channel = TestChannel::new();
+        let (runtime, conn, _calls) = runtime(channel.clone()).await?;
+        SenderRole::create_table(&conn).await?;
+        Invite::create_table(&conn).await?;
+        GroupBinding::create_table(&conn).await?;
+        let config: crate::config::AccessConfig = serde_json::from_value(serde_json::json!({
+            "roles": [{ "name": "admin", "agents": ["*"], "capabilities": ["*"] }],
+            "users": [{ "sender_id": "sender-alice", "roles": ["admin"] }]
+        }))?;
+        let mut runtime = runtime.with_access(Arc::new(AccessControl::new(config, conn.clone())));
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        let mut sent = 0;
+        reply(&channel, &mut sent, "first").await?;
+        let first = current_session(&conn).await?;
+        let markdown = reply(&channel, &mut sent, "/export").await?;
+        assert!(markdown.contains("echo: first"));
+        let unknown = reply(&channel, &mut sent, "/export pdf").await?;
+        assert!(unknown.starts_with("未知格式"));
+
+        let jsonl = reply(&channel, &mut sent, "/export jsonl").await?;
+        let imported = reply(&channel, &mut sent, &format!("/import {jsonl}")).await?;
+        let id: i64 = imported
+            .strip_prefix("已导入会话 #")
+            .expect("import should report the session")
+            .parse()?;
+        assert_ne!(id, first);
+        assert_eq!(Message::list_by_session(&conn, id).await?.len(), 2);
+        assert_eq!(
+            reply(&channel, &mut sent, &format!("/resume {id}")).await?,
+            format!("已切换到会话 #{id}")
+        );
+        let failed = reply(&channel, &mut sent, "/import nope").await?;
+        assert!(failed.starts_with("导入失败"));
+
+        runtime_task.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn model_command_switches_only_to_declared_models() -> anyhow::Result<()> {
+        let channel = TestChannel::new();
+        let (_, conn, _calls) = runtime(channel.clone()).await?;
+        let model = |name: &str| ModelOptions {
+            model: Some(name.to_owned()),
+            ..ModelOptions::default()
+        };
+        let config = AgentSessionConfig::new(
+            "Neko",
+            Arc::new(EchoProvider {
+                calls: Arc::new(AtomicUsize::new(0)),
+            }),
+            model("chat"),
+            Vec::new(),
+            crate::agent::MiddlewareRegistry::new(),
+            None,
+            10,
+        )
+        .with_available_models(vec![model("chat"), model("reasoner")]);
+        let mut runtime = ChannelRuntime::new(
+            Box::new(channel.clone()),
+            ChannelContext {
+                app_db: conn.clone(),
+            },
+            vec![config],
+        );
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        let mut sent = 0;
+        reply(&channel, &mut sent, "hello").await?;
+        assert_eq!(
+            reply(&channel, &mut sent, "/model gpt").await?,
+            "未知模型: gpt，可用: chat, reasoner"
+        );
+        assert!(reply(&channel, &mut sent, "/model reasoner").await?.starts_with("已切换到 reasoner"));
+        assert_eq!(
+            reply(&channel, &mut sent, "/params temperature 0.3").await?,
+            "temperature 已设为 0.3"
+        );
+        assert_eq!(
+            reply(&channel, &mut sent, "/model").await?,
+            "Neko: reasoner\n可用模型: chat, reasoner"
+        );
+
+        let mapping = ChannelChatAgent::get_by_channel_chat_agent(
+            &conn,
+            &ChannelId::from("test-channel"),
+            &ChatId::from("chat-1"),
+            &AgentName::from("Neko"),
+        )
+        .await?
+        .expect("mapping should exist");
+        let overrides = ChatModelOverride::get(&conn, mapping.id)
+            .await?
+            .expect("override should be stored");
+        assert_eq!(overrides.model.as_deref(), Some("reasoner"));
+        assert_eq!(overrides.temperature, Some(0.3));
+
+        runtime_task.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unknown_commands_fall_through_when_enabled() -> anyhow::Result<()> {
+        let channel = TestChannel::new();
+        let (runtime, _conn, calls) = runtime(channel.clone()).await?;
+        let mut runtime = runtime.with_pass_unknown_commands(true);
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        channel
+            .emit(Event::IncomingMessage {
+                chat: chat("chat-1", "Alice", "target-1"),
+                sender: sender("sender-alice", "Alice"),
+                content: "/nope x".to_owned(),
+            })
+            .await?;
+        wait_for_sent_requests(&channel, 1).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            channel.sent_requests().await,
+            vec![Request::SendMessage {
+                target: ReplyTarget::from("target-1"),
+                content: "echo: /nope x".to_owned(),
+            }]
+        );
+
+        runtime_task.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unknown_commands_create_no_session() -> anyhow::Result<()> {
+        let channel = TestChannel::new();
+        let (mut runtime, conn, _calls) = runtime(channel.clone()).await?;
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        let mut sent = 0;
+        assert_eq!(
+            reply(&channel, &mut sent, "/nope").await?,
+            "未知命令: /nope，发送 /help 查看可用命令"
+        );
+        let mapping = ChannelChatAgent::get_by_channel_chat_agent(
+            &conn,
+            &ChannelId::from("test-channel"),
+            &ChatId::from("chat-1"),
+            &AgentName::from("Neko"),
+        )
+        .await?;
+        assert!(mapping.is_none());
+
+        runtime_task.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shared_settings_of_a_group_need_an_admin() -> anyhow::Result<()> {
+        use crate::entity::{group_binding::GroupBinding, invite::Invite, sender_role::SenderRole};
+
+        let channel = TestChannel::new();
+        let (runtime, conn, _calls) = runtime(channel.clone()).await?;
+        SenderRole::create_table(&conn).await?;
+        Invite::create_table(&conn).await?;
+        GroupBinding::create_table(&conn).await?;
+        let config: crate::config::AccessConfig = serde_json::from_value(serde_json::json!({
+            "roles": [
+                { "name": "admin", "agents": ["*"], "capabilities": ["*"] },
+                { "name": "member", "agents": ["Neko"] }
+            ],
+            "users": [{ "sender_id": "owner", "roles": ["admin"] }],
+            "default_role": "member"
+        }))?;
+        let mut runtime = runtime.with_access(Arc::new(AccessControl::new(config, conn.clone())));
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        let group = ChatInfo {
+            chat_type: ChatType::Group,
+            ..chat("group-1", "Team", "group-target")
+        };
+        let mut sent = 0;
+        for (from, content, expected) in [
+            ("alice", "hello", "echo: hello"),
+            ("alice", "/reset", "需要管理员权限"),
+            ("alice", "/model", "需要管理员权限"),
+            ("alice", "/params temperature 0.3", "需要管理员权限"),
+            ("owner", "/params temperature 0.3", "temperature 已设为 0.3"),
+            ("owner", "/reset", "已清空 2 条消息"),
+        ] {
+            channel
+                .emit(Event::IncomingMessage {
+                    chat: group.clone(),
+                    sender: sender(from, from),
+                    content: content.to_owned(),
+                })
+                .await?;
+            sent += 1;
+            wait_for_sent_requests(&channel, sent).await;
+            match channel.sent_requests().await.pop() {
+                Some(Request::SendMessage { content, .. }) => assert_eq!(content, expected),
+                other => panic!("unexpected request: {other:?}"),
+            }
+        }
+
+        runtime_task.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn group_senders_need_the_agent_granted() -> anyhow::Result<()> {
+        use crate::entity::{group_binding::GroupBinding, invite::Invite, sender_role::SenderRole};
+
+        let channel = TestChannel::new();
+        let (runtime, conn, calls) = runtime(channel.clone()).await?;
+        SenderRole::create_table(&conn).await?;
+        Invite::create_table(&conn).await?;
+        GroupBinding::create_table(&conn).await?;
+        let config: crate::config::AccessConfig = serde_json::from_value(serde_json::json!({
+            "roles": [
+                { "name": "member", "agents": ["Neko"] },
+                { "name": "guest" }
+            ],
+            "users": [{ "sender_id": "alice", "roles": ["member"] }],
+            "default_role": "guest"
+        }))?;
+        let mut runtime = runtime.with_access(Arc::new(AccessControl::new(config, conn.clone())));
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        let group = ChatInfo {
+            chat_type: ChatType::Group,
+            ..chat("group-1", "Team", "group-target")
+        };
+        let mut sent = 0;
+        for (from, expected) in [
+            ("mallory", "无权使用 agent: Neko"),
+            ("alice", "echo: hello"),
+        ] {
+            channel
+                .emit(Event::IncomingMessage {
+                    chat: group.clone(),
+                    sender: sender(from, from),
+                    content: "hello".to_owned(),
+                })
+                .await?;
+            sent += 1;
+            wait_for_sent_requests(&channel, sent).await;
+            match channel.sent_requests().await.pop() {
+                Some(Request::SendMessage { content, .. }) => assert_eq!(content, expected),
+                other => panic!("unexpected request: {other:?}"),
+            }
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        runtime_task.abort();
+        Ok(())
+    }
+
+    /// Records the sessions it is started and shut down in.
+    struct Lifecycle(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl crate::agent::middleware::Middleware for Lifecycle {
+        async fn init(&self, ctx: &crate::agent::Context) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(format!("start {}", ctx.session_id));
+            Ok(())
+        }
+
+        async fn shutdown(&self, ctx: &crate::agent::Context) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(format!("stop {}", ctx.session_id));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn earlier_conversations_with_schedules_keep_running() -> anyhow::Result<()> {
+        use crate::entity::schedule::{NewSchedule, ScheduleKind};
+
+        let channel = TestChannel::new();
+        let (_, conn, _calls) = runtime(channel.clone()).await?;
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut registry = crate::agent::MiddlewareRegistry::new();
+        let recorded = Arc::clone(&events);
+        registry.register("lifecycle", move |_| {
+            Ok(Arc::new(Lifecycle(Arc::clone(&recorded)))
+                as Arc<dyn crate::agent::middleware::Middleware>)
+        })?;
+        let config = AgentSessionConfig::new(
+            "Neko",
+            Arc::new(EchoProvider {
+                calls: Arc::new(AtomicUsize::new(0)),
+            }),
+            ModelOptions::default(),
+            vec![crate::config::MiddlewareConfig {
+                name: "lifecycle".to_owned(),
+                data: Default::default(),
+            }],
+            registry,
+            None,
+            10,
+        );
+        let start = |channel: &TestChannel| {
+            let (trigger, signal) = crate::runtime::shutdown::channel();
+            let mut runtime = ChannelRuntime::new(
+                Box::new(channel.clone()),
+                ChannelContext {
+                    app_db: conn.clone(),
+                },
+                vec![config.clone()],
+            )
+            .with_shutdown(signal);
+            (tokio::spawn(async move { runtime.run().await }), trigger)
+        };
+
+        let (runtime_task, trigger) = start(&channel);
+        let mut sent = 0;
+        reply(&channel, &mut sent, "hello").await?;
+        let first = current_session(&conn).await?;
+        Schedule::create(
+            &conn,
+            NewSchedule {
+                session_id: first,
+                kind: ScheduleKind::Once,
+                spec: "later".to_owned(),
+                prompt: "remind Alice".to_owned(),
+                next_run: i64::MAX,
+                denied_tools: Vec::new(),
+            },
+        )
+        .await?;
+        reply(&channel, &mut sent, "/new").await?;
+        reply(&channel, &mut sent, "hello again").await?;
+        let second = current_session(&conn).await?;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(
+            *events.lock().unwrap(),
+            [format!("start {first}"), format!("start {second}")]
+        );
+
+        // Shutting down stops the background session of the earlier
+        // conversation as well, so that `run` returns.
+        trigger.trigger();
+        tokio::time::timeout(std::time::Duration::from_secs(5), runtime_task).await???;
+        let mut stopped = events.lock().unwrap().split_off(2);
+        stopped.sort();
+        let mut expected = [format!("stop {first}"), format!("stop {second}")];
+        expected.sort();
+        assert_eq!(stopped, expected);
+
+        // After a restart only the scheduled earlier conversation runs, and
+        // resuming it keeps that session.
+        events.lock().unwrap().clear();
+        let channel = TestChannel::new();
+        let (runtime_task, trigger) = start(&channel);
+        let mut sent = 0;
+        reply(&channel, &mut sent, &format!("/resume {first}")).await?;
+        reply(&channel, &mut sent, "back").await?;
+        assert_eq!(*events.lock().unwrap(), [format!("start {first}")]);
+
+        trigger.trigger();
+        tokio::time::timeout(std::time::Duration::from_secs(5), runtime_task).await???;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn agent_output_without_mapping_returns_error() -> anyhow::Result<()> {
         let channel = TestChannel::new();
         let (runtime, _conn, _calls) = runtime(channel).await?;
 
         let error = runtime
-            .handle_agent_output(AgentOutput::SendMessage {
-                session_id: 999,
-                content: "hello".to_owned(),
-            })
+            .handle_agent_output(
+                &channel_info(),
+                AgentOutput::SendMessage {
+                    session_id: 999,
+                    content: "hello".to_owned(),
+                },
+            )
             .await
             .expect_err("missing mapping should fail");
 
@@ -692,6 +1738,122 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn send_to_chat_uses_own_channel_or_outbox() -> anyhow::Result<()> {
+        let channel = TestChannel::new();
+        let (runtime, _conn, _calls) = runtime(channel.clone()).await?;
+        let outbox = Outbox::new();
+        let runtime = runtime.with_outbox(outbox.clone());
+        let mut other_inbox = outbox.register(ChannelId::from("other-channel"))?;
+
+        runtime
+            .handle_agent_output(
+                &channel_info(),
+                AgentOutput::SendToChat {
+                    channel_id: ChannelId::from("test-channel"),
+                    reply_target: ReplyTarget::from("ops-group"),
+                    content: "local".to_owned(),
+                },
+            )
+            .await?;
+        runtime
+            .handle_agent_output(
+                &channel_info(),
+                AgentOutput::SendToChat {
+                    channel_id: ChannelId::from("other-channel"),
+                    reply_target: ReplyTarget::from("dm-bob"),
+                    content: "remote".to_owned(),
+                },
+            )
+            .await?;
+
+        assert_eq!(
+            channel.sent_requests().await,
+            vec![Request::SendMessage {
+                target: ReplyTarget::from("ops-group"),
+                content: "local".to_owned(),
+            }]
+        );
+        assert_eq!(
+            other_inbox.recv().await,
+            Some(OutboundMessage {
+                target: ReplyTarget::from("dm-bob"),
+                content: "remote".to_owned(),
+            })
+        );
+        Ok(())
+    }
+
+    /// Send `content` from Alice in `chat-1` and return the bot's reply.
+    #[tokio::test]
+    async fn login_passwords_may_contain_spaces_before_the_code() -> anyhow::Result<()> {
+        use crate::{
+            auth::{PasswordHash, Totp},
+            config::LoginConfig,
+            entity::sender_gate_state::SenderGateState,
+        };
+
+        /// sha256("correct horse")
+        const HASH: &str = "4104d36f8da2c254349f85836793ebe029e0c957063a34c91c2e9203187b5631";
+        const TOTP_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+        let channel = TestChannel::new();
+        let (runtime, conn, _calls) = runtime(channel.clone()).await?;
+        SenderGateState::create_table(&conn).await?;
+        let gate = SessionGate::new(PasswordHash::parse(HASH)?, vec!["Neko".to_owned()], conn)
+            .with_login_config(LoginConfig {
+                totp_secret: Some(TOTP_SECRET.to_owned()),
+                ..LoginConfig::default()
+            })?;
+        let mut runtime = runtime.with_gate(Arc::new(gate));
+        let runtime_task = tokio::spawn(async move { runtime.run().await });
+
+        let mut sent = 0;
+        assert_eq!(
+            reply(&channel, &mut sent, "/login correct horse").await?,
+            "密码或验证码错误"
+        );
+        assert_eq!(
+            reply(&channel, &mut sent, "/login correct").await?,
+            "用法: /login <password> <code>"
+        );
+        let code = Totp::parse(TOTP_SECRET)?.code_at(chrono::Utc::now().timestamp());
+        let login = reply(&channel, &mut sent, &format!("/login correct horse {code}")).await?;
+        assert!(login.starts_with("登录成功"), "{login}");
+
+        runtime_task.abort();
+        Ok(())
+    }
+
+    async fn reply(channel: &TestChannel, sent: &mut usize, content: &str) -> anyhow::Result<String> {
+        channel
+            .emit(Event::IncomingMessage {
+                chat: chat("chat-1", "Alice", "target-1"),
+                sender: sender("sender-alice", "Alice"),
+                content: content.to_owned(),
+            })
+            .await?;
+        *sent += 1;
+        wait_for_sent_requests(channel, *sent).await;
+        match channel.sent_requests().await.pop() {
+            Some(Request::SendMessage { content, .. }) => Ok(content),
+            other => panic!("unexpected request: {other:?}"),
+        }
+    }
+
+    async fn current_session(conn: &Connection) -> anyhow::Result<i64> {
+        Ok(ChannelChatAgent::get_by_channel_chat_agent(
+            conn,
+            &ChannelId::from("test-channel"),
+            &ChatId::from("chat-1"),
+            &AgentName::from("Neko"),
+        )
+        .await?
+        .expect("mapping should exist")
+        .session_id
+        .as_i64())
+    }
+
     async fn wait_for_sent_requests(channel: &TestChannel, len: usize) {
         for _ in 0..100 {
             if channel.sent_requests().await.len() >= len {
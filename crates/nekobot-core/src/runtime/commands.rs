@@ -0,0 +1,622 @@
+//! Built-in slash commands registered by every [`ChannelRuntime`](super::channel::ChannelRuntime).
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{
+    agent::{
+        AgentSessionConfig,
+        command::{Command, CommandArgs, CommandContext, CommandRegistry, CommandSpec, Permission},
+    },
+    entity::{
+        channel_chat_agent::{AgentName, ChannelChatAgent, SessionId},
+        chat_model_override::ChatModelOverride,
+        chat_session::ChatSession,
+        message::Message,
+        session::Session,
+    },
+    provider::ModelOptions,
+    transcript::{self, Selection, TranscriptFormat},
+};
+
+/// Register `/help`, `/agents`, `/reset`, `/new`, `/sessions`, `/resume`,
+/// `/model`, `/params`, `/whoami`, `/export` and `/import`.
+pub fn register_builtin_commands(
+    registry: &CommandRegistry,
+    agent_configs: &[AgentSessionConfig],
+) -> anyhow::Result<()> {
+    let agents: Vec<AgentSummary> = agent_configs
+        .iter()
+        .map(|config| AgentSummary {
+            name: config.agent_name.clone(),
+            options: config.model_options.clone(),
+            available: config.available_models.clone(),
+        })
+        .collect();
+    let agents = Arc::new(agents);
+
+    registry.register(Arc::new(HelpCommand::new()))?;
+    registry.register(Arc::new(AgentsCommand::new(Arc::clone(&agents))))?;
+    registry.register(Arc::new(ResetCommand::new()))?;
+    registry.register(Arc::new(NewSessionCommand::new()))?;
+    registry.register(Arc::new(SessionsCommand::new()))?;
+    registry.register(Arc::new(ResumeCommand::new()))?;
+    registry.register(Arc::new(ModelCommand::new(Arc::clone(&agents))))?;
+    registry.register(Arc::new(ParamsCommand::new(agents)))?;
+    registry.register(Arc::new(WhoamiCommand::new()))?;
+    registry.register(Arc::new(ExportCommand::new()))?;
+    registry.register(Arc::new(ImportCommand::new()))?;
+    Ok(())
+}
+
+struct AgentSummary {
+    name: String,
+    /// Configured model options.
+    options: ModelOptions,
+    /// Models the provider declares.
+    available: Vec<ModelOptions>,
+}
+
+fn model_name(options: &ModelOptions) -> &str {
+    options.model.as_deref().unwrap_or("（provider 默认模型）")
+}
+
+/// The agent serving this chat, its mapping, and the chat's model overrides.
+async fn agent_overrides<'a>(
+    ctx: &CommandContext,
+    agents: &'a [AgentSummary],
+) -> anyhow::Result<Option<(&'a AgentSummary, Option<ChatModelOverride>)>> {
+    let Some(agent) = ctx
+        .agent_name
+        .as_deref()
+        .and_then(|name| agents.iter().find(|agent| agent.name == name))
+    else {
+        return Ok(None);
+    };
+    let overrides = match current_mapping(ctx).await? {
+        Some(mapping) => Some(
+            ChatModelOverride::get(&ctx.app_db, mapping.id)
+                .await?
+                .unwrap_or_else(|| ChatModelOverride::new(mapping.id)),
+        ),
+        None => None,
+    };
+    Ok(Some((agent, overrides)))
+}
+
+/// `/help [command]` — list available commands or show one command's usage.
+struct HelpCommand {
+    spec: CommandSpec,
+}
+
+impl HelpCommand {
+    fn new() -> Self {
+        Self {
+            spec: CommandSpec::new("help", "列出可用命令")
+                .optional_arg("command")
+                .permission(Permission::Anyone),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for HelpCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        if let Some(name) = args.get("command") {
+            let name = name.trim_start_matches('/');
+            return Ok(match ctx.commands.iter().find(|spec| spec.name == name) {
+                Some(spec) => format!("{} — {}", spec.usage(), spec.help),
+                None => format!("未知命令: /{name}"),
+            });
+        }
+
+        let lines: Vec<String> = ctx
+            .commands
+            .iter()
+            .map(|spec| format!("{} — {}", spec.usage(), spec.help))
+            .collect();
+        Ok(format!("可用命令:\n{}", lines.join("\n")))
+    }
+}
+
+/// `/agents` — list the configured agents the sender may use, marking the
+/// one serving this chat.
+struct AgentsCommand {
+    agents: Arc<Vec<AgentSummary>>,
+    spec: CommandSpec,
+}
+
+impl AgentsCommand {
+    fn new(agents: Arc<Vec<AgentSummary>>) -> Self {
+        Self {
+            agents,
+            spec: CommandSpec::new("agents", "列出可用 agent"),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for AgentsCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, _args: &CommandArgs) -> anyhow::Result<String> {
+        let lines: Vec<String> = self
+            .agents
+            .iter()
+            .filter(|agent| {
+                ctx.grants.can_use_agent(&agent.name)
+                    || ctx.agent_name.as_deref() == Some(agent.name.as_str())
+            })
+            .map(|agent| {
+                if ctx.agent_name.as_deref() == Some(agent.name.as_str()) {
+                    format!("* {}（当前）", agent.name)
+                } else {
+                    format!("* {}", agent.name)
+                }
+            })
+            .collect();
+        if lines.is_empty() {
+            return Ok("（无可用 agent）".into());
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+/// `/reset` — clear the conversation history of the current session.
+///
+/// In a group the history belongs to every member, so only admins may clear it.
+struct ResetCommand {
+    spec: CommandSpec,
+}
+
+impl ResetCommand {
+    fn new() -> Self {
+        Self {
+            spec: CommandSpec::new("reset", "清空当前会话的历史消息")
+                .group_permission(Permission::Admin),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for ResetCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, _args: &CommandArgs) -> anyhow::Result<String> {
+        let Some(session_id) = ctx.session_id else {
+            return Ok("当前没有会话".into());
+        };
+        let removed = Message::delete_by_session(&ctx.app_db, session_id).await?;
+        Ok(format!("已清空 {removed} 条消息"))
+    }
+}
+
+/// The mapping of the chat and agent a command was sent from, if any.
+async fn current_mapping(ctx: &CommandContext) -> anyhow::Result<Option<ChannelChatAgent>> {
+    let Some(agent_name) = &ctx.agent_name else {
+        return Ok(None);
+    };
+    ChannelChatAgent::get_by_channel_chat_agent(
+        &ctx.app_db,
+        &ctx.channel.id,
+        &ctx.chat.id,
+        &AgentName::from(agent_name.as_str()),
+    )
+    .await
+}
+
+/// `/new [title]` — start a fresh conversation, keeping the current one for `/resume`.
+struct NewSessionCommand {
+    spec: CommandSpec,
+}
+
+impl NewSessionCommand {
+    fn new() -> Self {
+        Self {
+            spec: CommandSpec::new("new", "开始新会话，旧会话可用 /resume 恢复").optional_arg("title"),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for NewSessionCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        let Some(mapping) = current_mapping(ctx).await? else {
+            return Ok("当前没有会话，直接发送消息即可开始".into());
+        };
+        // Mappings created before conversations were tracked have no row yet.
+        ChatSession::record(&ctx.app_db, mapping.id, mapping.session_id, None).await?;
+
+        let session = Session::create(&ctx.app_db, mapping.agent_name.as_str()).await?;
+        let session_id = SessionId::from(session.id);
+        let title = args.get("title").map(str::to_owned);
+        ChatSession::record(&ctx.app_db, mapping.id, session_id, title).await?;
+        ChannelChatAgent::set_session(&ctx.app_db, mapping.id, session_id).await?;
+        Ok(format!(
+            "已开始新会话 #{}，之前的会话 #{} 可用 /resume 恢复",
+            session.id,
+            mapping.session_id.as_i64()
+        ))
+    }
+}
+
+/// `/sessions` — list the conversations of this chat.
+struct SessionsCommand {
+    spec: CommandSpec,
+}
+
+impl SessionsCommand {
+    fn new() -> Self {
+        Self {
+            spec: CommandSpec::new("sessions", "列出本聊天的所有会话"),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for SessionsCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, _args: &CommandArgs) -> anyhow::Result<String> {
+        let Some(mapping) = current_mapping(ctx).await? else {
+            return Ok("当前没有会话".into());
+        };
+        ChatSession::record(&ctx.app_db, mapping.id, mapping.session_id, None).await?;
+
+        let mut lines = Vec::new();
+        for session in ChatSession::list_by_channel_chat_agent(&ctx.app_db, mapping.id).await? {
+            let id = session.session_id.as_i64();
+            let count = Message::count_by_session(&ctx.app_db, id).await?;
+            let title = session.title.unwrap_or_else(|| "（未命名）".to_owned());
+            let current = if session.session_id == mapping.session_id {
+                "（当前）"
+            } else {
+                ""
+            };
+            lines.push(format!("#{id} {title} · {count} 条消息{current}"));
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+/// `/resume <id>` — switch back to an earlier conversation of this chat.
+struct ResumeCommand {
+    spec: CommandSpec,
+}
+
+impl ResumeCommand {
+    fn new() -> Self {
+        Self {
+            spec: CommandSpec::new("resume", "切换到之前的会话").arg("id"),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for ResumeCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        let raw = args.get("id").unwrap_or_default();
+        let Ok(id) = raw.trim_start_matches('#').parse::<i64>() else {
+            return Ok(format!("无效的会话 id: {raw}"));
+        };
+        let Some(mapping) = current_mapping(ctx).await? else {
+            return Ok("当前没有会话".into());
+        };
+        let session_id = SessionId::from(id);
+        if session_id == mapping.session_id {
+            return Ok(format!("已经在会话 #{id} 中"));
+        }
+        match ChatSession::get(&ctx.app_db, session_id).await? {
+            Some(session) if session.channel_chat_agent_id == mapping.id => {}
+            _ => return Ok(format!("本聊天没有会话 #{id}，发送 /sessions 查看")),
+        }
+
+        ChatSession::record(&ctx.app_db, mapping.id, mapping.session_id, None).await?;
+        ChannelChatAgent::set_session(&ctx.app_db, mapping.id, session_id).await?;
+        Ok(format!("已切换到会话 #{id}"))
+    }
+}
+
+/// `/model [name|default]` — show or switch the model of this chat.
+///
+/// Only models declared by the agent's provider can be selected. A group's
+/// model is shared by all members, so only admins may switch it there.
+struct ModelCommand {
+    agents: Arc<Vec<AgentSummary>>,
+    spec: CommandSpec,
+}
+
+impl ModelCommand {
+    fn new(agents: Arc<Vec<AgentSummary>>) -> Self {
+        Self {
+            agents,
+            spec: CommandSpec::new("model", "查看或切换本聊天使用的模型")
+                .optional_arg("name")
+                .group_permission(Permission::Admin),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for ModelCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        let Some((agent, overrides)) = agent_overrides(ctx, &self.agents).await? else {
+            return Ok("当前没有连接 agent".into());
+        };
+        let available: Vec<&str> = agent
+            .available
+            .iter()
+            .filter_map(|m| m.model.as_deref())
+            .collect();
+
+        let Some(name) = args.get("name") else {
+            let effective = match &overrides {
+                Some(overrides) => overrides.apply(&agent.options, &agent.available),
+                None => agent.options.clone(),
+            };
+            return Ok(format!(
+                "{}: {}\n可用模型: {}",
+                agent.name,
+                model_name(&effective),
+                available.join(", ")
+            ));
+        };
+        let Some(mut overrides) = overrides else {
+            return Ok("请先发送一条消息开始会话".into());
+        };
+
+        if name == "default" {
+            overrides.model = None;
+            overrides.upsert(&ctx.app_db).await?;
+            return Ok(format!("已恢复默认模型 {}", model_name(&agent.options)));
+        }
+        let Some(target) = agent.available.iter().find(|m| m.model.as_deref() == Some(name)) else {
+            return Ok(format!("未知模型: {name}，可用: {}", available.join(", ")));
+        };
+
+        overrides.model = (agent.options.model.as_deref() != Some(name)).then(|| name.to_owned());
+        overrides.upsert(&ctx.app_db).await?;
+
+        let mut reply = format!("已切换到 {name}");
+        if !target.capabilities.tools {
+            reply.push_str("\n注意: 该模型不支持工具调用，工具将不会发送");
+        }
+        if !target.capabilities.vision {
+            reply.push_str("\n注意: 该模型不支持图片，图片将不会发送");
+        }
+        Ok(reply)
+    }
+}
+
+/// `/params [name] [value|default]` — show or adjust sampling parameters of this chat.
+///
+/// Like `/model`, it takes an admin in groups.
+struct ParamsCommand {
+    agents: Arc<Vec<AgentSummary>>,
+    spec: CommandSpec,
+}
+
+impl ParamsCommand {
+    fn new(agents: Arc<Vec<AgentSummary>>) -> Self {
+        Self {
+            agents,
+            spec: CommandSpec::new(
+                "params",
+                "查看或调整 temperature / top_p / max_tokens，值为 default 时恢复默认",
+            )
+            .optional_arg("name")
+            .optional_arg("value")
+            .group_permission(Permission::Admin),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for ParamsCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        let Some((agent, overrides)) = agent_overrides(ctx, &self.agents).await? else {
+            return Ok("当前没有连接 agent".into());
+        };
+
+        let (Some(name), Some(value)) = (args.get("name"), args.get("value")) else {
+            let effective = match &overrides {
+                Some(overrides) => overrides.apply(&agent.options, &agent.available),
+                None => agent.options.clone(),
+            };
+            let show = |value: Option<String>| value.unwrap_or_else(|| "默认".to_owned());
+            return Ok(format!(
+                "temperature: {}\ntop_p: {}\nmax_tokens: {}",
+                show(effective.temperature.map(|v| v.to_string())),
+                show(effective.top_p.map(|v| v.to_string())),
+                show(effective.max_output_tokens.map(|v| v.to_string())),
+            ));
+        };
+        let Some(mut overrides) = overrides else {
+            return Ok("请先发送一条消息开始会话".into());
+        };
+
+        let reset = value == "default";
+        match name {
+            "temperature" => {
+                overrides.temperature = match value.parse::<f32>() {
+                    _ if reset => None,
+                    Ok(v) if (0.0..=2.0).contains(&v) => Some(v),
+                    _ => return Ok("temperature 需在 0 到 2 之间".into()),
+                };
+            }
+            "top_p" => {
+                overrides.top_p = match value.parse::<f32>() {
+                    _ if reset => None,
+                    Ok(v) if (0.0..=1.0).contains(&v) => Some(v),
+                    _ => return Ok("top_p 需在 0 到 1 之间".into()),
+                };
+            }
+            "max_tokens" => {
+                overrides.max_output_tokens = match value.parse::<u32>() {
+                    _ if reset => None,
+                    Ok(v) if v > 0 => Some(v),
+                    _ => return Ok("max_tokens 需为正整数".into()),
+                };
+            }
+            _ => return Ok(format!("未知参数: {name}，可用: temperature, top_p, max_tokens")),
+        }
+        overrides.upsert(&ctx.app_db).await?;
+        Ok(if reset {
+            format!("{name} 已恢复默认")
+        } else {
+            format!("{name} 已设为 {value}")
+        })
+    }
+}
+
+/// `/whoami` — show the sender, chat and agent as seen by the bot.
+struct WhoamiCommand {
+    spec: CommandSpec,
+}
+
+impl WhoamiCommand {
+    fn new() -> Self {
+        Self {
+            spec: CommandSpec::new("whoami", "查看当前身份").permission(Permission::Anyone),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for WhoamiCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, _args: &CommandArgs) -> anyhow::Result<String> {
+        let permission = match ctx.permission {
+            Permission::Anyone => "未登录",
+            Permission::LoggedIn => "已登录",
+            Permission::Admin => "管理员",
+        };
+        let roles = if ctx.grants.roles.is_empty() {
+            "（无）".to_owned()
+        } else {
+            ctx.grants.roles.join(", ")
+        };
+        Ok(format!(
+            "用户: {} ({})\n会话: {} ({})\n频道: {}\n权限: {permission}\n角色: {roles}\nagent: {}",
+            ctx.sender.name,
+            ctx.sender.id.as_str(),
+            ctx.chat.name,
+            ctx.chat.id.as_str(),
+            ctx.channel.name,
+            ctx.agent_name.as_deref().unwrap_or("（未连接）"),
+        ))
+    }
+}
+
+/// `/export [format]` — reply with the transcripts of every session this chat
+/// has had, as `markdown` (the default), `jsonl` or `html`.
+struct ExportCommand {
+    spec: CommandSpec,
+}
+
+impl ExportCommand {
+    fn new() -> Self {
+        Self {
+            spec: CommandSpec::new("export", "导出本聊天的会话记录")
+                .optional_arg("format")
+                .permission(Permission::Admin),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for ExportCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        let format = match args.get("format") {
+            Some(raw) => match raw.parse::<TranscriptFormat>() {
+                Ok(format) => format,
+                Err(_) => return Ok(format!("未知格式: {raw}（可选 markdown、jsonl、html）")),
+            },
+            None => TranscriptFormat::Markdown,
+        };
+        let selection = Selection::Chat {
+            channel_id: ctx.channel.id.to_string(),
+            chat_id: ctx.chat.id.to_string(),
+        };
+        let transcripts = transcript::load(&ctx.app_db, &selection).await?;
+        if transcripts.is_empty() {
+            return Ok("本聊天还没有会话".into());
+        }
+        transcript::render(&transcripts, format)
+    }
+}
+
+/// `/import <data>` — import JSONL or OpenAI chat-format conversations into
+/// new sessions of this chat's agent, which `/resume` can then switch to.
+struct ImportCommand {
+    spec: CommandSpec,
+}
+
+impl ImportCommand {
+    fn new() -> Self {
+        Self {
+            spec: CommandSpec::new("import", "导入会话记录为新会话")
+                .arg("data")
+                .permission(Permission::Admin),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for ImportCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        let Some(mapping) = current_mapping(ctx).await? else {
+            return Ok("当前没有会话".into());
+        };
+        let data = args.get("data").unwrap_or_default();
+        let session_ids =
+            match transcript::import(&ctx.app_db, mapping.agent_name.as_str(), data).await {
+                Ok(session_ids) => session_ids,
+                Err(e) => return Ok(format!("导入失败: {e:#}")),
+            };
+        let mut lines = Vec::with_capacity(session_ids.len());
+        for id in session_ids {
+            ChatSession::record(&ctx.app_db, mapping.id, SessionId::from(id), None).await?;
+            lines.push(format!("已导入会话 #{id}"));
+        }
+        Ok(lines.join("\n"))
+    }
+}
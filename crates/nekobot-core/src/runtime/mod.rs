@@ -1,7 +1,13 @@
 //! Runtime abstraction — drives the main event loop for a channel+agent pair.
 
+pub mod access;
 pub mod channel;
+pub mod commands;
+pub mod outbox;
+pub mod route;
 pub mod session_gate;
+pub mod shutdown;
+pub mod supervisor;
 
 /// A long-running task that processes events for a channel+agent combination.
 ///
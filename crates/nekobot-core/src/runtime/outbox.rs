@@ -0,0 +1,106 @@
+//! Outbox — delivers messages to channels owned by other channel runtimes.
+//!
+//! Every running [`ChannelRuntime`](super::channel::ChannelRuntime) registers
+//! an inbox under its channel id; a runtime that receives
+//! [`AgentOutput::SendToChat`](crate::agent::AgentOutput::SendToChat) for a
+//! channel it does not own hands the message over here.
+//!
+//! Delivery never waits for room in an inbox: two runtimes sending to each
+//! other while both inboxes are full would otherwise block forever.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use nekobot_channel::{ChannelId, ReplyTarget};
+use tokio::sync::mpsc::{Receiver, Sender, error::TrySendError};
+
+/// A message waiting to be sent by the runtime that owns its channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboundMessage {
+    pub target: ReplyTarget,
+    pub content: String,
+}
+
+/// Shared map from channel id to the inbox of the runtime that owns it.
+#[derive(Clone, Default)]
+pub struct Outbox {
+    inboxes: Arc<RwLock<HashMap<ChannelId, Sender<OutboundMessage>>>>,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the inbox of `channel_id`, replacing the one of a previous run.
+    pub fn register(&self, channel_id: ChannelId) -> anyhow::Result<Receiver<OutboundMessage>> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(32);
+        self.inboxes
+            .write()
+            .map_err(|_| anyhow::anyhow!("outbox lock poisoned"))?
+            .insert(channel_id, sender);
+        Ok(receiver)
+    }
+
+    /// Queue `message` for the runtime that owns `channel_id`, failing if its inbox is full.
+    pub fn deliver(&self, channel_id: &ChannelId, message: OutboundMessage) -> anyhow::Result<()> {
+        let inbox = self
+            .inboxes
+            .read()
+            .map_err(|_| anyhow::anyhow!("outbox lock poisoned"))?
+            .get(channel_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("channel {channel_id} is not running"))?;
+        inbox.try_send(message).map_err(|e| match e {
+            TrySendError::Full(_) => {
+                anyhow::anyhow!("channel {channel_id} is busy, message dropped")
+            }
+            TrySendError::Closed(_) => anyhow::anyhow!("channel {channel_id} is not running"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn delivers_to_latest_registered_inbox() -> anyhow::Result<()> {
+        let outbox = Outbox::new();
+        let channel_id = ChannelId::from("qq-main");
+        let message = OutboundMessage {
+            target: ReplyTarget::from("group-1"),
+            content: "hello".to_owned(),
+        };
+
+        assert!(outbox.deliver(&channel_id, message.clone()).is_err());
+        let stale = outbox.register(channel_id.clone())?;
+        let mut inbox = outbox.register(channel_id.clone())?;
+        drop(stale);
+
+        outbox.deliver(&channel_id, message.clone())?;
+        assert_eq!(inbox.recv().await, Some(message));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn full_inbox_fails_instead_of_waiting() -> anyhow::Result<()> {
+        let outbox = Outbox::new();
+        let channel_id = ChannelId::from("qq-main");
+        let mut inbox = outbox.register(channel_id.clone())?;
+        let message = OutboundMessage {
+            target: ReplyTarget::from("group-1"),
+            content: "hello".to_owned(),
+        };
+
+        while outbox.deliver(&channel_id, message.clone()).is_ok() {}
+        let err = outbox.deliver(&channel_id, message.clone()).unwrap_err();
+        assert!(err.to_string().contains("busy"), "{err}");
+
+        inbox.recv().await;
+        outbox.deliver(&channel_id, message)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Declarative routing — builds an [`AgentRoute`] from the `routes:` config.
+
+use std::sync::Arc;
+
+use nekobot_channel::{ChatInfo, ChatType, SenderInfo};
+
+use super::channel::AgentRoute;
+use crate::config::{RouteChatType, RouteConfig};
+
+/// Build a route for the channel named `channel_name` from ordered rules.
+///
+/// Rules bound to other channels are dropped up front. The first rule whose
+/// conditions all match wins; `fallback` is used when none does.
+pub fn from_rules(rules: &[RouteConfig], channel_name: &str, fallback: String) -> AgentRoute {
+    let rules: Vec<RouteConfig> = rules
+        .iter()
+        .filter(|rule| rule.channel.as_deref().is_none_or(|c| c == channel_name))
+        .cloned()
+        .collect();
+
+    Arc::new(move |_, chat, sender| {
+        rules
+            .iter()
+            .find(|rule| matches(rule, chat, sender))
+            .map(|rule| rule.agent.clone())
+            .unwrap_or_else(|| fallback.clone())
+    })
+}
+
+/// Whether the chat and sender conditions of `rule` hold. The channel
+/// condition is checked by [`from_rules`].
+fn matches(rule: &RouteConfig, chat: &ChatInfo, sender: &SenderInfo) -> bool {
+    let chat_type = match rule.chat_type {
+        Some(RouteChatType::Private) => chat.chat_type == ChatType::Private,
+        Some(RouteChatType::Group) => chat.chat_type == ChatType::Group,
+        None => true,
+    };
+    chat_type
+        && rule
+            .chat_id
+            .as_deref()
+            .is_none_or(|pattern| glob_match(pattern, chat.id.as_str()))
+        && rule
+            .sender_id
+            .as_deref()
+            .is_none_or(|pattern| glob_match(pattern, sender.id.as_str()))
+}
+
+/// Match `value` against `pattern`, where `*` stands for any run of characters.
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` in the pattern: exact match.
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use nekobot_channel::{
+        ChannelId, ChannelInfo, ChannelName, ChatId, ChatName, ReplyTarget, SenderId, SenderName,
+    };
+
+    use super::*;
+
+    fn rule(agent: &str) -> RouteConfig {
+        RouteConfig {
+            agent: agent.to_owned(),
+            channel: None,
+            chat_id: None,
+            chat_type: None,
+            sender_id: None,
+        }
+    }
+
+    fn route(route_fn: &AgentRoute, chat_id: &str, chat_type: ChatType, sender_id: &str) -> String {
+        let channel = ChannelInfo {
+            id: ChannelId::from("qq-main"),
+            name: ChannelName::from("QQ"),
+        };
+        let chat = ChatInfo {
+            id: ChatId::from(chat_id),
+            name: ChatName::from("chat"),
+            reply_target: ReplyTarget::from("target"),
+            chat_type,
+        };
+        let sender = SenderInfo {
+            id: SenderId::from(sender_id),
+            name: SenderName::from("sender"),
+        };
+        route_fn(&channel, &chat, &sender)
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("group:*", "group:42"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+        assert!(!glob_match("a*a", "a"));
+        assert!(!glob_match("group:*", "user:42"));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = vec![
+            RouteConfig {
+                channel: Some("discord".to_owned()),
+                ..rule("Discord")
+            },
+            RouteConfig {
+                sender_id: Some("admin".to_owned()),
+                ..rule("Admin")
+            },
+            RouteConfig {
+                chat_id: Some("group:*".to_owned()),
+                chat_type: Some(RouteChatType::Group),
+                ..rule("Mimi")
+            },
+            RouteConfig {
+                chat_type: Some(RouteChatType::Private),
+                ..rule("Neko")
+            },
+        ];
+        let route_fn = from_rules(&rules, "qq-main", "Default".to_owned());
+
+        assert_eq!(route(&route_fn, "group:1", ChatType::Group, "admin"), "Admin");
+        assert_eq!(route(&route_fn, "group:1", ChatType::Group, "alice"), "Mimi");
+        assert_eq!(route(&route_fn, "user:1", ChatType::Private, "alice"), "Neko");
+        assert_eq!(route(&route_fn, "other", ChatType::Group, "alice"), "Default");
+
+        let discord = from_rules(&rules, "discord", "Default".to_owned());
+        assert_eq!(route(&discord, "user:1", ChatType::Private, "alice"), "Discord");
+    }
+}
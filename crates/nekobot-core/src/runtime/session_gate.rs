@@ -4,13 +4,34 @@
 //! 1. `/login <password>` — authenticate, become logged in
 //! 2. `/connect <agent>` — bind to a specific agent
 //!
-//! All interception happens before any agent session is created, so no
-//! LLM tokens are consumed for login/connect flows.
+//! Both steps are slash commands (see [`LoginCommand`] and [`ConnectCommand`])
+//! answered by the channel runtime before any agent session is created, so no
+//! LLM tokens are consumed for login/connect flows. `/logout` ends the login.
+//!
+//! Failed logins are throttled per sender, who is locked out after
+//! [`max_attempts`](LoginConfig::max_attempts) failures, and across all
+//! senders. Logins may expire, and may require a TOTP code as a second factor;
+//! a code is accepted once per sender. Logins of the same sender run one at a
+//! time, so concurrent attempts cannot each see the same failure count.
+//!
+//! Which agents a sender may connect to is decided by their [`Grants`].
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
 
-use sha2::{Digest, Sha256};
 use turso::Connection;
 
-use crate::entity::sender_gate_state::SenderGateState;
+use crate::{
+    agent::command::{Command, CommandArgs, CommandContext, CommandSpec, Permission},
+    auth::{PasswordHash, Totp},
+    config::LoginConfig,
+    entity::sender_gate_state::SenderGateState,
+    runtime::access::Grants,
+};
 
 /// Result of gate interception.
 pub enum InterceptResult {
@@ -25,61 +46,97 @@ pub enum InterceptResult {
 /// One instance can serve multiple channels — `channel_id` is passed
 /// to [`intercept`](SessionGate::intercept) per-call.
 pub struct SessionGate {
-    password_hash: String,
+    password_hash: PasswordHash,
+    totp: Option<Totp>,
+    config: LoginConfig,
     valid_agents: Vec<String>,
     conn: Connection,
+    /// Times of recent failed logins of all senders, oldest first.
+    recent_failures: Mutex<VecDeque<i64>>,
+    /// Held while a login of the keyed channel and sender runs. Entries are
+    /// removed when no login of that sender is waiting.
+    login_locks: Mutex<HashMap<SenderKey, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// Channel id and sender id.
+type SenderKey = (String, String);
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
 }
 
 impl SessionGate {
-    pub fn new(
-        password_hash: impl Into<String>,
-        valid_agents: Vec<String>,
-        conn: Connection,
-    ) -> Self {
+    /// A gate with the default [`LoginConfig`].
+    pub fn new(password_hash: PasswordHash, valid_agents: Vec<String>, conn: Connection) -> Self {
         Self {
-            password_hash: password_hash.into(),
+            password_hash,
+            totp: None,
+            config: LoginConfig::default(),
             valid_agents,
             conn,
+            recent_failures: Mutex::new(VecDeque::new()),
+            login_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Apply throttling, expiry and TOTP settings.
+    pub fn with_login_config(mut self, config: LoginConfig) -> anyhow::Result<Self> {
+        self.totp = config.totp_secret.as_deref().map(Totp::parse).transpose()?;
+        self.config = config;
+        Ok(self)
+    }
+
+    /// Whether `/login` takes a TOTP code after the password.
+    pub fn requires_code(&self) -> bool {
+        self.totp.is_some()
+    }
+
+    fn login_hint(&self) -> &'static str {
+        if self.requires_code() {
+            "/login <password> <code>"
+        } else {
+            "/login <password>"
         }
     }
 
-    /// Intercept a message and return an [`InterceptResult`].
+    /// Whether `state` is a login that has not expired.
+    pub fn is_logged_in(&self, state: &SenderGateState) -> bool {
+        state.is_logged_in
+            && match self.config.session_ttl_secs {
+                None => true,
+                Some(ttl) => state.logged_in_at.is_some_and(|at| now() - at < ttl as i64),
+            }
+    }
+
+    /// Intercept a non-command message and return an [`InterceptResult`].
     ///
-    /// - `/login <pw>` — validate password hash, persist login state
-    /// - `/connect <agent>` — bind sender to an agent
-    /// - other messages — pass through if logged in and connected, reject otherwise
+    /// Passes through if the sender is logged in and connected to an agent
+    /// `grants` covers, rejects otherwise.
     pub async fn intercept(
         &self,
         channel_id: &str,
         sender_id: &str,
-        content: &str,
+        grants: &Grants,
     ) -> anyhow::Result<InterceptResult> {
-        // `/login <password>`
-        if let Some(password) = content.strip_prefix("/login ").map(str::trim) {
-            return self.handle_login(channel_id, sender_id, password).await;
-        }
-
         let state = SenderGateState::get(&self.conn, channel_id, sender_id).await?;
 
         // Not logged in
-        let Some(state) = state else {
+        let Some(state) = state.filter(|state| state.is_logged_in) else {
             return Ok(InterceptResult::Reject {
-                reply: "请先 /login <password>".into(),
+                reply: format!("请先 {}", self.login_hint()),
             });
         };
-        if !state.is_logged_in {
+        if !self.is_logged_in(&state) {
             return Ok(InterceptResult::Reject {
-                reply: "请先 /login <password>".into(),
+                reply: format!("登录已过期，请重新 {}", self.login_hint()),
             });
         }
 
-        // `/connect <agent>`
-        if let Some(agent) = content.strip_prefix("/connect ").map(str::trim) {
-            return self.handle_connect(channel_id, sender_id, agent).await;
-        }
-
         // Connected — let through
         match state.connected_agent {
+            Some(agent) if !grants.can_use_agent(&agent) => Ok(InterceptResult::Reject {
+                reply: format!("无权使用 agent: {agent}，请重新 /connect <agent>"),
+            }),
             Some(agent) => Ok(InterceptResult::Pass { agent_name: agent }),
             None => Ok(InterceptResult::Reject {
                 reply: "请先 /connect <agent>".into(),
@@ -87,67 +144,488 @@ impl SessionGate {
         }
     }
 
-    async fn handle_login(
+    /// Persisted state of a sender, or `None` if they never tried to log in.
+    ///
+    /// Use [`is_logged_in`](SessionGate::is_logged_in) to check whether the
+    /// login is still valid.
+    pub async fn state(
+        &self,
+        channel_id: &str,
+        sender_id: &str,
+    ) -> anyhow::Result<Option<SenderGateState>> {
+        SenderGateState::get(&self.conn, channel_id, sender_id).await
+    }
+
+    /// Check the password (and TOTP code, if required) and persist the
+    /// login or the failed attempt. Returns the reply text, which lists the
+    /// agents `grants` covers.
+    pub async fn login(
         &self,
         channel_id: &str,
         sender_id: &str,
         password: &str,
-    ) -> anyhow::Result<InterceptResult> {
-        let input_hash = Sha256::digest(password.as_bytes())
-            .as_slice()
-            .iter()
-            .map(|b| format!("{:02x}", b))
-            .collect::<String>();
-
-        if input_hash == self.password_hash {
-            SenderGateState {
-                channel_id: channel_id.to_owned(),
-                sender_id: sender_id.to_owned(),
-                is_logged_in: true,
-                connected_agent: None,
+        code: Option<&str>,
+        grants: &Grants,
+    ) -> anyhow::Result<String> {
+        let key = (channel_id.to_owned(), sender_id.to_owned());
+        let lock = Arc::clone(
+            self.login_locks
+                .lock()
+                .map_err(|_| anyhow::anyhow!("gate lock poisoned"))?
+                .entry(key.clone())
+                .or_default(),
+        );
+        let result = {
+            let _guard = lock.lock().await;
+            self.login_serialized(channel_id, sender_id, password, code, grants)
+                .await
+        };
+        let mut locks = self
+            .login_locks
+            .lock()
+            .map_err(|_| anyhow::anyhow!("gate lock poisoned"))?;
+        // One reference in the map and ours: nobody else is waiting
+        if Arc::strong_count(&lock) == 2 {
+            locks.remove(&key);
+        }
+        result
+    }
+
+    async fn login_serialized(
+        &self,
+        channel_id: &str,
+        sender_id: &str,
+        password: &str,
+        code: Option<&str>,
+        grants: &Grants,
+    ) -> anyhow::Result<String> {
+        let now = now();
+        let mut state = SenderGateState::get(&self.conn, channel_id, sender_id)
+            .await?
+            .unwrap_or_else(|| SenderGateState::new(channel_id, sender_id));
+        if let Some(until) = state.locked_until
+            && until > now
+        {
+            return Ok(locked_out_reply(until - now));
+        }
+        if self.global_limit_reached(now)? {
+            return Ok("登录尝试过于频繁，请稍后再试".into());
+        }
+
+        let hash = self.password_hash.clone();
+        let input = password.to_owned();
+        let password_ok = tokio::task::spawn_blocking(move || hash.verify(&input)).await?;
+        let code_step = self
+            .totp
+            .as_ref()
+            .zip(code)
+            .and_then(|(totp, code)| totp.matching_step(code, now));
+        // A code is refused if it or a later one was already accepted
+        let code_ok = match code_step {
+            Some(step) => state.last_totp_step.is_none_or(|last| step > last),
+            None => self.totp.is_none(),
+        };
+
+        if password_ok && code_ok {
+            if code_step.is_some() {
+                state.last_totp_step = code_step;
             }
-            .upsert(&self.conn)
-            .await?;
+            state.is_logged_in = true;
+            state.connected_agent = None;
+            state.logged_in_at = Some(now);
+            state.failed_attempts = 0;
+            state.locked_until = None;
+            state.upsert(&self.conn).await?;
 
-            let agents = if self.valid_agents.is_empty() {
+            let agents: Vec<&str> = self
+                .valid_agents
+                .iter()
+                .map(String::as_str)
+                .filter(|agent| grants.can_use_agent(agent))
+                .collect();
+            let agents = if agents.is_empty() {
                 "（无可用 agent）".to_owned()
             } else {
-                self.valid_agents.join(", ")
+                agents.join(", ")
             };
-            Ok(InterceptResult::Reject {
-                reply: format!("登录成功，请 /connect <agent> 选择要连接的 agent。可用: {agents}"),
-            })
+            return Ok(format!(
+                "登录成功，请 /connect <agent> 选择要连接的 agent。可用: {agents}"
+            ));
+        }
+
+        self.record_failure(now)?;
+        state.failed_attempts += 1;
+        let reply = if state.failed_attempts >= self.config.max_attempts {
+            tracing::warn!(target: "gate", channel_id, sender_id, "sender locked out after failed logins");
+            state.failed_attempts = 0;
+            state.locked_until = Some(now + self.config.lockout_secs as i64);
+            locked_out_reply(self.config.lockout_secs as i64)
+        } else if self.requires_code() {
+            "密码或验证码错误".into()
         } else {
-            Ok(InterceptResult::Reject {
-                reply: "密码错误".into(),
-            })
+            "密码错误".into()
+        };
+        state.upsert(&self.conn).await?;
+        Ok(reply)
+    }
+
+    /// Whether the failed logins of all senders within the window reached
+    /// the global limit, forgetting failures that fell out of the window.
+    fn global_limit_reached(&self, now: i64) -> anyhow::Result<bool> {
+        let mut failures = self
+            .recent_failures
+            .lock()
+            .map_err(|_| anyhow::anyhow!("gate lock poisoned"))?;
+        let cutoff = now - self.config.global_window_secs as i64;
+        while failures.front().is_some_and(|at| *at <= cutoff) {
+            failures.pop_front();
         }
+        Ok(failures.len() >= self.config.global_max_attempts as usize)
+    }
+
+    fn record_failure(&self, now: i64) -> anyhow::Result<()> {
+        self.recent_failures
+            .lock()
+            .map_err(|_| anyhow::anyhow!("gate lock poisoned"))?
+            .push_back(now);
+        Ok(())
+    }
+
+    /// Log a sender in without a password, e.g. after they redeemed an
+    /// invite code.
+    pub async fn admit(&self, channel_id: &str, sender_id: &str) -> anyhow::Result<()> {
+        let mut state = SenderGateState::get(&self.conn, channel_id, sender_id)
+            .await?
+            .unwrap_or_else(|| SenderGateState::new(channel_id, sender_id));
+        state.is_logged_in = true;
+        state.connected_agent = None;
+        state.logged_in_at = Some(now());
+        state.failed_attempts = 0;
+        state.locked_until = None;
+        state.upsert(&self.conn).await?;
+        Ok(())
     }
 
-    async fn handle_connect(
+    /// End a sender's login. Returns the reply text.
+    pub async fn logout(&self, channel_id: &str, sender_id: &str) -> anyhow::Result<String> {
+        let state = SenderGateState::get(&self.conn, channel_id, sender_id).await?;
+        let Some(mut state) = state.filter(|state| state.is_logged_in) else {
+            return Ok("当前未登录".into());
+        };
+        state.is_logged_in = false;
+        state.connected_agent = None;
+        state.logged_in_at = None;
+        state.upsert(&self.conn).await?;
+        Ok("已退出登录".into())
+    }
+
+    /// Bind a logged-in sender to an agent `grants` covers. Returns the
+    /// reply text.
+    pub async fn connect(
         &self,
         channel_id: &str,
         sender_id: &str,
         agent: &str,
-    ) -> anyhow::Result<InterceptResult> {
+        grants: &Grants,
+    ) -> anyhow::Result<String> {
         if !self.valid_agents.iter().any(|a| a == agent) {
-            let agents = self.valid_agents.join(", ");
-            return Ok(InterceptResult::Reject {
-                reply: format!("未知 agent: {agent}，可用: {agents}"),
-            });
+            let agents: Vec<&str> = self
+                .valid_agents
+                .iter()
+                .map(String::as_str)
+                .filter(|agent| grants.can_use_agent(agent))
+                .collect();
+            return Ok(format!("未知 agent: {agent}，可用: {}", agents.join(", ")));
+        }
+        if !grants.can_use_agent(agent) {
+            return Ok(format!("无权使用 agent: {agent}"));
         }
 
-        SenderGateState {
-            channel_id: channel_id.to_owned(),
-            sender_id: sender_id.to_owned(),
-            is_logged_in: true,
-            connected_agent: Some(agent.to_owned()),
+        let state = SenderGateState::get(&self.conn, channel_id, sender_id).await?;
+        let Some(mut state) = state.filter(|state| self.is_logged_in(state)) else {
+            return Ok(format!("请先 {}", self.login_hint()));
+        };
+        state.connected_agent = Some(agent.to_owned());
+        state.upsert(&self.conn).await?;
+
+        Ok(format!("已连接到 {agent}，可以开始对话"))
+    }
+}
+
+fn locked_out_reply(remaining_secs: i64) -> String {
+    let minutes = (remaining_secs + 59) / 60;
+    format!("尝试次数过多，请 {} 分钟后再试", minutes.max(1))
+}
+
+/// `/login <password>` — log in to the [`SessionGate`] from a private chat.
+pub struct LoginCommand {
+    gate: Arc<SessionGate>,
+    spec: CommandSpec,
+}
+
+impl LoginCommand {
+    /// The password is the rest of the line, so it may contain spaces; with
+    /// TOTP on, the code is the last word of it.
+    pub fn new(gate: Arc<SessionGate>) -> Self {
+        let help = if gate.requires_code() {
+            "登录，密码后空格加验证码"
+        } else {
+            "登录"
+        };
+        Self {
+            gate,
+            spec: CommandSpec::new("login", help)
+                .arg("password")
+                .permission(Permission::Anyone),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for LoginCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        if !ctx.chat.chat_type.is_private() {
+            return Ok("请在私聊中登录".into());
         }
-        .upsert(&self.conn)
+        let input = args.get("password").unwrap_or_default();
+        let (password, code) = if self.gate.requires_code() {
+            match input.rsplit_once(char::is_whitespace) {
+                Some((password, code)) => (password.trim_end(), Some(code)),
+                None => return Ok(format!("用法: {}", self.gate.login_hint())),
+            }
+        } else {
+            (input, None)
+        };
+        self.gate
+            .login(
+                ctx.channel.id.as_str(),
+                ctx.sender.id.as_str(),
+                password,
+                code,
+                &ctx.grants,
+            )
+            .await
+    }
+}
+
+/// `/connect <agent>` — bind a logged-in private chat to an agent.
+pub struct ConnectCommand {
+    gate: Arc<SessionGate>,
+    spec: CommandSpec,
+}
+
+impl ConnectCommand {
+    pub fn new(gate: Arc<SessionGate>) -> Self {
+        Self {
+            gate,
+            spec: CommandSpec::new("connect", "选择要对话的 agent").arg("agent"),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for ConnectCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, args: &CommandArgs) -> anyhow::Result<String> {
+        if !ctx.chat.chat_type.is_private() {
+            return Ok("群聊中无需 /connect".into());
+        }
+        let agent = args.get("agent").unwrap_or_default();
+        self.gate
+            .connect(
+                ctx.channel.id.as_str(),
+                ctx.sender.id.as_str(),
+                agent,
+                &ctx.grants,
+            )
+            .await
+    }
+}
+
+/// `/logout` — end the login of a private chat.
+pub struct LogoutCommand {
+    gate: Arc<SessionGate>,
+    spec: CommandSpec,
+}
+
+impl LogoutCommand {
+    pub fn new(gate: Arc<SessionGate>) -> Self {
+        Self {
+            gate,
+            spec: CommandSpec::new("logout", "退出登录"),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for LogoutCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    async fn execute(&self, ctx: &CommandContext, _args: &CommandArgs) -> anyhow::Result<String> {
+        if !ctx.chat.chat_type.is_private() {
+            return Ok("群聊中无需 /logout".into());
+        }
+        self.gate
+            .logout(ctx.channel.id.as_str(), ctx.sender.id.as_str())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::Entity;
+
+    /// sha256("hunter2"); verifying it is fast, unlike Argon2 in debug builds.
+    const HASH: &str = "f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7";
+    const TOTP_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    async fn gate(config: LoginConfig) -> anyhow::Result<SessionGate> {
+        let conn = crate::entity::test_connection().await?;
+        SenderGateState::create_table(&conn).await?;
+        SessionGate::new(PasswordHash::parse(HASH)?, vec!["Neko".to_owned()], conn)
+            .with_login_config(config)
+    }
+
+    #[tokio::test]
+    async fn locks_out_a_sender_after_repeated_failures() -> anyhow::Result<()> {
+        let gate = gate(LoginConfig {
+            max_attempts: 2,
+            ..LoginConfig::default()
+        })
+        .await?;
+        let all = Grants::all();
+        assert_eq!(
+            gate.login("qq", "mallory", "guess", None, &all).await?,
+            "密码错误"
+        );
+        let reply = gate.login("qq", "mallory", "guess2", None, &all).await?;
+        assert!(reply.starts_with("尝试次数过多"), "{reply}");
+        let reply = gate.login("qq", "mallory", "hunter2", None, &all).await?;
+        assert!(reply.starts_with("尝试次数过多"), "{reply}");
+
+        let reply = gate.login("qq", "alice", "hunter2", None, &all).await?;
+        assert!(reply.starts_with("登录成功"), "{reply}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn throttles_failures_across_senders() -> anyhow::Result<()> {
+        let gate = gate(LoginConfig {
+            global_max_attempts: 2,
+            ..LoginConfig::default()
+        })
         .await?;
+        let all = Grants::all();
+        gate.login("qq", "a", "guess", None, &all).await?;
+        gate.login("qq", "b", "guess", None, &all).await?;
+        assert_eq!(
+            gate.login("qq", "alice", "hunter2", None, &all).await?,
+            "登录尝试过于频繁，请稍后再试"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logins_expire_and_end_with_logout() -> anyhow::Result<()> {
+        let gate = gate(LoginConfig {
+            session_ttl_secs: Some(60),
+            ..LoginConfig::default()
+        })
+        .await?;
+        let all = Grants::all();
+        gate.login("qq", "alice", "hunter2", None, &all).await?;
+        gate.connect("qq", "alice", "Neko", &all).await?;
+        assert!(matches!(
+            gate.intercept("qq", "alice", &all).await?,
+            InterceptResult::Pass { agent_name } if agent_name == "Neko"
+        ));
+
+        let mut state = gate.state("qq", "alice").await?.unwrap();
+        state.logged_in_at = Some(now() - 120);
+        state.upsert(&gate.conn).await?;
+        assert!(matches!(
+            gate.intercept("qq", "alice", &all).await?,
+            InterceptResult::Reject { reply } if reply.starts_with("登录已过期")
+        ));
+
+        gate.login("qq", "alice", "hunter2", None, &all).await?;
+        assert_eq!(gate.logout("qq", "alice").await?, "已退出登录");
+        assert!(!gate.is_logged_in(&gate.state("qq", "alice").await?.unwrap()));
+        assert_eq!(gate.logout("qq", "alice").await?, "当前未登录");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connects_only_to_granted_agents() -> anyhow::Result<()> {
+        let gate = gate(LoginConfig::default()).await?;
+        gate.admit("qq", "alice").await?;
+        assert_eq!(
+            gate.connect("qq", "alice", "Neko", &Grants::none()).await?,
+            "无权使用 agent: Neko"
+        );
+        gate.connect("qq", "alice", "Neko", &Grants::all()).await?;
+        assert!(matches!(
+            gate.intercept("qq", "alice", &Grants::none()).await?,
+            InterceptResult::Reject { reply } if reply.starts_with("无权使用")
+        ));
+        Ok(())
+    }
 
-        Ok(InterceptResult::Reject {
-            reply: format!("已连接到 {agent}，可以开始对话"),
+    #[tokio::test]
+    async fn requires_the_totp_code_when_configured() -> anyhow::Result<()> {
+        let gate = gate(LoginConfig {
+            totp_secret: Some(TOTP_SECRET.to_owned()),
+            ..LoginConfig::default()
         })
+        .await?;
+        let all = Grants::all();
+        assert!(gate.requires_code());
+        assert_eq!(
+            gate.login("qq", "alice", "hunter2", None, &all).await?,
+            "密码或验证码错误"
+        );
+        let code = Totp::parse(TOTP_SECRET)?.code_at(now());
+        let reply = gate
+            .login("qq", "alice", "hunter2", Some(&code), &all)
+            .await?;
+        assert!(reply.starts_with("登录成功"), "{reply}");
+
+        // The same code does not log in a second time
+        gate.logout("qq", "alice").await?;
+        assert_eq!(
+            gate.login("qq", "alice", "hunter2", Some(&code), &all)
+                .await?,
+            "密码或验证码错误"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_failed_logins_all_count() -> anyhow::Result<()> {
+        let gate = gate(LoginConfig {
+            max_attempts: 3,
+            ..LoginConfig::default()
+        })
+        .await?;
+        let all = Grants::all();
+        let attempt = || gate.login("qq", "alice", "wrong", None, &all);
+        let (first, second, third) = tokio::join!(attempt(), attempt(), attempt());
+        let replies = [first?, second?, third?];
+        assert_eq!(replies.iter().filter(|r| *r == "密码错误").count(), 2);
+
+        let state = gate.state("qq", "alice").await?.expect("state is stored");
+        assert!(state.locked_until.is_some());
+        assert!(gate.login_locks.lock().unwrap().is_empty());
+        Ok(())
     }
 }
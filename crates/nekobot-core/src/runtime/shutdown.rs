@@ -0,0 +1,78 @@
+//! Shutdown signalling shared by all runtimes.
+//!
+//! [`NekoBot::run`](crate::NekoBot::run) owns the [`ShutdownTrigger`]; every
+//! runtime holds a cloned [`ShutdownSignal`] and starts draining once it fires.
+
+use tokio::sync::watch;
+
+/// Sending half — fires the shutdown request exactly once.
+pub struct ShutdownTrigger {
+    sender: watch::Sender<bool>,
+}
+
+/// Receiving half — cheap to clone, one per runtime.
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+/// Create a connected trigger/signal pair.
+pub fn channel() -> (ShutdownTrigger, ShutdownSignal) {
+    let (sender, receiver) = watch::channel(false);
+    (ShutdownTrigger { sender }, ShutdownSignal { receiver })
+}
+
+impl ShutdownTrigger {
+    /// Request shutdown. Idempotent.
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Create another [`ShutdownSignal`] subscribed to this trigger.
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            receiver: self.sender.subscribe(),
+        }
+    }
+}
+
+impl ShutdownSignal {
+    /// Returns `true` once shutdown has been requested.
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Wait until shutdown is requested.
+    ///
+    /// Also resolves when the [`ShutdownTrigger`] is dropped, since nothing
+    /// can keep the runtime alive at that point.
+    pub async fn wait(&mut self) {
+        let _ = self.receiver.wait_for(|triggered| *triggered).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn signal_resolves_after_trigger() {
+        let (trigger, mut signal) = channel();
+        let mut late = trigger.signal();
+        assert!(!signal.is_triggered());
+
+        trigger.trigger();
+        signal.wait().await;
+        late.wait().await;
+
+        assert!(signal.is_triggered());
+    }
+
+    #[tokio::test]
+    async fn signal_resolves_when_trigger_dropped() {
+        let (trigger, mut signal) = channel();
+        drop(trigger);
+
+        signal.wait().await;
+    }
+}
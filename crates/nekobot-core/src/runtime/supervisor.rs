@@ -0,0 +1,288 @@
+//! Channel runtime supervision — restarts a failing [`ChannelRuntime`] with
+//! exponential backoff and tracks its health, without affecting other channels.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use super::Runtime;
+use super::channel::ChannelRuntime;
+use super::shutdown::ShutdownSignal;
+
+/// Lifecycle state of a supervised channel runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthState {
+    /// Registering with the channel backend.
+    Connecting,
+    /// Registered and processing events.
+    Up,
+    /// Exited or failed; waiting to be restarted.
+    Degraded,
+    /// Stopped for good (shutdown requested).
+    Down,
+}
+
+/// Health snapshot for one channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelHealth {
+    pub state: HealthState,
+    /// Number of restarts since the process started.
+    pub restarts: u32,
+    /// Error that caused the most recent restart, if any.
+    pub last_error: Option<String>,
+}
+
+/// Shared, cloneable view of every supervised channel's health, keyed by the
+/// configured channel name.
+#[derive(Clone, Default)]
+pub struct HealthMonitor {
+    channels: Arc<RwLock<BTreeMap<String, ChannelHealth>>>,
+}
+
+impl HealthMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Health of a single channel, or `None` if it is not supervised.
+    pub fn get(&self, channel: &str) -> Option<ChannelHealth> {
+        self.channels
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(channel)
+            .cloned()
+    }
+
+    /// Health of every supervised channel.
+    pub fn snapshot(&self) -> BTreeMap<String, ChannelHealth> {
+        self.channels
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Create a reporter that updates the entry for `channel`.
+    pub fn reporter(&self, channel: impl Into<String>) -> HealthReporter {
+        let channel = channel.into();
+        self.channels
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(channel.clone())
+            .or_insert(ChannelHealth {
+                state: HealthState::Connecting,
+                restarts: 0,
+                last_error: None,
+            });
+        HealthReporter {
+            monitor: self.clone(),
+            channel,
+        }
+    }
+
+    fn update(&self, channel: &str, f: impl FnOnce(&mut ChannelHealth)) {
+        if let Some(health) = self
+            .channels
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(channel)
+        {
+            f(health);
+        }
+    }
+}
+
+/// Write handle for a single channel's [`ChannelHealth`] entry.
+#[derive(Clone)]
+pub struct HealthReporter {
+    monitor: HealthMonitor,
+    channel: String,
+}
+
+impl HealthReporter {
+    /// Set the channel's state, keeping its restart count and last error.
+    pub fn set(&self, state: HealthState) {
+        self.monitor.update(&self.channel, |health| health.state = state);
+    }
+
+    fn degraded(&self, error: String) {
+        self.monitor.update(&self.channel, |health| {
+            health.state = HealthState::Degraded;
+            health.last_error = Some(error);
+        });
+    }
+
+    fn restarted(&self) {
+        self.monitor
+            .update(&self.channel, |health| health.restarts += 1);
+    }
+}
+
+/// Exponential backoff between restarts: `initial`, doubled per consecutive
+/// failure, capped at `max`. A runtime that stayed up for at least `max`
+/// starts over from `initial`.
+#[derive(Debug, Clone, Copy)]
+pub struct RestartBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for RestartBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RestartBackoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(1u32 << attempt.min(16))
+            .min(self.max)
+    }
+}
+
+/// Run `runtime` until shutdown, restarting it whenever it returns.
+///
+/// A [`ChannelRuntime`] returns either because registration failed or because
+/// the channel's event stream closed; both are logged, recorded on `health`
+/// and followed by a restart after the backoff delay.
+pub async fn supervise(
+    mut runtime: ChannelRuntime,
+    health: HealthReporter,
+    mut shutdown: ShutdownSignal,
+    backoff: RestartBackoff,
+) {
+    let mut attempt = 0;
+    loop {
+        health.set(HealthState::Connecting);
+        let started = Instant::now();
+        let result = runtime.run().await;
+
+        if shutdown.is_triggered() {
+            health.set(HealthState::Down);
+            return;
+        }
+
+        let error = match result {
+            Ok(()) => "event stream closed".to_owned(),
+            Err(e) => format!("{e:#}"),
+        };
+        if started.elapsed() >= backoff.max {
+            attempt = 0;
+        }
+        let delay = backoff.delay(attempt);
+        tracing::warn!(
+            target: "runtime",
+            "channel {} stopped ({error}), restarting in {}s",
+            health.channel,
+            delay.as_secs_f32()
+        );
+        health.degraded(error);
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.wait() => {
+                health.set(HealthState::Down);
+                return;
+            }
+        }
+        attempt = attempt.saturating_add(1);
+        health.restarted();
+        crate::telemetry::metrics().record_channel_restart(&health.channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use nekobot_channel::{Channel, ChannelId, ChannelInfo, ChannelName, Event, Request};
+
+    use super::*;
+    use crate::runtime::channel::ChannelContext;
+
+    /// Fails to register `failures` times, then registers and immediately
+    /// closes its event stream.
+    struct FlakyChannel {
+        failures: usize,
+        registrations: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Channel for FlakyChannel {
+        async fn register(
+            &self,
+            _sender: tokio::sync::mpsc::Sender<Event>,
+            _app_db: Option<turso::Connection>,
+        ) -> anyhow::Result<ChannelInfo> {
+            let attempt = self.registrations.fetch_add(1, Ordering::SeqCst);
+            if attempt < self.failures {
+                anyhow::bail!("register failed");
+            }
+            Ok(ChannelInfo {
+                id: ChannelId::from("flaky"),
+                name: ChannelName::from("Flaky"),
+            })
+        }
+
+        async fn send(&self, _request: Request) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_and_caps() {
+        let backoff = RestartBackoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(5),
+        };
+
+        assert_eq!(backoff.delay(0), Duration::from_secs(1));
+        assert_eq!(backoff.delay(1), Duration::from_secs(2));
+        assert_eq!(backoff.delay(2), Duration::from_secs(4));
+        assert_eq!(backoff.delay(3), Duration::from_secs(5));
+        assert_eq!(backoff.delay(40), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn failing_runtime_is_restarted_and_reported() -> anyhow::Result<()> {
+        let conn = crate::entity::test_connection().await?;
+        let registrations = Arc::new(AtomicUsize::new(0));
+        let runtime = ChannelRuntime::new(
+            Box::new(FlakyChannel {
+                failures: 2,
+                registrations: Arc::clone(&registrations),
+            }),
+            ChannelContext { app_db: conn },
+            Vec::new(),
+        );
+        let monitor = HealthMonitor::new();
+        let reporter = monitor.reporter("flaky");
+        let (trigger, signal) = crate::runtime::shutdown::channel();
+        let backoff = RestartBackoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(5),
+        };
+        let task = tokio::spawn(supervise(
+            runtime.with_health(reporter.clone()),
+            reporter,
+            signal,
+            backoff,
+        ));
+
+        while registrations.load(Ordering::SeqCst) < 4 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let health = monitor.get("flaky").expect("channel should be tracked");
+        assert!(health.restarts >= 3);
+        assert!(health.last_error.is_some());
+
+        trigger.trigger();
+        tokio::time::timeout(Duration::from_secs(5), task).await??;
+        assert_eq!(monitor.get("flaky").unwrap().state, HealthState::Down);
+        assert!(monitor.get("missing").is_none());
+        Ok(())
+    }
+}
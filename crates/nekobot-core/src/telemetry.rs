@@ -0,0 +1,398 @@
+//! Prometheus metrics for turns, provider calls, tool calls and channel
+//! restarts, plus a minimal HTTP endpoint serving them at `/metrics`.
+//!
+//! Spans are emitted through `tracing`; exporting them over OTLP is set up by
+//! the binary from [`TelemetryConfig`](crate::config::TelemetryConfig).
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use prometheus_client::{
+    encoding::{EncodeLabelSet, text::encode},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        histogram::{Histogram, exponential_buckets},
+    },
+    registry::{Registry, Unit},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{agent::types::Usage, provider::ProviderError};
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+/// Buckets from 50ms to about 100s.
+fn latency_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(0.05, 2.0, 12))
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct TurnLabels {
+    agent: String,
+    outcome: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ProviderLabels {
+    provider: String,
+    model: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct TokenLabels {
+    provider: String,
+    model: String,
+    kind: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ToolLabels {
+    tool: String,
+    outcome: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ChannelLabels {
+    channel: String,
+}
+
+/// Process-wide metric families. Use [`metrics`] to get the shared instance.
+pub struct Metrics {
+    registry: Registry,
+    turns: Family<TurnLabels, Counter>,
+    turn_duration: HistogramFamily<TurnLabels>,
+    provider_duration: HistogramFamily<ProviderLabels>,
+    provider_errors: Family<ProviderLabels, Counter>,
+    tokens: Family<TokenLabels, Counter>,
+    tool_calls: Family<ToolLabels, Counter>,
+    channel_events: Family<ChannelLabels, Counter>,
+    channel_restarts: Family<ChannelLabels, Counter>,
+}
+
+/// The shared metrics instance.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let mut registry = Registry::with_prefix("nekobot");
+        let turns = Family::default();
+        registry.register("turns", "Agent turns by outcome", turns.clone());
+        let turn_duration: HistogramFamily<TurnLabels> =
+            Family::new_with_constructor(latency_histogram);
+        registry.register_with_unit(
+            "turn_duration",
+            "Time from activation to reply",
+            Unit::Seconds,
+            turn_duration.clone(),
+        );
+        let provider_duration: HistogramFamily<ProviderLabels> =
+            Family::new_with_constructor(latency_histogram);
+        registry.register_with_unit(
+            "provider_request_duration",
+            "Provider completion latency",
+            Unit::Seconds,
+            provider_duration.clone(),
+        );
+        let provider_errors = Family::default();
+        registry.register(
+            "provider_errors",
+            "Failed provider completions",
+            provider_errors.clone(),
+        );
+        let tokens = Family::default();
+        registry.register("tokens", "Tokens reported by providers", tokens.clone());
+        let tool_calls = Family::default();
+        registry.register("tool_calls", "Tool calls by outcome", tool_calls.clone());
+        let channel_events = Family::default();
+        registry.register(
+            "channel_events",
+            "Events received from channels",
+            channel_events.clone(),
+        );
+        let channel_restarts = Family::default();
+        registry.register(
+            "channel_restarts",
+            "Channel runtime restarts after a failure or disconnect",
+            channel_restarts.clone(),
+        );
+
+        Self {
+            registry,
+            turns,
+            turn_duration,
+            provider_duration,
+            provider_errors,
+            tokens,
+            tool_calls,
+            channel_events,
+            channel_restarts,
+        }
+    }
+
+    /// Record a finished agent turn.
+    pub fn record_turn(&self, agent: &str, ok: bool, elapsed: Duration) {
+        let labels = TurnLabels {
+            agent: agent.to_owned(),
+            outcome: if ok { "ok" } else { "error" },
+        };
+        self.turns.get_or_create(&labels).inc();
+        self.turn_duration
+            .get_or_create(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Record a provider completion and, if it succeeded, the tokens it used.
+    pub fn record_provider_call(
+        &self,
+        provider: &str,
+        model: &str,
+        elapsed: Duration,
+        usage: Result<Option<&Usage>, &ProviderError>,
+    ) {
+        let labels = ProviderLabels {
+            provider: provider.to_owned(),
+            model: model.to_owned(),
+        };
+        self.provider_duration
+            .get_or_create(&labels)
+            .observe(elapsed.as_secs_f64());
+        let Ok(Some(usage)) = usage else {
+            if usage.is_err() {
+                self.provider_errors.get_or_create(&labels).inc();
+            }
+            return;
+        };
+        for (kind, count) in [("input", usage.input_tokens), ("output", usage.output_tokens)] {
+            if let Some(count) = count {
+                self.tokens
+                    .get_or_create(&TokenLabels {
+                        provider: provider.to_owned(),
+                        model: model.to_owned(),
+                        kind,
+                    })
+                    .inc_by(count);
+            }
+        }
+    }
+
+    /// Record a tool call; `outcome` is `ok`, `error` or `denied`.
+    pub fn record_tool_call(&self, tool: &str, outcome: &'static str) {
+        self.tool_calls
+            .get_or_create(&ToolLabels {
+                tool: tool.to_owned(),
+                outcome,
+            })
+            .inc();
+    }
+
+    /// Record an event received from `channel`.
+    pub fn record_channel_event(&self, channel: &str) {
+        self.channel_events
+            .get_or_create(&ChannelLabels {
+                channel: channel.to_owned(),
+            })
+            .inc();
+    }
+
+    /// Record a restart of the runtime of `channel`.
+    pub fn record_channel_restart(&self, channel: &str) {
+        self.channel_restarts
+            .get_or_create(&ChannelLabels {
+                channel: channel.to_owned(),
+            })
+            .inc();
+    }
+
+    /// Render every metric in the OpenMetrics text format.
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        // Writing into a String cannot fail.
+        let _ = encode(&mut out, &self.registry);
+        out
+    }
+}
+
+/// Serve [`metrics`] at `GET /metrics` on `listener` until the task is aborted.
+pub async fn serve_metrics(listener: TcpListener) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!(target: "telemetry", "metrics accept error: {e}");
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream).await {
+                tracing::debug!(target: "telemetry", "metrics request error: {e}");
+            }
+        });
+    }
+}
+
+/// Largest request head accepted, far more than a `GET /metrics` needs.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// Read up to the blank line ending the request head, however the client splits it.
+async fn read_request_head(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_HEAD {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "request head too large",
+            ));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Ok(head)
+}
+
+async fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    let head = read_request_head(&mut stream).await?;
+    let request = String::from_utf8_lossy(&head);
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            metrics().encode(),
+        ),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_owned()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send a request to a fresh endpoint in `parts`, pausing between them.
+    async fn get(parts: &[&[u8]]) -> anyhow::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = tokio::spawn(serve_metrics(listener));
+
+        let mut stream = TcpStream::connect(addr).await?;
+        for part in parts {
+            stream.write_all(part).await?;
+            stream.flush().await?;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        server.abort();
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn endpoint_serves_recorded_metrics() -> anyhow::Result<()> {
+        metrics().record_tool_call("telemetry_test_tool", "denied");
+        metrics().record_provider_call(
+            "test-provider",
+            "test-model",
+            Duration::from_millis(120),
+            Ok(Some(&Usage {
+                input_tokens: Some(7),
+                output_tokens: Some(3),
+                total_tokens: Some(10),
+            })),
+        );
+
+        let response = get(&[b"GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n"]).await?;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains(
+            r#"nekobot_tool_calls_total{tool="telemetry_test_tool",outcome="denied"} 1"#
+        ));
+        assert!(response.contains(
+            r#"nekobot_tokens_total{provider="test-provider",model="test-model",kind="input"} 7"#
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn endpoint_reads_requests_split_across_packets() -> anyhow::Result<()> {
+        let response = get(&[b"GET /met", b"rics HTTP/1.1\r\nHost: x\r\n", b"\r\n"]).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        let response = get(&[b"GET /other HTTP/1.1\r\n\r\n"]).await?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"), "{response}");
+        Ok(())
+    }
+
+    #[test]
+    fn records_turn_provider_and_channel_values() {
+        let metrics = Metrics::new();
+        metrics.record_turn("Neko", true, Duration::from_millis(100));
+        metrics.record_turn("Neko", true, Duration::from_millis(300));
+        metrics.record_turn("Neko", false, Duration::from_secs(2));
+        metrics.record_provider_call(
+            "deepseek",
+            "chat",
+            Duration::from_millis(50),
+            Ok(Some(&Usage {
+                input_tokens: Some(10),
+                output_tokens: None,
+                total_tokens: Some(10),
+            })),
+        );
+        metrics.record_provider_call(
+            "deepseek",
+            "chat",
+            Duration::from_millis(50),
+            Ok(Some(&Usage {
+                input_tokens: Some(5),
+                output_tokens: Some(2),
+                total_tokens: Some(7),
+            })),
+        );
+        metrics.record_provider_call(
+            "deepseek",
+            "chat",
+            Duration::from_millis(50),
+            Err(&ProviderError::Timeout("60s".to_owned())),
+        );
+        metrics.record_tool_call("bash", "ok");
+        metrics.record_tool_call("bash", "ok");
+        metrics.record_tool_call("bash", "error");
+        metrics.record_channel_event("qq");
+        metrics.record_channel_restart("qq");
+        metrics.record_channel_restart("qq");
+
+        let text = metrics.encode();
+        for line in [
+            r#"nekobot_turns_total{agent="Neko",outcome="ok"} 2"#,
+            r#"nekobot_turns_total{agent="Neko",outcome="error"} 1"#,
+            r#"nekobot_turn_duration_seconds_count{agent="Neko",outcome="ok"} 2"#,
+            r#"nekobot_turn_duration_seconds_sum{agent="Neko",outcome="ok"} 0.4"#,
+            r#"nekobot_provider_request_duration_seconds_count{provider="deepseek",model="chat"} 3"#,
+            r#"nekobot_provider_errors_total{provider="deepseek",model="chat"} 1"#,
+            r#"nekobot_tokens_total{provider="deepseek",model="chat",kind="input"} 15"#,
+            r#"nekobot_tokens_total{provider="deepseek",model="chat",kind="output"} 2"#,
+            r#"nekobot_tool_calls_total{tool="bash",outcome="ok"} 2"#,
+            r#"nekobot_tool_calls_total{tool="bash",outcome="error"} 1"#,
+            r#"nekobot_channel_events_total{channel="qq"} 1"#,
+            r#"nekobot_channel_restarts_total{channel="qq"} 2"#,
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+        }
+    }
+}
@@ -0,0 +1,589 @@
+//! Conversation export and import.
+//!
+//! Sessions are exported as JSONL, one message per line, or as Markdown or
+//! HTML transcripts for people to read. Reasoning and tool calls are kept in
+//! all formats. Imports accept the JSONL export as well as OpenAI chat-format
+//! files (a `messages` array per conversation, as JSON or JSONL) and create a
+//! new [`Session`] per conversation.
+
+use std::{fmt::Write as _, str::FromStr};
+
+use nekobot_channel::{ChannelId, ChatId};
+use serde_json::{Value, json};
+use turso::Connection;
+
+use crate::entity::{
+    channel_chat_agent::ChannelChatAgent, chat_session::ChatSession, message::Message,
+    session::Session,
+};
+
+/// Which sessions to export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+    Session(i64),
+    /// Every session of an agent.
+    Agent(String),
+    /// Every session a chat has had, with any agent.
+    Chat {
+        channel_id: String,
+        chat_id: String,
+    },
+}
+
+/// Output format of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    Jsonl,
+    Markdown,
+    Html,
+}
+
+impl FromStr for TranscriptFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jsonl" => Ok(Self::Jsonl),
+            "markdown" | "md" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            other => anyhow::bail!(
+                "unknown transcript format '{other}', expected jsonl, markdown or html"
+            ),
+        }
+    }
+}
+
+/// A session with its messages, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    pub session: Session,
+    /// Title of the chat session, if it was given one.
+    pub title: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+/// Load the selected sessions, ordered by session id.
+pub async fn load(conn: &Connection, selection: &Selection) -> anyhow::Result<Vec<Transcript>> {
+    let mut session_ids = match selection {
+        Selection::Session(id) => vec![*id],
+        Selection::Agent(agent_name) => Session::list_by_agent(conn, agent_name)
+            .await?
+            .into_iter()
+            .map(|s| s.id)
+            .collect(),
+        Selection::Chat {
+            channel_id,
+            chat_id,
+        } => {
+            let mut ids = Vec::new();
+            let mappings = ChannelChatAgent::list_by_chat(
+                conn,
+                &ChannelId::from(channel_id.as_str()),
+                &ChatId::from(chat_id.as_str()),
+            )
+            .await?;
+            for mapping in mappings {
+                ids.push(mapping.session_id.as_i64());
+                for chat_session in
+                    ChatSession::list_by_channel_chat_agent(conn, mapping.id).await?
+                {
+                    ids.push(chat_session.session_id.as_i64());
+                }
+            }
+            ids
+        }
+    };
+    session_ids.sort_unstable();
+    session_ids.dedup();
+
+    let mut transcripts = Vec::with_capacity(session_ids.len());
+    for id in session_ids {
+        let session = Session::get(conn, id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("session {id} not found"))?;
+        let title = ChatSession::get(conn, id.into())
+            .await?
+            .and_then(|chat_session| chat_session.title);
+        let messages = Message::list_by_session(conn, id).await?;
+        transcripts.push(Transcript {
+            session,
+            title,
+            messages,
+        });
+    }
+    Ok(transcripts)
+}
+
+/// Render transcripts in the given format.
+pub fn render(transcripts: &[Transcript], format: TranscriptFormat) -> anyhow::Result<String> {
+    match format {
+        TranscriptFormat::Jsonl => to_jsonl(transcripts),
+        TranscriptFormat::Markdown => Ok(to_markdown(transcripts)),
+        TranscriptFormat::Html => Ok(to_html(transcripts)),
+    }
+}
+
+fn to_jsonl(transcripts: &[Transcript]) -> anyhow::Result<String> {
+    let mut out = String::new();
+    for transcript in transcripts {
+        for message in &transcript.messages {
+            let line = json!({
+                "session_id": transcript.session.id,
+                "agent_name": transcript.session.agent_name,
+                "role": message.role,
+                "content": message.content,
+                "reasoning_content": message.reasoning_content,
+                "tool_call_id": message.tool_call_id,
+                "tool_calls": tool_calls(message),
+                "created_at": message.created_at,
+            });
+            out.push_str(&serde_json::to_string(&line)?);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+fn to_markdown(transcripts: &[Transcript]) -> String {
+    let mut out = String::new();
+    for transcript in transcripts {
+        let _ = write!(
+            out,
+            "# Session {} ({})",
+            transcript.session.id, transcript.session.agent_name
+        );
+        if let Some(title) = &transcript.title {
+            let _ = write!(out, ": {title}");
+        }
+        out.push_str("\n\n");
+        for message in &transcript.messages {
+            let _ = write!(
+                out,
+                "### {} · {}\n\n",
+                message.role,
+                timestamp(message.created_at)
+            );
+            if let Some(id) = &message.tool_call_id {
+                let _ = write!(out, "Result of `{id}`:\n\n");
+            }
+            if let Some(reasoning) = message
+                .reasoning_content
+                .as_deref()
+                .filter(|r| !r.is_empty())
+            {
+                out.push_str("> **Reasoning**\n>\n");
+                for line in reasoning.lines() {
+                    let _ = writeln!(out, "> {line}");
+                }
+                out.push('\n');
+            }
+            if !message.content.is_empty() {
+                out.push_str(&message.content);
+                out.push_str("\n\n");
+            }
+            for call in tool_calls_of(message) {
+                let _ = write!(
+                    out,
+                    "**Tool call** `{}` (`{}`):\n\n```json\n{}\n```\n\n",
+                    call.name, call.id, call.arguments
+                );
+            }
+        }
+    }
+    out
+}
+
+fn to_html(transcripts: &[Transcript]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Transcript</title>\n<style>\
+         body{font-family:sans-serif;max-width:50em;margin:auto}\
+         article{border-left:3px solid #ccc;margin:1em 0;padding:0 1em}\
+         article.user{border-color:#4a90d9}article.assistant{border-color:#5cb85c}\
+         .meta{color:#777;font-size:.85em}pre{white-space:pre-wrap;background:#f6f6f6;padding:.5em}\
+         </style>\n</head>\n<body>\n",
+    );
+    for transcript in transcripts {
+        let _ = write!(
+            out,
+            "<section>\n<h1>Session {} ({})",
+            transcript.session.id,
+            escape(&transcript.session.agent_name)
+        );
+        if let Some(title) = &transcript.title {
+            let _ = write!(out, ": {}", escape(title));
+        }
+        out.push_str("</h1>\n");
+        for message in &transcript.messages {
+            let _ = write!(
+                out,
+                "<article class=\"{0}\">\n<p class=\"meta\">{0} · {1}",
+                escape(&message.role),
+                timestamp(message.created_at)
+            );
+            if let Some(id) = &message.tool_call_id {
+                let _ = write!(out, " · result of <code>{}</code>", escape(id));
+            }
+            out.push_str("</p>\n");
+            if let Some(reasoning) = message
+                .reasoning_content
+                .as_deref()
+                .filter(|r| !r.is_empty())
+            {
+                let _ = writeln!(
+                    out,
+                    "<details><summary>Reasoning</summary><pre>{}</pre></details>",
+                    escape(reasoning)
+                );
+            }
+            if !message.content.is_empty() {
+                let _ = writeln!(out, "<pre>{}</pre>", escape(&message.content));
+            }
+            for call in tool_calls_of(message) {
+                let _ = writeln!(
+                    out,
+                    "<p>Tool call <code>{}</code> (<code>{}</code>):</p><pre>{}</pre>",
+                    escape(&call.name),
+                    escape(&call.id),
+                    escape(&call.arguments)
+                );
+            }
+            out.push_str("</article>\n");
+        }
+        out.push_str("</section>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Stored tool calls as JSON, or the raw string if it is not valid JSON.
+fn tool_calls(message: &Message) -> Value {
+    match &message.tool_calls {
+        Some(raw) => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.clone())),
+        None => Value::Null,
+    }
+}
+
+struct CallSummary {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+fn tool_calls_of(message: &Message) -> Vec<CallSummary> {
+    let Value::Array(calls) = tool_calls(message) else {
+        return Vec::new();
+    };
+    calls
+        .iter()
+        .map(|call| CallSummary {
+            id: call["id"].as_str().unwrap_or_default().to_owned(),
+            name: call["function"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_owned(),
+            arguments: call["function"]["arguments"]
+                .as_str()
+                .unwrap_or_default()
+                .to_owned(),
+        })
+        .collect()
+}
+
+fn timestamp(secs: i64) -> String {
+    chrono::DateTime::from_timestamp(secs, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A message read from an import file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ImportedMessage {
+    role: String,
+    content: String,
+    reasoning_content: Option<String>,
+    tool_call_id: Option<String>,
+    tool_calls: Option<String>,
+    created_at: Option<i64>,
+}
+
+/// Import conversations into new sessions of `agent_name`.
+///
+/// Returns the ids of the created sessions. Nothing is stored if any
+/// conversation in `data` is malformed.
+pub async fn import(conn: &Connection, agent_name: &str, data: &str) -> anyhow::Result<Vec<i64>> {
+    let conversations = parse_conversations(data)?;
+    conn.execute("BEGIN", ()).await?;
+    let result = async {
+        let mut session_ids = Vec::with_capacity(conversations.len());
+        for messages in conversations {
+            let session = Session::create(conn, agent_name).await?;
+            for message in messages {
+                let stored = Message::create(
+                    conn,
+                    session.id,
+                    message.role,
+                    message.content,
+                    message.reasoning_content,
+                    message.tool_call_id,
+                    message.tool_calls,
+                )
+                .await?;
+                if let Some(created_at) = message.created_at {
+                    Message::set_created_at(conn, stored.id, created_at).await?;
+                }
+            }
+            session_ids.push(session.id);
+        }
+        anyhow::Ok(session_ids)
+    }
+    .await;
+    match result {
+        Ok(session_ids) => {
+            conn.execute("COMMIT", ()).await?;
+            Ok(session_ids)
+        }
+        Err(error) => {
+            let _ = conn.execute("ROLLBACK", ()).await;
+            Err(error)
+        }
+    }
+}
+
+/// Split an import file into conversations.
+///
+/// Accepts a JSON array of messages, a JSON object or JSONL lines with a
+/// `messages` array (OpenAI chat format), or JSONL messages as written by
+/// the export, grouped into conversations by `session_id`.
+fn parse_conversations(data: &str) -> anyhow::Result<Vec<Vec<ImportedMessage>>> {
+    let data = data.trim();
+    if let Ok(value) = serde_json::from_str::<Value>(data) {
+        return match value {
+            Value::Array(messages) => Ok(vec![parse_messages(&messages)?]),
+            Value::Object(_) if value.get("messages").is_some() => {
+                Ok(vec![parse_messages(messages_of(&value)?)?])
+            }
+            Value::Object(_) => Ok(vec![vec![parse_message(&value)?]]),
+            _ => anyhow::bail!("expected a JSON object or array"),
+        };
+    }
+
+    let mut conversations: Vec<Vec<ImportedMessage>> = Vec::new();
+    let mut current_session: Option<Value> = None;
+    for (index, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(line)
+            .map_err(|e| anyhow::anyhow!("line {}: invalid JSON: {e}", index + 1))?;
+        if value.get("messages").is_some() {
+            conversations.push(
+                parse_messages(messages_of(&value)?)
+                    .map_err(|e| e.context(format!("line {}", index + 1)))?,
+            );
+            current_session = None;
+            continue;
+        }
+        let message =
+            parse_message(&value).map_err(|e| e.context(format!("line {}", index + 1)))?;
+        let session = value.get("session_id").cloned().unwrap_or(Value::Null);
+        match conversations.last_mut() {
+            Some(conversation) if current_session.as_ref() == Some(&session) => {
+                conversation.push(message)
+            }
+            _ => conversations.push(vec![message]),
+        }
+        current_session = Some(session);
+    }
+    if conversations.is_empty() {
+        anyhow::bail!("no messages to import");
+    }
+    Ok(conversations)
+}
+
+fn messages_of(value: &Value) -> anyhow::Result<&Vec<Value>> {
+    value["messages"]
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("`messages` must be an array"))
+}
+
+fn parse_messages(messages: &[Value]) -> anyhow::Result<Vec<ImportedMessage>> {
+    messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| {
+            parse_message(message).map_err(|e| e.context(format!("message {}", index + 1)))
+        })
+        .collect()
+}
+
+fn parse_message(value: &Value) -> anyhow::Result<ImportedMessage> {
+    let role = value["role"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("message has no role"))?;
+    let content = match &value["content"] {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        // Content parts; only text survives the import.
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        other => anyhow::bail!("unsupported content {other}"),
+    };
+    let tool_calls = match &value["tool_calls"] {
+        Value::Null => None,
+        Value::Array(calls) if calls.is_empty() => None,
+        Value::String(raw) => Some(raw.clone()),
+        calls @ Value::Array(_) => Some(serde_json::to_string(calls)?),
+        other => anyhow::bail!("unsupported tool_calls {other}"),
+    };
+    Ok(ImportedMessage {
+        role: role.to_owned(),
+        content,
+        reasoning_content: value["reasoning_content"].as_str().map(str::to_owned),
+        tool_call_id: value["tool_call_id"].as_str().map(str::to_owned),
+        tool_calls,
+        created_at: value["created_at"].as_i64(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{Entity, test_connection};
+
+    async fn connection() -> anyhow::Result<Connection> {
+        let conn = test_connection().await?;
+        Session::create_table(&conn).await?;
+        Message::create_table(&conn).await?;
+        ChannelChatAgent::create_table(&conn).await?;
+        ChatSession::create_table(&conn).await?;
+        Ok(conn)
+    }
+
+    async fn conversation(conn: &Connection) -> anyhow::Result<Session> {
+        let session = Session::create(conn, "Neko").await?;
+        Message::create(
+            conn,
+            session.id,
+            "user",
+            "what time is it?",
+            None,
+            None,
+            None,
+        )
+        .await?;
+        let calls =
+            r#"[{"id":"call-1","type":"function","function":{"name":"time","arguments":"{}"}}]"#;
+        Message::create(
+            conn,
+            session.id,
+            "assistant",
+            "",
+            Some("I should <check>".to_owned()),
+            None,
+            Some(calls.to_owned()),
+        )
+        .await?;
+        Message::create(
+            conn,
+            session.id,
+            "tool",
+            "12:00",
+            None,
+            Some("call-1".to_owned()),
+            None,
+        )
+        .await?;
+        Message::create(
+            conn,
+            session.id,
+            "assistant",
+            "It is noon.",
+            None,
+            None,
+            None,
+        )
+        .await?;
+        Ok(session)
+    }
+
+    #[tokio::test]
+    async fn jsonl_export_round_trips_through_import() -> anyhow::Result<()> {
+        let conn = connection().await?;
+        let session = conversation(&conn).await?;
+        let exported = load(&conn, &Selection::Session(session.id)).await?;
+        let jsonl = render(&exported, TranscriptFormat::Jsonl)?;
+
+        let imported = import(&conn, "Neko", &jsonl).await?;
+        assert_eq!(imported.len(), 1);
+        let original = Message::list_by_session(&conn, session.id).await?;
+        let copy = Message::list_by_session(&conn, imported[0]).await?;
+        assert_eq!(copy.len(), original.len());
+        for (copy, original) in copy.iter().zip(&original) {
+            assert_eq!(copy.role, original.role);
+            assert_eq!(copy.content, original.content);
+            assert_eq!(copy.reasoning_content, original.reasoning_content);
+            assert_eq!(copy.tool_call_id, original.tool_call_id);
+            assert_eq!(copy.created_at, original.created_at);
+        }
+        assert!(copy[1].tool_calls.as_deref().unwrap().contains("\"time\""));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn imports_openai_chat_format() -> anyhow::Result<()> {
+        let conn = connection().await?;
+        let data = r#"{"messages": [{"role": "system", "content": "Be brief."}, {"role": "user", "content": [{"type": "text", "text": "hi"}]}, {"role": "assistant", "content": "hello"}]}
+{"messages": [{"role": "user", "content": "again"}]}"#;
+
+        let sessions = import(&conn, "Neko", data).await?;
+        assert_eq!(sessions.len(), 2);
+        let first = Message::list_by_session(&conn, sessions[0]).await?;
+        let roles: Vec<&str> = first.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant"]);
+        assert_eq!(first[1].content, "hi");
+
+        assert!(
+            import(&conn, "Neko", r#"{"messages": [{"content": "no role"}]}"#)
+                .await
+                .is_err()
+        );
+        assert_eq!(Session::list_by_agent(&conn, "Neko").await?.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn readable_transcripts_show_reasoning_and_tool_calls() -> anyhow::Result<()> {
+        let conn = connection().await?;
+        conversation(&conn).await?;
+        let transcripts = load(&conn, &Selection::Agent("Neko".to_owned())).await?;
+
+        let markdown = render(&transcripts, TranscriptFormat::Markdown)?;
+        assert!(markdown.contains("> I should <check>"));
+        assert!(markdown.contains("**Tool call** `time` (`call-1`)"));
+        assert!(markdown.contains("Result of `call-1`"));
+
+        let html = render(&transcripts, TranscriptFormat::Html)?;
+        assert!(html.contains("<summary>Reasoning</summary><pre>I should &lt;check&gt;</pre>"));
+        assert!(html.contains("It is noon."));
+        Ok(())
+    }
+}
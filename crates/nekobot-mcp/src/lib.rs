@@ -19,14 +19,15 @@ use nekobot_core::agent::{
 use rmcp::{
     handler::client::ClientHandler,
     model::{CallToolRequestParams, PaginatedRequestParams},
-    service::{Peer, RoleClient, serve_client},
+    service::{Peer, RoleClient, RunningService, serve_client},
     transport::{
         child_process::TokioChildProcess, streamable_http_client::StreamableHttpClientTransport,
     },
 };
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::Value;
-use tokio::task::AbortHandle;
+use tokio::{sync::watch, task::JoinHandle};
 use tracing::debug;
 
 /// Deserialized from `MiddlewareConfig.data` via `#[serde(tag = "transport")]`.
@@ -45,7 +46,7 @@ use tracing::debug;
 ///   command: npx
 ///   args: ["-y", "@modelcontextprotocol/server-filesystem", "/path"]
 /// ```
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(tag = "transport")]
 pub enum McpConfig {
     /// Streamable HTTP transport (the default when `transport` is absent).
@@ -65,8 +66,10 @@ pub enum McpConfig {
 pub struct McpMiddleware {
     config: McpConfig,
     tool_specs: RwLock<Vec<ToolSpec>>,
-    /// Abort handles for the background MCP connection tasks. Aborted on drop.
-    _tasks: std::sync::Mutex<Vec<AbortHandle>>,
+    /// Background MCP connection tasks. Cancelled on shutdown, aborted on drop.
+    _tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
+    /// Flipped to `true` by [`Middleware::shutdown`] to close the connections.
+    stop: watch::Sender<bool>,
 }
 
 impl McpMiddleware {
@@ -76,6 +79,7 @@ impl McpMiddleware {
             config,
             tool_specs: RwLock::new(Vec::new()),
             _tasks: std::sync::Mutex::new(Vec::new()),
+            stop: watch::Sender::new(false),
         }
     }
 }
@@ -90,6 +94,21 @@ impl Drop for McpMiddleware {
     }
 }
 
+/// How long [`Middleware::shutdown`] waits for each connection to close.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keep an MCP connection alive until `stop` fires, then cancel it so the
+/// transport closes cleanly (for stdio this reaps the child process).
+async fn serve_until_stopped(
+    running: RunningService<RoleClient, EmptyHandler>,
+    mut stop: watch::Receiver<bool>,
+) {
+    let _ = stop.wait_for(|stopped| *stopped).await;
+    if let Err(e) = running.cancel().await {
+        tracing::warn!(target: "mcp", "failed to close MCP connection: {e}");
+    }
+}
+
 /// Empty client handler — in client role we don't need to handle requests from the server.
 struct EmptyHandler;
 
@@ -121,14 +140,14 @@ impl Middleware for McpMiddleware {
             McpConfig::Http { server, url } => {
                 let transport = StreamableHttpClientTransport::from_uri(url.as_str());
                 let (tx, rx) = tokio::sync::oneshot::channel();
+                let stop = self.stop.subscribe();
                 let task = tokio::spawn(async move {
                     match serve_client(EmptyHandler, transport).await {
                         Ok(running) => {
                             let peer = running.peer().clone();
                             let _ = tx.send(Ok(peer));
-                            // Keep the connection alive indefinitely
-                            std::future::pending::<()>().await;
-                            drop(running);
+                            // Keep the connection alive until shutdown
+                            serve_until_stopped(running, stop).await;
                         }
                         Err(e) => {
                             let _ = tx.send(Err(e));
@@ -138,7 +157,7 @@ impl Middleware for McpMiddleware {
                 self._tasks
                     .lock()
                     .map_err(|e| anyhow::anyhow!("MCP task lock poisoned: {e}"))?
-                    .push(task.abort_handle());
+                    .push(task);
 
                 let peer = tokio::time::timeout(Duration::from_secs(30), rx)
                     .await
@@ -176,13 +195,13 @@ impl Middleware for McpMiddleware {
                 }
 
                 let (tx, rx) = tokio::sync::oneshot::channel();
+                let stop = self.stop.subscribe();
                 let task = tokio::spawn(async move {
                     match serve_client(EmptyHandler, transport).await {
                         Ok(running) => {
                             let peer = running.peer().clone();
                             let _ = tx.send(Ok(peer));
-                            std::future::pending::<()>().await;
-                            drop(running);
+                            serve_until_stopped(running, stop).await;
                         }
                         Err(e) => {
                             let _ = tx.send(Err(e));
@@ -192,7 +211,7 @@ impl Middleware for McpMiddleware {
                 self._tasks
                     .lock()
                     .map_err(|e| anyhow::anyhow!("MCP task lock poisoned: {e}"))?
-                    .push(task.abort_handle());
+                    .push(task);
 
                 let peer = tokio::time::timeout(Duration::from_secs(30), rx)
                     .await
@@ -239,6 +258,24 @@ impl Middleware for McpMiddleware {
 
         Ok(())
     }
+
+    async fn shutdown(&self, _ctx: &Context) -> Result<(), anyhow::Error> {
+        self.stop.send_replace(true);
+        let tasks = std::mem::take(
+            &mut *self
+                ._tasks
+                .lock()
+                .map_err(|e| anyhow::anyhow!("MCP task lock poisoned: {e}"))?,
+        );
+        for task in tasks {
+            let abort = task.abort_handle();
+            if tokio::time::timeout(SHUTDOWN_TIMEOUT, task).await.is_err() {
+                tracing::warn!(target: "mcp", "MCP connection did not close in time, aborting");
+                abort.abort();
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Wraps an MCP server tool as a nekobot [`Tool`].
@@ -9,19 +9,18 @@ pub(crate) struct MemoryRow {
     pub content: String,
 }
 
+/// Schema of the `memories` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS memories (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    agent_name TEXT NOT NULL,
+    content TEXT NOT NULL,
+    embedding BLOB,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+)";
+
 /// Create the `memories` table if it doesn't exist.
 pub async fn create_table(conn: &Connection) -> anyhow::Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS memories (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            agent_name TEXT NOT NULL,
-            content TEXT NOT NULL,
-            embedding BLOB,
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        )",
-        (),
-    )
-    .await?;
+    conn.execute(CREATE_TABLE, ()).await?;
     Ok(())
 }
 
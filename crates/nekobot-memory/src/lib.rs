@@ -11,14 +11,26 @@ use nekobot_core::agent::{
     tool::{Tool, ToolError, ToolResult, ToolSpec},
     types::ChatRequest,
 };
+use nekobot_core::entity::migration::{Migration, MigrationSet};
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::Value;
 use turso::Connection;
 
 use embedding::EmbeddingClient;
 
+/// Schema migrations of the `memories` table; register them with the bot.
+pub const MIGRATIONS: MigrationSet = MigrationSet {
+    component: "memory",
+    migrations: &[Migration {
+        version: 1,
+        description: "memories table",
+        statements: &[entity::CREATE_TABLE],
+    }],
+};
+
 /// Deserialized from `MiddlewareConfig.data`.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct MemoryConfig {
     pub embedding_url: String,
     pub embedding_key: String,
@@ -0,0 +1,106 @@
+//! Classifier client — calls OpenAI-compatible `/v1/moderations` endpoints.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: Map<String, Value>,
+}
+
+#[derive(Clone)]
+pub struct ClassifierClient {
+    http: Arc<Client>,
+    api_url: String,
+    api_key: String,
+    model: Option<String>,
+}
+
+impl ClassifierClient {
+    pub fn new(api_url: String, api_key: String, model: Option<String>) -> Self {
+        Self {
+            http: Arc::new(Client::new()),
+            api_url,
+            api_key,
+            model,
+        }
+    }
+
+    /// Classify `text`; returns the flagged categories, or `None` if it passed.
+    pub async fn classify(&self, text: &str) -> anyhow::Result<Option<Vec<String>>> {
+        let mut body = serde_json::json!({ "input": text });
+        if let Some(model) = &self.model {
+            body["model"] = Value::String(model.clone());
+        }
+        let resp = self
+            .http
+            .post(&self.api_url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&body)
+            .send()
+            .await
+            .context("moderation request failed")?;
+
+        let status = resp.status();
+        let body = resp
+            .text()
+            .await
+            .context("failed to read moderation response")?;
+
+        if !status.is_success() {
+            anyhow::bail!("moderation API returned {status}: {body}");
+        }
+
+        parse_response(&body)
+    }
+}
+
+fn parse_response(body: &str) -> anyhow::Result<Option<Vec<String>>> {
+    let response: ModerationResponse =
+        serde_json::from_str(body).context("failed to parse moderation response")?;
+    let result = response
+        .results
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("empty moderation response"))?;
+    if !result.flagged {
+        return Ok(None);
+    }
+    Ok(Some(
+        result
+            .categories
+            .into_iter()
+            .filter(|(_, flagged)| flagged.as_bool() == Some(true))
+            .map(|(category, _)| category)
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_moderation_response() {
+        let flagged =
+            r#"{"results":[{"flagged":true,"categories":{"hate":true,"violence":false}}]}"#;
+        assert_eq!(
+            parse_response(flagged).unwrap(),
+            Some(vec!["hate".to_owned()])
+        );
+
+        let clean = r#"{"results":[{"flagged":false,"categories":{"hate":false}}]}"#;
+        assert_eq!(parse_response(clean).unwrap(), None);
+    }
+}
@@ -0,0 +1,105 @@
+//! Moderation hit entity — every blocked or rewritten message, kept for review.
+
+use turso::Connection;
+
+/// Whether a hit was found in user input or model output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Input => "input",
+            Direction::Output => "output",
+        }
+    }
+}
+
+/// A row in the `moderation_hits` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HitRow {
+    pub id: i64,
+    pub agent_name: String,
+    pub session_id: i64,
+    /// `input` or `output`.
+    pub direction: String,
+    /// Rule that matched, e.g. `keyword:foo`, `pattern:\d+` or `classifier:hate`.
+    pub rule: String,
+    /// What was done: `reply`, `block`, `mask` or `regenerate`.
+    pub action: String,
+    /// The offending text as it was before moderation.
+    pub content: String,
+    pub created_at: String,
+}
+
+/// Data needed to log a hit.
+pub struct NewHit<'a> {
+    pub agent_name: &'a str,
+    pub session_id: i64,
+    pub direction: Direction,
+    pub rule: &'a str,
+    pub action: &'a str,
+    pub content: &'a str,
+}
+
+/// Schema of the `moderation_hits` table (migration 1).
+pub(crate) const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS moderation_hits (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    agent_name TEXT NOT NULL,
+    session_id INTEGER NOT NULL,
+    direction TEXT NOT NULL,
+    rule TEXT NOT NULL,
+    action TEXT NOT NULL,
+    content TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+)";
+
+/// Create the `moderation_hits` table if it doesn't exist.
+pub async fn create_table(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute(CREATE_TABLE, ()).await?;
+    Ok(())
+}
+
+/// Log a hit.
+pub async fn insert(conn: &Connection, hit: &NewHit<'_>) -> anyhow::Result<i64> {
+    conn.execute(
+        "INSERT INTO moderation_hits (agent_name, session_id, direction, rule, action, content)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (
+            hit.agent_name,
+            hit.session_id,
+            hit.direction.as_str(),
+            hit.rule,
+            hit.action,
+            hit.content,
+        ),
+    )
+    .await?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Return the most recent hits, newest first.
+pub async fn list_recent(conn: &Connection, limit: usize) -> anyhow::Result<Vec<HitRow>> {
+    let sql = format!(
+        "SELECT id, agent_name, session_id, direction, rule, action, content, created_at \
+         FROM moderation_hits ORDER BY id DESC LIMIT {limit}"
+    );
+    let mut rows = conn.query(&sql, ()).await?;
+    let mut results = Vec::new();
+    while let Some(row) = rows.next().await? {
+        results.push(HitRow {
+            id: row.get(0)?,
+            agent_name: row.get(1)?,
+            session_id: row.get(2)?,
+            direction: row.get(3)?,
+            rule: row.get(4)?,
+            action: row.get(5)?,
+            content: row.get(6)?,
+            created_at: row.get(7)?,
+        });
+    }
+    Ok(results)
+}
@@ -0,0 +1,512 @@
+//! Moderation middleware — keeps prohibited content out of user input and
+//! model output.
+//!
+//! Messages are checked against case-insensitive keywords, regular
+//! expressions and, if configured, an OpenAI-compatible moderation endpoint.
+//! A flagged user message is answered with a canned reply without reaching
+//! the provider, and its stored copy is replaced with [`WITHHELD_INPUT`] so
+//! later turns do not send it either. A flagged model reply is blocked,
+//! masked or regenerated depending on [`OutputAction`]. Every hit is logged
+//! to the `moderation_hits` table. If the classifier cannot be reached, the
+//! message is only checked against the keywords and patterns.
+
+mod classifier;
+pub mod entity;
+
+use std::sync::Mutex;
+
+use nekobot_core::{
+    agent::{
+        Context,
+        middleware::{Middleware, MiddlewareFlow, ResponseFlow},
+        types::{ChatRequest, ChatResponse, Role},
+    },
+    entity::{
+        message::{Message, Role as MessageRole},
+        migration::{Migration, MigrationSet},
+    },
+};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use classifier::ClassifierClient;
+use entity::{Direction, NewHit};
+
+/// Instruction given to the model when its reply is regenerated.
+const REGENERATE_INSTRUCTION: &str = "Your previous answer was withheld because it contained \
+    prohibited content. Answer the user again without such content.";
+
+/// Schema migrations of the `moderation_hits` table; register them with the bot.
+pub const MIGRATIONS: MigrationSet = MigrationSet {
+    component: "moderation",
+    migrations: &[Migration {
+        version: 1,
+        description: "moderation hits table",
+        statements: &[entity::CREATE_TABLE],
+    }],
+};
+
+/// Stored in place of a flagged user message.
+pub const WITHHELD_INPUT: &str = "[message withheld by moderation]";
+
+/// Deserialized from `MiddlewareConfig.data`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ModerationConfig {
+    /// Words or phrases to flag, matched case-insensitively anywhere in the text.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Regular expressions to flag.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    #[serde(default)]
+    pub classifier: Option<ClassifierConfig>,
+    /// Reply sent instead of answering a flagged user message.
+    #[serde(default = "default_input_reply")]
+    pub input_reply: String,
+    #[serde(default)]
+    pub output_action: OutputAction,
+    /// Reply sent instead of a blocked model reply.
+    #[serde(default = "default_output_reply")]
+    pub output_reply: String,
+    /// Max regenerations per turn with [`OutputAction::Regenerate`]; a reply
+    /// still flagged afterwards is blocked. Default 1.
+    #[serde(default = "default_max_regenerations")]
+    pub max_regenerations: usize,
+}
+
+/// An OpenAI-compatible moderation endpoint, e.g. `https://api.openai.com/v1/moderations`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ClassifierConfig {
+    pub url: String,
+    pub api_key: String,
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// What to do with a flagged model reply.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputAction {
+    /// Replace the reply with `output_reply`.
+    #[default]
+    Block,
+    /// Replace matched keywords and patterns with `*`; replies flagged only
+    /// by the classifier are blocked.
+    Mask,
+    /// Ask the model for a new reply.
+    Regenerate,
+}
+
+fn default_input_reply() -> String {
+    "抱歉，这条消息包含不适宜的内容，我无法回复。".to_owned()
+}
+
+fn default_output_reply() -> String {
+    "抱歉，这条回复包含不适宜的内容，已被屏蔽。".to_owned()
+}
+
+fn default_max_regenerations() -> usize {
+    1
+}
+
+/// A rule that flagged a text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hit {
+    rule: String,
+    /// Whether the matched parts are known and can be masked.
+    maskable: bool,
+}
+
+pub struct ModerationMiddleware {
+    config: ModerationConfig,
+    /// Keywords and patterns as `(rule label, regex)`.
+    rules: Vec<(String, Regex)>,
+    classifier: Option<ClassifierClient>,
+    /// Regenerations used in the current turn.
+    regenerations: Mutex<usize>,
+    /// Verdict of the last checked reply, so `after_chat` does not classify
+    /// the reply `check_response` already saw.
+    last_output: Mutex<Option<(String, Option<Hit>)>>,
+}
+
+impl ModerationMiddleware {
+    /// Build the middleware, rejecting invalid patterns.
+    pub fn from_config(config: ModerationConfig) -> anyhow::Result<Self> {
+        let mut rules = Vec::new();
+        for keyword in config.keywords.iter().filter(|k| !k.trim().is_empty()) {
+            let regex = Regex::new(&format!("(?i){}", regex::escape(keyword)))?;
+            rules.push((format!("keyword:{keyword}"), regex));
+        }
+        for pattern in &config.patterns {
+            let regex = Regex::new(pattern)
+                .map_err(|e| anyhow::anyhow!("invalid moderation pattern {pattern}: {e}"))?;
+            rules.push((format!("pattern:{pattern}"), regex));
+        }
+        let classifier = config
+            .classifier
+            .as_ref()
+            .map(|c| ClassifierClient::new(c.url.clone(), c.api_key.clone(), c.model.clone()));
+        Ok(Self {
+            config,
+            rules,
+            classifier,
+            regenerations: Mutex::new(0),
+            last_output: Mutex::new(None),
+        })
+    }
+
+    /// Find the first rule flagging `text`, trying the classifier last.
+    async fn check(&self, text: &str) -> Option<Hit> {
+        if let Some((rule, _)) = self.rules.iter().find(|(_, regex)| regex.is_match(text)) {
+            return Some(Hit {
+                rule: rule.clone(),
+                maskable: true,
+            });
+        }
+        let classifier = self.classifier.as_ref()?;
+        match classifier.classify(text).await {
+            Ok(Some(categories)) => Some(Hit {
+                rule: format!("classifier:{}", categories.join(",")),
+                maskable: false,
+            }),
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!("moderation classifier failed: {e:#}");
+                None
+            }
+        }
+    }
+
+    async fn check_output(&self, text: &str) -> Option<Hit> {
+        if let Some((checked, hit)) = lock(&self.last_output).as_ref()
+            && checked == text
+        {
+            return hit.clone();
+        }
+        let hit = self.check(text).await;
+        *lock(&self.last_output) = Some((text.to_owned(), hit.clone()));
+        hit
+    }
+
+    /// Replace every keyword and pattern match with `*`, one per character.
+    fn mask(&self, text: &str) -> String {
+        self.rules.iter().fold(text.to_owned(), |text, (_, regex)| {
+            regex
+                .replace_all(&text, |caps: &regex::Captures| {
+                    "*".repeat(caps[0].chars().count())
+                })
+                .into_owned()
+        })
+    }
+
+    async fn record(
+        &self,
+        ctx: &Context,
+        direction: Direction,
+        hit: &Hit,
+        action: &str,
+        content: &str,
+    ) {
+        tracing::info!(
+            agent = %ctx.agent_name,
+            session_id = ctx.session_id,
+            "moderation hit on {direction:?} by {}, action {action}",
+            hit.rule
+        );
+        let new_hit = NewHit {
+            agent_name: &ctx.agent_name,
+            session_id: ctx.session_id,
+            direction,
+            rule: &hit.rule,
+            action,
+            content,
+        };
+        if let Err(e) = entity::insert(&ctx.app_db, &new_hit).await {
+            tracing::warn!("failed to log moderation hit: {e:#}");
+        }
+    }
+
+    /// Replace the stored copy of the flagged input, the latest user message
+    /// of the session. Its text may differ from the request when an earlier
+    /// middleware rewrote the request, such as redaction.
+    async fn withhold_input(&self, ctx: &Context) -> anyhow::Result<()> {
+        let user = MessageRole::User.to_string();
+        let Some(message) = Message::latest_by_role(&ctx.app_db, ctx.session_id, &user).await?
+        else {
+            return Ok(());
+        };
+        Message::update(
+            &ctx.app_db,
+            message.id,
+            message.session_id,
+            user,
+            WITHHELD_INPUT,
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for ModerationMiddleware {
+    fn name(&self) -> &'static str {
+        "moderation"
+    }
+
+    async fn init(&self, ctx: &Context) -> Result<(), anyhow::Error> {
+        entity::create_table(&ctx.app_db).await
+    }
+
+    async fn before_chat(
+        &self,
+        ctx: &Context,
+        request: &mut ChatRequest,
+    ) -> Result<MiddlewareFlow, anyhow::Error> {
+        *lock(&self.regenerations) = 0;
+        let Some(message) = request.messages.last().filter(|m| m.role == Role::User) else {
+            return Ok(MiddlewareFlow::Continue);
+        };
+        let text = message.content.text();
+        let Some(hit) = self.check(text).await else {
+            return Ok(MiddlewareFlow::Continue);
+        };
+        self.record(ctx, Direction::Input, &hit, "reply", text)
+            .await;
+        self.withhold_input(ctx).await?;
+        Ok(MiddlewareFlow::Respond(ChatResponse {
+            content: self.config.input_reply.clone(),
+            reasoning_content: None,
+            tool_calls: Vec::new(),
+            images: Vec::new(),
+            usage: None,
+        }))
+    }
+
+    async fn check_response(
+        &self,
+        ctx: &Context,
+        response: &ChatResponse,
+    ) -> Result<ResponseFlow, anyhow::Error> {
+        if self.config.output_action != OutputAction::Regenerate
+            || *lock(&self.regenerations) >= self.config.max_regenerations
+        {
+            return Ok(ResponseFlow::Accept);
+        }
+        let Some(hit) = self.check_output(&response.content).await else {
+            return Ok(ResponseFlow::Accept);
+        };
+        *lock(&self.regenerations) += 1;
+        self.record(
+            ctx,
+            Direction::Output,
+            &hit,
+            "regenerate",
+            &response.content,
+        )
+        .await;
+        Ok(ResponseFlow::Regenerate(REGENERATE_INSTRUCTION.to_owned()))
+    }
+
+    async fn after_chat(
+        &self,
+        ctx: &Context,
+        response: &mut ChatResponse,
+    ) -> Result<(), anyhow::Error> {
+        if response.content == self.config.input_reply {
+            return Ok(());
+        }
+        let Some(hit) = self.check_output(&response.content).await else {
+            return Ok(());
+        };
+        let original = std::mem::take(&mut response.content);
+        let action = if self.config.output_action == OutputAction::Mask && hit.maskable {
+            response.content = self.mask(&original);
+            "mask"
+        } else {
+            response.content = self.config.output_reply.clone();
+            response.reasoning_content = None;
+            response.images.clear();
+            "block"
+        };
+        self.record(ctx, Direction::Output, &hit, action, &original)
+            .await;
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nekobot_core::{
+        agent::{
+            tool::ToolRegistry,
+            types::{ChatMessage, ChatMessageContent},
+        },
+        entity::{Entity, session::Session},
+    };
+
+    use super::*;
+
+    fn middleware(action: &str) -> ModerationMiddleware {
+        let config: ModerationConfig = serde_json::from_value(serde_json::json!({
+            "keywords": ["BadWord"],
+            "patterns": [r"\d{3}-\d{4}"],
+            "output_action": action,
+        }))
+        .unwrap();
+        ModerationMiddleware::from_config(config).unwrap()
+    }
+
+    async fn context() -> anyhow::Result<Context> {
+        let db = turso::Builder::new_local(":memory:").build().await?;
+        let conn = db.connect()?;
+        Session::create_table(&conn).await?;
+        Message::create_table(&conn).await?;
+        let session = Session::create(&conn, "Neko").await?;
+        let (event_sender, _event_receiver) = tokio::sync::mpsc::channel(1);
+        Ok(Context::new(
+            "Neko",
+            session.id,
+            event_sender,
+            Arc::new(ToolRegistry::new()),
+            conn,
+        ))
+    }
+
+    fn reply(content: &str) -> ChatResponse {
+        ChatResponse {
+            content: content.to_owned(),
+            reasoning_content: None,
+            tool_calls: Vec::new(),
+            images: Vec::new(),
+            usage: None,
+        }
+    }
+
+    #[test]
+    fn masks_keywords_case_insensitively_and_patterns() {
+        let middleware = middleware("mask");
+        assert_eq!(
+            middleware.mask("a badword and 555-1234"),
+            "a ******* and ********"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let config: ModerationConfig =
+            serde_json::from_value(serde_json::json!({ "patterns": ["("] })).unwrap();
+        assert!(ModerationMiddleware::from_config(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn answers_flagged_input_and_blocks_flagged_output() -> anyhow::Result<()> {
+        let ctx = context().await?;
+        let middleware = middleware("block");
+        middleware.init(&ctx).await?;
+        let stored = Message::create(
+            &ctx.app_db,
+            ctx.session_id,
+            "user",
+            "say BADWORD",
+            None,
+            None,
+            None,
+        )
+        .await?;
+
+        let mut request = ChatRequest {
+            messages: vec![ChatMessage {
+                role: Role::User,
+                content: ChatMessageContent::User {
+                    text: "say BADWORD".to_owned(),
+                    images: Vec::new(),
+                },
+            }],
+            ..ChatRequest::default()
+        };
+        let flow = middleware.before_chat(&ctx, &mut request).await?;
+        assert!(matches!(
+            flow,
+            MiddlewareFlow::Respond(response) if response.content == default_input_reply()
+        ));
+        let stored = Message::get(&ctx.app_db, stored.id).await?.unwrap();
+        assert_eq!(stored.content, WITHHELD_INPUT);
+
+        let mut response = reply("call 555-1234");
+        middleware.after_chat(&ctx, &mut response).await?;
+        assert_eq!(response.content, default_output_reply());
+
+        let hits = entity::list_recent(&ctx.app_db, 10).await?;
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].direction, "output");
+        assert_eq!(hits[0].action, "block");
+        assert_eq!(hits[0].content, "call 555-1234");
+        assert_eq!(hits[1].rule, "keyword:BadWord");
+        assert_eq!(hits[1].action, "reply");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn regenerates_until_the_limit_then_blocks() -> anyhow::Result<()> {
+        let ctx = context().await?;
+        let middleware = middleware("regenerate");
+        middleware.init(&ctx).await?;
+        middleware
+            .before_chat(&ctx, &mut ChatRequest::default())
+            .await?;
+
+        let flagged = reply("badword");
+        assert!(matches!(
+            middleware.check_response(&ctx, &flagged).await?,
+            ResponseFlow::Regenerate(_)
+        ));
+        assert_eq!(
+            middleware.check_response(&ctx, &flagged).await?,
+            ResponseFlow::Accept
+        );
+        let mut response = flagged.clone();
+        middleware.after_chat(&ctx, &mut response).await?;
+        assert_eq!(response.content, default_output_reply());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn withholds_input_that_redaction_rewrote() -> anyhow::Result<()> {
+        let ctx = context().await?;
+        let middleware = middleware("block");
+        middleware.init(&ctx).await?;
+        let redaction = nekobot_redaction::RedactionMiddleware::from_config(
+            serde_json::from_value(serde_json::json!({}))?,
+        )?;
+        let text = "BADWORD, call 13812345678";
+        let stored =
+            Message::create(&ctx.app_db, ctx.session_id, "user", text, None, None, None).await?;
+
+        let mut request = ChatRequest {
+            messages: vec![ChatMessage {
+                role: Role::User,
+                content: ChatMessageContent::User {
+                    text: text.to_owned(),
+                    images: Vec::new(),
+                },
+            }],
+            ..ChatRequest::default()
+        };
+        redaction.before_chat(&ctx, &mut request).await?;
+        assert_ne!(request.messages[0].content.text(), text);
+        let flow = middleware.before_chat(&ctx, &mut request).await?;
+        assert!(matches!(flow, MiddlewareFlow::Respond(_)));
+
+        let stored = Message::get(&ctx.app_db, stored.id).await?.unwrap();
+        assert_eq!(stored.content, WITHHELD_INPUT);
+        Ok(())
+    }
+}
@@ -8,9 +8,16 @@ use nekobot_core::agent::{
     tool::{Tool, ToolError, ToolResult, ToolSpec},
     types::ChatRequest,
 };
+use schemars::JsonSchema;
+use serde::Deserialize;
 use serde_json::Value;
 use turso::Connection;
 
+/// Deserialized from `MiddlewareConfig.data`. Persona takes no settings.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PersonaConfig {}
+
 /// Middleware that persists and injects agent persona.
 pub struct PersonaMiddleware {
     tool_specs: RwLock<Vec<ToolSpec>>,
@@ -25,6 +32,10 @@ impl PersonaMiddleware {
         }
     }
 
+    pub fn from_config(_config: PersonaConfig) -> Self {
+        Self::new()
+    }
+
     fn conn(&self) -> anyhow::Result<Connection> {
         self.app_db
             .read()
@@ -0,0 +1,639 @@
+//! Cassette provider — records provider traffic to a file and replays it.
+//!
+//! In record mode every request goes to the wrapped provider, and the
+//! normalized request, the response and any stream events are appended to a
+//! JSON cassette file. In replay mode responses are served from that file,
+//! keyed by a SHA-256 hash of the normalized request, so agents can be tested
+//! end-to-end without a live LLM. A request missing from the cassette fails
+//! with [`ProviderError::InvalidRequest`] naming its hash.
+//!
+//! Normalization drops what does not reach the model (capability flags,
+//! streaming) and makes message text, tool order and tool call arguments
+//! insensitive to whitespace and key order.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use nekobot_core::{
+    agent::types::{ChatResponse, Image, ToolCall, Usage},
+    provider::{Provider, ProviderError, ProviderEvent, ProviderRequest},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::{self, Sender};
+
+/// Provider that records to or replays from a cassette file.
+pub struct CassetteProvider {
+    path: PathBuf,
+    mode: Mode,
+}
+
+enum Mode {
+    Record {
+        inner: Arc<dyn Provider>,
+        cassette: tokio::sync::Mutex<Cassette>,
+    },
+    Replay {
+        interactions: HashMap<String, Vec<Interaction>>,
+        /// Next interaction to serve per request hash.
+        cursors: Mutex<HashMap<String, usize>>,
+    },
+}
+
+impl CassetteProvider {
+    /// Record the traffic of `inner` into a new cassette at `path`.
+    ///
+    /// The file is rewritten after every exchange; an existing cassette is
+    /// replaced.
+    pub fn record(inner: Arc<dyn Provider>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: Mode::Record {
+                inner,
+                cassette: tokio::sync::Mutex::new(Cassette::default()),
+            },
+        }
+    }
+
+    /// Replay the cassette at `path`.
+    ///
+    /// Identical requests get the recorded responses in order; once those
+    /// run out the last one is repeated.
+    pub fn replay(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let data = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read cassette {}", path.display()))?;
+        let cassette: Cassette = serde_json::from_str(&data)
+            .with_context(|| format!("invalid cassette {}", path.display()))?;
+
+        let mut interactions: HashMap<String, Vec<Interaction>> = HashMap::new();
+        for interaction in cassette.interactions {
+            interactions
+                .entry(interaction.key.clone())
+                .or_default()
+                .push(interaction);
+        }
+        Ok(Self {
+            path,
+            mode: Mode::Replay {
+                interactions,
+                cursors: Mutex::new(HashMap::new()),
+            },
+        })
+    }
+
+    /// Path of the cassette file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn lookup(
+        &self,
+        interactions: &HashMap<String, Vec<Interaction>>,
+        cursors: &Mutex<HashMap<String, usize>>,
+        request: &ProviderRequest,
+    ) -> Result<Interaction, ProviderError> {
+        let normalized = normalize_request(request);
+        let key = request_key(&normalized);
+        let Some(recorded) = interactions.get(&key) else {
+            tracing::error!(
+                "request {key} not found in cassette {}: {normalized}",
+                self.path.display()
+            );
+            return Err(ProviderError::InvalidRequest(format!(
+                "request {key} not found in cassette {}",
+                self.path.display()
+            )));
+        };
+
+        let mut cursors = cursors.lock().unwrap_or_else(|e| e.into_inner());
+        let cursor = cursors.entry(key).or_default();
+        let interaction = recorded[(*cursor).min(recorded.len() - 1)].clone();
+        *cursor += 1;
+        Ok(interaction)
+    }
+
+    async fn save(
+        &self,
+        cassette: &mut Cassette,
+        request: &ProviderRequest,
+        events: Vec<RecordedEvent>,
+        response: &ChatResponse,
+    ) -> Result<(), ProviderError> {
+        let normalized = normalize_request(request);
+        cassette.interactions.push(Interaction {
+            key: request_key(&normalized),
+            request: normalized,
+            events,
+            response: response.into(),
+        });
+        let data =
+            serde_json::to_string_pretty(cassette).context("failed to serialize cassette")?;
+        tokio::fs::write(&self.path, data)
+            .await
+            .with_context(|| format!("failed to write cassette {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for CassetteProvider {
+    /// Returns the id of the recorded provider, or `"cassette"` when replaying.
+    fn id(&self) -> &'static str {
+        match &self.mode {
+            Mode::Record { inner, .. } => inner.id(),
+            Mode::Replay { .. } => "cassette",
+        }
+    }
+
+    async fn complete(&self, request: ProviderRequest) -> Result<ChatResponse, ProviderError> {
+        match &self.mode {
+            Mode::Record { inner, cassette } => {
+                let response = inner.complete(request.clone()).await?;
+                let mut cassette = cassette.lock().await;
+                self.save(&mut cassette, &request, Vec::new(), &response)
+                    .await?;
+                Ok(response)
+            }
+            Mode::Replay {
+                interactions,
+                cursors,
+            } => Ok(self
+                .lookup(interactions, cursors, &request)?
+                .response
+                .into()),
+        }
+    }
+
+    /// Streams from the wrapped provider while recording its events. When
+    /// replaying, the recorded events are re-sent; exchanges recorded with
+    /// [`complete`](Provider::complete) are streamed as a single delta.
+    async fn stream(
+        &self,
+        request: ProviderRequest,
+        events: Sender<ProviderEvent>,
+    ) -> Result<ChatResponse, ProviderError> {
+        match &self.mode {
+            Mode::Record { inner, cassette } => {
+                let (sender, mut receiver) = mpsc::channel(64);
+                let forward = async move {
+                    let mut recorded = Vec::new();
+                    while let Some(event) = receiver.recv().await {
+                        recorded.push(RecordedEvent::from(&event));
+                        let _ = events.send(event).await;
+                    }
+                    recorded
+                };
+                let (response, recorded) =
+                    tokio::join!(inner.stream(request.clone(), sender), forward);
+                let response = response?;
+                let mut cassette = cassette.lock().await;
+                self.save(&mut cassette, &request, recorded, &response)
+                    .await?;
+                Ok(response)
+            }
+            Mode::Replay {
+                interactions,
+                cursors,
+            } => {
+                let interaction = self.lookup(interactions, cursors, &request)?;
+                let response = ChatResponse::from(interaction.response);
+                let replayed = if interaction.events.is_empty() {
+                    synthesized_events(&response)
+                } else {
+                    interaction.events.into_iter().map(Into::into).collect()
+                };
+                for event in replayed {
+                    let _ = events.send(event).await;
+                }
+                Ok(response)
+            }
+        }
+    }
+}
+
+/// Events for replaying a response that was recorded without streaming.
+fn synthesized_events(response: &ChatResponse) -> Vec<ProviderEvent> {
+    let mut events = vec![ProviderEvent::Started];
+    if let Some(reasoning) = response.reasoning_content.clone() {
+        events.push(ProviderEvent::ReasoningDelta(reasoning));
+    }
+    if !response.content.is_empty() {
+        events.push(ProviderEvent::ContentDelta(response.content.clone()));
+    }
+    events.push(ProviderEvent::Finished {
+        usage: response.usage.clone(),
+    });
+    events
+}
+
+/// The parts of a request that decide the response, in a stable form.
+fn normalize_request(request: &ProviderRequest) -> Value {
+    let options = &request.options;
+    let mut tools: Vec<Value> = request
+        .chat
+        .tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "name": tool.name,
+                "description": tool.description.trim(),
+                "parameters": tool.parameters_schema,
+            })
+        })
+        .collect();
+    tools.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+
+    let messages: Vec<Value> = request
+        .chat
+        .messages
+        .iter()
+        .map(|message| {
+            let content = &message.content;
+            json!({
+                "role": message.role.to_string(),
+                "text": content.text().trim(),
+                "reasoning": content.reasoning().map(str::trim),
+                "tool_calls": content.tool_calls().iter().map(|call| json!({
+                    "id": call.id,
+                    "name": call.function.name,
+                    "arguments": serde_json::from_str::<Value>(&call.function.arguments)
+                        .unwrap_or_else(|_| Value::String(call.function.arguments.clone())),
+                })).collect::<Vec<_>>(),
+                "tool_call_id": content.tool_call_id(),
+                "images": content.images().iter().map(|image| json!({
+                    "mime_type": image.mime_type,
+                    "sha256": hex_digest(&image.data),
+                })).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    json!({
+        "model": options.model,
+        "temperature": options.temperature,
+        "top_p": options.top_p,
+        "max_output_tokens": options.max_output_tokens,
+        "extra": options.extra,
+        "system_prompt": request.chat.system_prompt.as_deref().map(str::trim),
+        "tools": tools,
+        "messages": messages,
+    })
+}
+
+fn request_key(normalized: &Value) -> String {
+    hex_digest(canonical_json(normalized).as_bytes())
+}
+
+/// JSON text with object keys sorted, independent of map ordering.
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let fields: Vec<String> = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    format!("{}:{}", Value::String(key.clone()), canonical_json(value))
+                })
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn hex_digest(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .as_slice()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    /// Hash of `request`.
+    key: String,
+    /// The normalized request, kept for reviewing and diffing cassettes.
+    request: Value,
+    /// Events of a streamed exchange.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    events: Vec<RecordedEvent>,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RecordedEvent {
+    Started,
+    ContentDelta { text: String },
+    ReasoningDelta { text: String },
+    Finished { usage: Option<RecordedUsage> },
+}
+
+impl From<&ProviderEvent> for RecordedEvent {
+    fn from(event: &ProviderEvent) -> Self {
+        match event {
+            ProviderEvent::Started => Self::Started,
+            ProviderEvent::ContentDelta(text) => Self::ContentDelta { text: text.clone() },
+            ProviderEvent::ReasoningDelta(text) => Self::ReasoningDelta { text: text.clone() },
+            ProviderEvent::Finished { usage } => Self::Finished {
+                usage: usage.as_ref().map(Into::into),
+            },
+        }
+    }
+}
+
+impl From<RecordedEvent> for ProviderEvent {
+    fn from(event: RecordedEvent) -> Self {
+        match event {
+            RecordedEvent::Started => Self::Started,
+            RecordedEvent::ContentDelta { text } => Self::ContentDelta(text),
+            RecordedEvent::ReasoningDelta { text } => Self::ReasoningDelta(text),
+            RecordedEvent::Finished { usage } => Self::Finished {
+                usage: usage.map(Into::into),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    content: String,
+    #[serde(default)]
+    reasoning_content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCall>,
+    #[serde(default)]
+    images: Vec<RecordedImage>,
+    #[serde(default)]
+    usage: Option<RecordedUsage>,
+}
+
+impl From<&ChatResponse> for RecordedResponse {
+    fn from(response: &ChatResponse) -> Self {
+        Self {
+            content: response.content.clone(),
+            reasoning_content: response.reasoning_content.clone(),
+            tool_calls: response.tool_calls.clone(),
+            images: response
+                .images
+                .iter()
+                .map(|image| RecordedImage {
+                    mime_type: image.mime_type.clone(),
+                    data: image.data.clone(),
+                })
+                .collect(),
+            usage: response.usage.as_ref().map(Into::into),
+        }
+    }
+}
+
+impl From<RecordedResponse> for ChatResponse {
+    fn from(response: RecordedResponse) -> Self {
+        Self {
+            content: response.content,
+            reasoning_content: response.reasoning_content,
+            tool_calls: response.tool_calls,
+            images: response
+                .images
+                .into_iter()
+                .map(|image| Image {
+                    data: image.data,
+                    mime_type: image.mime_type,
+                })
+                .collect(),
+            usage: response.usage.map(Into::into),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedImage {
+    mime_type: String,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedUsage {
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    total_tokens: Option<u64>,
+}
+
+impl From<&Usage> for RecordedUsage {
+    fn from(usage: &Usage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+impl From<RecordedUsage> for Usage {
+    fn from(usage: RecordedUsage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use nekobot_core::{
+        agent::types::{ChatMessage, ChatMessageContent, ChatRequest, Role, ToolCallFunction},
+        provider::ModelOptions,
+    };
+
+    use super::*;
+
+    /// Answers with a tool call, counting how often it is asked.
+    struct CountingProvider {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for CountingProvider {
+        fn id(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn complete(&self, _request: ProviderRequest) -> Result<ChatResponse, ProviderError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(ChatResponse {
+                content: format!("answer {call}"),
+                reasoning_content: None,
+                tool_calls: vec![ToolCall {
+                    id: "call-1".to_owned(),
+                    r#type: "function".to_owned(),
+                    function: ToolCallFunction {
+                        name: "time".to_owned(),
+                        arguments: "{}".to_owned(),
+                    },
+                }],
+                images: Vec::new(),
+                usage: Some(Usage {
+                    input_tokens: Some(5),
+                    output_tokens: Some(2),
+                    total_tokens: Some(7),
+                }),
+            })
+        }
+
+        async fn stream(
+            &self,
+            request: ProviderRequest,
+            events: Sender<ProviderEvent>,
+        ) -> Result<ChatResponse, ProviderError> {
+            let response = self.complete(request).await?;
+            for event in [
+                ProviderEvent::Started,
+                ProviderEvent::ContentDelta("answer ".to_owned()),
+                ProviderEvent::ContentDelta("1".to_owned()),
+                ProviderEvent::Finished { usage: None },
+            ] {
+                let _ = events.send(event).await;
+            }
+            Ok(response)
+        }
+    }
+
+    fn request(text: &str) -> ProviderRequest {
+        ProviderRequest {
+            chat: ChatRequest {
+                messages: vec![ChatMessage {
+                    role: Role::User,
+                    content: ChatMessageContent::User {
+                        text: text.to_owned(),
+                        images: Vec::new(),
+                    },
+                }],
+                system_prompt: Some("You are Neko.".to_owned()),
+                tools: Vec::new(),
+            },
+            options: ModelOptions {
+                model: Some("test-model".to_owned()),
+                ..ModelOptions::default()
+            },
+        }
+    }
+
+    async fn collect_events(
+        provider: &CassetteProvider,
+        request: ProviderRequest,
+    ) -> (ChatResponse, Vec<ProviderEvent>) {
+        let (sender, mut receiver) = mpsc::channel(16);
+        let response = provider.stream(request, sender).await.unwrap();
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+        (response, events)
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_responses_and_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.json");
+        let inner = Arc::new(CountingProvider {
+            calls: AtomicUsize::new(0),
+        });
+        let recorder = CassetteProvider::record(inner.clone(), &path);
+        let completed = recorder.complete(request("hello")).await.unwrap();
+        let (streamed, streamed_events) = collect_events(&recorder, request("again")).await;
+        assert_eq!(recorder.id(), "counting");
+
+        let player = CassetteProvider::replay(&path).unwrap();
+        assert_eq!(
+            player.complete(request("  hello\n")).await.unwrap(),
+            completed
+        );
+        let (replayed, replayed_events) = collect_events(&player, request("again")).await;
+        assert_eq!(replayed, streamed);
+        assert_eq!(replayed_events, streamed_events);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+
+        let (_, synthesized) = collect_events(&player, request("hello")).await;
+        assert_eq!(
+            synthesized,
+            vec![
+                ProviderEvent::Started,
+                ProviderEvent::ContentDelta("answer 0".to_owned()),
+                ProviderEvent::Finished {
+                    usage: completed.usage.clone()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_fails_on_unrecorded_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.json");
+        let recorder = CassetteProvider::record(
+            Arc::new(CountingProvider {
+                calls: AtomicUsize::new(0),
+            }),
+            &path,
+        );
+        recorder.complete(request("hello")).await.unwrap();
+
+        let player = CassetteProvider::replay(&path).unwrap();
+        let result = player.complete(request("something else")).await;
+        assert!(matches!(
+            result,
+            Err(ProviderError::InvalidRequest(message)) if message.contains("not found in cassette")
+        ));
+    }
+
+    #[test]
+    fn request_key_ignores_key_order_and_argument_formatting() {
+        let a = json!({ "b": 1, "a": { "y": [1, 2], "x": null } });
+        let b = json!({ "a": { "x": null, "y": [1, 2] }, "b": 1 });
+        assert_eq!(request_key(&a), request_key(&b));
+
+        let with_args = |arguments: &str| {
+            let mut request = request("hi");
+            request.chat.messages.push(ChatMessage {
+                role: Role::Assistant,
+                content: ChatMessageContent::Assistant {
+                    text: String::new(),
+                    reasoning: None,
+                    tool_calls: vec![ToolCall {
+                        id: "call-1".to_owned(),
+                        r#type: "function".to_owned(),
+                        function: ToolCallFunction {
+                            name: "bash".to_owned(),
+                            arguments: arguments.to_owned(),
+                        },
+                    }],
+                },
+            });
+            request_key(&normalize_request(&request))
+        };
+        assert_eq!(
+            with_args(r#"{"command":"ls","timeout":5}"#),
+            with_args(r#"{ "timeout": 5, "command": "ls" }"#)
+        );
+    }
+}
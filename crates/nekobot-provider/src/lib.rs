@@ -1,17 +1,20 @@
 //! Concrete provider implementations for the NekoBot framework.
 //!
-//! Provides [`DeepSeekProvider`] and [`OpenAiCodexProvider`], plus a
-//! convenience function [`register_providers`] that registers both into a
+//! Provides [`DeepSeekProvider`] and [`OpenAiCodexProvider`], the
+//! record/replay [`CassetteProvider`], plus a convenience function
+//! [`register_providers`] that registers all of them into a
 //! [`ProviderRegistry`](nekobot_core::provider::ProviderRegistry).
 
 use std::sync::Arc;
 
-use nekobot_core::config::ProviderConfig;
+use nekobot_core::config::{CassetteMode, ProviderConfig};
 
+pub mod cassette;
 pub mod deepseek;
 pub mod openai_codex;
 pub(crate) mod utils;
 
+pub use cassette::CassetteProvider;
 pub use deepseek::DeepSeekProvider;
 pub use nekobot_core::provider::{
     ModelCapabilities, ModelOptions, Provider, ProviderError, ProviderEvent, ProviderRegistry,
@@ -19,7 +22,7 @@ pub use nekobot_core::provider::{
 };
 pub use openai_codex::OpenAiCodexProvider;
 
-/// Register the DeepSeek and OpenAI Codex provider factories into a registry.
+/// Register the DeepSeek, OpenAI Codex and Cassette provider factories into a registry.
 ///
 /// Call this once at startup before [`NekoBot::run`](nekobot_core::NekoBot::run).
 pub fn register_providers(
@@ -72,5 +75,39 @@ pub fn register_providers(
         ),
     })?;
 
-    Ok(())
+    register_cassette(registry)
+}
+
+/// Register the `Cassette` provider factory.
+///
+/// In record mode the wrapped provider is created by the factories already in
+/// `registry`, so call this after registering every provider type that should
+/// be recordable. Replay mode never creates the wrapped provider.
+pub fn register_cassette(
+    registry: &mut nekobot_core::provider::ProviderRegistry,
+) -> anyhow::Result<()> {
+    let providers = registry.clone();
+    registry.register("Cassette", move |config| match config {
+        ProviderConfig::Cassette {
+            mode,
+            path,
+            provider,
+            ..
+        } => {
+            let cassette = match mode {
+                CassetteMode::Record => {
+                    let inner = providers.create(provider)?.ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "no provider factory registered for type {}",
+                            provider.type_name()
+                        )
+                    })?;
+                    CassetteProvider::record(inner, path)
+                }
+                CassetteMode::Replay => CassetteProvider::replay(path)?,
+            };
+            Ok(Arc::new(cassette) as Arc<dyn Provider>)
+        }
+        _ => anyhow::bail!("expected Cassette provider config, got {}", config.name()),
+    })
 }
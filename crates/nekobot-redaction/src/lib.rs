@@ -0,0 +1,382 @@
+//! Redaction middleware — replaces personal data in requests with placeholders
+//! before they reach the provider.
+//!
+//! Every detected value gets a placeholder like `[MOBILE_1]` that stays the
+//! same for the rest of the session. With `restore` enabled (the default),
+//! placeholders in the model's reply and in its tool call arguments are
+//! replaced with the original values again, so users and tools see real
+//! data while the provider never does. Tool results are redacted before the
+//! model sees them.
+
+mod detect;
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use nekobot_core::agent::{
+    Context,
+    middleware::{Middleware, MiddlewareFlow, ToolCallFlow},
+    tool::ToolResult,
+    types::{ChatMessageContent, ChatRequest, ChatResponse, ToolCall},
+};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+
+use detect::Detector;
+pub use detect::PiiKind;
+
+/// Appended to the system prompt when a request contains placeholders.
+const PLACEHOLDER_NOTE: &str = "Personal data in this conversation has been replaced with \
+    placeholders such as [MOBILE_1]. Refer to these values by repeating the placeholder exactly.";
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[[A-Z0-9_]+_\d+\]").unwrap());
+
+/// Deserialized from `MiddlewareConfig.data`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct RedactionConfig {
+    /// Built-in detectors to enable. Default: all of them.
+    #[serde(default = "default_builtin")]
+    pub builtin: Vec<PiiKind>,
+    /// Extra patterns; matches become `[NAME_n]`.
+    #[serde(default)]
+    pub patterns: Vec<PatternConfig>,
+    /// Put the original values back into replies and tool arguments. Default true.
+    #[serde(default = "default_restore")]
+    pub restore: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct PatternConfig {
+    pub name: String,
+    pub regex: String,
+}
+
+fn default_builtin() -> Vec<PiiKind> {
+    PiiKind::ALL.to_vec()
+}
+
+fn default_restore() -> bool {
+    true
+}
+
+/// Values seen in this session and their placeholders.
+#[derive(Default)]
+struct Mapping {
+    placeholders: HashMap<String, String>,
+    values: HashMap<String, String>,
+    counts: HashMap<String, usize>,
+}
+
+impl Mapping {
+    fn placeholder(&mut self, label: &str, value: &str) -> String {
+        if let Some(placeholder) = self.placeholders.get(value) {
+            return placeholder.clone();
+        }
+        let count = self.counts.entry(label.to_owned()).or_default();
+        *count += 1;
+        let placeholder = format!("[{label}_{count}]");
+        self.placeholders
+            .insert(value.to_owned(), placeholder.clone());
+        self.values.insert(placeholder.clone(), value.to_owned());
+        placeholder
+    }
+}
+
+pub struct RedactionMiddleware {
+    detectors: Vec<Detector>,
+    restore: bool,
+    mapping: Mutex<Mapping>,
+}
+
+impl RedactionMiddleware {
+    /// Build the middleware, rejecting invalid patterns.
+    pub fn from_config(config: RedactionConfig) -> anyhow::Result<Self> {
+        let mut detectors: Vec<Detector> = PiiKind::ALL
+            .into_iter()
+            .filter(|kind| config.builtin.contains(kind))
+            .map(Detector::builtin)
+            .collect();
+        for pattern in &config.patterns {
+            detectors.push(Detector::custom(&pattern.name, &pattern.regex)?);
+        }
+        Ok(Self {
+            detectors,
+            restore: config.restore,
+            mapping: Mutex::new(Mapping::default()),
+        })
+    }
+
+    /// Replace detected values in `text`; returns whether anything changed.
+    fn redact(&self, text: &mut String) -> bool {
+        let found = detect::find_all(&self.detectors, text);
+        if found.is_empty() {
+            return false;
+        }
+        let mut mapping = lock(&self.mapping);
+        let mut redacted = String::with_capacity(text.len());
+        let mut last = 0;
+        for f in found {
+            redacted.push_str(&text[last..f.start]);
+            redacted.push_str(
+                &mapping.placeholder(self.detectors[f.detector].label(), &text[f.start..f.end]),
+            );
+            last = f.end;
+        }
+        redacted.push_str(&text[last..]);
+        *text = redacted;
+        true
+    }
+
+    /// Replace known placeholders in `text` with their values.
+    fn restore(&self, text: &mut String) {
+        if !PLACEHOLDER.is_match(text) {
+            return;
+        }
+        let mapping = lock(&self.mapping);
+        let restored = PLACEHOLDER.replace_all(text, |caps: &regex::Captures| {
+            mapping
+                .values
+                .get(&caps[0])
+                .cloned()
+                .unwrap_or_else(|| caps[0].to_owned())
+        });
+        *text = restored.into_owned();
+    }
+
+    fn redact_value(&self, value: &mut Value) {
+        visit_strings(value, &mut |s| {
+            self.redact(s);
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RedactionMiddleware {
+    fn name(&self) -> &'static str {
+        "redaction"
+    }
+
+    async fn before_chat(
+        &self,
+        _ctx: &Context,
+        request: &mut ChatRequest,
+    ) -> Result<MiddlewareFlow, anyhow::Error> {
+        let mut redacted = false;
+        if let Some(prompt) = &mut request.system_prompt {
+            redacted |= self.redact(prompt);
+        }
+        for message in &mut request.messages {
+            match &mut message.content {
+                ChatMessageContent::User { text, .. } => redacted |= self.redact(text),
+                ChatMessageContent::Assistant {
+                    text,
+                    reasoning,
+                    tool_calls,
+                } => {
+                    redacted |= self.redact(text);
+                    if let Some(reasoning) = reasoning {
+                        redacted |= self.redact(reasoning);
+                    }
+                    for call in tool_calls {
+                        redacted |= self.redact(&mut call.function.arguments);
+                    }
+                }
+                ChatMessageContent::Tool { result, .. } => redacted |= self.redact(result),
+            }
+        }
+        if redacted {
+            let prompt = request.system_prompt.get_or_insert_default();
+            if !prompt.is_empty() {
+                prompt.push_str("\n\n");
+            }
+            prompt.push_str(PLACEHOLDER_NOTE);
+        }
+        Ok(MiddlewareFlow::Continue)
+    }
+
+    async fn after_chat(
+        &self,
+        _ctx: &Context,
+        response: &mut ChatResponse,
+    ) -> Result<(), anyhow::Error> {
+        if self.restore {
+            self.restore(&mut response.content);
+            if let Some(reasoning) = &mut response.reasoning_content {
+                self.restore(reasoning);
+            }
+        }
+        Ok(())
+    }
+
+    async fn before_tool_call(
+        &self,
+        _ctx: &Context,
+        _call: &ToolCall,
+        args: &mut Value,
+    ) -> Result<ToolCallFlow, anyhow::Error> {
+        if self.restore {
+            visit_strings(args, &mut |s| self.restore(s));
+        }
+        Ok(ToolCallFlow::Continue)
+    }
+
+    async fn after_tool_call(
+        &self,
+        _ctx: &Context,
+        _call: &ToolCall,
+        result: &mut ToolResult<Value>,
+    ) -> Result<(), anyhow::Error> {
+        if let Ok(value) = result {
+            self.redact_value(value);
+        }
+        Ok(())
+    }
+}
+
+fn visit_strings(value: &mut Value, f: &mut impl FnMut(&mut String)) {
+    match value {
+        Value::String(s) => f(s),
+        Value::Array(items) => items.iter_mut().for_each(|item| visit_strings(item, f)),
+        Value::Object(map) => map.values_mut().for_each(|item| visit_strings(item, f)),
+        _ => {}
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nekobot_core::agent::{
+        tool::ToolRegistry,
+        types::{ChatMessage, Role, ToolCallFunction},
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    fn middleware(restore: bool) -> RedactionMiddleware {
+        let config: RedactionConfig = serde_json::from_value(json!({
+            "patterns": [{ "name": "order", "regex": r"NO\.\d{6}" }],
+            "restore": restore,
+        }))
+        .unwrap();
+        RedactionMiddleware::from_config(config).unwrap()
+    }
+
+    async fn context() -> anyhow::Result<Context> {
+        let db = turso::Builder::new_local(":memory:").build().await?;
+        let (event_sender, _event_receiver) = tokio::sync::mpsc::channel(1);
+        Ok(Context::new(
+            "Neko",
+            1,
+            event_sender,
+            Arc::new(ToolRegistry::new()),
+            db.connect()?,
+        ))
+    }
+
+    fn user(text: &str) -> ChatMessage {
+        ChatMessage {
+            role: Role::User,
+            content: ChatMessageContent::User {
+                text: text.to_owned(),
+                images: Vec::new(),
+            },
+        }
+    }
+
+    fn tool_call() -> ToolCall {
+        ToolCall {
+            id: "call-1".to_owned(),
+            r#type: "function".to_owned(),
+            function: ToolCallFunction {
+                name: "sms".to_owned(),
+                arguments: String::new(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn placeholders_are_stable_and_restored() -> anyhow::Result<()> {
+        let ctx = context().await?;
+        let middleware = middleware(true);
+        let mut request = ChatRequest {
+            messages: vec![
+                user("我的手机是13812345678，订单NO.123456"),
+                user("再说一次：13812345678，备用13900001111"),
+            ],
+            ..ChatRequest::default()
+        };
+        middleware.before_chat(&ctx, &mut request).await?;
+
+        assert_eq!(
+            request.messages[0].content.text(),
+            "我的手机是[MOBILE_1]，订单[ORDER_1]"
+        );
+        assert_eq!(
+            request.messages[1].content.text(),
+            "再说一次：[MOBILE_1]，备用[MOBILE_2]"
+        );
+        assert!(request.system_prompt.unwrap().contains("[MOBILE_1]"));
+
+        let mut args = json!({ "to": ["[MOBILE_2]"], "text": "[UNKNOWN_9]" });
+        middleware
+            .before_tool_call(&ctx, &tool_call(), &mut args)
+            .await?;
+        assert_eq!(
+            args,
+            json!({ "to": ["13900001111"], "text": "[UNKNOWN_9]" })
+        );
+
+        let mut result = Ok(json!({ "sent_to": "13900001111", "from": "neko@example.com" }));
+        middleware
+            .after_tool_call(&ctx, &tool_call(), &mut result)
+            .await?;
+        assert_eq!(
+            result.unwrap(),
+            json!({ "sent_to": "[MOBILE_2]", "from": "[EMAIL_1]" })
+        );
+
+        let mut response = ChatResponse {
+            content: "已发送到[MOBILE_2]".to_owned(),
+            reasoning_content: None,
+            tool_calls: Vec::new(),
+            images: Vec::new(),
+            usage: None,
+        };
+        middleware.after_chat(&ctx, &mut response).await?;
+        assert_eq!(response.content, "已发送到13900001111");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restore_can_be_disabled() -> anyhow::Result<()> {
+        let ctx = context().await?;
+        let middleware = middleware(false);
+        let mut request = ChatRequest {
+            messages: vec![user("邮箱 neko@example.com")],
+            ..ChatRequest::default()
+        };
+        middleware.before_chat(&ctx, &mut request).await?;
+        assert_eq!(request.messages[0].content.text(), "邮箱 [EMAIL_1]");
+
+        let mut response = ChatResponse {
+            content: "[EMAIL_1]".to_owned(),
+            reasoning_content: None,
+            tool_calls: Vec::new(),
+            images: Vec::new(),
+            usage: None,
+        };
+        middleware.after_chat(&ctx, &mut response).await?;
+        assert_eq!(response.content, "[EMAIL_1]");
+        Ok(())
+    }
+}
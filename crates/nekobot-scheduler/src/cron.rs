@@ -0,0 +1,226 @@
+//! Five-field cron expressions: `minute hour day-of-month month day-of-week`.
+//!
+//! Each field accepts `*`, numbers, ranges (`1-5`), lists (`1,15`) and steps
+//! (`*/15`, `8-18/2`). Day-of-week runs from 0 (Sunday) to 7 (Sunday again).
+//! As in classic cron, when both day fields are restricted a day matches if
+//! either of them does.
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Timelike};
+
+/// How many years ahead [`CronSchedule::next_after`] searches before giving up,
+/// e.g. for `0 0 30 2 *`.
+const SEARCH_YEARS: i32 = 5;
+
+/// A parsed cron expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// Whether the day-of-month field was `*`.
+    any_day_of_month: bool,
+    /// Whether the day-of-week field was `*`.
+    any_day_of_week: bool,
+}
+
+impl std::str::FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let &[minute, hour, day_of_month, month, day_of_week] = fields.as_slice() else {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        };
+
+        let mut days_of_week = parse_field(day_of_week, 0, 7)?;
+        // 7 is another name for Sunday.
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days_of_month: parse_field(day_of_month, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            days_of_week,
+            any_day_of_month: day_of_month == "*",
+            any_day_of_week: day_of_week == "*",
+        })
+    }
+}
+
+impl CronSchedule {
+    /// First matching minute strictly after `after`, in `after`'s time zone.
+    ///
+    /// Local times skipped by a DST change are not matched.
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+        let start = after.naive_local();
+        let mut t = start.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        let limit = start.year() + SEARCH_YEARS;
+
+        while t.year() <= limit {
+            if !bit(self.months, t.month()) {
+                t = first_of_next_month(t.date())?;
+                continue;
+            }
+            if !self.day_matches(t.date()) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !bit(self.hours, t.hour()) {
+                t = t.with_minute(0)? + TimeDelta::hours(1);
+                continue;
+            }
+            if !bit(self.minutes, t.minute()) {
+                t += TimeDelta::minutes(1);
+                continue;
+            }
+            if let Some(at) = tz.from_local_datetime(&t).earliest() {
+                return Some(at);
+            }
+            t += TimeDelta::minutes(1);
+        }
+        None
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day_of_month = bit(self.days_of_month, date.day());
+        let day_of_week = bit(self.days_of_week, date.weekday().num_days_from_sunday());
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+}
+
+fn bit(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+fn first_of_next_month(date: NaiveDate) -> Option<NaiveDateTime> {
+    let (year, month) = if date.month() == 12 {
+        (date.year() + 1, 1)
+    } else {
+        (date.year(), date.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)
+}
+
+/// Parse one field into a bit set of the values it matches.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| format!("invalid step in {part:?}"))?;
+                if step == 0 {
+                    return Err(format!("step must be positive in {part:?}"));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_value(start, min, max)?, parse_value(end, min, max)?)
+        } else {
+            let value = parse_value(range, min, max)?;
+            // `5/15` means every 15 starting at 5.
+            (value, if step > 1 { max } else { value })
+        };
+        if start > end {
+            return Err(format!("range {range:?} is reversed"));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+fn parse_value(value: &str, min: u32, max: u32) -> Result<u32, String> {
+    let parsed: u32 = value
+        .parse()
+        .map_err(|_| format!("invalid value {value:?}"))?;
+    if !(min..=max).contains(&parsed) {
+        return Err(format!("{parsed} is outside {min}-{max}"));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn next(expr: &str, after: &str) -> Option<DateTime<Utc>> {
+        expr.parse::<CronSchedule>().unwrap().next_after(&at(after))
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        for expr in [
+            "",
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(expr.parse::<CronSchedule>().is_err(), "{expr:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn finds_next_matching_minute() {
+        assert_eq!(
+            next("0 9 * * *", "2026-10-19T08:30:00Z"),
+            Some(at("2026-10-19T09:00:00Z"))
+        );
+        assert_eq!(
+            next("0 9 * * *", "2026-10-19T09:00:00Z"),
+            Some(at("2026-10-20T09:00:00Z"))
+        );
+        assert_eq!(
+            next("*/15 * * * *", "2026-10-19T08:31:10Z"),
+            Some(at("2026-10-19T08:45:00Z"))
+        );
+        assert_eq!(
+            next("30 8-18/2 * * 1-5", "2026-10-24T12:00:00Z"),
+            Some(at("2026-10-26T08:30:00Z"))
+        );
+        assert_eq!(
+            next("0 0 1 1 *", "2026-10-19T00:00:00Z"),
+            Some(at("2027-01-01T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn day_fields_combine_like_cron() {
+        // Either the 20th or a Sunday (2026-10-25).
+        assert_eq!(
+            next("0 12 20 * 7", "2026-10-19T00:00:00Z"),
+            Some(at("2026-10-20T12:00:00Z"))
+        );
+        assert_eq!(
+            next("0 12 20 * 7", "2026-10-20T12:00:00Z"),
+            Some(at("2026-10-25T12:00:00Z"))
+        );
+        assert_eq!(next("0 0 30 2 *", "2026-10-19T00:00:00Z"), None);
+    }
+}
@@ -0,0 +1,589 @@
+//! Scheduler middleware — one-shot reminders and recurring activations that
+//! survive restarts.
+//!
+//! Schedules live in the `schedules` table, one set per session. Every session
+//! runs a timer task that sleeps until its earliest schedule is due and then
+//! wakes the agent with [`MiddlewareEvent::Activate`]. At startup the channel
+//! runtime restarts sessions that still have schedules, which re-arms them;
+//! earlier conversations of a chat keep running for their schedules too.
+//! Sessions whose activations nobody receives, such as delegated tasks,
+//! cannot create schedules. A schedule fires with the tools denied to the
+//! sender it was created for, not those of whoever wrote last in the chat.
+//! Times are interpreted in the server's local time zone.
+
+mod cron;
+
+use std::{
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use nekobot_core::{
+    agent::{
+        Context,
+        middleware::{Middleware, MiddlewareEvent, MiddlewareFlow},
+        tool::{Tool, ToolError, ToolRegistry, ToolResult, ToolSpec},
+        types::ChatRequest,
+    },
+    entity::{
+        Entity,
+        schedule::{NewSchedule, Schedule, ScheduleKind},
+    },
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::{sync::Notify, task::JoinHandle};
+use turso::Connection;
+
+pub use cron::CronSchedule;
+
+/// Pause before retrying after the schedule table could not be read.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Deserialized from `MiddlewareConfig.data`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct SchedulerConfig {
+    /// Max pending schedules per session. Default 20.
+    #[serde(default = "default_max_schedules")]
+    pub max_schedules: usize,
+}
+
+fn default_max_schedules() -> usize {
+    20
+}
+
+/// Middleware that registers the scheduling tools and fires due schedules.
+pub struct SchedulerMiddleware {
+    config: SchedulerConfig,
+    tool_specs: RwLock<Vec<ToolSpec>>,
+    timer_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SchedulerMiddleware {
+    pub fn from_config(config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            tool_specs: RwLock::new(Vec::new()),
+            timer_task: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for SchedulerMiddleware {
+    fn name(&self) -> &'static str {
+        "scheduler"
+    }
+
+    async fn init(&self, ctx: &Context) -> Result<(), anyhow::Error> {
+        Schedule::create_table(&ctx.app_db).await?;
+        let timer = Arc::new(Timer {
+            session_id: ctx.session_id,
+            app_db: ctx.app_db.clone(),
+            event_sender: ctx.event_sender.clone(),
+            tools: Arc::downgrade(&ctx.tool_registry),
+            wake: Notify::new(),
+            max_schedules: self.config.max_schedules,
+        });
+
+        let tools: Vec<Arc<dyn Tool>> = vec![
+            Arc::new(ScheduleOnceTool {
+                timer: Arc::clone(&timer),
+            }),
+            Arc::new(ScheduleCronTool {
+                timer: Arc::clone(&timer),
+            }),
+            Arc::new(ListSchedulesTool {
+                timer: Arc::clone(&timer),
+            }),
+            Arc::new(CancelScheduleTool {
+                timer: Arc::clone(&timer),
+            }),
+        ];
+        {
+            let mut specs = self
+                .tool_specs
+                .write()
+                .map_err(|e| anyhow::anyhow!("scheduler lock: {e}"))?;
+            for tool in &tools {
+                specs.push(ToolSpec::from_tool(tool.as_ref()));
+                ctx.tool_registry().register(Arc::clone(tool))?;
+            }
+        }
+
+        let task = tokio::spawn(timer.run());
+        *self
+            .timer_task
+            .lock()
+            .map_err(|e| anyhow::anyhow!("scheduler lock: {e}"))? = Some(task);
+        Ok(())
+    }
+
+    async fn before_chat(
+        &self,
+        _ctx: &Context,
+        request: &mut ChatRequest,
+    ) -> Result<MiddlewareFlow, anyhow::Error> {
+        let specs = self
+            .tool_specs
+            .read()
+            .map_err(|e| anyhow::anyhow!("scheduler lock: {e}"))?;
+        request.tools.extend(specs.iter().cloned());
+        Ok(MiddlewareFlow::Continue)
+    }
+
+    async fn shutdown(&self, _ctx: &Context) -> Result<(), anyhow::Error> {
+        let task = self
+            .timer_task
+            .lock()
+            .map_err(|e| anyhow::anyhow!("scheduler lock: {e}"))?
+            .take();
+        if let Some(task) = task {
+            task.abort();
+        }
+        Ok(())
+    }
+}
+
+/// Per-session timer that delivers due schedules as activations.
+struct Timer {
+    session_id: i64,
+    app_db: Connection,
+    event_sender: tokio::sync::mpsc::Sender<MiddlewareEvent>,
+    /// Registry of the session, whose denied tools are those of the sender
+    /// of the running turn.
+    tools: Weak<ToolRegistry>,
+    /// Notified when schedules change so the next wake-up is recomputed.
+    wake: Notify,
+    max_schedules: usize,
+}
+
+impl Timer {
+    async fn run(self: Arc<Self>) {
+        loop {
+            match Schedule::next_run(&self.app_db, self.session_id).await {
+                Ok(Some(next_run)) => {
+                    let delay = next_run.saturating_sub(Local::now().timestamp()).max(0);
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_secs(delay as u64)) => {}
+                        _ = self.wake.notified() => continue,
+                    }
+                }
+                Ok(None) => {
+                    self.wake.notified().await;
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!(target: "scheduler", "failed to read schedules: {e:#}");
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            }
+
+            if let Err(e) = self.fire_due(Local::now()).await {
+                tracing::error!(target: "scheduler", "failed to fire schedules: {e:#}");
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+
+    /// Activate the session for every schedule due at `now`, then delete
+    /// one-shot schedules and move cron schedules to their next match.
+    async fn fire_due(&self, now: DateTime<Local>) -> anyhow::Result<()> {
+        for schedule in Schedule::list_due(&self.app_db, self.session_id, now.timestamp()).await? {
+            self.event_sender
+                .send(MiddlewareEvent::activate(
+                    format!("Scheduled task #{} is due: {}", schedule.id, schedule.prompt),
+                    schedule.denied_tools,
+                ))
+                .await?;
+
+            let next = match schedule.kind {
+                ScheduleKind::Once => None,
+                ScheduleKind::Cron => schedule
+                    .spec
+                    .parse::<CronSchedule>()
+                    .ok()
+                    .and_then(|cron| cron.next_after(&now)),
+            };
+            match next {
+                Some(next) => {
+                    Schedule::set_next_run(&self.app_db, schedule.id, next.timestamp()).await?
+                }
+                None => {
+                    Schedule::delete(&self.app_db, self.session_id, schedule.id).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn add(
+        &self,
+        kind: ScheduleKind,
+        spec: String,
+        prompt: String,
+        next_run: DateTime<Local>,
+    ) -> ToolResult<Value> {
+        if self.event_sender.is_closed() {
+            return Err(ToolError::Execution(
+                "this session cannot be woken up (e.g. a delegated task); \
+                 leave scheduling to the agent of the chat"
+                    .to_owned(),
+            ));
+        }
+        let pending = Schedule::list_by_session(&self.app_db, self.session_id)
+            .await
+            .map_err(|e| ToolError::Execution(format!("list: {e}")))?;
+        if pending.len() >= self.max_schedules {
+            return Err(ToolError::Execution(format!(
+                "too many schedules ({}); cancel one first",
+                pending.len()
+            )));
+        }
+
+        let denied_tools = match self.tools.upgrade() {
+            Some(tools) => tools
+                .denied()
+                .map_err(|e| ToolError::Execution(e.to_string()))?
+                .into_iter()
+                .collect(),
+            None => Vec::new(),
+        };
+        let schedule = Schedule::create(
+            &self.app_db,
+            NewSchedule {
+                session_id: self.session_id,
+                kind,
+                spec,
+                prompt,
+                next_run: next_run.timestamp(),
+                denied_tools,
+            },
+        )
+        .await
+        .map_err(|e| ToolError::Execution(format!("create: {e}")))?;
+        self.wake.notify_one();
+        Ok(schedule_json(&schedule))
+    }
+}
+
+fn schedule_json(schedule: &Schedule) -> Value {
+    let next_run = Local
+        .timestamp_opt(schedule.next_run, 0)
+        .single()
+        .map(|t| t.to_rfc3339());
+    json!({
+        "id": schedule.id,
+        "kind": schedule.kind.as_str(),
+        "spec": schedule.spec,
+        "prompt": schedule.prompt,
+        "next_run": next_run,
+    })
+}
+
+/// Parse an RFC 3339 time, or a local time without offset (`2026-10-20T09:00:00`).
+fn parse_time(value: &str) -> Option<DateTime<Local>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Local));
+    }
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+        .ok()?;
+    Local.from_local_datetime(&naive).earliest()
+}
+
+fn required_str<'a>(args: &'a Value, name: &str) -> ToolResult<&'a str> {
+    args.get(name)
+        .and_then(Value::as_str)
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| ToolError::InvalidArguments(format!("missing '{name}'")))
+}
+
+struct ScheduleOnceTool {
+    timer: Arc<Timer>,
+}
+
+#[async_trait::async_trait]
+impl Tool for ScheduleOnceTool {
+    fn name(&self) -> &str {
+        "schedule_once"
+    }
+    fn description(&self) -> &str {
+        "Wake yourself up once at a given time with a prompt, e.g. to deliver a reminder. \
+         Use current_time first to resolve relative times like 'tomorrow at 9am'."
+    }
+    fn parameters_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "at": {
+                    "type": "string",
+                    "description": "RFC 3339 time, or local time as YYYY-MM-DDTHH:MM:SS"
+                },
+                "prompt": {
+                    "type": "string",
+                    "description": "What to do when the time comes"
+                }
+            },
+            "required": ["at", "prompt"]
+        })
+    }
+    async fn call(&self, args: Value) -> ToolResult<Value> {
+        let at = required_str(&args, "at")?;
+        let prompt = required_str(&args, "prompt")?;
+        let time = parse_time(at)
+            .ok_or_else(|| ToolError::InvalidArguments(format!("invalid time: {at}")))?;
+        if time <= Local::now() {
+            return Err(ToolError::InvalidArguments(format!("{at} is in the past")));
+        }
+        self.timer
+            .add(ScheduleKind::Once, time.to_rfc3339(), prompt.to_owned(), time)
+            .await
+    }
+}
+
+struct ScheduleCronTool {
+    timer: Arc<Timer>,
+}
+
+#[async_trait::async_trait]
+impl Tool for ScheduleCronTool {
+    fn name(&self) -> &str {
+        "schedule_cron"
+    }
+    fn description(&self) -> &str {
+        "Wake yourself up repeatedly on a cron schedule (minute hour day-of-month month \
+         day-of-week, server local time), e.g. '0 9 * * 1-5' for weekdays at 9:00."
+    }
+    fn parameters_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "expr": { "type": "string", "description": "Five-field cron expression" },
+                "prompt": { "type": "string", "description": "What to do each time" }
+            },
+            "required": ["expr", "prompt"]
+        })
+    }
+    async fn call(&self, args: Value) -> ToolResult<Value> {
+        let expr = required_str(&args, "expr")?;
+        let prompt = required_str(&args, "prompt")?;
+        let cron: CronSchedule = expr
+            .parse()
+            .map_err(|e| ToolError::InvalidArguments(format!("invalid cron expression: {e}")))?;
+        let next_run = cron.next_after(&Local::now()).ok_or_else(|| {
+            ToolError::InvalidArguments(format!("cron expression never matches: {expr}"))
+        })?;
+        self.timer
+            .add(ScheduleKind::Cron, expr.to_owned(), prompt.to_owned(), next_run)
+            .await
+    }
+}
+
+struct ListSchedulesTool {
+    timer: Arc<Timer>,
+}
+
+#[async_trait::async_trait]
+impl Tool for ListSchedulesTool {
+    fn name(&self) -> &str {
+        "list_schedules"
+    }
+    fn description(&self) -> &str {
+        "List the pending schedules of this conversation, soonest first."
+    }
+    fn parameters_schema(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+    async fn call(&self, _args: Value) -> ToolResult<Value> {
+        let schedules = Schedule::list_by_session(&self.timer.app_db, self.timer.session_id)
+            .await
+            .map_err(|e| ToolError::Execution(format!("list: {e}")))?;
+        Ok(Value::Array(schedules.iter().map(schedule_json).collect()))
+    }
+}
+
+struct CancelScheduleTool {
+    timer: Arc<Timer>,
+}
+
+#[async_trait::async_trait]
+impl Tool for CancelScheduleTool {
+    fn name(&self) -> &str {
+        "cancel_schedule"
+    }
+    fn description(&self) -> &str {
+        "Cancel a pending schedule by id."
+    }
+    fn parameters_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "id": { "type": "integer", "description": "Schedule id from list_schedules" }
+            },
+            "required": ["id"]
+        })
+    }
+    async fn call(&self, args: Value) -> ToolResult<Value> {
+        let id = args
+            .get("id")
+            .and_then(Value::as_i64)
+            .ok_or_else(|| ToolError::InvalidArguments("missing 'id'".to_owned()))?;
+        let cancelled = Schedule::delete(&self.timer.app_db, self.timer.session_id, id)
+            .await
+            .map_err(|e| ToolError::Execution(format!("delete: {e}")))?;
+        self.timer.wake.notify_one();
+        Ok(json!({ "cancelled": cancelled }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nekobot_core::entity::session::Session;
+
+    use super::*;
+
+    type Events = tokio::sync::mpsc::Receiver<MiddlewareEvent>;
+
+    async fn timer() -> anyhow::Result<(Arc<Timer>, Events, Arc<ToolRegistry>)> {
+        let db = turso::Builder::new_local(":memory:").build().await?;
+        let conn = db.connect()?;
+        Session::create_table(&conn).await?;
+        Schedule::create_table(&conn).await?;
+        let session = Session::create(&conn, "Neko").await?;
+        let (event_sender, event_receiver) = tokio::sync::mpsc::channel(8);
+        let tools = Arc::new(ToolRegistry::new());
+        let timer = Arc::new(Timer {
+            session_id: session.id,
+            app_db: conn,
+            event_sender,
+            tools: Arc::downgrade(&tools),
+            wake: Notify::new(),
+            max_schedules: 2,
+        });
+        Ok((timer, event_receiver, tools))
+    }
+
+    #[tokio::test]
+    async fn tools_validate_and_limit_schedules() -> anyhow::Result<()> {
+        let (timer, _events, _tools) = timer().await?;
+        let once = ScheduleOnceTool {
+            timer: Arc::clone(&timer),
+        };
+        let cron = ScheduleCronTool {
+            timer: Arc::clone(&timer),
+        };
+
+        let past = once
+            .call(json!({ "at": "2000-01-01T00:00:00Z", "prompt": "too late" }))
+            .await;
+        assert!(matches!(past, Err(ToolError::InvalidArguments(_))));
+        let invalid = cron.call(json!({ "expr": "every day", "prompt": "x" })).await;
+        assert!(matches!(invalid, Err(ToolError::InvalidArguments(_))));
+
+        let reminder = once
+            .call(json!({ "at": "2999-01-01T09:00:00+08:00", "prompt": "remind Alice" }))
+            .await?;
+        cron.call(json!({ "expr": "0 9 * * *", "prompt": "daily report" }))
+            .await?;
+        let full = cron.call(json!({ "expr": "0 10 * * *", "prompt": "x" })).await;
+        assert!(matches!(full, Err(ToolError::Execution(_))));
+
+        let listed = ListSchedulesTool {
+            timer: Arc::clone(&timer),
+        }
+        .call(json!({}))
+        .await?;
+        assert_eq!(listed.as_array().map(Vec::len), Some(2));
+        assert_eq!(listed[1], reminder);
+
+        let cancel = CancelScheduleTool { timer };
+        assert_eq!(cancel.call(json!({ "id": reminder["id"] })).await?["cancelled"], true);
+        assert_eq!(cancel.call(json!({ "id": reminder["id"] })).await?["cancelled"], false);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sessions_nobody_wakes_cannot_schedule() -> anyhow::Result<()> {
+        let (timer, events, _tools) = timer().await?;
+        drop(events);
+        let once = ScheduleOnceTool {
+            timer: Arc::clone(&timer),
+        };
+
+        let result = once
+            .call(json!({ "at": "2999-01-01T09:00:00+08:00", "prompt": "remind Alice" }))
+            .await;
+        assert!(matches!(result, Err(ToolError::Execution(_))));
+        assert!(Schedule::list_by_session(&timer.app_db, timer.session_id).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn due_schedules_activate_session_and_advance() -> anyhow::Result<()> {
+        let (timer, mut events, _tools) = timer().await?;
+        let now = Local::now();
+        for (kind, spec) in [(ScheduleKind::Once, "once"), (ScheduleKind::Cron, "* * * * *")] {
+            Schedule::create(
+                &timer.app_db,
+                NewSchedule {
+                    session_id: timer.session_id,
+                    kind,
+                    spec: spec.to_owned(),
+                    prompt: format!("{} prompt", kind.as_str()),
+                    next_run: now.timestamp() - 60,
+                    denied_tools: Vec::new(),
+                },
+            )
+            .await?;
+        }
+
+        timer.fire_due(now).await?;
+
+        let MiddlewareEvent::Activate { prompt, .. } = events.recv().await.unwrap() else {
+            panic!("expected an activation");
+        };
+        assert!(prompt.ends_with("once prompt"));
+        let MiddlewareEvent::Activate { prompt, .. } = events.recv().await.unwrap() else {
+            panic!("expected an activation");
+        };
+        assert!(prompt.ends_with("cron prompt"));
+
+        let remaining = Schedule::list_by_session(&timer.app_db, timer.session_id).await?;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].kind, ScheduleKind::Cron);
+        assert!(remaining[0].next_run > now.timestamp());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn schedules_fire_with_the_grants_of_their_creator() -> anyhow::Result<()> {
+        let (timer, mut events, tools) = timer().await?;
+        let once = ScheduleOnceTool {
+            timer: Arc::clone(&timer),
+        };
+
+        // A guest asks for a reminder, then an admin writes in the same chat.
+        tools.set_denied(["bash".to_owned()])?;
+        let reminder = once
+            .call(json!({ "at": "2999-01-01T09:00:00+08:00", "prompt": "run the backup" }))
+            .await?;
+        tools.set_denied(Vec::new())?;
+
+        timer.fire_due(Local.with_ymd_and_hms(3000, 1, 1, 0, 0, 0).unwrap()).await?;
+
+        let MiddlewareEvent::Activate {
+            prompt,
+            denied_tools,
+        } = events.recv().await.unwrap()
+        else {
+            panic!("expected an activation");
+        };
+        assert!(prompt.starts_with(&format!("Scheduled task #{} is due", reminder["id"])));
+        assert_eq!(denied_tools, vec!["bash".to_owned()]);
+        Ok(())
+    }
+}
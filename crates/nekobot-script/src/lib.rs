@@ -1,4 +1,8 @@
-use std::sync::{Arc, RwLock};
+use std::sync::{
+    Arc, Mutex, RwLock,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use nekobot_core::agent::{
@@ -7,24 +11,30 @@ use nekobot_core::agent::{
     tool::Tool,
     types::ChatRequest,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    runtime::{NekobotContext, Runtime},
+    runtime::{DeniedTools, NekobotContext, Runtime},
     tools::eval_ts::EvalTsTool,
 };
 mod runtime;
 mod tools;
 mod utils;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ScriptConfig {
     timeout_seconds: u64,
 }
 
+/// How long [`Middleware::shutdown`] waits for the script thread to exit.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct ScriptMiddleware {
     config: ScriptConfig,
     tool_specs: RwLock<Vec<nekobot_core::agent::tool::ToolSpec>>,
+    shutdown: Arc<AtomicBool>,
+    runtime_task: Mutex<Option<tokio::task::JoinHandle<()>>>,
 }
 
 impl ScriptMiddleware {
@@ -32,6 +42,8 @@ impl ScriptMiddleware {
         ScriptMiddleware {
             config,
             tool_specs: RwLock::new(Vec::new()),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            runtime_task: Mutex::new(None),
         }
     }
 }
@@ -39,29 +51,41 @@ impl ScriptMiddleware {
 #[async_trait]
 impl Middleware for ScriptMiddleware {
     async fn init(&self, context: &Context) -> anyhow::Result<()> {
+        let denied_tools = DeniedTools::default();
         let nekobot_ctx = NekobotContext {
             event_sender: context.event_sender.clone(),
+            denied_tools: Arc::clone(&denied_tools),
             app_db: context.app_db.clone(),
             session_id: context.session_id.clone(),
             agent_name: context.agent_name.clone(),
         };
         let (runtime_handle, task_receiver) = runtime::RuntimeHandle::new();
-        tokio::task::spawn_blocking(move || {
+        let shutdown = Arc::clone(&self.shutdown);
+        let runtime_task = tokio::task::spawn_blocking(move || {
             tokio::runtime::Builder::new_current_thread()
                 .enable_all()
                 .build()
                 .unwrap()
                 .block_on(
-                    Runtime::try_new(task_receiver, nekobot_ctx)
+                    Runtime::try_new(task_receiver, nekobot_ctx, shutdown)
                         .expect("Failed to create script runtime")
                         .start(),
                 );
         });
+        *self
+            .runtime_task
+            .lock()
+            .map_err(|_| anyhow::anyhow!("runtime task lock poisoned"))? = Some(runtime_task);
         let mut tool_specs = self
             .tool_specs
             .write()
             .map_err(|_| anyhow::anyhow!("tool specs lock poisoned"))?;
-        let eval_ts_tool = EvalTsTool::new(self.config.timeout_seconds, runtime_handle.clone());
+        let eval_ts_tool = EvalTsTool::new(
+            self.config.timeout_seconds,
+            runtime_handle.clone(),
+            Arc::downgrade(&context.tool_registry),
+            denied_tools,
+        );
         tool_specs.push(nekobot_core::agent::tool::ToolSpec {
             name: eval_ts_tool.name().to_string(),
             description: eval_ts_tool.description().to_string(),
@@ -101,4 +125,19 @@ declare const nekobot: Nekobot;
         ));
         Ok(MiddlewareFlow::Continue)
     }
+
+    async fn shutdown(&self, _context: &Context) -> anyhow::Result<()> {
+        self.shutdown.store(true, Ordering::Relaxed);
+        let runtime_task = self
+            .runtime_task
+            .lock()
+            .map_err(|_| anyhow::anyhow!("runtime task lock poisoned"))?
+            .take();
+        if let Some(runtime_task) = runtime_task
+            && tokio::time::timeout(SHUTDOWN_TIMEOUT, runtime_task).await.is_err()
+        {
+            tracing::warn!(target: "script", "script runtime did not stop in time");
+        }
+        Ok(())
+    }
 }
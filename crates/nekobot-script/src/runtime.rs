@@ -8,7 +8,9 @@ use boa_engine::{
     context::ContextBuilder,
     job::{Job, JobExecutor, NativeAsyncJob, PromiseJob},
 };
-use boa_engine::{Finalize, JsData, JsError, JsString, JsValue, NativeFunction, Trace};
+use boa_engine::{
+    Finalize, JsData, JsError, JsNativeError, JsString, JsValue, NativeFunction, Trace,
+};
 use boa_runtime::fetch::Fetcher;
 use boa_runtime::fetch::request::JsRequest;
 use boa_runtime::fetch::response::JsResponse;
@@ -16,19 +18,29 @@ use futures_concurrency::future::FutureGroup;
 use futures_lite::{StreamExt, future};
 use nekobot_core::agent::middleware::MiddlewareEvent;
 use serde_json::Value;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ops::DerefMut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::{cell::RefCell, collections::VecDeque, rc::Rc};
 use tokio::sync::{mpsc, oneshot};
 use tokio::task;
 use tracing::{debug, error};
 use turso::Connection;
 
+/// Tools denied to any sender whose code ran in a runtime.
+///
+/// Timers started by one sender may call `notify` during a later turn of
+/// another, so activations are restricted by everyone who ran code.
+pub type DeniedTools = Arc<Mutex<BTreeSet<String>>>;
+
 #[derive(Debug, Clone, Trace, Finalize, JsData)]
 pub struct NekobotContext {
     #[unsafe_ignore_trace]
     pub event_sender: mpsc::Sender<MiddlewareEvent>,
     #[unsafe_ignore_trace]
+    pub denied_tools: DeniedTools,
+    #[unsafe_ignore_trace]
     pub app_db: Connection,
     pub session_id: i64,
     pub agent_name: String,
@@ -153,12 +165,16 @@ pub struct Runtime {
     context: RefCell<Context>,
     queue: Rc<Queue>,
     task_receiver: mpsc::UnboundedReceiver<EvalTask>,
+    /// Set by the owning middleware on shutdown; the event loop exits without
+    /// waiting for pending timers.
+    shutdown: Arc<AtomicBool>,
 }
 
 impl Runtime {
     pub fn try_new(
         receiver: mpsc::UnboundedReceiver<EvalTask>,
         ctx: NekobotContext,
+        shutdown: Arc<AtomicBool>,
     ) -> Result<Self> {
         let queue = Rc::new(Queue::new());
         let context = ContextBuilder::new()
@@ -169,6 +185,7 @@ impl Runtime {
             context: RefCell::new(context),
             queue,
             task_receiver: receiver,
+            shutdown,
         };
         runtime.add_runtime(ctx);
         Ok(runtime)
@@ -208,9 +225,16 @@ impl Runtime {
             debug!(target: "actor", "notify called with message: {}", msg);
             let host_defined = ctx.realm().host_defined();
             let nekobot_ctx = host_defined.get::<NekobotContext>().unwrap();
+            let denied_tools = nekobot_ctx
+                .denied_tools
+                .lock()
+                .map_err(|_| JsNativeError::error().with_message("denied tools lock poisoned"))?
+                .iter()
+                .cloned()
+                .collect();
             nekobot_ctx
                 .event_sender
-                .try_send(MiddlewareEvent::activate(msg))
+                .try_send(MiddlewareEvent::activate(msg, denied_tools))
                 .map(|_| JsValue::undefined())
                 .map_err(|e| JsError::from_rust(e))
         });
@@ -241,6 +265,10 @@ impl Runtime {
                 .map(|job| job.call(&context));
 
             loop {
+                if self.shutdown.load(Ordering::Relaxed) {
+                    debug!(target: "js runtime", "shutdown requested, stopping event loop");
+                    break;
+                }
                 if self.task_receiver.is_closed()
                     && queue.promise_jobs.borrow().is_empty()
                     && queue.timeout_jobs.borrow().is_empty()
@@ -1,22 +1,49 @@
+use std::sync::Weak;
+
 use anyhow::Context;
 use async_trait::async_trait;
-use nekobot_core::agent::tool::{Tool, ToolError, ToolResult};
+use nekobot_core::agent::tool::{Tool, ToolError, ToolRegistry, ToolResult};
 use serde_json::Value;
 
-use crate::runtime::RuntimeHandle;
+use crate::runtime::{DeniedTools, RuntimeHandle};
 
 pub struct EvalTsTool {
     pub timeout_seconds: u64,
     pub handle: RuntimeHandle,
+    /// Registry of the session, holding the tools denied to the sender of
+    /// the running turn.
+    pub tools: Weak<ToolRegistry>,
+    /// Tools denied to any sender whose code ran in the runtime.
+    pub denied_tools: DeniedTools,
 }
 
 impl EvalTsTool {
-    pub fn new(timeout_seconds: u64, handle: RuntimeHandle) -> Self {
+    pub fn new(
+        timeout_seconds: u64,
+        handle: RuntimeHandle,
+        tools: Weak<ToolRegistry>,
+        denied_tools: DeniedTools,
+    ) -> Self {
         Self {
             timeout_seconds,
             handle,
+            tools,
+            denied_tools,
         }
     }
+
+    /// Add the tools denied to the current sender to those notifications of
+    /// the runtime are restricted by, since timers outlive the turn.
+    fn record_caller(&self) -> anyhow::Result<()> {
+        let Some(tools) = self.tools.upgrade() else {
+            return Ok(());
+        };
+        self.denied_tools
+            .lock()
+            .map_err(|_| anyhow::anyhow!("denied tools lock poisoned"))?
+            .extend(tools.denied()?);
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -56,6 +83,8 @@ impl Tool for EvalTsTool {
         let js_code = crate::utils::transpile(code)
             .context("failed to transpile TypeScript code")
             .map_err(|e| ToolError::Execution(format!("transpilation error: {e}")))?;
+        self.record_caller()
+            .map_err(|e| ToolError::Execution(e.to_string()))?;
         self.handle
             .eval(js_code)
             .await
@@ -0,0 +1,338 @@
+//! Encryption of secrets stored in the database.
+//!
+//! A [`Keyring`] holds one or more named 256-bit keys. The first key is the
+//! primary key and encrypts new values; the others only decrypt values
+//! written before a key rotation. Each value is sealed with
+//! XChaCha20-Poly1305 under a random nonce and bound to its table and row, so
+//! an encrypted value copied into another row does not decrypt.
+//!
+//! Sealed values are stored as `enc:v1:<key id>:<base64 nonce and ciphertext>`.
+//! Values without that prefix are plaintext from before encryption was
+//! configured; they are read as-is until [`Keyring::rotate`] encrypts them.
+
+use std::path::Path;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chacha20poly1305::{
+    Key, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use turso::Connection;
+use zeroize::Zeroizing;
+
+/// Environment variable with the keys, as `id:base64-key` entries separated
+/// by commas or newlines. Takes precedence over a key file.
+pub const KEYS_ENV: &str = "NEKOBOT_SECRET_KEYS";
+
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 24;
+
+/// A database column that holds sealed secrets, with the column that
+/// identifies its rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecretColumn {
+    pub table: &'static str,
+    /// Primary key column; its value is part of the associated data.
+    pub key_column: &'static str,
+    pub column: &'static str,
+}
+
+impl SecretColumn {
+    fn associated_data(&self, row_key: &str) -> String {
+        format!("{}.{}/{row_key}", self.table, self.column)
+    }
+}
+
+/// The keys secrets are sealed with.
+///
+/// An empty keyring stores values as plaintext, which keeps installations
+/// without a configured key working. Key bytes are wiped when dropped.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    keys: Vec<(String, Zeroizing<[u8; 32]>)>,
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field(
+                "key_ids",
+                &self.keys.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Keyring {
+    /// A keyring without keys; see [`Keyring::is_empty`].
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Load keys from [`KEYS_ENV`], or else from `key_file` when given.
+    pub fn load(key_file: Option<&Path>) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        if let Ok(keys) = std::env::var(KEYS_ENV).map(Zeroizing::new) {
+            return Self::parse(&keys).with_context(|| format!("invalid {KEYS_ENV}"));
+        }
+        match key_file {
+            Some(path) => {
+                let keys = std::fs::read_to_string(path)
+                    .map(Zeroizing::new)
+                    .with_context(|| format!("failed to read key file {}", path.display()))?;
+                Self::parse(&keys).with_context(|| format!("invalid key file {}", path.display()))
+            }
+            None => Ok(Self::empty()),
+        }
+    }
+
+    /// Parse `id:base64-key` entries separated by commas or newlines, primary
+    /// key first. Blank entries and lines starting with `#` are skipped.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut keys = Vec::new();
+        for entry in text.split([',', '\n']).map(str::trim) {
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+            let (id, key) = entry
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("expected 'id:base64-key', got '{entry}'"))?;
+            let id = id.trim();
+            if id.is_empty() {
+                anyhow::bail!("key id must not be empty");
+            }
+            if keys.iter().any(|(existing, _)| existing == id) {
+                anyhow::bail!("duplicate key id '{id}'");
+            }
+            let bytes = STANDARD
+                .decode(key.trim())
+                .map(Zeroizing::new)
+                .map_err(|e| anyhow::anyhow!("key '{id}' is not valid base64: {e}"))?;
+            if bytes.len() != 32 {
+                anyhow::bail!("key '{id}' must be 32 bytes, got {}", bytes.len());
+            }
+            let mut key = Zeroizing::new([0u8; 32]);
+            key.copy_from_slice(&bytes);
+            keys.push((id.to_owned(), key));
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Id of the key new values are sealed with.
+    pub fn primary_key_id(&self) -> Option<&str> {
+        self.keys.first().map(|(id, _)| id.as_str())
+    }
+
+    /// Seal `plaintext` for the row `row_key` of `column` with the primary
+    /// key. Returns `plaintext` unchanged if the keyring is empty.
+    pub fn seal(
+        &self,
+        column: &SecretColumn,
+        row_key: &str,
+        plaintext: &str,
+    ) -> anyhow::Result<String> {
+        let Some((id, key)) = self.keys.first() else {
+            return Ok(plaintext.to_owned());
+        };
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = column.associated_data(row_key);
+        let ciphertext = cipher(key)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("failed to encrypt {aad}"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{PREFIX}{id}:{}", STANDARD.encode(sealed)))
+    }
+
+    /// Open a value read from the row `row_key` of `column`.
+    ///
+    /// Plaintext values are returned as-is. Fails if the value was sealed
+    /// with a key that is not in the keyring, or was tampered with.
+    pub fn open(
+        &self,
+        column: &SecretColumn,
+        row_key: &str,
+        stored: &str,
+    ) -> anyhow::Result<String> {
+        let Some(rest) = stored.strip_prefix(PREFIX) else {
+            return Ok(stored.to_owned());
+        };
+        let aad = column.associated_data(row_key);
+        let (id, data) = rest
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("malformed encrypted value in {aad}"))?;
+        let (_, key) = self
+            .keys
+            .iter()
+            .find(|(key_id, _)| key_id == id)
+            .ok_or_else(|| anyhow::anyhow!("{aad} is encrypted with unknown key '{id}'"))?;
+        let data = STANDARD
+            .decode(data)
+            .map_err(|_| anyhow::anyhow!("malformed encrypted value in {aad}"))?;
+        if data.len() < NONCE_LEN {
+            anyhow::bail!("malformed encrypted value in {aad}");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plaintext = cipher(key)
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                anyhow::anyhow!("failed to decrypt {aad}: wrong key or corrupted value")
+            })?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    /// Re-encrypt every row of `column` with the primary key, including
+    /// plaintext rows; returns the number of rows rewritten.
+    ///
+    /// Runs in one transaction, so a row that cannot be opened leaves the
+    /// whole column unchanged.
+    pub async fn rotate(&self, conn: &Connection, column: &SecretColumn) -> anyhow::Result<u64> {
+        if self.is_empty() {
+            anyhow::bail!("no secret key configured; set {KEYS_ENV} or a key file");
+        }
+        let update = format!(
+            "UPDATE {} SET {} = ?1 WHERE {} = ?2",
+            column.table, column.column, column.key_column
+        );
+        conn.execute("BEGIN", ()).await?;
+        let result = async {
+            let mut values = Vec::new();
+            let mut rows = conn
+                .query(
+                    &format!(
+                        "SELECT {}, {} FROM {}",
+                        column.key_column, column.column, column.table
+                    ),
+                    (),
+                )
+                .await?;
+            while let Some(row) = rows.next().await? {
+                let row_key: String = row.get(0)?;
+                let stored: String = row.get(1)?;
+                values.push((row_key, stored));
+            }
+            drop(rows);
+
+            for (row_key, stored) in &values {
+                let plaintext = Zeroizing::new(self.open(column, row_key, stored)?);
+                let sealed = self.seal(column, row_key, &plaintext)?;
+                conn.execute(&update, (sealed.as_str(), row_key.as_str()))
+                    .await?;
+            }
+            anyhow::Ok(values.len() as u64)
+        }
+        .await;
+        match result {
+            Ok(count) => {
+                conn.execute("COMMIT", ()).await?;
+                Ok(count)
+            }
+            Err(error) => {
+                let _ = conn.execute("ROLLBACK", ()).await;
+                Err(error.context(format!(
+                    "failed to rotate {}.{}",
+                    column.table, column.column
+                )))
+            }
+        }
+    }
+}
+
+fn cipher(key: &[u8; 32]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTES: SecretColumn = SecretColumn {
+        table: "notes",
+        key_column: "name",
+        column: "secret",
+    };
+
+    fn key(byte: u8) -> String {
+        STANDARD.encode([byte; 32])
+    }
+
+    #[test]
+    fn seals_and_opens_bound_to_the_row() {
+        let keyring = Keyring::parse(&format!("a:{}", key(1))).unwrap();
+        let sealed = keyring.seal(&NOTES, "one", "token").unwrap();
+        assert!(sealed.starts_with("enc:v1:a:"));
+        assert!(!sealed.contains("token"));
+        assert_eq!(keyring.open(&NOTES, "one", &sealed).unwrap(), "token");
+        assert!(keyring.open(&NOTES, "two", &sealed).is_err());
+
+        let other = Keyring::parse(&format!("b:{}", key(2))).unwrap();
+        assert!(other.open(&NOTES, "one", &sealed).is_err());
+        assert_eq!(keyring.open(&NOTES, "one", "plain").unwrap(), "plain");
+        assert_eq!(
+            Keyring::empty().seal(&NOTES, "one", "plain").unwrap(),
+            "plain"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert!(Keyring::parse("nocolon").is_err());
+        assert!(Keyring::parse(&format!("a:{}", STANDARD.encode([0u8; 16]))).is_err());
+        assert!(Keyring::parse(&format!("a:{},a:{}", key(1), key(2))).is_err());
+        let keyring =
+            Keyring::parse(&format!("# current\nnew:{}\nold:{}\n", key(1), key(2))).unwrap();
+        assert_eq!(keyring.primary_key_id(), Some("new"));
+    }
+
+    #[tokio::test]
+    async fn rotation_reencrypts_every_row() -> anyhow::Result<()> {
+        let db = turso::Builder::new_local(":memory:").build().await?;
+        let conn = db.connect()?;
+        conn.execute(
+            "CREATE TABLE notes (name TEXT PRIMARY KEY, secret TEXT NOT NULL)",
+            (),
+        )
+        .await?;
+        let old = Keyring::parse(&format!("old:{}", key(1)))?;
+        let sealed = old.seal(&NOTES, "one", "first")?;
+        conn.execute(
+            "INSERT INTO notes VALUES ('one', ?1), ('two', 'second')",
+            (sealed.as_str(),),
+        )
+        .await?;
+
+        let rotated = Keyring::parse(&format!("new:{},old:{}", key(2), key(1)))?;
+        assert_eq!(rotated.rotate(&conn, &NOTES).await?, 2);
+
+        let current = Keyring::parse(&format!("new:{}", key(2)))?;
+        let mut rows = conn
+            .query("SELECT name, secret FROM notes ORDER BY name", ())
+            .await?;
+        let mut opened = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let name: String = row.get(0)?;
+            let secret: String = row.get(1)?;
+            assert!(secret.starts_with("enc:v1:new:"));
+            opened.push(current.open(&NOTES, &name, &secret)?);
+        }
+        assert_eq!(opened, ["first", "second"]);
+        Ok(())
+    }
+}
@@ -15,13 +15,14 @@ use nekobot_core::agent::{
     tool::{Tool, ToolResult, ToolSpec},
     types::ChatRequest,
 };
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::Value;
 
 use loader::SkillMeta;
 
 /// Deserialized from `MiddlewareConfig.data`.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct SkillConfig {
     /// Path to the skills directory. Default `"./skills"`.
     #[serde(default = "default_skills_dir")]
@@ -0,0 +1,163 @@
+//! Fake channel — lets tests emit messages and await what the bot sends.
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use nekobot_channel::{
+    Channel, ChannelId, ChannelInfo, ChannelName, ChatId, ChatInfo, ChatName, ChatType, Event,
+    ReplyTarget, Request, SenderId, SenderInfo, SenderName,
+};
+use tokio::sync::{mpsc, watch};
+
+/// How long [`FakeChannel`] waits for the bot before failing the test.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A [`Channel`] driven by the test instead of a chat platform.
+///
+/// Clones share state, so a test can keep a handle while the bot owns
+/// another. Requests the bot sends are logged and can be awaited one by one
+/// with [`next_request`](Self::next_request).
+#[derive(Clone)]
+pub struct FakeChannel {
+    state: Arc<State>,
+}
+
+struct State {
+    info: ChannelInfo,
+    chats: Mutex<Vec<ChatInfo>>,
+    event_sender: watch::Sender<Option<mpsc::Sender<Event>>>,
+    sent: Mutex<Vec<Request>>,
+    sent_sender: mpsc::UnboundedSender<Request>,
+    sent_receiver: tokio::sync::Mutex<mpsc::UnboundedReceiver<Request>>,
+}
+
+impl FakeChannel {
+    /// Create a channel reporting `id` and `name` when registered.
+    pub fn new(id: impl Into<ChannelId>, name: impl Into<ChannelName>) -> Self {
+        let (sent_sender, sent_receiver) = mpsc::unbounded_channel();
+        Self {
+            state: Arc::new(State {
+                info: ChannelInfo {
+                    id: id.into(),
+                    name: name.into(),
+                },
+                chats: Mutex::new(Vec::new()),
+                event_sender: watch::Sender::new(None),
+                sent: Mutex::new(Vec::new()),
+                sent_sender,
+                sent_receiver: tokio::sync::Mutex::new(sent_receiver),
+            }),
+        }
+    }
+
+    /// Chats returned by [`Channel::list_chats`].
+    pub fn with_chats(self, chats: Vec<ChatInfo>) -> Self {
+        *lock(&self.state.chats) = chats;
+        self
+    }
+
+    /// Send an event to the bot, waiting for it to register the channel first.
+    pub async fn emit(&self, event: Event) -> anyhow::Result<()> {
+        let mut receiver = self.state.event_sender.subscribe();
+        let sender = tokio::time::timeout(TIMEOUT, receiver.wait_for(Option::is_some))
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out waiting for the bot to register the channel"))??
+            .clone()
+            .expect("waited for a registered sender");
+        sender.send(event).await?;
+        Ok(())
+    }
+
+    /// Send a private message from `sender` to the bot.
+    ///
+    /// The chat id, chat name and reply target are all the sender id.
+    pub async fn message(&self, sender: &str, content: impl Into<String>) -> anyhow::Result<()> {
+        self.emit(Event::IncomingMessage {
+            chat: chat(sender, ChatType::Private),
+            sender: SenderInfo {
+                id: SenderId::from(sender),
+                name: SenderName::from(sender),
+            },
+            content: content.into(),
+        })
+        .await
+    }
+
+    /// Send a message from `sender` in the group chat `group`.
+    pub async fn group_message(
+        &self,
+        group: &str,
+        sender: &str,
+        content: impl Into<String>,
+    ) -> anyhow::Result<()> {
+        self.emit(Event::IncomingMessage {
+            chat: chat(group, ChatType::Group),
+            sender: SenderInfo {
+                id: SenderId::from(sender),
+                name: SenderName::from(sender),
+            },
+            content: content.into(),
+        })
+        .await
+    }
+
+    /// Every request the bot sent so far.
+    pub fn sent_requests(&self) -> Vec<Request> {
+        lock(&self.state.sent).clone()
+    }
+
+    /// Wait for the next request the bot sends.
+    pub async fn next_request(&self) -> anyhow::Result<Request> {
+        let mut receiver = self.state.sent_receiver.lock().await;
+        tokio::time::timeout(TIMEOUT, receiver.recv())
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out waiting for the bot to send a request"))?
+            .ok_or_else(|| anyhow::anyhow!("fake channel closed"))
+    }
+
+    /// Wait for the next message the bot sends, skipping typing indicators.
+    pub async fn next_message(&self) -> anyhow::Result<(ReplyTarget, String)> {
+        loop {
+            if let Request::SendMessage { target, content } = self.next_request().await? {
+                return Ok((target, content));
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Channel for FakeChannel {
+    async fn register(
+        &self,
+        sender: mpsc::Sender<Event>,
+        _app_db: Option<turso::Connection>,
+    ) -> anyhow::Result<ChannelInfo> {
+        self.state.event_sender.send_replace(Some(sender));
+        Ok(self.state.info.clone())
+    }
+
+    async fn send(&self, request: Request) -> anyhow::Result<()> {
+        lock(&self.state.sent).push(request.clone());
+        let _ = self.state.sent_sender.send(request);
+        Ok(())
+    }
+
+    async fn list_chats(&self) -> anyhow::Result<Vec<ChatInfo>> {
+        Ok(lock(&self.state.chats).clone())
+    }
+}
+
+fn chat(id: &str, chat_type: ChatType) -> ChatInfo {
+    ChatInfo {
+        id: ChatId::from(id),
+        name: ChatName::from(id),
+        reply_target: ReplyTarget::from(id),
+        chat_type,
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,276 @@
+//! Test kit for integration tests of NekoBot setups.
+//!
+//! Provides a [`ScriptedProvider`] that answers with queued responses, a
+//! [`FakeChannel`] driven by the test, and [`TestBot`], which builds a full
+//! [`NekoBot`] from a YAML config with every configured channel and provider
+//! replaced by those fakes and the database kept in memory.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let bot = nekobot_testkit::TestBot::from_yaml(
+//!     r#"
+//! channels:
+//!   - { type: QQ, name: qq, app_id: test, client_secret: test }
+//! providers:
+//!   - { type: DeepSeek, name: deepseek, api_key: test, models: [{ model: test-model }] }
+//! agents:
+//!   - { name: Neko, provider: deepseek, model: test-model, middlewares: [] }
+//! "#,
+//! )?
+//! .start();
+//!
+//! bot.provider("deepseek").respond("hi!");
+//! bot.channel("qq").message("alice", "hello").await?;
+//! let (_, reply) = bot.channel("qq").next_message().await?;
+//! assert_eq!(reply, "hi!");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::HashMap, sync::Arc};
+
+use nekobot_core::{
+    NekoBot,
+    agent::middleware::Middleware,
+    config::{Config, MiddlewareConfig},
+    provider::Provider,
+};
+
+mod channel;
+mod provider;
+
+pub use channel::FakeChannel;
+pub use provider::ScriptedProvider;
+
+/// A [`NekoBot`] wired to fakes, before it is started.
+pub struct TestBot {
+    bot: NekoBot,
+    fakes: Fakes,
+}
+
+/// The fakes standing in for configured channels and providers, by name.
+#[derive(Clone, Default)]
+struct Fakes {
+    channels: HashMap<String, FakeChannel>,
+    providers: HashMap<String, ScriptedProvider>,
+}
+
+impl TestBot {
+    /// Parse a YAML config and build a bot from it.
+    ///
+    /// Each channel becomes a [`FakeChannel`] whose id and name are the
+    /// configured channel name, and each provider becomes a
+    /// [`ScriptedProvider`]. The database path is replaced with an in-memory
+    /// database. Middlewares must be registered with
+    /// [`with_middleware`](Self::with_middleware).
+    pub fn from_yaml(yaml: &str) -> anyhow::Result<Self> {
+        let config: Config = serde_yml::from_str(yaml)?;
+        Self::new(config)
+    }
+
+    /// Build a bot from an already parsed config; see [`from_yaml`](Self::from_yaml).
+    pub fn new(mut config: Config) -> anyhow::Result<Self> {
+        config.database_path = ":memory:".to_owned();
+        config.telemetry.metrics_addr = None;
+
+        let mut fakes = Fakes::default();
+        for channel in &config.channels {
+            fakes.channels.insert(
+                channel.name().to_owned(),
+                FakeChannel::new(channel.name(), channel.name()),
+            );
+        }
+        for provider in &config.providers {
+            fakes
+                .providers
+                .insert(provider.name().to_owned(), ScriptedProvider::new());
+        }
+
+        let channel_types: Vec<String> =
+            unique(config.channels.iter().map(|c| c.type_name().to_owned()));
+        let provider_types: Vec<String> =
+            unique(config.providers.iter().map(|p| p.type_name().to_owned()));
+
+        let mut bot = NekoBot::new(config);
+        for type_name in channel_types {
+            let channels = fakes.channels.clone();
+            bot = bot.with_channel(type_name, move |config| {
+                Ok(Box::new(channels[config.name()].clone()) as Box<dyn nekobot_channel::Channel>)
+            })?;
+        }
+        for type_name in provider_types {
+            let providers = fakes.providers.clone();
+            bot = bot.with_provider(type_name, move |config| {
+                Ok(Arc::new(providers[config.name()].clone()) as Arc<dyn Provider>)
+            })?;
+        }
+
+        Ok(Self { bot, fakes })
+    }
+
+    /// Register a middleware factory by name.
+    pub fn with_middleware<F>(mut self, name: impl Into<String>, create: F) -> anyhow::Result<Self>
+    where
+        F: Fn(&MiddlewareConfig) -> anyhow::Result<Arc<dyn Middleware>> + Send + Sync + 'static,
+    {
+        self.bot = self.bot.with_middleware(name, create)?;
+        Ok(self)
+    }
+
+    /// The underlying bot, e.g. to register middleware factories in bulk.
+    pub fn bot_mut(&mut self) -> &mut NekoBot {
+        &mut self.bot
+    }
+
+    /// Run the bot in the background.
+    ///
+    /// Startup errors surface through [`RunningBot::stop`]; the bot is
+    /// stopped when the returned handle is dropped.
+    pub fn start(self) -> RunningBot {
+        let mut bot = self.bot;
+        RunningBot {
+            fakes: self.fakes,
+            task: Some(tokio::spawn(async move { bot.run().await })),
+        }
+    }
+}
+
+/// A started [`TestBot`].
+pub struct RunningBot {
+    fakes: Fakes,
+    /// Taken by [`stop`](Self::stop); aborted on drop otherwise.
+    task: Option<tokio::task::JoinHandle<anyhow::Result<()>>>,
+}
+
+impl RunningBot {
+    /// The fake standing in for the channel configured as `name`.
+    ///
+    /// # Panics
+    ///
+    /// If no channel named `name` is configured.
+    pub fn channel(&self, name: &str) -> &FakeChannel {
+        self.fakes
+            .channels
+            .get(name)
+            .unwrap_or_else(|| panic!("no channel named {name} in the config"))
+    }
+
+    /// The scripted provider standing in for the provider configured as `name`.
+    ///
+    /// # Panics
+    ///
+    /// If no provider named `name` is configured.
+    pub fn provider(&self, name: &str) -> &ScriptedProvider {
+        self.fakes
+            .providers
+            .get(name)
+            .unwrap_or_else(|| panic!("no provider named {name} in the config"))
+    }
+
+    /// Stop the bot, returning the error it failed with, if any.
+    pub async fn stop(mut self) -> anyhow::Result<()> {
+        let Some(task) = self.task.take() else {
+            return Ok(());
+        };
+        if task.is_finished() {
+            return task.await?;
+        }
+        task.abort();
+        Ok(())
+    }
+}
+
+impl Drop for RunningBot {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+fn unique(names: impl Iterator<Item = String>) -> Vec<String> {
+    let mut unique = Vec::new();
+    for name in names {
+        if !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use nekobot_core::agent::{
+        Context,
+        tool::{Tool, ToolResult},
+    };
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    const CONFIG: &str = r#"
+channels:
+  - { type: QQ, name: qq, app_id: test, client_secret: test }
+providers:
+  - { type: DeepSeek, name: deepseek, api_key: test, models: [{ model: test-model }] }
+agents:
+  - name: Neko
+    provider: deepseek
+    model: test-model
+    middlewares:
+      - { name: greeter }
+"#;
+
+    struct GreetTool;
+
+    #[async_trait::async_trait]
+    impl Tool for GreetTool {
+        fn name(&self) -> &str {
+            "greet"
+        }
+        fn description(&self) -> &str {
+            "Greet someone"
+        }
+        fn parameters_schema(&self) -> Value {
+            json!({ "type": "object", "properties": { "name": { "type": "string" } } })
+        }
+        async fn call(&self, args: Value) -> ToolResult<Value> {
+            Ok(json!(format!(
+                "hello, {}",
+                args["name"].as_str().unwrap_or("you")
+            )))
+        }
+    }
+
+    struct Greeter;
+
+    #[async_trait::async_trait]
+    impl Middleware for Greeter {
+        async fn init(&self, ctx: &Context) -> anyhow::Result<()> {
+            ctx.tool_registry.register(Arc::new(GreetTool))?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_a_tool_call_round_trip_through_a_middleware() -> anyhow::Result<()> {
+        let bot = TestBot::from_yaml(CONFIG)?
+            .with_middleware("greeter", |_| Ok(Arc::new(Greeter) as Arc<dyn Middleware>))?
+            .start();
+        bot.provider("deepseek")
+            .call_tool("greet", json!({ "name": "Alice" }))
+            .respond("done");
+
+        bot.channel("qq").message("alice", "greet me").await?;
+        let (target, reply) = bot.channel("qq").next_message().await?;
+
+        assert_eq!(target.as_str(), "alice");
+        assert_eq!(reply, "done");
+        let requests = bot.provider("deepseek").wait_for_requests(2).await?;
+        let tool_result = requests[1].chat.messages.last().unwrap();
+        assert_eq!(tool_result.content.tool_call_id(), Some("call-1"));
+        assert!(tool_result.content.text().contains("hello, Alice"));
+        assert_eq!(bot.provider("deepseek").remaining(), 0);
+        bot.stop().await
+    }
+}
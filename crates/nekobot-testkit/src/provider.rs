@@ -0,0 +1,246 @@
+//! Scripted provider — answers requests from a queue prepared by the test.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use nekobot_core::{
+    agent::types::{ChatResponse, ToolCall, ToolCallFunction, Usage},
+    provider::{Provider, ProviderError, ProviderEvent, ProviderRequest},
+};
+use serde_json::Value;
+use tokio::sync::{Notify, mpsc::Sender};
+
+/// A [`Provider`] that replies with queued steps, in order.
+///
+/// Clones share the same script and request log, so a test can keep a handle
+/// while the bot owns another. Every request is recorded; a request arriving
+/// after the script ran out fails with [`ProviderError::InvalidRequest`].
+#[derive(Clone, Default)]
+pub struct ScriptedProvider {
+    state: Arc<State>,
+}
+
+#[derive(Default)]
+struct State {
+    script: Mutex<VecDeque<Step>>,
+    requests: Mutex<Vec<ProviderRequest>>,
+    requested: Notify,
+    next_call_id: Mutex<usize>,
+}
+
+struct Step {
+    result: Result<ChatResponse, ProviderError>,
+    /// Events sent by [`Provider::stream`]; derived from the response if unset.
+    events: Option<Vec<ProviderEvent>>,
+}
+
+impl ScriptedProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a plain text reply.
+    pub fn respond(&self, content: impl Into<String>) -> &Self {
+        self.respond_with(response(content.into(), Vec::new()))
+    }
+
+    /// Queue a full response.
+    pub fn respond_with(&self, response: ChatResponse) -> &Self {
+        self.push(Ok(response), None)
+    }
+
+    /// Queue a reply asking for one tool call.
+    pub fn call_tool(&self, name: impl Into<String>, arguments: Value) -> &Self {
+        self.call_tools([(name, arguments)])
+    }
+
+    /// Queue a reply asking for several tool calls at once.
+    ///
+    /// Calls get the ids `call-1`, `call-2`, … in the order they are queued.
+    pub fn call_tools<N: Into<String>>(
+        &self,
+        calls: impl IntoIterator<Item = (N, Value)>,
+    ) -> &Self {
+        let tool_calls = calls
+            .into_iter()
+            .map(|(name, arguments)| ToolCall {
+                id: self.next_call_id(),
+                r#type: "function".to_owned(),
+                function: ToolCallFunction {
+                    name: name.into(),
+                    arguments: arguments.to_string(),
+                },
+            })
+            .collect();
+        self.respond_with(response(String::new(), tool_calls))
+    }
+
+    /// Queue a failure.
+    pub fn fail(&self, error: ProviderError) -> &Self {
+        self.push(Err(error), None)
+    }
+
+    /// Queue a reply streamed as the given content deltas.
+    ///
+    /// [`complete`](Provider::complete) returns the joined text.
+    pub fn stream_deltas<S: Into<String>>(&self, deltas: impl IntoIterator<Item = S>) -> &Self {
+        let deltas: Vec<String> = deltas.into_iter().map(Into::into).collect();
+        let mut events = vec![ProviderEvent::Started];
+        events.extend(deltas.iter().cloned().map(ProviderEvent::ContentDelta));
+        events.push(ProviderEvent::Finished { usage: None });
+        self.push(Ok(response(deltas.concat(), Vec::new())), Some(events))
+    }
+
+    /// Queue a response together with the exact events to stream before it.
+    pub fn stream_with(&self, events: Vec<ProviderEvent>, response: ChatResponse) -> &Self {
+        self.push(Ok(response), Some(events))
+    }
+
+    /// Number of queued steps not yet used.
+    pub fn remaining(&self) -> usize {
+        lock(&self.state.script).len()
+    }
+
+    /// Every request received so far.
+    pub fn requests(&self) -> Vec<ProviderRequest> {
+        lock(&self.state.requests).clone()
+    }
+
+    /// Wait until at least `count` requests were received and return them.
+    ///
+    /// Fails after five seconds so a stuck bot does not hang the test.
+    pub async fn wait_for_requests(&self, count: usize) -> anyhow::Result<Vec<ProviderRequest>> {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let requested = self.state.requested.notified();
+                let requests = self.requests();
+                if requests.len() >= count {
+                    return requests;
+                }
+                requested.await;
+            }
+        })
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "timed out waiting for {count} provider request(s), got {}",
+                self.requests().len()
+            )
+        })
+    }
+
+    fn push(
+        &self,
+        result: Result<ChatResponse, ProviderError>,
+        events: Option<Vec<ProviderEvent>>,
+    ) -> &Self {
+        lock(&self.state.script).push_back(Step { result, events });
+        self
+    }
+
+    fn next_call_id(&self) -> String {
+        let mut next = lock(&self.state.next_call_id);
+        *next += 1;
+        format!("call-{next}")
+    }
+
+    fn next_step(&self, request: ProviderRequest) -> Result<Step, ProviderError> {
+        lock(&self.state.requests).push(request);
+        self.state.requested.notify_waiters();
+        lock(&self.state.script).pop_front().ok_or_else(|| {
+            ProviderError::InvalidRequest("scripted provider has no response left".to_owned())
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for ScriptedProvider {
+    fn id(&self) -> &'static str {
+        "scripted"
+    }
+
+    async fn complete(&self, request: ProviderRequest) -> Result<ChatResponse, ProviderError> {
+        self.next_step(request)?.result
+    }
+
+    async fn stream(
+        &self,
+        request: ProviderRequest,
+        events: Sender<ProviderEvent>,
+    ) -> Result<ChatResponse, ProviderError> {
+        let step = self.next_step(request)?;
+        let response = step.result?;
+        let streamed = step.events.unwrap_or_else(|| {
+            vec![
+                ProviderEvent::Started,
+                ProviderEvent::ContentDelta(response.content.clone()),
+                ProviderEvent::Finished {
+                    usage: response.usage.clone(),
+                },
+            ]
+        });
+        for event in streamed {
+            let _ = events.send(event).await;
+        }
+        Ok(response)
+    }
+}
+
+fn response(content: String, tool_calls: Vec<ToolCall>) -> ChatResponse {
+    ChatResponse {
+        content,
+        reasoning_content: None,
+        tool_calls,
+        images: Vec::new(),
+        usage: Some(Usage::default()),
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_steps_in_order_then_fails() {
+        let provider = ScriptedProvider::new();
+        provider
+            .call_tool("time", json!({}))
+            .fail(ProviderError::Timeout("slow".to_owned()))
+            .stream_deltas(["he", "llo"]);
+
+        let first = provider.complete(ProviderRequest::default()).await.unwrap();
+        assert_eq!(first.tool_calls[0].id, "call-1");
+        assert_eq!(first.tool_calls[0].function.name, "time");
+        assert!(matches!(
+            provider.complete(ProviderRequest::default()).await,
+            Err(ProviderError::Timeout(_))
+        ));
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(8);
+        let streamed = provider
+            .stream(ProviderRequest::default(), sender)
+            .await
+            .unwrap();
+        assert_eq!(streamed.content, "hello");
+        assert_eq!(receiver.recv().await, Some(ProviderEvent::Started));
+        assert_eq!(
+            receiver.recv().await,
+            Some(ProviderEvent::ContentDelta("he".to_owned()))
+        );
+
+        assert!(matches!(
+            provider.complete(ProviderRequest::default()).await,
+            Err(ProviderError::InvalidRequest(_))
+        ));
+        assert_eq!(provider.wait_for_requests(4).await.unwrap().len(), 4);
+    }
+}
@@ -1,7 +1,8 @@
-//! Built-in utility tools — provides bash, current_time, and search tools.
+//! Built-in utility tools — provides bash, current_time, search, and send_message tools.
 
 mod bash;
 mod search;
+mod send;
 mod sleep;
 mod time;
 
@@ -13,12 +14,13 @@ use nekobot_core::agent::{
     tool::{Tool, ToolSpec},
     types::ChatRequest,
 };
+use schemars::JsonSchema;
 use serde::Deserialize;
 
 const MAX_OUTPUT_BYTES: usize = 100 * 1024;
 
 /// Deserialized from `MiddlewareConfig.data`.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct ToolsConfig {
     /// Timeout for bash commands in seconds. Default 30.
     #[serde(default = "default_timeout")]
@@ -32,6 +34,10 @@ pub struct ToolsConfig {
     /// List of tool names to enable. Default: all.
     #[serde(default = "default_enabled")]
     pub enabled: Vec<String>,
+    /// Chat ids `send_message` may post to; `*` allows every known chat.
+    /// The tool is only registered when this is non-empty.
+    #[serde(default)]
+    pub send_message_allow: Vec<String>,
 }
 
 fn default_timeout() -> u64 {
@@ -131,6 +137,25 @@ impl Middleware for ToolsMiddleware {
             }
         }
 
+        if self.enabled("send_message") && !self.config.send_message_allow.is_empty() {
+            match ctx.output_sender.clone() {
+                Some(output_sender) => {
+                    let tool = Arc::new(send::SendMessageTool::new(
+                        self.config.send_message_allow.clone(),
+                        ctx.app_db.clone(),
+                        output_sender,
+                    ));
+                    specs.push(ToolSpec {
+                        name: tool.name().to_owned(),
+                        description: tool.description().to_owned(),
+                        parameters_schema: tool.parameters_schema(),
+                    });
+                    ctx.tool_registry().register(tool)?;
+                }
+                None => tracing::debug!("session has no output channel, skipping send_message"),
+            }
+        }
+
         Ok(())
     }
 }
@@ -145,6 +170,7 @@ mod tests {
         assert_eq!(cfg.bash_timeout_secs, 30);
         assert!(cfg.enabled.contains(&"bash".to_owned()));
         assert!(cfg.enabled.contains(&"time".to_owned()));
+        assert!(cfg.send_message_allow.is_empty());
     }
 
     #[test]
@@ -0,0 +1,217 @@
+//! Send message tool — posts to another known chat, limited to an allowlist.
+
+use nekobot_core::{
+    agent::{
+        AgentOutput,
+        tool::{ToolError, ToolResult},
+    },
+    entity::channel_chat_agent::{ChannelChatAgent, KnownChat},
+};
+use serde_json::Value;
+use tokio::sync::mpsc::Sender;
+use turso::Connection;
+
+pub struct SendMessageTool {
+    allow: Vec<String>,
+    description: String,
+    app_db: Connection,
+    output_sender: Sender<AgentOutput>,
+}
+
+impl SendMessageTool {
+    pub fn new(allow: Vec<String>, app_db: Connection, output_sender: Sender<AgentOutput>) -> Self {
+        let description = format!(
+            "Send a message to another chat the bot knows, e.g. to forward an alert to a group \
+             or notify a user. Allowed chats: {}.",
+            allow.join(", ")
+        );
+        Self {
+            allow,
+            description,
+            app_db,
+            output_sender,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl nekobot_core::agent::tool::Tool for SendMessageTool {
+    fn name(&self) -> &str {
+        "send_message"
+    }
+    fn description(&self) -> &str {
+        &self.description
+    }
+    fn parameters_schema(&self) -> Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "chat": {
+                    "type": "string",
+                    "description": "Chat id or name"
+                },
+                "channel": {
+                    "type": "string",
+                    "description": "Channel id, only needed if the chat is known on several channels"
+                },
+                "content": {
+                    "type": "string",
+                    "description": "Message text"
+                }
+            },
+            "required": ["chat", "content"]
+        })
+    }
+    async fn call(&self, args: Value) -> ToolResult<Value> {
+        let chat = args
+            .get("chat")
+            .and_then(Value::as_str)
+            .ok_or_else(|| ToolError::InvalidArguments("missing 'chat'".to_owned()))?;
+        let content = args
+            .get("content")
+            .and_then(Value::as_str)
+            .filter(|content| !content.trim().is_empty())
+            .ok_or_else(|| ToolError::InvalidArguments("missing 'content'".to_owned()))?;
+        let channel = args.get("channel").and_then(Value::as_str);
+
+        let chats = ChannelChatAgent::list_chats(&self.app_db)
+            .await
+            .map_err(|e| ToolError::Execution(format!("list chats: {e}")))?;
+        let target = select_target(chats, &self.allow, chat, channel)
+            .map_err(ToolError::InvalidArguments)?;
+
+        self.output_sender
+            .send(AgentOutput::SendToChat {
+                channel_id: target.channel_id,
+                reply_target: target.reply_target,
+                content: content.to_owned(),
+            })
+            .await
+            .map_err(|e| ToolError::Execution(format!("send: {e}")))?;
+
+        Ok(serde_json::json!({
+            "sent": true,
+            "channel": target.channel_name.as_str(),
+            "chat": target.chat_name.as_str(),
+        }))
+    }
+}
+
+/// Pick the chat with id or name `chat`, optionally on channel `channel`,
+/// and check that it is allowed.
+///
+/// Ids take precedence over names, and the allowlist is checked against the
+/// id of the chat found, so renaming a chat cannot get it past the allowlist.
+fn select_target(
+    chats: Vec<KnownChat>,
+    allow: &[String],
+    chat: &str,
+    channel: Option<&str>,
+) -> Result<KnownChat, String> {
+    let (mut matches, by_name): (Vec<KnownChat>, Vec<KnownChat>) = chats
+        .into_iter()
+        .filter(|c| channel.is_none_or(|ch| c.channel_id.as_str() == ch))
+        .filter(|c| c.chat_id.as_str() == chat || c.chat_name.as_str() == chat)
+        .partition(|c| c.chat_id.as_str() == chat);
+    if matches.is_empty() {
+        matches = by_name;
+    }
+
+    let target = match matches.len() {
+        0 => return Err(format!("no known chat matches '{chat}'")),
+        1 => matches.remove(0),
+        _ => {
+            return Err(format!(
+                "'{chat}' matches several chats, pass a chat id or a channel id: {}",
+                matches
+                    .iter()
+                    .map(|c| format!("{} on {} ({})", c.chat_id, c.channel_id, c.channel_name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    };
+    if !allow
+        .iter()
+        .any(|a| a == "*" || a == target.chat_id.as_str())
+    {
+        return Err(format!("chat {} is not in the allowlist", target.chat_id));
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use nekobot_channel::{ChannelId, ChannelName, ChatId, ChatName, ReplyTarget};
+
+    use super::*;
+
+    fn known(channel: &str, chat_id: &str, chat_name: &str) -> KnownChat {
+        KnownChat {
+            channel_id: ChannelId::from(channel),
+            channel_name: ChannelName::from(channel),
+            chat_id: ChatId::from(chat_id),
+            chat_name: ChatName::from(chat_name),
+            reply_target: ReplyTarget::from(chat_id),
+        }
+    }
+
+    #[test]
+    fn select_target_respects_allowlist_and_ambiguity() {
+        let chats = vec![
+            known("qq", "group-1", "Ops"),
+            known("weixin", "group-1", "Ops"),
+            known("qq", "user-7", "Bob"),
+            known("qq", "user-8", "group-1"),
+        ];
+        let allow = vec!["group-1".to_owned()];
+
+        assert!(select_target(chats.clone(), &allow, "user-7", None).is_err());
+        assert!(select_target(chats.clone(), &allow, "group-1", None).is_err());
+        let target = select_target(chats.clone(), &allow, "group-1", Some("weixin")).unwrap();
+        assert_eq!(target.channel_id, ChannelId::from("weixin"));
+
+        // A chat renamed after an allowed id is neither allowed nor addressable by name.
+        let target = select_target(chats.clone(), &allow, "group-1", Some("qq")).unwrap();
+        assert_eq!(target.chat_name, ChatName::from("Ops"));
+        assert!(select_target(chats.clone(), &allow, "user-8", None).is_err());
+
+        let allow_all = vec!["*".to_owned()];
+        let target = select_target(chats, &allow_all, "user-7", None).unwrap();
+        assert_eq!(target.chat_name, ChatName::from("Bob"));
+    }
+
+    #[test]
+    fn select_target_looks_up_chats_by_name() {
+        let chats = vec![
+            known("qq", "group-1", "Ops"),
+            known("weixin", "group-1", "Ops"),
+            known("qq", "user-7", "Bob"),
+        ];
+        let allow = vec!["group-1".to_owned()];
+
+        let target = select_target(chats.clone(), &allow, "Ops", Some("qq")).unwrap();
+        assert_eq!(target.chat_id, ChatId::from("group-1"));
+        assert_eq!(target.channel_id, ChannelId::from("qq"));
+        let error = select_target(chats.clone(), &allow, "Bob", None).unwrap_err();
+        assert!(error.contains("not in the allowlist"), "{error}");
+
+        let allow_all = vec!["*".to_owned()];
+        let target = select_target(chats, &allow_all, "Bob", None).unwrap();
+        assert_eq!(target.chat_id, ChatId::from("user-7"));
+    }
+
+    #[test]
+    fn select_target_rejects_ambiguous_names() {
+        let chats = vec![known("qq", "user-7", "Bob"), known("qq", "user-9", "Bob")];
+        let allow = vec!["*".to_owned()];
+
+        let error = select_target(chats.clone(), &allow, "Bob", None).unwrap_err();
+        assert!(
+            error.contains("user-7 on qq") && error.contains("user-9 on qq"),
+            "{error}"
+        );
+        let target = select_target(chats, &allow, "user-9", None).unwrap();
+        assert_eq!(target.chat_name, ChatName::from("Bob"));
+    }
+}
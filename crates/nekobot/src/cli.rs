@@ -0,0 +1,120 @@
+//! Command-line arguments.
+//!
+//! ```text
+//! nekobot [--dry-run-migrations]
+//! nekobot export (--session ID | --agent NAME | --channel ID --chat ID)
+//!                [--format jsonl|markdown|html] [--output PATH]
+//! nekobot import --agent NAME PATH
+//! nekobot rotate-secrets
+//! nekobot schema
+//! nekobot hash-password < password.txt
+//! ```
+
+use nekobot_core::transcript::{Selection, TranscriptFormat};
+
+/// What the binary was asked to do.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    DryRunMigrations,
+    /// Write transcripts to `output`, or to stdout.
+    Export {
+        selection: Selection,
+        format: TranscriptFormat,
+        output: Option<String>,
+    },
+    /// Import conversations from `path` (`-` for stdin) into new sessions.
+    Import {
+        agent_name: String,
+        path: String,
+    },
+    /// Re-encrypt stored secrets with the primary key.
+    RotateSecrets,
+    /// Print the JSON Schema of `config.yaml`.
+    Schema,
+    /// Print an Argon2id hash of the password read from stdin, for
+    /// `password_hash`.
+    HashPassword,
+}
+
+/// Parse the arguments after the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Command> {
+    let mut args = args.into_iter();
+    let Some(first) = args.next() else {
+        return Ok(Command::Run);
+    };
+    match first.as_str() {
+        "--dry-run-migrations" => Ok(Command::DryRunMigrations),
+        "export" => parse_export(args),
+        "import" => parse_import(args),
+        "rotate-secrets" => Ok(Command::RotateSecrets),
+        "schema" => Ok(Command::Schema),
+        "hash-password" => Ok(Command::HashPassword),
+        other => anyhow::bail!("unknown argument '{other}'"),
+    }
+}
+
+fn parse_export(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+    let mut session = None;
+    let mut agent = None;
+    let mut channel = None;
+    let mut chat = None;
+    let mut format = TranscriptFormat::Jsonl;
+    let mut output = None;
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("{flag} needs a value"))
+        };
+        match flag.as_str() {
+            "--session" => {
+                let id = value()?;
+                session = Some(
+                    id.parse::<i64>()
+                        .map_err(|_| anyhow::anyhow!("invalid session id '{id}'"))?,
+                );
+            }
+            "--agent" => agent = Some(value()?),
+            "--channel" => channel = Some(value()?),
+            "--chat" => chat = Some(value()?),
+            "--format" => format = value()?.parse()?,
+            "--output" => output = Some(value()?),
+            other => anyhow::bail!("unknown export argument '{other}'"),
+        }
+    }
+    let selection = match (session, agent, channel, chat) {
+        (Some(id), None, None, None) => Selection::Session(id),
+        (None, Some(agent_name), None, None) => Selection::Agent(agent_name),
+        (None, None, Some(channel_id), Some(chat_id)) => Selection::Chat {
+            channel_id,
+            chat_id,
+        },
+        _ => anyhow::bail!("export needs one of --session, --agent or --channel with --chat"),
+    };
+    Ok(Command::Export {
+        selection,
+        format,
+        output,
+    })
+}
+
+fn parse_import(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+    let mut agent_name = None;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--agent" => {
+                agent_name = Some(
+                    args.next()
+                        .ok_or_else(|| anyhow::anyhow!("--agent needs a value"))?,
+                )
+            }
+            _ if path.is_none() => path = Some(arg),
+            other => anyhow::bail!("unexpected import argument '{other}'"),
+        }
+    }
+    match (agent_name, path) {
+        (Some(agent_name), Some(path)) => Ok(Command::Import { agent_name, path }),
+        _ => anyhow::bail!("import needs --agent NAME and a file path"),
+    }
+}
@@ -1,19 +1,28 @@
 //! NekoBot — modular multi-agent chatbot.
 //!
 //! Bootstrap flow:
-//! 1. Load and parse `config.yaml`
+//! 1. Load `config.yaml` with its includes and secret references, then set up
+//!    logging and span export
 //! 2. Create [`NekoBot`](nekobot_core::NekoBot) from the config
 //! 3. Register channel implementations (QQ Bot, WeiXin)
 //! 4. Register provider implementations (DeepSeek, OpenAI Codex)
-//! 5. Register middleware factories (mcp, script, skills, tools, memory, persona)
-//! 6. Run the system
+//! 5. Register middleware factories (mcp, script, skills, tools, memory, scheduler, persona,
+//!    moderation, redaction)
+//! 6. Register schema migrations of middleware crates
+//! 7. Check the config against the registered factories and run the system,
+//!    or run one of the maintenance commands in [`cli`]: list pending
+//!    migrations, export or import conversations, or re-encrypt stored
+//!    secrets with the current key
+//!
+//! `nekobot schema` prints the JSON Schema of `config.yaml` and
+//! `nekobot hash-password` hashes a login password; neither reads the config.
+
+mod cli;
 
 macro_rules! register_middleware {
     ($bot:expr, $name:literal, $cfg_type:ty, $factory:expr) => {
         $bot.middleware_registry_mut()
-            .register($name, |config| {
-                let cfg: $cfg_type =
-                    serde_json::from_value(serde_json::Value::Object(config.data.clone()))?;
+            .register_with_config::<$cfg_type, _>($name, |cfg| {
                 Ok(std::sync::Arc::new($factory(cfg))
                     as std::sync::Arc<
                         dyn nekobot_core::agent::middleware::Middleware,
@@ -23,24 +32,63 @@ macro_rules! register_middleware {
     };
 }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
+/// Install the log subscriber, exporting spans over OTLP when configured.
+///
+/// Returns the tracer provider so pending spans can be flushed on exit.
+fn init_tracing(
+    telemetry: &nekobot_core::config::TelemetryConfig,
+) -> anyhow::Result<Option<opentelemetry_sdk::trace::SdkTracerProvider>> {
+    use anyhow::Context as _;
+    use opentelemetry_otlp::WithExportConfig as _;
+    use tracing_subscriber::{layer::SubscriberExt as _, util::SubscriberInitExt as _};
+
+    let tracer_provider = telemetry
+        .otlp_endpoint
+        .as_ref()
+        .map(|endpoint| {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()
+                .with_context(|| format!("failed to create the OTLP exporter for {endpoint}"))?;
+            anyhow::Ok(
+                opentelemetry_sdk::trace::SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(
+                        opentelemetry_sdk::Resource::builder()
+                            .with_service_name(telemetry.service_name.clone())
+                            .build(),
+                    )
+                    .build(),
+            )
+        })
+        .transpose()?;
+
+    tracing_subscriber::registry()
+        .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
         )
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracer_provider.as_ref().map(otel_layer))
         .init();
+    Ok(tracer_provider)
+}
 
-    // Read and parse config
-    let config_content = tokio::fs::read_to_string("config.yaml")
-        .await
-        .expect("Failed to read config.yaml");
-    let config: nekobot_core::config::Config =
-        serde_yml::from_str(&config_content).expect("Failed to parse config.yaml");
+/// The layer turning `tracing` spans into OpenTelemetry spans of `provider`.
+fn otel_layer<S>(
+    provider: &opentelemetry_sdk::trace::SdkTracerProvider,
+) -> tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::trace::TracerProvider as _;
 
-    // Build NekoBot with the config
-    let mut bot = nekobot_core::NekoBot::new(config);
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("nekobot"))
+}
 
+/// Register the channel, provider and middleware factories and the schema
+/// migrations of middleware crates.
+fn register_factories(bot: &mut nekobot_core::NekoBot, keyring: &nekobot_secrets::Keyring) {
     // Register concrete channel implementations
     bot.channel_registry_mut()
         .register("QQ", |cfg| match cfg {
@@ -60,10 +108,12 @@ async fn main() {
         })
         .expect("Failed to register QQ channel");
 
+    let weixin_keyring = keyring.clone();
     bot.channel_registry_mut()
-        .register("WeiXin", |cfg| match cfg {
+        .register("WeiXin", move |cfg| match cfg {
             nekobot_core::config::ChannelConfig::WeiXin { name, base_url, .. } => Ok(Box::new(
-                nekobot_channel::channel::WeiXinChannel::new(name.clone(), base_url.clone()),
+                nekobot_channel::channel::WeiXinChannel::new(name.clone(), base_url.clone())
+                    .with_keyring(weixin_keyring.clone()),
             )
                 as Box<dyn nekobot_channel::Channel>),
             nekobot_core::config::ChannelConfig::QQ { .. } => {
@@ -89,9 +139,6 @@ async fn main() {
         nekobot_script::ScriptConfig,
         nekobot_script::ScriptMiddleware::from_config
     );
-    register_middleware!(bot, "skills", nekobot_skills::SkillConfig, |c| {
-        nekobot_skills::SkillMiddleware::from_config(c).unwrap()
-    });
     register_middleware!(
         bot,
         "tools",
@@ -104,19 +151,346 @@ async fn main() {
         nekobot_memory::MemoryConfig,
         nekobot_memory::MemoryMiddleware::from_config
     );
+    register_middleware!(
+        bot,
+        "scheduler",
+        nekobot_scheduler::SchedulerConfig,
+        nekobot_scheduler::SchedulerMiddleware::from_config
+    );
 
-    // Persona has no config
+    register_middleware!(
+        bot,
+        "persona",
+        nekobot_persona::PersonaConfig,
+        nekobot_persona::PersonaMiddleware::from_config
+    );
+
+    // Skills scan their directory, and moderation and redaction compile their
+    // patterns up front, so a bad one fails the agent
     bot.middleware_registry_mut()
-        .register("persona", |_config| {
+        .register_with_config::<nekobot_skills::SkillConfig, _>("skills", |cfg| {
             Ok(
-                std::sync::Arc::new(nekobot_persona::PersonaMiddleware::new())
+                std::sync::Arc::new(nekobot_skills::SkillMiddleware::from_config(cfg)?)
                     as std::sync::Arc<dyn nekobot_core::agent::middleware::Middleware>,
             )
         })
-        .expect("Failed to register persona middleware");
+        .expect("Failed to register skills middleware");
+    bot.middleware_registry_mut()
+        .register_with_config::<nekobot_moderation::ModerationConfig, _>("moderation", |cfg| {
+            Ok(
+                std::sync::Arc::new(nekobot_moderation::ModerationMiddleware::from_config(cfg)?)
+                    as std::sync::Arc<dyn nekobot_core::agent::middleware::Middleware>,
+            )
+        })
+        .expect("Failed to register moderation middleware");
+    bot.middleware_registry_mut()
+        .register_with_config::<nekobot_redaction::RedactionConfig, _>("redaction", |cfg| {
+            Ok(
+                std::sync::Arc::new(nekobot_redaction::RedactionMiddleware::from_config(cfg)?)
+                    as std::sync::Arc<dyn nekobot_core::agent::middleware::Middleware>,
+            )
+        })
+        .expect("Failed to register redaction middleware");
+
+    // Register schema migrations (core and channel ones are built in)
+    bot.migration_registry_mut()
+        .register(nekobot_memory::MIGRATIONS)
+        .expect("Failed to register memory migrations");
+    bot.migration_registry_mut()
+        .register(nekobot_moderation::MIGRATIONS)
+        .expect("Failed to register moderation migrations");
+}
+
+/// Database columns that hold secrets sealed with the keyring.
+const SECRET_COLUMNS: &[nekobot_secrets::SecretColumn] = &[nekobot_channel::entity::CREDENTIALS];
+
+/// Re-encrypt every stored secret with the primary key, so that older keys
+/// can be removed afterwards.
+async fn rotate_secrets(
+    bot: &nekobot_core::NekoBot,
+    keyring: &nekobot_secrets::Keyring,
+) -> anyhow::Result<()> {
+    let db = bot.open_database().await?;
+    let conn = db.connect()?;
+    for column in SECRET_COLUMNS {
+        let count = keyring.rotate(&conn, column).await?;
+        println!(
+            "re-encrypted {count} row(s) of {}.{} with key '{}'",
+            column.table,
+            column.column,
+            keyring.primary_key_id().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Write the selected transcripts to `output`, or to stdout.
+async fn export_transcripts(
+    bot: &nekobot_core::NekoBot,
+    selection: &nekobot_core::transcript::Selection,
+    format: nekobot_core::transcript::TranscriptFormat,
+    output: Option<&str>,
+) -> anyhow::Result<()> {
+    use nekobot_core::transcript;
+
+    let db = bot.open_database().await?;
+    let conn = db.connect()?;
+    let transcripts = transcript::load(&conn, selection).await?;
+    let rendered = transcript::render(&transcripts, format)?;
+    match output {
+        Some(path) => tokio::fs::write(path, rendered).await?,
+        None => print!("{rendered}"),
+    }
+    eprintln!("exported {} session(s)", transcripts.len());
+    Ok(())
+}
+
+/// Import the conversations in `path` (`-` for stdin) into new sessions.
+async fn import_transcripts(
+    bot: &nekobot_core::NekoBot,
+    agent_name: &str,
+    path: &str,
+) -> anyhow::Result<()> {
+    let data = if path == "-" {
+        use tokio::io::AsyncReadExt as _;
+        let mut data = String::new();
+        tokio::io::stdin().read_to_string(&mut data).await?;
+        data
+    } else {
+        tokio::fs::read_to_string(path).await?
+    };
+    let db = bot.open_database().await?;
+    let conn = db.connect()?;
+    for session_id in nekobot_core::transcript::import(&conn, agent_name, &data).await? {
+        println!("imported session {session_id}");
+    }
+    Ok(())
+}
+
+/// Print the JSON Schema of `config.yaml`.
+///
+/// The schema describes the registered factories and needs no config.
+fn print_schema() {
+    let config = serde_json::from_value(serde_json::json!({
+        "channels": [],
+        "providers": [],
+        "agents": [],
+    }))
+    .expect("empty config is valid");
+    let mut bot = nekobot_core::NekoBot::new(config);
+    register_factories(&mut bot, &nekobot_secrets::Keyring::empty());
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&bot.config_schema()).expect("schema serializes")
+    );
+}
+
+/// Print an Argon2id hash of the password read from stdin.
+fn hash_password() {
+    let mut password = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut password) {
+        eprintln!("Failed to read the password: {e}");
+        std::process::exit(1);
+    }
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        eprintln!("The password must not be empty");
+        std::process::exit(1);
+    }
+    match nekobot_core::auth::PasswordHash::hash(password) {
+        Ok(hash) => println!("{hash}"),
+        Err(e) => {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The bot built from `config.yaml`, with tracing set up and the secret keys
+/// loaded; exits the process when any of it fails.
+struct Setup {
+    bot: nekobot_core::NekoBot,
+    keyring: nekobot_secrets::Keyring,
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+fn setup() -> Setup {
+    // Read config, following includes and resolving secret references
+    let config = match nekobot_core::config::Config::load("config.yaml") {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load config.yaml: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let tracer_provider = match init_tracing(&config.telemetry) {
+        Ok(tracer_provider) => tracer_provider,
+        Err(e) => {
+            eprintln!("Failed to set up tracing: {e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    // Keys for secrets stored in the database
+    let key_file = config.secret_key_file.as_deref().map(std::path::Path::new);
+    let keyring = match nekobot_secrets::Keyring::load(key_file) {
+        Ok(keyring) => keyring,
+        Err(e) => {
+            eprintln!("Failed to load secret keys: {e:#}");
+            std::process::exit(1);
+        }
+    };
+    if keyring.is_empty() {
+        tracing::warn!("no secret key configured; channel credentials are stored unencrypted");
+    }
+
+    // Build NekoBot with the config
+    let mut bot = nekobot_core::NekoBot::new(config);
+
+    register_factories(&mut bot, &keyring);
+
+    Setup {
+        bot,
+        keyring,
+        tracer_provider,
+    }
+}
+
+/// Exit with status 1 when a one-shot command failed.
+fn exit_on_error(result: anyhow::Result<()>, what: &str) {
+    if let Err(e) = result {
+        tracing::error!("{what}: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    match command {
+        cli::Command::Schema => print_schema(),
+        cli::Command::HashPassword => hash_password(),
+        cli::Command::DryRunMigrations => {
+            let Setup { bot, .. } = setup();
+            match bot.pending_migrations().await {
+                Ok(pending) if pending.is_empty() => println!("database is up to date"),
+                Ok(pending) => {
+                    for m in pending {
+                        println!("{} {}: {}", m.component, m.version, m.description);
+                    }
+                }
+                Err(e) => exit_on_error(Err(e), "failed to plan migrations"),
+            }
+        }
+        cli::Command::RotateSecrets => {
+            let Setup { bot, keyring, .. } = setup();
+            exit_on_error(
+                rotate_secrets(&bot, &keyring).await,
+                "failed to rotate secrets",
+            );
+        }
+        cli::Command::Export {
+            selection,
+            format,
+            output,
+        } => {
+            let Setup { bot, .. } = setup();
+            exit_on_error(
+                export_transcripts(&bot, &selection, format, output.as_deref()).await,
+                "failed to export transcripts",
+            );
+        }
+        cli::Command::Import { agent_name, path } => {
+            let Setup { bot, .. } = setup();
+            exit_on_error(
+                import_transcripts(&bot, &agent_name, &path).await,
+                "failed to import transcripts",
+            );
+        }
+        cli::Command::Run => {
+            let Setup {
+                mut bot,
+                tracer_provider,
+                ..
+            } = setup();
+
+            // Start the bot (connects channels, runs agents)
+            if let Err(e) = bot.run().await {
+                tracing::error!("NekoBot exited: {e}");
+            }
+
+            if let Some(provider) = tracer_provider
+                && let Err(e) = provider.shutdown()
+            {
+                eprintln!("Failed to flush spans: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+channels:
+  - { type: QQ, name: qq, app_id: test, client_secret: test }
+providers:
+  - { type: DeepSeek, name: deepseek, api_key: test, models: [{ model: test-model }] }
+agents:
+  - { name: Neko, provider: deepseek, model: test-model, middlewares: [] }
+"#;
+
+    #[tokio::test]
+    async fn exports_turn_and_provider_spans() -> anyhow::Result<()> {
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(otel_layer(&provider)),
+        );
+
+        let bot = nekobot_testkit::TestBot::from_yaml(CONFIG)?.start();
+        bot.provider("deepseek").respond("hi!");
+        bot.channel("qq").message("alice", "hello").await?;
+        bot.channel("qq").next_message().await?;
+        let span = |name: &str| {
+            exporter
+                .get_finished_spans()
+                .unwrap()
+                .into_iter()
+                .find(|span| span.name == name)
+        };
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        while span("agent.turn").is_none() && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        bot.stop().await?;
 
-    // Start the bot (connects channels, runs agents)
-    if let Err(e) = bot.run().await {
-        tracing::error!("NekoBot exited: {e}");
+        let turn = span("agent.turn").expect("turn span exported");
+        let complete = span("provider.complete").expect("provider span exported");
+        assert_eq!(complete.parent_span_id, turn.span_context.span_id());
+        assert_eq!(
+            complete.span_context.trace_id(),
+            turn.span_context.trace_id()
+        );
+        assert!(
+            turn.attributes
+                .iter()
+                .any(|kv| kv.key.as_str() == "agent" && kv.value.as_str() == "Neko")
+        );
+        assert!(span("channel.event").is_some());
+        Ok(())
     }
 }